@@ -2,6 +2,7 @@
 
 use ci::{self, CiId};
 use crossbeam;
+use db;
 use hyper::Url;
 use hyper::buffer::BufReader;
 use hyper::header::Headers;
@@ -11,7 +12,9 @@ use hyper::status::StatusCode;
 use pipeline;
 use serde_json::{from_slice as json_from_slice};
 use std::io::BufWriter;
+use std::sync::Mutex;
 use std::sync::mpsc::{Sender, Receiver};
+use util::github_deliveries::{self, Deliveries};
 use util::github_headers;
 
 pub trait PipelinesConfig: Send + Sync + 'static {
@@ -30,18 +33,30 @@ pub struct Worker {
     listen: String,
     pipelines: Box<PipelinesConfig>,
     secret: String,
+    /// Reject webhooks that are not signed with SHA-256
+    require_sha256: bool,
+    deliveries: Mutex<Deliveries>,
 }
 
 impl Worker {
     pub fn new(
         listen: String,
         secret: String,
+        require_sha256: bool,
         pipelines: Box<PipelinesConfig>,
+        deliveries_builder: db::Builder,
     ) -> Worker {
         Worker {
             listen: listen,
             pipelines: pipelines,
             secret: secret,
+            require_sha256: require_sha256,
+            deliveries: Mutex::new(
+                github_deliveries::from_builder(
+                    &deliveries_builder,
+                    "ci_github_status",
+                ).expect("to get a deliveries table")
+            ),
         }
     }}
 
@@ -128,11 +143,42 @@ impl Worker {
         mut res: Response,
         send_event: &Sender<ci::Event>
     ) {
-        let head = github_headers::parse(&mut req, self.secret.as_bytes());
-        let (x_github_event, body) = match head {
+        let head = github_headers::parse(
+            &mut req,
+            self.secret.as_bytes(),
+            self.require_sha256,
+        );
+        let head = match head {
             Some(head) => head,
             None => return,
         };
+        if let Some(ref delivery) = head.delivery {
+            if self.deliveries.lock().unwrap().seen(delivery) {
+                info!("Got duplicate delivery {}; do nothing", delivery);
+                *res.status_mut() = StatusCode::NoContent;
+                if let Err(e) = res.send(&[]) {
+                    warn!("Failed to send response to duplicate: {:?}", e);
+                }
+                return;
+            }
+        }
+        let handled = self.handle_event(head.event, head.body, res, send_event);
+        // Only remember it once it has been handled, so that a delivery
+        // that failed partway is handled again when GitHub redelivers it.
+        if let (true, Some(delivery)) = (handled, head.delivery) {
+            self.deliveries.lock().unwrap().record(&delivery);
+        }
+    }
+
+    /// Returns false if the event could not be handled,
+    /// and is worth handling again if it's redelivered.
+    fn handle_event(
+        &self,
+        x_github_event: Vec<u8>,
+        body: Vec<u8>,
+        mut res: Response,
+        send_event: &Sender<ci::Event>
+    ) -> bool {
         match &x_github_event[..] {
             b"status" => {
                 if let Ok(desc) = json_from_slice::<StatusDesc>(&body) {
@@ -169,6 +215,7 @@ impl Worker {
                                 desc.target_url.as_ref().and_then(|u|
                                     Url::parse(&u[..]).ok()
                                 ),
+                                ci::FailureSummary::default(),
                             ),
                             "success" => ci::Event::BuildSucceeded(
                                 id,
@@ -182,7 +229,7 @@ impl Worker {
                                     "Unknown status state: {}",
                                     desc.state
                                 );
-                                return;
+                                return true;
                             },
                         };
                         send_event.send(event).expect("pipeline");
@@ -224,6 +271,7 @@ impl Worker {
                 );
             }
         }
+        true
     }
 
     fn handle_message(
@@ -5,21 +5,36 @@ use crossbeam;
 use rest::{authorization, Authorization, Client, IntoUrl};
 use pipeline;
 use serde_json::from_reader as json_from_reader;
+use std::fmt;
+use std::io::Read;
 use std::net::TcpListener;
 use std::sync::mpsc::{Sender, Receiver};
-use util::USER_AGENT;
+use util::{REDACTED, USER_AGENT};
+
+/// How much of the console log to attach to a failure
+/// when the build did not publish a test report.
+const LOG_LINES: usize = 50;
 
 pub trait PipelinesConfig: Send + Sync + 'static {
     fn job_by_id(&self, CiId) -> Option<Job>;
     fn ids_by_job_name(&self, &str) -> Vec<CiId>;
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Eq, Hash, PartialEq)]
 pub struct Job {
     pub name: String,
     pub token: String,
 }
 
+impl fmt::Debug for Job {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Job")
+            .field("name", &self.name)
+            .field("token", &REDACTED)
+            .finish()
+    }
+}
+
 pub struct Worker {
     listen: String,
     pipelines: Box<PipelinesConfig>,
@@ -115,6 +130,13 @@ impl Worker {
             if ids.is_empty() {
                 warn!("Got result of unknown job: {}", desc.name);
             }
+            // Fetched once, however many pipelines share the job.
+            let summary = match desc.build.status {
+                Some(ref status) if desc.build.phase == "COMPLETED" &&
+                        status != "SUCCESS" && !ids.is_empty() =>
+                    self.failure_summary(&desc.build.full_url),
+                _ => ci::FailureSummary::default(),
+            };
             for id in ids {
                 let commit = desc.build.scm.commit.clone().into();
                 if desc.build.phase == "STARTED" {
@@ -143,6 +165,7 @@ impl Worker {
                                     id,
                                     commit,
                                     desc.build.full_url.into_url().ok(),
+                                    summary.clone(),
                                 )
                             ).expect("Pipeline");
                         }
@@ -174,7 +197,8 @@ impl Worker {
                     job.name,
                     job.token,
                 );
-                info!("Trigger build: {}", url);
+                // The URL has the token in it, so only the job is logged.
+                info!("Trigger build: {}", job.name);
                 let result = self.client
                     .get(&url).expect("valid url")
                     .send();
@@ -185,6 +209,7 @@ impl Worker {
                             id,
                             commit,
                             None,
+                            ci::FailureSummary::default(),
                         )).expect("Pipeline");
                     }
                     Err(e) => {
@@ -193,6 +218,7 @@ impl Worker {
                             id,
                             commit,
                             None,
+                            ci::FailureSummary::default(),
                         )).expect("Pipeline");
                     }
                     Ok(_) => {}
@@ -201,4 +227,113 @@ impl Worker {
         }
     }
 
+    /// Find out why a build failed.
+    ///
+    /// The names of the failed tests are preferred, since they are short;
+    /// builds without a test report get the end of the console log instead.
+    fn failure_summary(&self, build_url: &str) -> ci::FailureSummary {
+        let path = match build_url.into_url() {
+            Ok(url) => url.path().to_owned(),
+            Err(e) => {
+                warn!("Invalid build URL {}: {:?}", build_url, e);
+                return ci::FailureSummary::default();
+            }
+        };
+        let path = if path.ends_with('/') { path } else { path + "/" };
+        let tests = self.failed_tests(&path);
+        let log = if tests.is_empty() {
+            self.log_tail(&path)
+        } else {
+            None
+        };
+        ci::FailureSummary{
+            tests: tests,
+            log: log,
+        }
+    }
+
+    fn failed_tests(&self, build_path: &str) -> Vec<String> {
+        #[derive(Deserialize)]
+        struct CaseDesc {
+            #[serde(rename="className")]
+            class_name: String,
+            name: String,
+            status: String,
+        }
+        #[derive(Deserialize)]
+        struct SuiteDesc {
+            cases: Vec<CaseDesc>,
+        }
+        #[derive(Deserialize)]
+        struct ReportDesc {
+            suites: Vec<SuiteDesc>,
+        }
+        let url = format!("{}testReport/api/json", build_path);
+        let resp = match self.client.get(&url).expect("valid url").send() {
+            Ok(resp) => resp,
+            Err(e) => {
+                warn!("Failed to get test report: {:?}", e);
+                return Vec::new();
+            }
+        };
+        if !resp.is_success() {
+            // Most likely, the job does not publish test results.
+            return Vec::new();
+        }
+        let report: ReportDesc = match resp.json() {
+            Ok(report) => report,
+            Err(e) => {
+                warn!("Test report parse failed: {:?}", e);
+                return Vec::new();
+            }
+        };
+        let mut tests = Vec::new();
+        for suite in report.suites {
+            for case in suite.cases {
+                if case.status == "FAILED" || case.status == "REGRESSION" {
+                    tests.push(format!("{}.{}", case.class_name, case.name));
+                }
+            }
+        }
+        tests
+    }
+
+    fn log_tail(&self, build_path: &str) -> Option<String> {
+        let url = format!("{}consoleText", build_path);
+        let mut resp = match self.client.get(&url).expect("valid url").send() {
+            Ok(resp) => resp,
+            Err(e) => {
+                warn!("Failed to get console log: {:?}", e);
+                return None;
+            }
+        };
+        if !resp.is_success() {
+            warn!("Failed to get console log: {:?}", resp.http.status);
+            return None;
+        }
+        let mut log = String::new();
+        if let Err(e) = resp.http.read_to_string(&mut log) {
+            warn!("Failed to read console log: {:?}", e);
+            return None;
+        }
+        Some(tail_lines(&log, LOG_LINES))
+    }
+}
+
+fn tail_lines(text: &str, count: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let start = lines.len().saturating_sub(count);
+    lines[start..].join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::tail_lines;
+
+    #[test]
+    fn test_tail_lines() {
+        assert_eq!(tail_lines("a\nb\nc\n", 2), "b\nc");
+        assert_eq!(tail_lines("a\nb", 5), "a\nb");
+        assert_eq!(tail_lines("", 5), "");
+    }
 }
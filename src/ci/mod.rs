@@ -2,6 +2,7 @@
 
 pub mod github_status;
 pub mod jenkins;
+pub mod webhook;
 
 use config::PipelinesConfig;
 use hyper::Url;
@@ -16,20 +17,38 @@ pub enum Message {
     StartBuild(CiId, Commit),
 }
 
+/// What the CI could tell us about why a build failed.
+///
+/// Both parts are optional; backends fill in whatever they can get.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FailureSummary {
+    /// Names of the tests that failed.
+    pub tests: Vec<String>,
+    /// The tail of the build log.
+    pub log: Option<String>,
+}
+
+impl FailureSummary {
+    pub fn is_empty(&self) -> bool {
+        self.tests.is_empty() && self.log.is_none()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Event {
     BuildStarted(CiId, Commit, Option<Url>),
     BuildSucceeded(CiId, Commit, Option<Url>),
-    BuildFailed(CiId, Commit, Option<Url>),
+    BuildFailed(CiId, Commit, Option<Url>, FailureSummary),
 }
 
 impl GetPipelineId for Event {
-    fn pipeline_id<C: PipelinesConfig + ?Sized>(&self, config: &C) -> PipelineId {
+    fn pipeline_id<C: PipelinesConfig + ?Sized>(&self, config: &C)
+            -> Option<PipelineId> {
         let ci_id = match *self {
             Event::BuildStarted(i, _, _) => i,
             Event::BuildSucceeded(i, _, _) => i,
-            Event::BuildFailed(i, _, _) => i,
+            Event::BuildFailed(i, _, _, _) => i,
         };
-        config.by_ci_id(ci_id).pipeline_id
+        config.by_ci_id(ci_id).map(|pipeline| pipeline.pipeline_id)
     }
 }
\ No newline at end of file
@@ -0,0 +1,445 @@
+// This file is released under the same terms as Rust itself.
+
+//! A generic CI backend, driven by outgoing and incoming webhooks.
+//!
+//! Builds are triggered by POSTing a templated JSON body to a URL.
+//! Results come back as signed POSTs to our listener,
+//! and the interesting fields are picked out with JSON pointers,
+//! so most hosted CI services can be wired up without new code.
+
+use ci::{self, CiId};
+use crossbeam;
+use hyper::Client;
+use hyper::Url;
+use hyper::buffer::BufReader;
+use hyper::header::{Authorization, Bearer, ContentType, Headers, UserAgent};
+use hyper::net::{HttpListener, NetworkListener, NetworkStream};
+use hyper::server::{Request, Response};
+use hyper::status::StatusCode;
+use pipeline;
+use serde_json::{self, from_slice as json_from_slice, Value};
+use std::fmt;
+use std::io::BufWriter;
+use std::sync::mpsc::{Sender, Receiver};
+use util::{REDACTED, USER_AGENT};
+use util::github_headers;
+use vcs::Commit;
+
+pub trait PipelinesConfig: Send + Sync + 'static {
+    fn job_by_id(&self, CiId) -> Option<Job>;
+    fn ids_by_job_name(&self, &str) -> Vec<CiId>;
+}
+
+#[derive(Clone, Eq, Hash, PartialEq)]
+pub struct Job {
+    /// The name the CI service reports results under.
+    pub name: String,
+    /// Where to POST to start a build.
+    pub url: String,
+    /// The body of the trigger request.
+    ///
+    /// `{{commit}}` and `{{name}}` are replaced
+    /// with the commit to build and the job name.
+    /// They are escaped to go inside a JSON string.
+    pub body: String,
+    /// Sent as a bearer token with the trigger request, if given.
+    pub token: Option<String>,
+}
+
+impl fmt::Debug for Job {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Job")
+            .field("name", &self.name)
+            .field("url", &self.url)
+            .field("body", &self.body)
+            .field("token", &self.token.as_ref().map(|_| REDACTED))
+            .finish()
+    }
+}
+
+/// Where to find things in the JSON body of a result notification.
+#[derive(Clone, Debug)]
+pub struct Fields {
+    /// JSON pointer to the job name.
+    pub name: String,
+    /// JSON pointer to the commit that was built.
+    pub commit: String,
+    /// JSON pointer to the state of the build.
+    pub state: String,
+    /// JSON pointer to a web page for the build, if there is one.
+    pub url: Option<String>,
+    /// Values of the state that mean the build is running.
+    pub started: Vec<String>,
+    /// Values of the state that mean the build passed.
+    pub succeeded: Vec<String>,
+    /// Values of the state that mean the build failed.
+    pub failed: Vec<String>,
+}
+
+pub struct Worker {
+    listen: String,
+    secret: String,
+    signature_header: String,
+    fields: Fields,
+    pipelines: Box<PipelinesConfig>,
+    client: Client,
+}
+
+impl Worker {
+    pub fn new(
+        listen: String,
+        secret: String,
+        signature_header: String,
+        fields: Fields,
+        pipelines: Box<PipelinesConfig>,
+    ) -> Worker {
+        Worker {
+            listen: listen,
+            secret: secret,
+            signature_header: signature_header,
+            fields: fields,
+            pipelines: pipelines,
+            client: Client::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum State {
+    Started,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct BuildResult {
+    name: String,
+    commit: Commit,
+    state: State,
+    url: Option<Url>,
+}
+
+impl pipeline::Worker<ci::Event, ci::Message> for Worker {
+    fn run(
+        &self,
+        recv_msg: Receiver<ci::Message>,
+        mut send_event: Sender<ci::Event>
+    ) {
+        crossbeam::scope(|scope| {
+            let s2 = &*self;
+            let send_event_2 = send_event.clone();
+            scope.spawn(move || {
+                s2.run_webhook(send_event_2);
+            });
+            loop {
+                s2.handle_message(
+                    recv_msg.recv().expect("Pipeline went away"),
+                    &mut send_event,
+                );
+            }
+        })
+    }
+}
+
+impl Worker {
+    fn run_webhook(
+        &self,
+        send_event: Sender<ci::Event>,
+    ) {
+        let mut listener = HttpListener::new(&self.listen[..])
+            .expect("webhook");
+        while let Ok(mut stream) = listener.accept() {
+            let addr = stream.peer_addr()
+                .expect("webhook client address");
+            let mut stream_clone = stream.clone();
+            let mut buf_read = BufReader::new(
+                &mut stream_clone as &mut NetworkStream
+            );
+            let mut buf_write = BufWriter::new(&mut stream);
+            let req = match Request::new(&mut buf_read, addr) {
+                Ok(req) => req,
+                Err(e) => {
+                    warn!("Invalid webhook HTTP: {:?}", e);
+                    continue;
+                }
+            };
+            let mut head = Headers::new();
+            let res = Response::new(&mut buf_write, &mut head);
+            self.handle_webhook(req, res, &send_event);
+        }
+    }
+
+    fn handle_webhook(
+        &self,
+        mut req: Request,
+        mut res: Response,
+        send_event: &Sender<ci::Event>
+    ) {
+        let body = github_headers::parse_body(
+            &mut req,
+            &self.signature_header,
+            self.secret.as_bytes(),
+        );
+        let body = match body {
+            Some(body) => body,
+            None => {
+                *res.status_mut() = StatusCode::Forbidden;
+                if let Err(e) = res.send(&[]) {
+                    warn!("Failed to send response to bad webhook: {:?}", e);
+                }
+                return;
+            }
+        };
+        let result = match parse_result(&self.fields, &body) {
+            Some(result) => result,
+            None => {
+                *res.status_mut() = StatusCode::BadRequest;
+                if let Err(e) = res.send(&[]) {
+                    warn!("Failed to send response to bad result: {:?}", e);
+                }
+                return;
+            }
+        };
+        *res.status_mut() = StatusCode::NoContent;
+        if let Err(e) = res.send(&[]) {
+            warn!("Failed to send response to webhook: {:?}", e);
+        }
+        let ids = self.pipelines.ids_by_job_name(&result.name);
+        if ids.is_empty() {
+            warn!("Got result of unknown job: {}", result.name);
+        }
+        for id in ids {
+            let commit = result.commit.clone();
+            let url = result.url.clone();
+            let event = match result.state {
+                State::Started => ci::Event::BuildStarted(id, commit, url),
+                State::Succeeded =>
+                    ci::Event::BuildSucceeded(id, commit, url),
+                State::Failed => ci::Event::BuildFailed(
+                    id,
+                    commit,
+                    url,
+                    ci::FailureSummary::default(),
+                ),
+            };
+            send_event.send(event).expect("Pipeline");
+        }
+    }
+
+    fn handle_message(
+        &self,
+        msg: ci::Message,
+        send_event: &mut Sender<ci::Event>,
+    ) {
+        match msg {
+            ci::Message::StartBuild(id, commit) => {
+                let job = match self.pipelines.job_by_id(id) {
+                    Some(job) => job,
+                    None => {
+                        warn!(
+                            "Got start build for bad CI instance {:?}",
+                            id
+                        );
+                        return;
+                    },
+                };
+                let body = render_body(&job, &commit);
+                info!("Trigger build: {}", job.url);
+                let mut req = self.client
+                    .post(&job.url[..])
+                    .header(UserAgent(USER_AGENT.to_owned()))
+                    .header(ContentType::json())
+                    .body(&body[..]);
+                if let Some(ref token) = job.token {
+                    req = req.header(Authorization(Bearer{
+                        token: token.clone(),
+                    }));
+                }
+                match req.send() {
+                    Ok(ref res) if !res.status.is_success() => {
+                        warn!("Build refused: {:?}", res.status);
+                        send_event.send(ci::Event::BuildFailed(
+                            id,
+                            commit,
+                            None,
+                            ci::FailureSummary::default(),
+                        )).expect("Pipeline");
+                    }
+                    Err(e) => {
+                        warn!("Failed to contact CI: {:?}", e);
+                        send_event.send(ci::Event::BuildFailed(
+                            id,
+                            commit,
+                            None,
+                            ci::FailureSummary::default(),
+                        )).expect("Pipeline");
+                    }
+                    Ok(_) => {}
+                }
+            }
+        }
+    }
+}
+
+fn render_body(job: &Job, commit: &Commit) -> String {
+    job.body
+        .replace("{{commit}}", &json_escape(commit.as_str()))
+        .replace("{{name}}", &json_escape(&job.name))
+}
+
+/// Escape a value to go between the quotes of a JSON string.
+fn json_escape(value: &str) -> String {
+    let quoted = serde_json::to_string(value)
+        .expect("strings to serialize");
+    quoted[1..quoted.len() - 1].to_owned()
+}
+
+fn parse_result(fields: &Fields, body: &[u8]) -> Option<BuildResult> {
+    let body: Value = match json_from_slice(body) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Result parse failed: {:?}", e);
+            return None;
+        }
+    };
+    let name = match pointer_str(&body, &fields.name) {
+        Some(name) => name,
+        None => {
+            warn!("Result has no job name at {}", fields.name);
+            return None;
+        }
+    };
+    let commit = match pointer_str(&body, &fields.commit) {
+        Some(commit) => commit,
+        None => {
+            warn!("Result has no commit at {}", fields.commit);
+            return None;
+        }
+    };
+    let state = match pointer_str(&body, &fields.state) {
+        Some(state) => state,
+        None => {
+            warn!("Result has no state at {}", fields.state);
+            return None;
+        }
+    };
+    let state = if fields.started.contains(&state) {
+        State::Started
+    } else if fields.succeeded.contains(&state) {
+        State::Succeeded
+    } else if fields.failed.contains(&state) {
+        State::Failed
+    } else {
+        info!("Ignoring build state: {}", state);
+        return None;
+    };
+    let url = fields.url.as_ref()
+        .and_then(|url| pointer_str(&body, url))
+        .and_then(|url| Url::parse(&url[..]).ok());
+    Some(BuildResult{
+        name: name,
+        commit: commit.into(),
+        state: state,
+        url: url,
+    })
+}
+
+fn pointer_str(body: &Value, pointer: &str) -> Option<String> {
+    match body.pointer(pointer) {
+        Some(&Value::String(ref s)) => Some(s.clone()),
+        Some(v @ &Value::I64(_)) |
+        Some(v @ &Value::U64(_)) => serde_json::to_string(v).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Fields, Job, State, parse_result, render_body};
+
+    fn fields() -> Fields {
+        Fields{
+            name: "/pipeline/slug".to_owned(),
+            commit: "/build/commit".to_owned(),
+            state: "/build/state".to_owned(),
+            url: Some("/build/web_url".to_owned()),
+            started: vec!["running".to_owned()],
+            succeeded: vec!["passed".to_owned()],
+            failed: vec!["failed".to_owned(), "canceled".to_owned()],
+        }
+    }
+
+    #[test]
+    fn test_render_body() {
+        let job = Job{
+            name: "testp".to_owned(),
+            url: "http://localhost/".to_owned(),
+            body: r#"{"commit":"{{commit}}","message":"{{name}}"}"#
+                .to_owned(),
+            token: None,
+        };
+        assert_eq!(
+            render_body(&job, &"abc123".to_owned().into()),
+            r#"{"commit":"abc123","message":"testp"}"#
+        );
+        let job = Job{
+            name: r#"te"st\p", "admin": "true"#.to_owned(),
+            ..job
+        };
+        assert_eq!(
+            render_body(&job, &"abc123".to_owned().into()),
+            r#"{"commit":"abc123","message":"te\"st\\p\", \"admin\": \"true"}"#
+        );
+    }
+
+    #[test]
+    fn test_debug_hides_token() {
+        let job = Job{
+            name: "testp".to_owned(),
+            url: "http://localhost/".to_owned(),
+            body: String::new(),
+            token: Some("hunter2".to_owned()),
+        };
+        let debug = format!("{:?}", job);
+        assert!(debug.contains("testp"));
+        assert!(!debug.contains("hunter2"));
+    }
+
+    #[test]
+    fn test_parse_result() {
+        let body = br#"{
+            "pipeline": { "slug": "testp" },
+            "build": {
+                "commit": "abc123",
+                "state": "failed",
+                "web_url": "http://ci.example.com/builds/1"
+            }
+        }"#;
+        let result = parse_result(&fields(), body).unwrap();
+        assert_eq!(result.name, "testp");
+        assert_eq!(result.commit, "abc123".to_owned().into());
+        assert_eq!(result.state, State::Failed);
+        assert_eq!(
+            result.url.unwrap().as_str(),
+            "http://ci.example.com/builds/1"
+        );
+    }
+
+    #[test]
+    fn test_parse_result_unknown_state() {
+        let body = br#"{
+            "pipeline": { "slug": "testp" },
+            "build": { "commit": "abc123", "state": "scheduled" }
+        }"#;
+        assert!(parse_result(&fields(), body).is_none());
+    }
+
+    #[test]
+    fn test_parse_result_missing_commit() {
+        let body = br#"{
+            "pipeline": { "slug": "testp" },
+            "build": { "state": "passed" }
+        }"#;
+        assert!(parse_result(&fields(), body).is_none());
+    }
+}
@@ -0,0 +1,368 @@
+// This file is released under the same terms as Rust itself.
+
+/*! Admin commands, for looking at and fixing the state store without
+    hand-written SQL.
+
+    They are given after the config path, like `aelita config.toml queue
+    list my-project`, or `aelita -12 running reset my-project`. Pipelines go
+    by the names the view uses, so a project's try pipeline is `NAME/try`.
+
+    The bot may be running while they are used. Changes are made in one
+    transaction, but the bot only notices them the next time something
+    happens in that pipeline.
+
+    `check-config` only reads the config, and reports what is wrong with it.
+    `config check` is the same command.
+ */
+
+use config;
+use db::{Db, InfoTransaction, PipelineInfo, RunningEntry, Transaction};
+use pipeline::PipelineId;
+use quickersort::sort_by;
+use serde_json;
+use std::error::Error;
+use std::io::{self, Write};
+use ui::Pr;
+use view;
+
+const USAGE: &'static str = r###"Usage: aelita [CONFIG] [COMMAND]
+
+CONFIG is the path to the TOML config, or -12 to use environment variables.
+It defaults to config.toml. Without a COMMAND, the bot is started.
+
+Commands:
+    queue list PIPELINE         Show what is running and what is queued
+    queue cancel PIPELINE PR    Take a pull request out of the queue
+    running reset PIPELINE      Forget the running build, even if it is stuck
+    db export                   Print every pipeline's state as JSON
+    config list-pipelines       Read the config and list the pipelines
+    check-config [--online]     Report every mistake in the config, and exit
+                                nonzero if there are any; with --online, try
+                                the GitHub and Jenkins credentials, too
+    config check [--online]     The same as check-config
+"###;
+
+/// The words commands start with, so they aren't taken for a config path.
+const COMMANDS: &'static [&'static str] = &[
+    "queue",
+    "running",
+    "db",
+    "config",
+    "check-config",
+    "help",
+];
+
+enum Command<'a> {
+    QueueList(&'a str),
+    QueueCancel(&'a str, &'a str),
+    RunningReset(&'a str),
+    DbExport,
+    ConfigListPipelines,
+}
+
+pub fn is_command(arg: &str) -> bool {
+    COMMANDS.contains(&arg)
+}
+
+/// Run an admin command, and return the exit code.
+pub fn run(config_path: &str, args: &[String]) -> i32 {
+    if arg(args, 0) == Some("help") {
+        print!("{}", USAGE);
+        return 0;
+    }
+    match (arg(args, 0), arg(args, 1), arg(args, 2), args.len()) {
+        (Some("check-config"), None, _, 1) |
+        (Some("config"), Some("check"), None, 2) =>
+            return check_config(config_path, false),
+        (Some("check-config"), Some("--online"), _, 2) |
+        (Some("config"), Some("check"), Some("--online"), 3) =>
+            return check_config(config_path, true),
+        _ => {}
+    }
+    let command = match parse(args) {
+        Some(command) => command,
+        None => {
+            let _ = write!(io::stderr(), "{}", USAGE);
+            return 2;
+        }
+    };
+    match run_command(config_path, command) {
+        Ok(()) => 0,
+        Err(e) => {
+            let _ = writeln!(io::stderr(), "aelita: {}", e);
+            1
+        }
+    }
+}
+
+/// Report every problem with the config file, without starting anything.
+fn check_config(config_path: &str, online: bool) -> i32 {
+    if config_path == "-12" {
+        let _ = writeln!(
+            io::stderr(),
+            "aelita: check-config only reads TOML config files",
+        );
+        return 2;
+    }
+    let problems = config::check::check_file(config_path, online);
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        println!("{}: no problems found", config_path);
+        0
+    } else {
+        1
+    }
+}
+
+fn arg(args: &[String], i: usize) -> Option<&str> {
+    args.get(i).map(|arg| &arg[..])
+}
+
+fn parse(args: &[String]) -> Option<Command> {
+    match (arg(args, 0), arg(args, 1), args.len()) {
+        (Some("queue"), Some("list"), 3) =>
+            Some(Command::QueueList(&args[2])),
+        (Some("queue"), Some("cancel"), 4) =>
+            Some(Command::QueueCancel(&args[2], &args[3])),
+        (Some("running"), Some("reset"), 3) =>
+            Some(Command::RunningReset(&args[2])),
+        (Some("db"), Some("export"), 2) =>
+            Some(Command::DbExport),
+        (Some("config"), Some("list-pipelines"), 2) =>
+            Some(Command::ConfigListPipelines),
+        _ => None,
+    }
+}
+
+fn run_command(
+    config_path: &str,
+    command: Command,
+) -> Result<(), Box<Error + Send + Sync>> {
+    let (db_build, pipelines) = if config_path == "-12" {
+        try!(config::twelvef::admin_from_os_env())
+    } else {
+        try!(config::toml::admin_from_file(config_path))
+    };
+    let mut db = try!(db_build.open());
+    match command {
+        Command::QueueList(name) => {
+            let pipeline_id = try!(pipeline_by_name(&*pipelines, name));
+            let info = try!(db.transaction(InfoTransaction{
+                pipeline_id: pipeline_id,
+            }));
+            if info.paused {
+                println!("Paused");
+            }
+            if let Some(running) = info.running {
+                println!(
+                    "running  #{} {}{}",
+                    running.pr,
+                    running.pull_commit,
+                    if running.canceled { " (canceled)" } else { "" },
+                );
+            }
+            for (i, entry) in info.queue.iter().enumerate() {
+                println!(
+                    "{:>7}  #{} {} priority={}",
+                    i + 1,
+                    entry.pr,
+                    entry.commit,
+                    entry.priority,
+                );
+            }
+        }
+        Command::QueueCancel(name, pr) => {
+            let pipeline_id = try!(pipeline_by_name(&*pipelines, name));
+            try!(db.transaction(CancelTransaction{
+                pipeline_id: pipeline_id,
+                pr: Pr::from(pr.to_owned()),
+            }));
+        }
+        Command::RunningReset(name) => {
+            let pipeline_id = try!(pipeline_by_name(&*pipelines, name));
+            let running = try!(db.transaction(ResetTransaction{
+                pipeline_id: pipeline_id,
+            }));
+            match running {
+                Some(running) => println!(
+                    "Removed #{} {}",
+                    running.pr,
+                    running.pull_commit,
+                ),
+                None => println!("Nothing was running"),
+            }
+        }
+        Command::DbExport => {
+            let mut all = pipelines.all();
+            sort_by(&mut all, &|a, b| a.0.cmp(&b.0));
+            let mut export = Vec::with_capacity(all.len());
+            for (name, pipeline_id) in all {
+                let info = try!(db.transaction(InfoTransaction{
+                    pipeline_id: pipeline_id,
+                }));
+                export.push(PipelineExport::new(
+                    name.into_owned(),
+                    pipeline_id,
+                    info,
+                ));
+            }
+            println!("{}", try!(serde_json::to_string_pretty(&export)));
+        }
+        Command::ConfigListPipelines => {
+            let mut all = pipelines.all();
+            sort_by(&mut all, &|a, b| (a.1).0.cmp(&(b.1).0));
+            for (name, pipeline_id) in all {
+                println!("{:>4}  {}", pipeline_id.0, name);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn pipeline_by_name(
+    pipelines: &view::PipelinesConfig,
+    name: &str,
+) -> Result<PipelineId, Box<Error + Send + Sync>> {
+    match pipelines.pipeline_by_name(name) {
+        Some(pipeline_id) => Ok(pipeline_id),
+        None => Err(From::from(format!("No pipeline named {}", name))),
+    }
+}
+
+struct CancelTransaction {
+    pipeline_id: PipelineId,
+    pr: Pr,
+}
+
+impl Transaction for CancelTransaction {
+    type Return = ();
+    fn run<D: Db>(
+        self,
+        db: &mut D
+    ) -> Result<Self::Return, Box<Error + Send + Sync>> {
+        db.cancel_by_pr(self.pipeline_id, &self.pr)
+    }
+}
+
+struct ResetTransaction {
+    pipeline_id: PipelineId,
+}
+
+impl Transaction for ResetTransaction {
+    type Return = Option<RunningEntry>;
+    fn run<D: Db>(
+        self,
+        db: &mut D
+    ) -> Result<Self::Return, Box<Error + Send + Sync>> {
+        db.take_running(self.pipeline_id)
+    }
+}
+
+#[derive(Serialize)]
+struct PipelineExport {
+    name: String,
+    id: i32,
+    paused: bool,
+    running: Option<RunningExport>,
+    queue: Vec<QueueExport>,
+    pending: Vec<PendingExport>,
+}
+
+impl PipelineExport {
+    fn new(
+        name: String,
+        pipeline_id: PipelineId,
+        info: PipelineInfo,
+    ) -> Self {
+        PipelineExport{
+            name: name,
+            id: pipeline_id.0,
+            paused: info.paused,
+            running: info.running.map(|entry| RunningExport{
+                pr: entry.pr.into(),
+                pull_commit: entry.pull_commit.into(),
+                merge_commit: entry.merge_commit.map(Into::into),
+                message: entry.message,
+                canceled: entry.canceled,
+                built: entry.built,
+            }),
+            queue: info.queue.into_iter().map(|entry| QueueExport{
+                pr: entry.pr.into(),
+                commit: entry.commit.into(),
+                message: entry.message,
+                priority: entry.priority,
+            }).collect(),
+            pending: info.pending.into_iter().map(|entry| PendingExport{
+                pr: entry.pr.into(),
+                commit: entry.commit.into(),
+                title: entry.title,
+                url: entry.url.to_string(),
+                draft: entry.draft,
+            }).collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RunningExport {
+    pr: String,
+    pull_commit: String,
+    merge_commit: Option<String>,
+    message: String,
+    canceled: bool,
+    built: bool,
+}
+
+#[derive(Serialize)]
+struct QueueExport {
+    pr: String,
+    commit: String,
+    message: String,
+    priority: i32,
+}
+
+#[derive(Serialize)]
+struct PendingExport {
+    pr: String,
+    commit: String,
+    title: String,
+    url: String,
+    draft: bool,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Command, is_command, parse};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_commands() {
+        match parse(&args(&["queue", "cancel", "proj", "12"])) {
+            Some(Command::QueueCancel("proj", "12")) => {}
+            _ => panic!("queue cancel"),
+        }
+        match parse(&args(&["running", "reset", "proj/try"])) {
+            Some(Command::RunningReset("proj/try")) => {}
+            _ => panic!("running reset"),
+        }
+        match parse(&args(&["config", "list-pipelines"])) {
+            Some(Command::ConfigListPipelines) => {}
+            _ => panic!("config list-pipelines"),
+        }
+        assert!(parse(&args(&["queue", "list"])).is_none());
+        assert!(parse(&args(&["db", "import"])).is_none());
+    }
+
+    #[test]
+    fn config_path_is_not_command() {
+        assert!(is_command("queue"));
+        assert!(is_command("check-config"));
+        assert!(!is_command("config.toml"));
+        assert!(!is_command("-12"));
+    }
+}
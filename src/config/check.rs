@@ -0,0 +1,1274 @@
+// This file is released under the same terms as Rust itself.
+
+/*! Checking a TOML config file without starting anything.
+
+    The builder in `config::toml` stops at the first mistake it runs into,
+    and some of them only show up after a few workers are listening. This
+    reads the same keys, and reports every mistake at once, each with the
+    TOML path it is about, like `config.github.token` or
+    `projects.my-project.jenkins[1].name`.
+
+    Secrets given as `{ env = "VAR" }` or `{ file = "PATH" }` are read
+    first, and reported if they can't be.
+
+    When nothing is wrong with the file itself, it can also ask GitHub and
+    Jenkins whether the credentials work and the repos and jobs are there.
+
+    A repo's own `.aelita.toml` is checked here, too. It can only hold the
+    project keys in `REPO_SETTINGS`, which are none of the secret ones.
+ */
+
+use config::secret;
+use db;
+use rest::{authorization, Authorization, Client};
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use toml;
+use util::USER_AGENT;
+use util::github_auth::{Auth, Credentials};
+
+/// Something wrong with the config, and where it is.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Problem {
+    /// The TOML path of the key or section,
+    /// or the line and column of a syntax error
+    pub path: String,
+    pub message: String,
+}
+
+impl Problem {
+    fn new<M: Into<String>>(path: &str, message: M) -> Problem {
+        Problem{
+            path: path.to_owned(),
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Check the config file at `path`, and return every problem with it.
+/// With `online`, and no problems in the file itself,
+/// try out the GitHub and Jenkins credentials, too.
+pub fn check_file<P: AsRef<Path>>(path: P, online: bool) -> Vec<Problem> {
+    let path = path.as_ref();
+    let mut text = String::new();
+    if let Err(e) = File::open(path).and_then(|mut f| {
+        f.read_to_string(&mut text)
+    }) {
+        return vec![Problem::new(&path.display().to_string(), e.to_string())];
+    }
+    check_str(&text, online)
+}
+
+/// Check the settings a repo keeps in its own `.aelita.toml`, and return
+/// them if there is nothing wrong with them.
+pub fn check_repo_config(text: &str) -> Result<toml::Table, Vec<Problem>> {
+    let settings = try!(parse(text));
+    let mut problems = Vec::new();
+    check_table("", &settings, REPO_SETTINGS, &mut problems);
+    let def = toml::Value::Table(settings.clone());
+    check_listed_once(&def, "github.status", &mut problems);
+    check_retry(&def, "", &mut problems);
+    if problems.is_empty() {
+        Ok(settings)
+    } else {
+        Err(problems)
+    }
+}
+
+fn check_str(text: &str, online: bool) -> Vec<Problem> {
+    let mut config_main = match parse(text) {
+        Ok(config_main) => config_main,
+        Err(problems) => return problems,
+    };
+    let secrets = secret::resolve(&mut config_main);
+    let mut problems = Vec::new();
+    check_table("", &config_main, ROOT, &mut problems);
+    // A secret that can't be read is left as a table; don't report that.
+    problems.retain(|problem| !secrets.iter().any(|e| e.path == problem.path));
+    for e in secrets {
+        problems.push(Problem::new(&e.path, e.message));
+    }
+    let config = match config_main.get("config") {
+        Some(config) if config.as_table().is_some() => config,
+        Some(_) => return problems,
+        None => {
+            problems.push(Problem::new("config", "missing"));
+            return problems;
+        }
+    };
+    let projects = match config_main.get("projects") {
+        Some(&toml::Value::Table(ref projects)) => projects,
+        Some(_) => return problems,
+        None => {
+            problems.push(Problem::new("projects", "missing"));
+            return problems;
+        }
+    };
+    check_config(config, &mut problems);
+    for (name, def) in projects {
+        if def.as_table().is_some() {
+            check_project(name, def, config, &mut problems);
+        }
+    }
+    check_job_names(projects, &mut problems);
+    check_ports(config, &mut problems);
+    if online && problems.is_empty() {
+        check_github_online(config, projects, &mut problems);
+        check_jenkins_online(config, projects, &mut problems);
+    }
+    problems
+}
+
+/// Parse TOML, or report where the syntax errors are.
+fn parse(text: &str) -> Result<toml::Table, Vec<Problem>> {
+    let mut parser = toml::Parser::new(text);
+    match parser.parse() {
+        Some(table) => Ok(table),
+        None => Err(parser.errors.iter().map(|e| {
+            let (line, col) = parser.to_linecol(e.lo);
+            Problem::new(
+                &format!("line {}, column {}", line + 1, col + 1),
+                e.desc.clone(),
+            )
+        }).collect()),
+    }
+}
+
+// Every key the builder reads, and what it should hold.
+
+#[derive(Clone, Copy)]
+enum Kind {
+    String,
+    Integer,
+    Boolean,
+    /// An array of strings
+    Strings,
+    /// A string, or an array of them
+    StringOrStrings,
+    Table(&'static [(&'static str, Kind)]),
+    /// A table, or an array of them
+    TableOrTables(&'static [(&'static str, Kind)]),
+    /// A table with keys picked by the user, like project names
+    TableOf(&'static Kind),
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::String => "a string",
+            Kind::Integer => "an integer",
+            Kind::Boolean => "a boolean",
+            Kind::Strings => "an array of strings",
+            Kind::StringOrStrings => "a string or an array of strings",
+            Kind::Table(_) | Kind::TableOf(_) => "a table",
+            Kind::TableOrTables(_) => "a table or an array of tables",
+        }
+    }
+}
+
+const ROOT: &'static [(&'static str, Kind)] = &[
+    ("config", CONFIG),
+    ("projects", Kind::TableOf(&PROJECT)),
+];
+
+const CONFIG: Kind = Kind::Table(&[
+    ("db", Kind::String),
+    ("github", Kind::Table(&[
+        ("listen", Kind::String),
+        ("host", Kind::String),
+        ("token", Kind::String),
+        ("app_id", Kind::Integer),
+        ("private_key", Kind::String),
+        ("user", Kind::String),
+        ("secret", Kind::String),
+        ("owner", Kind::String),
+        ("require_sha256", Kind::Boolean),
+        ("checks", Kind::Boolean),
+        ("status_comment", Kind::Boolean),
+        ("db", Kind::String),
+        ("cache_ttl", Kind::Integer),
+        ("status", Kind::Table(&[
+            ("listen", Kind::String),
+            ("secret", Kind::String),
+            ("require_sha256", Kind::Boolean),
+            ("db", Kind::String),
+        ])),
+        ("git", Kind::Table(&[
+            ("host", Kind::String),
+            ("token", Kind::String),
+            ("app_id", Kind::Integer),
+            ("private_key", Kind::String),
+        ])),
+    ])),
+    ("gitlab", Kind::Table(&[
+        ("listen", Kind::String),
+        ("host", Kind::String),
+        ("token", Kind::String),
+        ("user", Kind::String),
+        ("secret", Kind::String),
+        ("namespace", Kind::String),
+    ])),
+    ("gitea", Kind::Table(&[
+        ("listen", Kind::String),
+        ("host", Kind::String),
+        ("token", Kind::String),
+        ("user", Kind::String),
+        ("secret", Kind::String),
+        ("owner", Kind::String),
+        ("git", Kind::Table(&[
+            ("host", Kind::String),
+            ("token", Kind::String),
+        ])),
+    ])),
+    ("jenkins", Kind::Table(&[
+        ("listen", Kind::String),
+        ("host", Kind::String),
+        ("user", Kind::String),
+        ("token", Kind::String),
+    ])),
+    ("webhook", Kind::Table(&[
+        ("listen", Kind::String),
+        ("secret", Kind::String),
+        ("signature_header", Kind::String),
+        ("name", Kind::String),
+        ("commit", Kind::String),
+        ("state", Kind::String),
+        ("url", Kind::String),
+        ("started", Kind::Strings),
+        ("succeeded", Kind::Strings),
+        ("failed", Kind::Strings),
+    ])),
+    ("git", Kind::Table(&[
+        ("executable", Kind::String),
+        ("name", Kind::String),
+        ("email", Kind::String),
+        ("path", Kind::String),
+    ])),
+    ("view", Kind::Table(&[
+        ("listen", Kind::String),
+        ("secret", Kind::String),
+        ("api_token", Kind::String),
+        ("auth", Kind::Table(&[
+            ("type", Kind::String),
+            ("app_id", Kind::String),
+            ("app_secret", Kind::String),
+            ("organization", Kind::String),
+        ])),
+    ])),
+]);
+
+const TRY_BRANCH: Kind = Kind::Table(&[("branch", Kind::String)]);
+
+const PROJECT: Kind = Kind::Table(&[
+    ("branches", Kind::Strings),
+    ("retry", Kind::Integer),
+    ("retry_only_flaky", Kind::Boolean),
+    ("try", Kind::Table(&[
+        ("git", TRY_BRANCH),
+        ("github", TRY_BRANCH),
+        ("gitlab", TRY_BRANCH),
+        ("gitea", TRY_BRANCH),
+    ])),
+    ("github", Kind::Table(&[
+        ("owner", Kind::String),
+        ("repo", Kind::String),
+        ("master_branch", Kind::String),
+        ("staging_branch", Kind::String),
+        ("review_approval", Kind::Boolean),
+        ("status", Kind::StringOrStrings),
+        ("labels", Kind::Table(&[
+            ("approved", Kind::String),
+            ("testing", Kind::String),
+            ("failed", Kind::String),
+            ("merge_conflict", Kind::String),
+        ])),
+    ])),
+    ("gitlab", Kind::Table(&[
+        ("namespace", Kind::String),
+        ("project", Kind::String),
+        ("master_branch", Kind::String),
+        ("staging_branch", Kind::String),
+    ])),
+    ("gitea", Kind::Table(&[
+        ("owner", Kind::String),
+        ("repo", Kind::String),
+        ("master_branch", Kind::String),
+        ("staging_branch", Kind::String),
+    ])),
+    ("git", Kind::Table(&[
+        ("path", Kind::String),
+        ("origin", Kind::String),
+        ("master_branch", Kind::String),
+        ("staging_branch", Kind::String),
+    ])),
+    ("jenkins", Kind::TableOrTables(&[
+        ("name", Kind::String),
+        ("token", Kind::String),
+    ])),
+    ("webhook", Kind::TableOrTables(&[
+        ("name", Kind::String),
+        ("url", Kind::String),
+        ("body", Kind::String),
+        ("token", Kind::String),
+    ])),
+]);
+
+/// The keys of `PROJECT` that a repo can set for itself. They are laid over
+/// the project's own, so `[github.labels]` only replaces the labels it has,
+/// and `github.status` only adds contexts to the project's.
+const REPO_SETTINGS: &'static [(&'static str, Kind)] = &[
+    ("retry", Kind::Integer),
+    ("retry_only_flaky", Kind::Boolean),
+    ("github", Kind::Table(&[
+        ("review_approval", Kind::Boolean),
+        ("status", Kind::StringOrStrings),
+        ("labels", Kind::Table(&[
+            ("approved", Kind::String),
+            ("testing", Kind::String),
+            ("failed", Kind::String),
+            ("merge_conflict", Kind::String),
+        ])),
+    ])),
+];
+
+/// Report keys that aren't read, and values of the wrong type.
+fn check_kind(
+    path: &str,
+    value: &toml::Value,
+    kind: Kind,
+    problems: &mut Vec<Problem>,
+) {
+    match (kind, value) {
+        (Kind::String, &toml::Value::String(_)) |
+        (Kind::Integer, &toml::Value::Integer(_)) |
+        (Kind::Boolean, &toml::Value::Boolean(_)) |
+        (Kind::StringOrStrings, &toml::Value::String(_)) => {}
+        (Kind::Strings, &toml::Value::Array(ref values)) |
+        (Kind::StringOrStrings, &toml::Value::Array(ref values)) => {
+            for (i, value) in values.iter().enumerate() {
+                if value.as_str().is_none() {
+                    problems.push(Problem::new(
+                        &format!("{}[{}]", path, i),
+                        format!("expected a string, found {}", describe(value)),
+                    ));
+                }
+            }
+        }
+        (Kind::Table(keys), &toml::Value::Table(ref table)) |
+        (Kind::TableOrTables(keys), &toml::Value::Table(ref table)) => {
+            check_table(path, table, keys, problems);
+        }
+        (Kind::TableOrTables(keys), &toml::Value::Array(ref values)) => {
+            for (i, value) in values.iter().enumerate() {
+                check_kind(
+                    &format!("{}[{}]", path, i),
+                    value,
+                    Kind::Table(keys),
+                    problems,
+                );
+            }
+        }
+        (Kind::TableOf(kind), &toml::Value::Table(ref table)) => {
+            for (key, value) in table {
+                check_kind(&join(path, key), value, *kind, problems);
+            }
+        }
+        (kind, value) => problems.push(Problem::new(
+            path,
+            format!("expected {}, found {}", kind.name(), describe(value)),
+        )),
+    }
+}
+
+fn check_table(
+    path: &str,
+    table: &toml::Table,
+    keys: &[(&str, Kind)],
+    problems: &mut Vec<Problem>,
+) {
+    for (key, value) in table {
+        let path = join(path, key);
+        match keys.iter().find(|&&(name, _)| name == &key[..]) {
+            Some(&(_, kind)) => check_kind(&path, value, kind, problems),
+            None => problems.push(Problem::new(&path, "unknown key")),
+        }
+    }
+}
+
+fn describe(value: &toml::Value) -> &'static str {
+    match *value {
+        toml::Value::String(_) => "a string",
+        toml::Value::Integer(_) => "an integer",
+        toml::Value::Float(_) => "a float",
+        toml::Value::Boolean(_) => "a boolean",
+        toml::Value::Datetime(_) => "a datetime",
+        toml::Value::Array(_) => "an array",
+        toml::Value::Table(_) => "a table",
+    }
+}
+
+/// Add a key to a TOML path, quoting it if it can't be written bare.
+fn join(path: &str, key: &str) -> String {
+    let bare = !key.is_empty() && key.chars().all(|c| match c {
+        'a'...'z' | 'A'...'Z' | '0'...'9' | '-' | '_' => true,
+        _ => false,
+    });
+    match (path.is_empty(), bare) {
+        (true, true) => key.to_owned(),
+        (true, false) => format!("\"{}\"", key),
+        (false, true) => format!("{}.{}", path, key),
+        (false, false) => format!("{}.\"{}\"", path, key),
+    }
+}
+
+// What the builder needs, but the types don't say.
+
+/// Look up a section, but only if it is a table.
+/// If it's something else, `check_kind` has already complained.
+fn section<'a>(value: &'a toml::Value, key: &str) -> Option<&'a toml::Value> {
+    value.lookup(key).and_then(|section| {
+        if section.as_table().is_some() { Some(section) } else { None }
+    })
+}
+
+fn has(value: &toml::Value, key: &str) -> bool {
+    value.lookup(key).is_some()
+}
+
+fn require(
+    section: &toml::Value,
+    path: &str,
+    keys: &[&str],
+    problems: &mut Vec<Problem>,
+) {
+    for key in keys {
+        if !has(section, key) {
+            problems.push(Problem::new(&join(path, key), "missing"));
+        }
+    }
+}
+
+fn check_positive(
+    section: &toml::Value,
+    path: &str,
+    key: &str,
+    problems: &mut Vec<Problem>,
+) {
+    if let Some(&toml::Value::Integer(value)) = section.lookup(key) {
+        if value <= 0 {
+            problems.push(Problem::new(
+                &join(path, key),
+                "must be more than zero",
+            ));
+        }
+    }
+}
+
+fn check_config(config: &toml::Value, problems: &mut Vec<Problem>) {
+    let github = section(config, "github");
+    let gitlab = section(config, "gitlab");
+    let gitea = section(config, "gitea");
+    if github.is_none() && gitlab.is_none() && gitea.is_none() {
+        problems.push(Problem::new(
+            "config",
+            "needs a github, gitlab or gitea section",
+        ));
+    }
+    if let Some(github) = github {
+        require(
+            github,
+            "config.github",
+            &["listen", "user", "secret"],
+            problems,
+        );
+        check_github_credentials(github, "config.github", true, problems);
+        check_positive(github, "config.github", "cache_ttl", problems);
+        let checks = github.lookup("checks").and_then(toml::Value::as_bool);
+        if checks == Some(true) &&
+                (has(github, "token") || !has(github, "app_id")) {
+            problems.push(Problem::new(
+                "config.github.checks",
+                "needs a GitHub App; the Checks API does not take a token",
+            ));
+        }
+    }
+    if let Some(status) = section(config, "github.status") {
+        require(status, "config.github.status", &["listen"], problems);
+        if !has(status, "secret") && !has(config, "github.secret") {
+            problems.push(Problem::new(
+                "config.github.status.secret",
+                "missing, and there is no config.github.secret",
+            ));
+        }
+    }
+    if let Some(git) = section(config, "github.git") {
+        // Without credentials of its own, it uses the front-end's.
+        check_github_credentials(git, "config.github.git", false, problems);
+    }
+    if let Some(gitlab) = gitlab {
+        require(
+            gitlab,
+            "config.gitlab",
+            &["listen", "token", "user", "secret"],
+            problems,
+        );
+    }
+    if let Some(gitea) = gitea {
+        require(
+            gitea,
+            "config.gitea",
+            &["listen", "host", "token", "user", "secret"],
+            problems,
+        );
+    }
+    if let Some(jenkins) = section(config, "jenkins") {
+        require(jenkins, "config.jenkins", &["listen", "host"], problems);
+        // One without the other is ignored.
+        match (has(jenkins, "user"), has(jenkins, "token")) {
+            (true, false) => problems.push(Problem::new(
+                "config.jenkins.token",
+                "missing, so config.jenkins.user is not used",
+            )),
+            (false, true) => problems.push(Problem::new(
+                "config.jenkins.user",
+                "missing, so config.jenkins.token is not used",
+            )),
+            _ => {}
+        }
+    }
+    if let Some(webhook) = section(config, "webhook") {
+        require(webhook, "config.webhook", &["listen", "secret"], problems);
+    }
+    // Merges through either of these are committed with it. If there is
+    // a git section, its own check below covers this.
+    if (gitlab.is_some() || gitea.is_some()) &&
+            section(config, "git").is_none() {
+        problems.push(Problem::new(
+            "config.git.email",
+            "missing; merges through GitLab or Gitea are committed with it",
+        ));
+    }
+    if let Some(git) = section(config, "git") {
+        if !has(git, "name") && !has(config, "github.user") &&
+                !has(config, "gitlab.user") && !has(config, "gitea.user") {
+            problems.push(Problem::new(
+                "config.git.name",
+                "missing, and there is no front-end user to use instead",
+            ));
+        }
+        if !has(git, "email") && !has(config, "github.user") {
+            problems.push(Problem::new(
+                "config.git.email",
+                "missing, and there is no config.github.user to use instead",
+            ));
+        }
+    }
+    if let Some(view) = section(config, "view") {
+        require(view, "config.view", &["listen", "secret"], problems);
+        if let Some(auth) = section(view, "auth") {
+            match auth.lookup("type").and_then(toml::Value::as_str) {
+                Some("github") => require(
+                    auth,
+                    "config.view.auth",
+                    &["app_id", "app_secret", "organization"],
+                    problems,
+                ),
+                Some(ty) => problems.push(Problem::new(
+                    "config.view.auth.type",
+                    format!("unknown type {}; it can only be github", ty),
+                )),
+                None => {}
+            }
+        }
+    }
+    for key in &["db", "github.db", "github.status.db"] {
+        if let Some(desc) = config.lookup(key).and_then(toml::Value::as_str) {
+            if let Err(e) = db::Builder::from_str(desc) {
+                problems.push(Problem::new(
+                    &format!("config.{}", key),
+                    e.to_string(),
+                ));
+            }
+        }
+    }
+}
+
+/// A GitHub section needs a personal access token,
+/// or a GitHub App's ID and private key.
+fn check_github_credentials(
+    section: &toml::Value,
+    path: &str,
+    required: bool,
+    problems: &mut Vec<Problem>,
+) {
+    if has(section, "token") {
+        return;
+    }
+    if !has(section, "app_id") {
+        if required {
+            problems.push(Problem::new(
+                &join(path, "token"),
+                "missing, and there is no app_id",
+            ));
+        }
+        return;
+    }
+    check_positive(section, path, "app_id", problems);
+    match section.lookup("private_key") {
+        Some(&toml::Value::String(ref key_path)) => {
+            if let Err(e) = File::open(key_path) {
+                problems.push(Problem::new(
+                    &join(path, "private_key"),
+                    format!("cannot open {}: {}", key_path, e),
+                ));
+            }
+        }
+        Some(_) => {}
+        None => problems.push(Problem::new(
+            &join(path, "private_key"),
+            "missing, but app_id is set",
+        )),
+    }
+}
+
+fn check_project(
+    name: &str,
+    def: &toml::Value,
+    config: &toml::Value,
+    problems: &mut Vec<Problem>,
+) {
+    let path = join("projects", name);
+    // Where pull requests come from.
+    let front_ends: Vec<&str> = ["github", "gitlab", "gitea"].iter()
+        .cloned()
+        .filter(|front_end| has(def, front_end))
+        .collect();
+    match front_ends.len() {
+        0 => problems.push(Problem::new(
+            &path,
+            "needs a github, gitlab or gitea section",
+        )),
+        1 => {}
+        _ => problems.push(Problem::new(
+            &path,
+            "can only use one of github, gitlab and gitea",
+        )),
+    }
+    for front_end in &front_ends {
+        if section(config, front_end).is_none() {
+            problems.push(Problem::new(
+                &join(&path, front_end),
+                format!("needs a [config.{}] section", front_end),
+            ));
+        }
+    }
+    let owners = [
+        ("github", "github.owner"),
+        ("gitlab", "gitlab.namespace"),
+        ("gitea", "gitea.owner"),
+    ];
+    for &(front_end, key) in &owners {
+        if has(def, front_end) && !has(def, key) && !has(config, key) {
+            problems.push(Problem::new(
+                &format!("{}.{}", path, key),
+                format!("missing, and there is no config.{}", key),
+            ));
+        }
+    }
+    // What builds it.
+    let mut cis = 0;
+    if def.lookup("github.status").is_some() {
+        cis += 1;
+        if section(config, "github.status").is_none() {
+            problems.push(Problem::new(
+                &format!("{}.github.status", path),
+                "needs a [config.github.status] section",
+            ));
+        }
+    }
+    check_listed_once(def, &format!("{}.github.status", path), problems);
+    for &(ci, key) in &[("jenkins", "token"), ("webhook", "url")] {
+        let jobs = match def.lookup(ci) {
+            Some(jobs) => jobs,
+            None => continue,
+        };
+        cis += 1;
+        if section(config, ci).is_none() {
+            problems.push(Problem::new(
+                &join(&path, ci),
+                format!("needs a [config.{}] section", ci),
+            ));
+        }
+        for (job_path, job) in tables(jobs, &join(&path, ci)) {
+            require(job, &job_path, &[key], problems);
+        }
+    }
+    if cis == 0 {
+        problems.push(Problem::new(
+            &path,
+            "needs a github.status, jenkins or webhook section",
+        ));
+    }
+    // Where it gets merged.
+    if let Some(git) = section(def, "git") {
+        require(git, &join(&path, "git"), &["origin"], problems);
+        if section(config, "git").is_none() {
+            problems.push(Problem::new(
+                &join(&path, "git"),
+                "needs a [config.git] section",
+            ));
+        }
+    } else if front_ends.first() == Some(&"github") &&
+            section(config, "github.git").is_none() {
+        problems.push(Problem::new(
+            &path,
+            "needs a git section, or a [config.github.git] section",
+        ));
+    }
+    match def.lookup("branches") {
+        Some(&toml::Value::Array(ref branches)) if branches.is_empty() =>
+            problems.push(Problem::new(
+                &join(&path, "branches"),
+                "must not be empty",
+            )),
+        _ => {}
+    }
+    check_retry(def, &path, problems);
+}
+
+fn check_retry(def: &toml::Value, path: &str, problems: &mut Vec<Problem>) {
+    if let Some(&toml::Value::Integer(retry)) = def.lookup("retry") {
+        if retry < 0 {
+            problems.push(Problem::new(
+                &join(path, "retry"),
+                "must not be negative",
+            ));
+        }
+    }
+}
+
+/// Report the status contexts that are listed more than once.
+fn check_listed_once(
+    def: &toml::Value,
+    path: &str,
+    problems: &mut Vec<Problem>,
+) {
+    if let Some(&toml::Value::Array(ref contexts)) =
+            def.lookup("github.status") {
+        for (i, context) in contexts.iter().enumerate() {
+            if contexts[..i].contains(context) {
+                problems.push(Problem::new(
+                    &format!("{}[{}]", path, i),
+                    "listed twice",
+                ));
+            }
+        }
+    }
+}
+
+/// Each table in a key that holds either a table or an array of them,
+/// along with its path.
+fn tables<'a>(value: &'a toml::Value, path: &str)
+        -> Vec<(String, &'a toml::Value)> {
+    match *value {
+        toml::Value::Table(_) => vec![(path.to_owned(), value)],
+        toml::Value::Array(ref values) => values.iter()
+            .enumerate()
+            .filter(|&(_, value)| value.as_table().is_some())
+            .map(|(i, value)| (format!("{}[{}]", path, i), value))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Results are routed by job name, so a job that two projects share
+/// would have its results go to both of them.
+fn check_job_names(projects: &toml::Table, problems: &mut Vec<Problem>) {
+    for ci in &["jenkins", "webhook"] {
+        let mut seen: HashMap<String, String> = HashMap::new();
+        for (name, def) in projects {
+            let path = join(&join("projects", name), ci);
+            let jobs = match def.lookup(ci) {
+                Some(jobs) => jobs,
+                None => continue,
+            };
+            for (job_path, job) in tables(jobs, &path) {
+                // Jobs are named after the project by default.
+                let job_name = job.lookup("name")
+                    .and_then(toml::Value::as_str)
+                    .unwrap_or(&name[..]);
+                if let Some(other) = seen.get(job_name) {
+                    problems.push(Problem::new(
+                        &job_path,
+                        format!(
+                            "job {} is already used by {}",
+                            job_name,
+                            other,
+                        ),
+                    ));
+                    continue;
+                }
+                seen.insert(job_name.to_owned(), job_path);
+            }
+        }
+    }
+}
+
+/// Every key a worker's address is in.
+const LISTENERS: &'static [&'static str] = &[
+    "view.listen",
+    "github.listen",
+    "github.status.listen",
+    "gitlab.listen",
+    "gitea.listen",
+    "jenkins.listen",
+    "webhook.listen",
+];
+
+fn check_ports(config: &toml::Value, problems: &mut Vec<Problem>) {
+    let mut seen: Vec<(&str, &str, &str)> = Vec::new();
+    for &key in LISTENERS {
+        let path = format!("config.{}", key);
+        let listen = match config.lookup(key).and_then(toml::Value::as_str) {
+            Some(listen) => listen,
+            None => continue,
+        };
+        let (host, port) = match listen.rfind(':') {
+            Some(i) => (&listen[..i], &listen[i+1..]),
+            None => {
+                problems.push(Problem::new(&path, "expected HOST:PORT"));
+                continue;
+            }
+        };
+        if port.parse::<u16>().is_err() {
+            problems.push(Problem::new(
+                &path,
+                format!("{} is not a port number", port),
+            ));
+            continue;
+        }
+        // Port 0 picks a free one.
+        if port == "0" {
+            continue;
+        }
+        let clash = seen.iter().find(|&&(_, other_host, other_port)| {
+            other_port == port && (
+                other_host == host ||
+                is_any_address(other_host) ||
+                is_any_address(host)
+            )
+        }).map(|&(other, _, _)| other);
+        if let Some(other) = clash {
+            problems.push(Problem::new(
+                &path,
+                format!("port {} is already used by config.{}", port, other),
+            ));
+        }
+        seen.push((key, host, port));
+    }
+}
+
+fn is_any_address(host: &str) -> bool {
+    host == "" || host == "0.0.0.0" || host == "::" || host == "[::]"
+}
+
+// Trying the credentials out.
+
+/// Read the credentials the same way the builder does.
+fn github_credentials(section: &toml::Value) -> Option<Credentials> {
+    if let Some(token) = section.lookup("token").and_then(toml::Value::as_str) {
+        return Some(Credentials::Token(token.to_owned()));
+    }
+    let app_id = match section.lookup("app_id") {
+        Some(&toml::Value::Integer(app_id)) => app_id as u64,
+        _ => return None,
+    };
+    let path = match section.lookup("private_key") {
+        Some(&toml::Value::String(ref path)) => path,
+        _ => return None,
+    };
+    let mut key = Vec::new();
+    match File::open(path).and_then(|mut f| f.read_to_end(&mut key)) {
+        Ok(_) => Some(Credentials::App(app_id, key)),
+        Err(_) => None,
+    }
+}
+
+/// Make sure the bot can see, and push to, every project's repo.
+fn check_github_online(
+    config: &toml::Value,
+    projects: &toml::Table,
+    problems: &mut Vec<Problem>,
+) {
+    #[derive(Deserialize)]
+    struct PermissionsDesc {
+        push: bool,
+    }
+    #[derive(Deserialize)]
+    struct RepoDesc {
+        permissions: Option<PermissionsDesc>,
+    }
+    let github = match section(config, "github") {
+        Some(github) => github,
+        None => return,
+    };
+    let credentials = match github_credentials(github) {
+        Some(credentials) => credentials,
+        None => return,
+    };
+    let host = github.lookup("host")
+        .and_then(toml::Value::as_str)
+        .unwrap_or("https://api.github.com");
+    let auth = Auth::new("check_github", host.to_owned(), credentials);
+    for (name, def) in projects {
+        if section(def, "github").is_none() {
+            continue;
+        }
+        let path = join(&join("projects", name), "github");
+        // The front-end prefers the owner in [config.github].
+        let owner = match github.lookup("owner")
+                .or_else(|| def.lookup("github.owner"))
+                .and_then(toml::Value::as_str) {
+            Some(owner) => owner,
+            None => continue,
+        };
+        let repo = def.lookup("github.repo")
+            .and_then(toml::Value::as_str)
+            .unwrap_or(&name[..]);
+        let client = match auth.client(owner, repo) {
+            Ok(client) => client,
+            Err(e) => {
+                problems.push(Problem::new(
+                    &path,
+                    format!("cannot act on {}/{}: {}", owner, repo, e),
+                ));
+                continue;
+            }
+        };
+        let url = format!("/repos/{}/{}", owner, repo);
+        let req = match client.get(&url) {
+            Ok(req) => req,
+            Err(e) => {
+                problems.push(Problem::new(
+                    &path,
+                    format!("bad repo {}/{}: {:?}", owner, repo, e),
+                ));
+                continue;
+            }
+        };
+        let resp = match req.send() {
+            Ok(resp) => resp,
+            Err(e) => {
+                problems.push(Problem::new(
+                    "config.github.host",
+                    format!("cannot reach GitHub: {:?}", e),
+                ));
+                return;
+            }
+        };
+        if !resp.is_success() {
+            problems.push(Problem::new(
+                &path,
+                format!(
+                    "GitHub answered {} for {}/{}",
+                    resp.http.status,
+                    owner,
+                    repo,
+                ),
+            ));
+            continue;
+        }
+        let desc: RepoDesc = match resp.json() {
+            Ok(desc) => desc,
+            Err(e) => {
+                problems.push(Problem::new(
+                    &path,
+                    format!("cannot read {}/{}: {:?}", owner, repo, e),
+                ));
+                continue;
+            }
+        };
+        // Installation tokens don't say; the installation decides.
+        if let Some(PermissionsDesc{ push: false }) = desc.permissions {
+            problems.push(Problem::new(
+                &path,
+                format!("cannot push to {}/{}", owner, repo),
+            ));
+        }
+    }
+}
+
+/// Make sure every Jenkins job is there.
+fn check_jenkins_online(
+    config: &toml::Value,
+    projects: &toml::Table,
+    problems: &mut Vec<Problem>,
+) {
+    let jenkins = match section(config, "jenkins") {
+        Some(jenkins) => jenkins,
+        None => return,
+    };
+    let host = match jenkins.lookup("host").and_then(toml::Value::as_str) {
+        Some(host) => host,
+        None => return,
+    };
+    let user = jenkins.lookup("user").and_then(toml::Value::as_str);
+    let token = jenkins.lookup("token").and_then(toml::Value::as_str);
+    let (username, password) = match (user, token) {
+        (Some(user), Some(token)) => (user.to_owned(), Some(token.to_owned())),
+        _ => (String::new(), None),
+    };
+    let client = Client::new(USER_AGENT.to_owned())
+        .base(host)
+        .authorization(Authorization(authorization::Basic{
+            username: username,
+            password: password,
+        }));
+    for (name, def) in projects {
+        let jobs = match def.lookup("jenkins") {
+            Some(jobs) => jobs,
+            None => continue,
+        };
+        let path = join(&join("projects", name), "jenkins");
+        for (job_path, job) in tables(jobs, &path) {
+            let job_name = job.lookup("name")
+                .and_then(toml::Value::as_str)
+                .unwrap_or(&name[..]);
+            // Jobs in folders are named like `folder/job`.
+            let url = format!(
+                "/job/{}/api/json",
+                job_name.replace("/", "/job/"),
+            );
+            let req = match client.get(&url) {
+                Ok(req) => req,
+                Err(e) => {
+                    problems.push(Problem::new(
+                        &job_path,
+                        format!("bad job name {}: {:?}", job_name, e),
+                    ));
+                    continue;
+                }
+            };
+            match req.send() {
+                Ok(ref resp) if resp.is_success() => {}
+                Ok(resp) => problems.push(Problem::new(
+                    &job_path,
+                    format!(
+                        "Jenkins answered {} for job {}",
+                        resp.http.status,
+                        job_name,
+                    ),
+                )),
+                Err(e) => {
+                    problems.push(Problem::new(
+                        "config.jenkins.host",
+                        format!("cannot reach Jenkins: {:?}", e),
+                    ));
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Problem, check_repo_config, check_str};
+
+    const GITHUB: &'static str = r#"
+[config.view]
+listen = "localhost:8000"
+secret = "view secret"
+[config.github]
+listen = "localhost:6000"
+user = "bot"
+owner = "owner"
+token = "github token"
+secret = "github secret"
+[config.github.git]
+[config.jenkins]
+listen = "localhost:7000"
+host = "http://jenkins"
+"#;
+
+    fn problem(path: &str, message: &str) -> Problem {
+        Problem::new(path, message)
+    }
+
+    #[test]
+    fn examples_have_no_problems() {
+        let examples = [
+            include_str!("../../config.toml.gitea-example"),
+            include_str!("../../config.toml.gitlab-example"),
+            include_str!("../../config.toml.jenkins-example"),
+            include_str!("../../config.toml.taskcluster-example"),
+            include_str!("../../config.toml.travis-example"),
+            include_str!("../../config.toml.webhook-example"),
+        ];
+        for example in &examples {
+            let problems = check_str(example, false);
+            assert!(problems.is_empty(), "{:?}", problems);
+        }
+    }
+
+    #[test]
+    fn unknown_keys_and_wrong_types() {
+        let config = format!("{}{}", GITHUB, r#"
+tokne = "oops"
+[projects.p]
+github = { labels = { approved = 1 } }
+jenkins = [{ token = "t" }, { name = "p-try", token = 2 }]
+branches = "master"
+"#);
+        assert_eq!(check_str(&config, false), vec![
+            problem("config.jenkins.tokne", "unknown key"),
+            problem(
+                "projects.p.branches",
+                "expected an array of strings, found a string",
+            ),
+            problem(
+                "projects.p.github.labels.approved",
+                "expected a string, found an integer",
+            ),
+            problem(
+                "projects.p.jenkins[1].token",
+                "expected a string, found an integer",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn missing_keys() {
+        let config = r#"
+[config.github]
+listen = "localhost:6000"
+user = "bot"
+secret = "github secret"
+app_id = 12
+[config.webhook]
+listen = "localhost:7000"
+[projects."my.project"]
+github = {}
+webhook = {}
+"#;
+        assert_eq!(check_str(config, false), vec![
+            problem("config.github.private_key", "missing, but app_id is set"),
+            problem("config.webhook.secret", "missing"),
+            problem(
+                "projects.\"my.project\".github.owner",
+                "missing, and there is no config.github.owner",
+            ),
+            problem("projects.\"my.project\".webhook.url", "missing"),
+            problem(
+                "projects.\"my.project\"",
+                "needs a git section, or a [config.github.git] section",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn shared_jobs_and_ports() {
+        let config = format!("{}{}", GITHUB, r#"
+[config.github.status]
+listen = "0.0.0.0:6000"
+[projects.a]
+github = {}
+jenkins = [{ token = "t" }, { name = "shared", token = "t" }]
+[projects.b]
+github = { status = ["ci", "ci"] }
+jenkins = { name = "shared", token = "t" }
+"#);
+        assert_eq!(check_str(&config, false), vec![
+            problem("projects.b.github.status[1]", "listed twice"),
+            problem(
+                "projects.b.jenkins.name",
+                "job shared is already used by projects.a.jenkins[1]",
+            ),
+            problem(
+                "config.github.status.listen",
+                "port 6000 is already used by config.github.listen",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn repo_settings() {
+        let settings = check_repo_config(r#"
+retry = 2
+[github]
+status = ["ci/travis"]
+labels = { approved = "ready" }
+"#).expect("valid repo settings");
+        assert!(settings.contains_key("retry"));
+        assert_eq!(check_repo_config(r#"
+retry = -1
+merge_strategy = "squash"
+[github]
+owner = "someone-else"
+status = ["ci", "ci"]
+"#), Err(vec![
+            problem("github.owner", "unknown key"),
+            problem("merge_strategy", "unknown key"),
+            problem("github.status[1]", "listed twice"),
+            problem("retry", "must not be negative"),
+        ]));
+    }
+
+    #[test]
+    fn checks_need_an_app() {
+        let config = format!("{}{}", GITHUB.replace(
+            "secret = \"github secret\"\n",
+            "secret = \"github secret\"\nchecks = true\n",
+        ), r#"
+[projects.p]
+github = {}
+jenkins = { token = "t" }
+"#);
+        assert_eq!(check_str(&config, false), vec![
+            problem(
+                "config.github.checks",
+                "needs a GitHub App; the Checks API does not take a token",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn api_merges_need_an_email() {
+        let config = r#"
+[config.gitlab]
+listen = "localhost:6000"
+user = "bot"
+namespace = "group"
+token = "gitlab token"
+secret = "gitlab secret"
+[config.webhook]
+listen = "localhost:7000"
+secret = "webhook secret"
+[projects.p]
+gitlab = {}
+webhook = { url = "http://ci/p" }
+"#;
+        assert_eq!(check_str(config, false), vec![
+            problem(
+                "config.git.email",
+                "missing; merges through GitLab or Gitea are committed with it",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let problems = check_str("[config]\ndb = \n", false);
+        assert!(!problems.is_empty());
+        assert!(problems[0].path.starts_with("line 2, "));
+    }
+}
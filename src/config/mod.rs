@@ -1,11 +1,15 @@
 // This file is released under the same terms as Rust itself.
 
+pub mod check;
+pub mod secret;
 pub mod toml;
 pub mod twelvef;
 
 use ci::CiId;
 use db::DbBox;
 use pipeline::{PipelineId, WorkerManager};
+use std::collections::HashMap;
+use std::path::Path;
 
 pub trait WorkerBuilder {
     fn start(
@@ -13,9 +17,34 @@ pub trait WorkerBuilder {
     ) -> (WorkerManager, DbBox);
 }
 
+/// Picks up changes to the config while the bot is running.
+///
+/// The methods are called from the main loop, between events.
+pub trait Reload {
+    /// The file to watch.
+    fn path(&self) -> &Path;
+    /// The file was changed; read it again.
+    fn reload(&mut self, db: &mut DbBox);
+    /// Forget removed pipelines once they are done building.
+    fn drain(&mut self, db: &mut DbBox);
+    /// The pipelines whose target branch holds the repo's own settings,
+    /// in `vcs::REPO_CONFIG`. There is one for each branch PRs go into;
+    /// try pipelines use the settings of the one they share a branch with.
+    fn repo_config_pipelines(&self) -> Vec<PipelineId>;
+    /// A project's settings were read, or found missing.
+    fn repo_config(
+        &mut self,
+        pipeline_id: PipelineId,
+        contents: Option<String>,
+        db: &mut DbBox,
+    );
+}
+
 pub trait PipelinesConfig {
-    fn by_pipeline_id(&self, PipelineId) -> PipelineConfig;
-    fn by_ci_id(&self, CiId) -> PipelineConfig;
+    /// `None` if there is no such pipeline, like after it was taken out of
+    /// the config while the bot was running.
+    fn by_pipeline_id(&self, PipelineId) -> Option<PipelineConfig>;
+    fn by_ci_id(&self, CiId) -> Option<PipelineConfig>;
     fn len(&self) -> usize;
 }
 
@@ -25,4 +54,16 @@ pub struct PipelineConfig {
 	pub ci: Vec<(CiId, usize)>,
 	pub ui: usize,
 	pub vcs: usize,
+	/// Human-readable names for the CI jobs, such as the Jenkins job name.
+	pub ci_names: HashMap<CiId, String>,
+	pub retry: RetryConfig,
+}
+
+/// When to rebuild a merge commit after one of its CI jobs fails.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RetryConfig {
+	/// How many times each job may be retried. Zero turns retrying off.
+	pub count: u32,
+	/// Only retry failures that are all known to be flaky.
+	pub only_flaky: bool,
 }
\ No newline at end of file
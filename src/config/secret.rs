@@ -0,0 +1,180 @@
+// This file is released under the same terms as Rust itself.
+
+/*! Secrets that are kept out of the config file.
+
+    Any string in the TOML config can be given as `{ env = "VAR" }`, to read
+    it from an environment variable, or as `{ file = "/run/secrets/x" }`, to
+    read it from a file. Trailing newlines are left out of files.
+ */
+
+use std::env;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{self, Read};
+use toml;
+
+/// A secret that could not be read, and where it was asked for.
+/// Neither of them gives away anything about the secret itself.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SecretError {
+    /// The TOML path of the value
+    pub path: String,
+    pub message: String,
+}
+
+impl Error for SecretError {
+    fn description(&self) -> &str {
+        "Secret could not be read"
+    }
+}
+
+impl Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Replace every `{ env = ... }` and `{ file = ... }` with what it points to.
+/// The ones that can't be read are left alone, and returned.
+pub fn resolve(table: &mut toml::Table) -> Vec<SecretError> {
+    let mut errors = Vec::new();
+    resolve_table("", table, &mut errors);
+    errors
+}
+
+fn resolve_table(
+    path: &str,
+    table: &mut toml::Table,
+    errors: &mut Vec<SecretError>,
+) {
+    for (key, value) in table.iter_mut() {
+        let path = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", path, key)
+        };
+        resolve_value(&path, value, errors);
+    }
+}
+
+fn resolve_value(
+    path: &str,
+    value: &mut toml::Value,
+    errors: &mut Vec<SecretError>,
+) {
+    let secret = match *value {
+        toml::Value::Table(ref mut table) => match read(table) {
+            Some(secret) => secret,
+            None => {
+                resolve_table(path, table, errors);
+                return;
+            }
+        },
+        toml::Value::Array(ref mut values) => {
+            for (i, value) in values.iter_mut().enumerate() {
+                resolve_value(&format!("{}[{}]", path, i), value, errors);
+            }
+            return;
+        }
+        _ => return,
+    };
+    match secret {
+        Ok(secret) => *value = toml::Value::String(secret),
+        Err(message) => errors.push(SecretError{
+            path: path.to_owned(),
+            message: message,
+        }),
+    }
+}
+
+/// Read the secret a table points to,
+/// or return `None` if it isn't an `env` or `file` table.
+fn read(table: &toml::Table) -> Option<Result<String, String>> {
+    if table.len() != 1 {
+        return None;
+    }
+    match (table.get("env"), table.get("file")) {
+        (Some(&toml::Value::String(ref var)), _) => Some(
+            env::var(var).map_err(|e| {
+                format!("cannot read environment variable {}: {}", var, e)
+            })
+        ),
+        (_, Some(&toml::Value::String(ref file))) => Some(
+            read_file(file).map_err(|e| format!("cannot read {}: {}", file, e))
+        ),
+        _ => None,
+    }
+}
+
+fn read_file(path: &str) -> io::Result<String> {
+    let mut secret = String::new();
+    try!(File::open(path).and_then(|mut f| f.read_to_string(&mut secret)));
+    let len = secret.trim_right_matches(|c: char| c == '\n' || c == '\r').len();
+    secret.truncate(len);
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+    use super::resolve;
+    use toml;
+
+    fn parse(text: &str) -> toml::Table {
+        toml::Parser::new(text).parse().expect("test config to parse")
+    }
+
+    #[test]
+    fn env_and_file() {
+        env::set_var("AELITA_TEST_SECRET_TOKEN", "hunter2");
+        let path = env::temp_dir().join("aelita-test-secret");
+        File::create(&path)
+            .and_then(|mut f| f.write_all(b"swordfish\n"))
+            .expect("to write the test secret");
+        let mut table = parse(&format!(r#"
+            [config.github]
+            token = {{ env = "AELITA_TEST_SECRET_TOKEN" }}
+            secret = {{ file = "{}" }}
+            [config.view]
+            auth = {{ type = "none" }}
+        "#, path.display()));
+        assert!(resolve(&mut table).is_empty());
+        let lookup = |key| {
+            table.get("config")
+                .and_then(|config| config.lookup(key))
+                .cloned()
+        };
+        assert_eq!(
+            lookup("github.token"),
+            Some(toml::Value::String("hunter2".to_owned())),
+        );
+        assert_eq!(
+            lookup("github.secret"),
+            Some(toml::Value::String("swordfish".to_owned())),
+        );
+        assert!(lookup("view.auth.type").is_some());
+    }
+
+    #[test]
+    fn missing_secrets() {
+        env::remove_var("AELITA_TEST_SECRET_MISSING");
+        let mut table = parse(r#"
+            [config.github]
+            token = { env = "AELITA_TEST_SECRET_MISSING" }
+            [[projects.p.jenkins]]
+            token = { file = "/nonexistent/aelita-secret" }
+        "#);
+        let errors = resolve(&mut table);
+        let paths: Vec<&str> = errors.iter().map(|e| &e.path[..]).collect();
+        assert_eq!(
+            paths,
+            ["config.github.token", "projects.p.jenkins[0].token"],
+        );
+        for e in &errors {
+            assert!(e.message.starts_with("cannot read"), "{}", e);
+        }
+    }
+}
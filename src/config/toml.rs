@@ -1,22 +1,32 @@
 // This file is released under the same terms as Rust itself.
 
-use ci::{self, CiId, github_status, jenkins};
-use config::{PipelineConfig, PipelinesConfig, WorkerBuilder};
-use db::{self, DbBox};
+use ci::{self, CiId, github_status, jenkins, webhook};
+use config::{PipelineConfig, PipelinesConfig, Reload, RetryConfig};
+use config::WorkerBuilder;
+use config::check;
+use config::secret::{self, SecretError};
+use db::{self, ConfigIdEntry, Db, DbBox, Transaction};
 use pipeline::{PipelineId, WorkerManager};
 use pipeline::WorkerThread;
 use std::any::Any;
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fmt::{self, Debug, Display};
 use std::fs::File;
+use std::hash::Hash;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
 use toml;
-use ui::{self, github};
+use ui::{self, gitea, github, gitlab};
+use util::gitea_api;
+use util::github_auth;
+use util::gitlab_api;
 use vcs::{self, git};
+use vcs::gitea as gitea_git;
 use vcs::github as github_git;
+use vcs::gitlab as gitlab_git;
 use view;
 
 pub struct GithubBuilder {
@@ -37,36 +47,49 @@ pub struct GithubBuilder {
         view::Message,
     >>,
     db: DbBox,
-    pipelines: StaticPipelinesConfig,
+    pipelines: Shared<StaticPipelinesConfig>,
+    reloader: Option<Reloader>,
 }
 
-#[derive(Clone, Copy, Eq, PartialEq)]
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
 enum CiType {
     Jenkins,
     GithubStatus,
+    Webhook,
+}
+
+impl CiType {
+    /// What the CI ids in the database are filed under.
+    fn name(self) -> &'static str {
+        match self {
+            CiType::Jenkins => "jenkins",
+            CiType::GithubStatus => "github_status",
+            CiType::Webhook => "webhook",
+        }
+    }
+    fn from_name(name: &str) -> Option<CiType> {
+        match name {
+            "jenkins" => Some(CiType::Jenkins),
+            "github_status" => Some(CiType::GithubStatus),
+            "webhook" => Some(CiType::Webhook),
+            _ => None,
+        }
+    }
 }
 
 impl GithubBuilder {
     pub fn build_from_file<P: AsRef<Path>>(path: P)
             -> Result<Self, GithubBuilderError> {
         let path = path.as_ref();
-        let mut config_file = match File::open(&*path) {
-            Ok(config_file) => config_file,
-            Err(e) => return Err(GithubBuilderError::OpenFile(e)),
-        };
-        let mut config_string = String::new();
-        match config_file.read_to_string(&mut config_string) {
-            Ok(_) => {},
-            Err(e) => return Err(GithubBuilderError::ReadFile(e)),
-        }
-        let config_main = match toml::Parser::new(&config_string).parse() {
-            Some(config) => config,
-            None => return Err(GithubBuilderError::Parse),
-        };
-        Self::build_from_toml(config_main)
+        Self::build(try!(parse_file(path)), Some(path.to_owned()))
     }
     pub fn build_from_toml(config_main: toml::Table)
             -> Result<Self, GithubBuilderError> {
+        Self::build(config_main, None)
+    }
+    /// With a `path`, the projects are read again when the file changes.
+    fn build(config_main: toml::Table, path: Option<PathBuf>)
+            -> Result<Self, GithubBuilderError> {
         let config = 
             match config_main.get("config") {
                 Some(config) => config,
@@ -77,294 +100,897 @@ impl GithubBuilder {
                 Some(config_projects) => config_projects,
                 None => return Err(GithubBuilderError::NoProjects),
             };
-        if config.lookup("github").is_none() {
+        if config.lookup("github").is_none() &&
+                config.lookup("gitlab").is_none() &&
+                config.lookup("gitea").is_none() {
             return Err(GithubBuilderError::NoConfigGithub);
         }
+        let db_build = db::Builder::from_str(db_path(config))
+            .expect("to parse db path");
+        let mut db = db_build
+            .open()
+            .expect("to open up db");
+        let mut ids = match Ids::load(&mut db) {
+            Ok(ids) => ids,
+            Err(e) => return Err(GithubBuilderError::Db(e)),
+        };
+        let mut projects;
+        loop {
+            projects = try!(ProjectsConfigs::build(
+                config,
+                config_projects,
+                &RepoConfigs::new(),
+                &mut ids,
+            ));
+            match ids.save(&mut db) {
+                Ok(false) => break,
+                // Build them again with the ids the database has.
+                Ok(true) => {}
+                Err(e) => return Err(GithubBuilderError::Db(e)),
+            }
+        }
+        let shared = SharedConfigs::new();
+        shared.replace(projects.workers);
+        let github = match setup_github(
+            config,
+            shared.github_projects.clone(),
+        ) {
+            Ok(github) => Some(WorkerThread::start(github)),
+            Err(SetupError::NotFoundConfig) => None,
+            Err(e) => return Err(GithubBuilderError::Github(e)),
+        };
+        let gitlab = match setup_gitlab(
+            config,
+            shared.gitlab_projects.clone(),
+        ) {
+            Ok(gitlab) => Some(WorkerThread::start(gitlab)),
+            Err(SetupError::NotFoundConfig) => None,
+            Err(e) => return Err(GithubBuilderError::Gitlab(e)),
+        };
+        let gitea = match setup_gitea(config, shared.gitea_projects.clone()) {
+            Ok(gitea) => Some(WorkerThread::start(gitea)),
+            Err(SetupError::NotFoundConfig) => None,
+            Err(e) => return Err(GithubBuilderError::Gitea(e)),
+        };
+        let github_status = match setup_github_status(
+            config,
+            shared.github_status_pipelines.clone(),
+        ) {
+            Ok(github_status) => Some(WorkerThread::start(github_status)),
+            Err(SetupError::NotFoundConfig) => None,
+            Err(e) => return Err(GithubBuilderError::GithubStatus(e)),
+        };
+        let jenkins = match setup_jenkins(
+            config,
+            shared.jenkins_pipelines.clone(),
+        ) {
+            Ok(jenkins) => Some(WorkerThread::start(jenkins)),
+            Err(SetupError::NotFoundConfig) => None,
+            Err(e) => return Err(GithubBuilderError::Jenkins(e)),
+        };
+        let webhook = match setup_webhook(
+            config,
+            shared.webhook_pipelines.clone(),
+        ) {
+            Ok(webhook) => Some(WorkerThread::start(webhook)),
+            Err(SetupError::NotFoundConfig) => None,
+            Err(e) => return Err(GithubBuilderError::Webhook(e)),
+        };
+        let git = match setup_git(config, shared.git_pipelines.clone()) {
+            Ok(git) => Some(WorkerThread::start(git)),
+            Err(SetupError::NotFoundConfig) => None,
+            Err(e) => return Err(GithubBuilderError::Git(e)),
+        };
+        let github_git = match setup_github_git(
+            config,
+            shared.github_git_pipelines.clone(),
+        ) {
+            Ok(github_git) => Some(WorkerThread::start(github_git)),
+            Err(SetupError::NotFoundConfig) => None,
+            Err(e) => return Err(GithubBuilderError::GithubGit(e)),
+        };
+        let gitlab_git = match setup_gitlab_git(
+            config,
+            shared.gitlab_git_pipelines.clone(),
+        ) {
+            Ok(gitlab_git) => Some(WorkerThread::start(gitlab_git)),
+            Err(SetupError::NotFoundConfig) => None,
+            Err(e) => return Err(GithubBuilderError::GitlabGit(e)),
+        };
+        let gitea_git = match setup_gitea_git(
+            config,
+            shared.gitea_git_pipelines.clone(),
+        ) {
+            Ok(gitea_git) => Some(WorkerThread::start(gitea_git)),
+            Err(SetupError::NotFoundConfig) => None,
+            Err(e) => return Err(GithubBuilderError::GiteaGit(e)),
+        };
+        let view = match setup_view(config, shared.view_pipelines.clone()) {
+            Ok(view) => Some(WorkerThread::start(view)),
+            Err(SetupError::NotFoundConfig) => None,
+            Err(e) => return Err(GithubBuilderError::View(e)),
+        };
+        let mut uis = vec![];
+        let github_idx = if let Some(github) = github {
+            uis.push(github);
+            Some(uis.len()-1)
+        } else {
+            None
+        };
+        let gitlab_idx = if let Some(gitlab) = gitlab {
+            uis.push(gitlab);
+            Some(uis.len()-1)
+        } else {
+            None
+        };
+        let gitea_idx = if let Some(gitea) = gitea {
+            uis.push(gitea);
+            Some(uis.len()-1)
+        } else {
+            None
+        };
+        let mut cis = vec![];
+        let github_status_idx = if let Some(github_status) = github_status {
+            cis.push(github_status);
+            Some(cis.len()-1)
+        } else {
+            None
+        };
+        let jenkins_idx = if let Some(jenkins) = jenkins {
+            cis.push(jenkins);
+            Some(cis.len()-1)
+        } else {
+            None
+        };
+        let webhook_idx = if let Some(webhook) = webhook {
+            cis.push(webhook);
+            Some(cis.len()-1)
+        } else {
+            None
+        };
+        let mut vcss = vec![];
+        let git_idx = if let Some(git) = git {
+            vcss.push(git);
+            Some(vcss.len()-1)
+        } else {
+            None
+        };
+        let github_git_idx = if let Some(github_git) = github_git {
+            vcss.push(github_git);
+            Some(vcss.len()-1)
+        } else {
+            None
+        };
+        let gitlab_git_idx = if let Some(gitlab_git) = gitlab_git {
+            vcss.push(gitlab_git);
+            Some(vcss.len()-1)
+        } else {
+            None
+        };
+        let gitea_git_idx = if let Some(gitea_git) = gitea_git {
+            vcss.push(gitea_git);
+            Some(vcss.len()-1)
+        } else {
+            None
+        };
+        let idxs = WorkerIdxs{
+            github: github_idx,
+            gitlab: gitlab_idx,
+            gitea: gitea_idx,
+            github_status: github_status_idx,
+            jenkins: jenkins_idx,
+            webhook: webhook_idx,
+            git: git_idx,
+            github_git: github_git_idx,
+            gitlab_git: gitlab_git_idx,
+            gitea_git: gitea_git_idx,
+        };
+        shared.pipelines.replace(try!(pipelines_config(
+            &projects.ci_to_pipeline,
+            &projects.project_pipelines,
+            &idxs,
+        )));
+        let pipelines = shared.pipelines.clone();
+        let reloader = match path {
+            Some(path) => Some(Reloader{
+                path: path,
+                config: config.clone(),
+                projects: config_projects.clone(),
+                draining: toml::Table::new(),
+                pipeline_ids: projects.pipeline_ids,
+                paused: HashSet::new(),
+                targets: projects.targets,
+                repo_configs: RepoConfigs::new(),
+                ids: ids,
+                idxs: idxs,
+                shared: shared,
+            }),
+            None => {
+                // The repo settings are fetched and applied by the reloader.
+                warn!(
+                    "Repos' own {} is not read without a config file",
+                    vcs::REPO_CONFIG,
+                );
+                None
+            }
+        };
+        Ok(GithubBuilder{
+            cis: cis,
+            uis: uis,
+            vcss: vcss,
+            view: view,
+            db: db,
+            pipelines: pipelines,
+            reloader: reloader,
+        })
+    }
+}
+
+/// Which worker, in `WorkerManager`'s lists, does each job.
+/// `None` for the ones that aren't configured.
+#[derive(Clone, Copy)]
+struct WorkerIdxs {
+    github: Option<usize>,
+    gitlab: Option<usize>,
+    gitea: Option<usize>,
+    github_status: Option<usize>,
+    jenkins: Option<usize>,
+    webhook: Option<usize>,
+    git: Option<usize>,
+    github_git: Option<usize>,
+    gitlab_git: Option<usize>,
+    gitea_git: Option<usize>,
+}
+
+/// Hands out pipeline and CI ids.
+///
+/// A pipeline that was already given an id, going by its view name, gets the
+/// same one again, and so does each of its CI jobs. The ids are kept in the
+/// database, so adding, removing or renaming a project doesn't change the ids
+/// of the others, and the queues stay with the right repos.
+///
+/// A database from before the ids were kept has none of them. The new ones
+/// are handed out in the same order as they used to be, so as long as the
+/// config hasn't changed, the pipelines keep the ids they had.
+#[derive(Clone)]
+struct Ids {
+    pipelines: HashMap<String, PipelineId>,
+    cis: HashMap<(PipelineId, CiType, String), Vec<CiId>>,
+    next_pipeline_id: PipelineId,
+    next_ci_id: CiId,
+    /// The CI ids handed out since `start`, so a job that is listed twice
+    /// gets two of them.
+    taken: HashSet<CiId>,
+    /// New ids that aren't in the database yet.
+    unsaved: Vec<ConfigIdEntry>,
+}
+
+impl Ids {
+    fn new() -> Self {
+        Ids{
+            pipelines: HashMap::new(),
+            cis: HashMap::new(),
+            next_pipeline_id: PipelineId(0),
+            next_ci_id: CiId(0),
+            taken: HashSet::new(),
+            unsaved: Vec::new(),
+        }
+    }
+    fn load(db: &mut DbBox) -> Result<Self, Box<Error + Send + Sync>> {
+        let mut ids = Ids::new();
+        for entry in try!(db.transaction(ListIdsTransaction)) {
+            if entry.pipeline_id.0 >= ids.next_pipeline_id.0 {
+                ids.next_pipeline_id = PipelineId(entry.pipeline_id.0 + 1);
+            }
+            match entry.ci_id {
+                None => {
+                    ids.pipelines.insert(entry.name, entry.pipeline_id);
+                }
+                Some(ci_id) => {
+                    if ci_id.0 >= ids.next_ci_id.0 {
+                        ids.next_ci_id = CiId(ci_id.0 + 1);
+                    }
+                    let mut parts = entry.name.splitn(2, ':');
+                    let ci_type = parts.next().and_then(CiType::from_name);
+                    let name = parts.next();
+                    if let (Some(ci_type), Some(name)) = (ci_type, name) {
+                        let key = (entry.pipeline_id, ci_type, name.to_owned());
+                        ids.cis.entry(key).or_insert_with(Vec::new).push(ci_id);
+                    }
+                }
+            }
+        }
+        Ok(ids)
+    }
+    /// Write the new ids to the database, and read them all back.
+    ///
+    /// Another process using the same database may have saved some of
+    /// the same names first, and the ids it gave them are the ones kept.
+    /// Returns true if any of the ids read back are not the ones that
+    /// were handed out, so whatever was built with them is wrong.
+    fn save(&mut self, db: &mut DbBox)
+            -> Result<bool, Box<Error + Send + Sync>> {
+        if self.unsaved.is_empty() {
+            return Ok(false);
+        }
+        try!(db.transaction(AddIdsTransaction{
+            entries: self.unsaved.clone(),
+        }));
+        let stored = try!(Ids::load(db));
+        let changed = stored.pipelines != self.pipelines ||
+            stored.cis != self.cis;
+        *self = stored;
+        Ok(changed)
+    }
+    fn start(&mut self) {
+        self.taken.clear();
+    }
+    fn pipeline_id(&mut self, view_name: &str) -> PipelineId {
+        if let Some(&pipeline_id) = self.pipelines.get(view_name) {
+            return pipeline_id;
+        }
+        let pipeline_id = self.next_pipeline_id;
+        self.next_pipeline_id.0 += 1;
+        self.pipelines.insert(view_name.to_owned(), pipeline_id);
+        self.unsaved.push(ConfigIdEntry{
+            name: view_name.to_owned(),
+            pipeline_id: pipeline_id,
+            ci_id: None,
+        });
+        pipeline_id
+    }
+    fn ci_id(
+        &mut self,
+        pipeline_id: PipelineId,
+        ci_type: CiType,
+        name: &str,
+    ) -> CiId {
+        let ci_ids = self.cis
+            .entry((pipeline_id, ci_type, name.to_owned()))
+            .or_insert_with(Vec::new);
+        for &ci_id in ci_ids.iter() {
+            if !self.taken.contains(&ci_id) {
+                self.taken.insert(ci_id);
+                return ci_id;
+            }
+        }
+        let ci_id = self.next_ci_id;
+        self.next_ci_id.0 += 1;
+        ci_ids.push(ci_id);
+        self.taken.insert(ci_id);
+        self.unsaved.push(ConfigIdEntry{
+            name: format!("{}:{}", ci_type.name(), name),
+            pipeline_id: pipeline_id,
+            ci_id: Some(ci_id),
+        });
+        ci_id
+    }
+}
+
+/// Everything under `[projects]`, split up for each worker.
+struct ProjectsConfigs {
+    workers: WorkerConfigs,
+    ci_to_pipeline: HashMap<CiId, (CiType, PipelineId, String)>,
+    /// Every pipeline, and the settings of the project it is for.
+    project_pipelines: Vec<(PipelineId, toml::Value)>,
+    /// Every project's pipelines, by project name.
+    pipeline_ids: HashMap<String, Vec<PipelineId>>,
+    /// The pipeline for each branch PRs go into, and its project's name.
+    targets: HashMap<PipelineId, String>,
+}
+
+struct WorkerConfigs {
+    github_projects: StaticGithubProjectsConfig,
+    gitlab_projects: StaticGitlabProjectsConfig,
+    gitea_projects: StaticGiteaProjectsConfig,
+    github_status_pipelines: StaticGithubStatusPipelinesConfig,
+    jenkins_pipelines: StaticJenkinsPipelinesConfig,
+    webhook_pipelines: StaticWebhookPipelinesConfig,
+    git_pipelines: StaticGitPipelinesConfig,
+    github_git_pipelines: StaticGithubGitPipelinesConfig,
+    gitlab_git_pipelines: StaticGitlabGitPipelinesConfig,
+    gitea_git_pipelines: StaticGiteaGitPipelinesConfig,
+    view_pipelines: StaticViewPipelinesConfig,
+}
+
+impl ProjectsConfigs {
+    fn build(
+        config: &toml::Value,
+        config_projects: &toml::Table,
+        repo_configs: &RepoConfigs,
+        ids: &mut Ids,
+    ) -> Result<Self, GithubBuilderError> {
         let mut github_projects =
             StaticGithubProjectsConfig::new();
+        let mut gitlab_projects =
+            StaticGitlabProjectsConfig::new();
+        let mut gitea_projects =
+            StaticGiteaProjectsConfig::new();
         let mut github_status_pipelines =
             StaticGithubStatusPipelinesConfig::new();
         let mut jenkins_pipelines =
             StaticJenkinsPipelinesConfig::new();
+        let mut webhook_pipelines =
+            StaticWebhookPipelinesConfig::new();
         let mut git_pipelines =
             StaticGitPipelinesConfig::new();
         let mut github_git_pipelines =
             StaticGithubGitPipelinesConfig::new();
+        let mut gitlab_git_pipelines =
+            StaticGitlabGitPipelinesConfig::new();
+        let mut gitea_git_pipelines =
+            StaticGiteaGitPipelinesConfig::new();
         let mut view_pipelines =
             StaticViewPipelinesConfig::new();
-        let mut pipeline_id = PipelineId(0);
-        let mut ci_id = CiId(0);
-        let mut ci_to_pipeline: HashMap<CiId, (CiType, PipelineId)> = HashMap::new();
+        let mut ci_to_pipeline: HashMap<CiId, (CiType, PipelineId, String)> =
+            HashMap::new();
+        let mut project_pipelines: Vec<(PipelineId, toml::Value)> =
+            Vec::new();
+        let mut pipeline_ids = HashMap::new();
+        let mut targets = HashMap::new();
+        ids.start();
         for (name, def) in config_projects.iter() {
             if def.as_table().is_none() {
                 return Err(GithubBuilderError::Project(
                     SetupError::InvalidArg(ProjectArg::Project, Ty::Table)
                 ));
             }
-            match github_projects.add_project(
-                name,
-                config,
-                def,
-                pipeline_id
-            ) {
-                Ok(()) | Err(SetupError::NotFoundConfig) => {},
-                Err(e) => return Err(GithubBuilderError::GithubProject(e)),
-            }
-            match github_status_pipelines.add_pipeline(
-                name,
-                config,
-                def,
-                pipeline_id,
-                &mut ci_id,
-                &mut ci_to_pipeline,
-            ) {
-                Ok(()) | Err(SetupError::NotFoundConfig) => {},
-                Err(e) =>
-                    return Err(GithubBuilderError::GithubStatusProject(e)),
-            }
-            match jenkins_pipelines.add_pipeline(
-                name,
-                config,
-                def,
-                pipeline_id,
-                &mut ci_id,
-                &mut ci_to_pipeline,
-            ) {
-                Ok(()) | Err(SetupError::NotFoundConfig) => {},
-                Err(e) => return Err(GithubBuilderError::JenkinsProject(e)),
-            }
-            match git_pipelines.add_pipeline(
-                name,
-                config,
-                def,
-                pipeline_id,
-                false,
-            ) {
-                Ok(()) | Err(SetupError::NotFoundConfig) => {},
-                Err(e) => return Err(GithubBuilderError::GitProject(e)),
-            }
-            match github_git_pipelines.add_pipeline(
-                name,
-                config,
-                def,
-                pipeline_id,
-                false
-            ) {
-                Ok(()) | Err(SetupError::NotFoundConfig) => {},
-                Err(e) => return Err(GithubBuilderError::GithubGitProject(e)),
-            }
-            match view_pipelines.add_pipeline(
-                name,
-                config,
-                def,
-                pipeline_id,
-            ) {
-                Ok(()) | Err(SetupError::NotFoundConfig) => {},
-                Err(e) => return Err(GithubBuilderError::ViewProject(e)),
-            }
-            pipeline_id.0 += 1;
-            if def.lookup("try").is_some() {
+            let targets = match project_targets(def) {
+                Ok(targets) => targets,
+                Err(e) => return Err(GithubBuilderError::Project(e)),
+            };
+            let mut project_ids = Vec::new();
+            for target in &targets {
+                // Handed out in the same order as `pipeline_names`.
+                let pipeline_id = ids.pipeline_id(&target.view_name(name));
+                let try_pipeline_id = if def.lookup("try").is_some() {
+                    Some(ids.pipeline_id(&target.try_view_name(name)))
+                } else {
+                    None
+                };
+                // Laid over with the settings kept on the target branch.
+                let def = &repo_configs.layer(def, pipeline_id);
+                match github_projects.add_project(
+                    name,
+                    config,
+                    def,
+                    target,
+                    pipeline_id,
+                    try_pipeline_id,
+                ) {
+                    Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                    Err(e) => return Err(GithubBuilderError::GithubProject(e)),
+                }
+                match gitlab_projects.add_project(
+                    name,
+                    config,
+                    def,
+                    target,
+                    pipeline_id,
+                    try_pipeline_id,
+                ) {
+                    Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                    Err(e) => return Err(GithubBuilderError::GitlabProject(e)),
+                }
+                match gitea_projects.add_project(
+                    name,
+                    config,
+                    def,
+                    target,
+                    pipeline_id,
+                    try_pipeline_id,
+                ) {
+                    Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                    Err(e) => return Err(GithubBuilderError::GiteaProject(e)),
+                }
                 match github_status_pipelines.add_pipeline(
                     name,
                     config,
                     def,
                     pipeline_id,
-                    &mut ci_id,
+                    ids,
                     &mut ci_to_pipeline,
                 ) {
                     Ok(()) | Err(SetupError::NotFoundConfig) => {},
-                    Err(e) =>
-                        return Err(GithubBuilderError::GithubStatusProject(e)),
+                    Err(e) => return Err(
+                        GithubBuilderError::GithubStatusProject(e)
+                    ),
                 }
                 match jenkins_pipelines.add_pipeline(
                     name,
                     config,
                     def,
                     pipeline_id,
-                    &mut ci_id,
+                    ids,
                     &mut ci_to_pipeline,
                 ) {
                     Ok(()) | Err(SetupError::NotFoundConfig) => {},
-                    Err(e) =>
-                        return Err(GithubBuilderError::JenkinsProject(e)),
+                    Err(e) => return Err(GithubBuilderError::JenkinsProject(e)),
+                }
+                match webhook_pipelines.add_pipeline(
+                    name,
+                    config,
+                    def,
+                    pipeline_id,
+                    ids,
+                    &mut ci_to_pipeline,
+                ) {
+                    Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                    Err(e) => return Err(GithubBuilderError::WebhookProject(e)),
                 }
                 match git_pipelines.add_pipeline(
                     name,
                     config,
                     def,
+                    target,
                     pipeline_id,
-                    true
+                    false,
                 ) {
                     Ok(()) | Err(SetupError::NotFoundConfig) => {},
-                    Err(e) =>
-                        return Err(GithubBuilderError::GitProject(e)),
+                    Err(e) => return Err(GithubBuilderError::GitProject(e)),
                 }
                 match github_git_pipelines.add_pipeline(
                     name,
                     config,
                     def,
+                    target,
                     pipeline_id,
-                    true
+                    false
                 ) {
                     Ok(()) | Err(SetupError::NotFoundConfig) => {},
                     Err(e) =>
                         return Err(GithubBuilderError::GithubGitProject(e)),
                 }
-                match view_pipelines.add_pipeline(
+                match gitlab_git_pipelines.add_pipeline(
+                    name,
+                    config,
+                    def,
+                    target,
+                    pipeline_id,
+                    false
+                ) {
+                    Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                    Err(e) =>
+                        return Err(GithubBuilderError::GitlabGitProject(e)),
+                }
+                match gitea_git_pipelines.add_pipeline(
                     name,
                     config,
                     def,
+                    target,
                     pipeline_id,
+                    false
                 ) {
                     Ok(()) | Err(SetupError::NotFoundConfig) => {},
                     Err(e) =>
-                        return Err(GithubBuilderError::ViewProject(e)),
+                        return Err(GithubBuilderError::GiteaGitProject(e)),
+                }
+                match view_pipelines.add_pipeline(
+                    &target.view_name(name),
+                    config,
+                    def,
+                    pipeline_id,
+                ) {
+                    Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                    Err(e) => return Err(GithubBuilderError::ViewProject(e)),
+                }
+                project_pipelines.push((pipeline_id, def.clone()));
+                project_ids.push(pipeline_id);
+                targets.insert(pipeline_id, name.clone());
+                let problems = repo_configs.problems(pipeline_id);
+                for id in Some(pipeline_id).into_iter().chain(try_pipeline_id) {
+                    view_pipelines.add_problems(id, problems);
                 }
-                pipeline_id.0 += 1;
+                if let Some(pipeline_id) = try_pipeline_id {
+                    match github_status_pipelines.add_pipeline(
+                        name,
+                        config,
+                        def,
+                        pipeline_id,
+                        ids,
+                        &mut ci_to_pipeline,
+                    ) {
+                        Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                        Err(e) => return Err(
+                            GithubBuilderError::GithubStatusProject(e)
+                        ),
+                    }
+                    match jenkins_pipelines.add_pipeline(
+                        name,
+                        config,
+                        def,
+                        pipeline_id,
+                        ids,
+                        &mut ci_to_pipeline,
+                    ) {
+                        Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                        Err(e) =>
+                            return Err(GithubBuilderError::JenkinsProject(e)),
+                    }
+                    match webhook_pipelines.add_pipeline(
+                        name,
+                        config,
+                        def,
+                        pipeline_id,
+                        ids,
+                        &mut ci_to_pipeline,
+                    ) {
+                        Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                        Err(e) =>
+                            return Err(GithubBuilderError::WebhookProject(e)),
+                    }
+                    match git_pipelines.add_pipeline(
+                        name,
+                        config,
+                        def,
+                        target,
+                        pipeline_id,
+                        true
+                    ) {
+                        Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                        Err(e) =>
+                            return Err(GithubBuilderError::GitProject(e)),
+                    }
+                    match github_git_pipelines.add_pipeline(
+                        name,
+                        config,
+                        def,
+                        target,
+                        pipeline_id,
+                        true
+                    ) {
+                        Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                        Err(e) =>
+                            return Err(GithubBuilderError::GithubGitProject(e)),
+                    }
+                    match gitlab_git_pipelines.add_pipeline(
+                        name,
+                        config,
+                        def,
+                        target,
+                        pipeline_id,
+                        true
+                    ) {
+                        Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                        Err(e) =>
+                            return Err(GithubBuilderError::GitlabGitProject(e)),
+                    }
+                    match gitea_git_pipelines.add_pipeline(
+                        name,
+                        config,
+                        def,
+                        target,
+                        pipeline_id,
+                        true
+                    ) {
+                        Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                        Err(e) =>
+                            return Err(GithubBuilderError::GiteaGitProject(e)),
+                    }
+                    match view_pipelines.add_pipeline(
+                        &target.try_view_name(name),
+                        config,
+                        def,
+                        pipeline_id,
+                    ) {
+                        Ok(()) | Err(SetupError::NotFoundConfig) => {},
+                        Err(e) =>
+                            return Err(GithubBuilderError::ViewProject(e)),
+                    }
+                    project_pipelines.push((pipeline_id, def.clone()));
+                    project_ids.push(pipeline_id);
+                }
+            }
+            pipeline_ids.insert(name.clone(), project_ids);
+        }
+        for (&ci_id, &(_, ci_pipeline_id, ref ci_name)) in &ci_to_pipeline {
+            view_pipelines.add_ci(ci_pipeline_id, ci_id, ci_name);
+        }
+        Ok(ProjectsConfigs{
+            workers: WorkerConfigs{
+                github_projects: github_projects,
+                gitlab_projects: gitlab_projects,
+                gitea_projects: gitea_projects,
+                github_status_pipelines: github_status_pipelines,
+                jenkins_pipelines: jenkins_pipelines,
+                webhook_pipelines: webhook_pipelines,
+                git_pipelines: git_pipelines,
+                github_git_pipelines: github_git_pipelines,
+                gitlab_git_pipelines: gitlab_git_pipelines,
+                gitea_git_pipelines: gitea_git_pipelines,
+                view_pipelines: view_pipelines,
+            },
+            ci_to_pipeline: ci_to_pipeline,
+            project_pipelines: project_pipelines,
+            pipeline_ids: pipeline_ids,
+            targets: targets,
+        })
+    }
+}
+
+/// Tie each pipeline to the workers that do its jobs.
+fn pipelines_config(
+    ci_to_pipeline: &HashMap<CiId, (CiType, PipelineId, String)>,
+    project_pipelines: &[(PipelineId, toml::Value)],
+    idxs: &WorkerIdxs,
+) -> Result<StaticPipelinesConfig, GithubBuilderError> {
+    let mut pipelines = StaticPipelinesConfig::new();
+    for &(pipeline_id, ref def) in project_pipelines {
+        let mut ci_idxs = Vec::new();
+        let mut ci_names = HashMap::new();
+        for (&ci_id, &(ci_type, ci_pipeline_id, ref ci_name)) in
+                ci_to_pipeline {
+            if ci_pipeline_id == pipeline_id {
+                let ci_idx = match ci_type {
+                    CiType::Jenkins => idxs.jenkins,
+                    CiType::GithubStatus => idxs.github_status,
+                    CiType::Webhook => idxs.webhook,
+                };
+                let ci_idx = if let Some(ci_idx) = ci_idx {
+                    ci_idx
+                } else {
+                    return Err(GithubBuilderError::Dangling);
+                };
+                ci_idxs.push((ci_id, ci_idx));
+                ci_names.insert(ci_id, ci_name.clone());
             }
         }
-        let github = match setup_github(config, github_projects) {
-            Ok(github) => Some(WorkerThread::start(github)),
-            Err(SetupError::NotFoundConfig) => None,
-            Err(e) => return Err(GithubBuilderError::Github(e)),
-        };
-        let github_status =
-            match setup_github_status(config, github_status_pipelines) {
-                Ok(github_status) => Some(WorkerThread::start(github_status)),
-                Err(SetupError::NotFoundConfig) => None,
-                Err(e) => return Err(GithubBuilderError::GithubStatus(e)),
-            };
-        let jenkins = match setup_jenkins(config, jenkins_pipelines) {
-            Ok(jenkins) => Some(WorkerThread::start(jenkins)),
-            Err(SetupError::NotFoundConfig) => None,
-            Err(e) => return Err(GithubBuilderError::Jenkins(e)),
-        };
-        let git = match setup_git(config, git_pipelines) {
-            Ok(git) => Some(WorkerThread::start(git)),
-            Err(SetupError::NotFoundConfig) => None,
-            Err(e) => return Err(GithubBuilderError::Git(e)),
-        };
-        let github_git = match setup_github_git(config, github_git_pipelines) {
-            Ok(github_git) => Some(WorkerThread::start(github_git)),
-            Err(SetupError::NotFoundConfig) => None,
-            Err(e) => return Err(GithubBuilderError::GithubGit(e)),
-        };
-        let view = match setup_view(config, view_pipelines) {
-            Ok(view) => Some(WorkerThread::start(view)),
-            Err(SetupError::NotFoundConfig) => None,
-            Err(e) => return Err(GithubBuilderError::View(e)),
-        };
-        let mut uis = vec![];
-        let github_idx = if let Some(github) = github {
-            uis.push(github);
-            Some(uis.len()-1)
-        } else {
-            None
-        };
-        let mut cis = vec![];
-        let github_status_idx = if let Some(github_status) = github_status {
-            cis.push(github_status);
-            Some(cis.len()-1)
-        } else {
-            None
-        };
-        let jenkins_idx = if let Some(jenkins) = jenkins {
-            cis.push(jenkins);
-            Some(cis.len()-1)
-        } else {
-            None
-        };
-        let mut vcss = vec![];
-        let git_idx = if let Some(git) = git {
-            vcss.push(git);
-            Some(vcss.len()-1)
-        } else {
-            None
-        };
-        let github_git_idx = if let Some(github_git) = github_git {
-            vcss.push(github_git);
-            Some(vcss.len()-1)
+        if ci_idxs.len() == 0 {
+            return Err(GithubBuilderError::Dangling);
+        }
+        let ui_idx = if def.lookup("github").is_some() {
+            if let Some(github_idx) = idxs.github {
+                github_idx
+            } else {
+                return Err(GithubBuilderError::Dangling);
+            }
+        } else if def.lookup("gitlab").is_some() {
+            if let Some(gitlab_idx) = idxs.gitlab {
+                gitlab_idx
+            } else {
+                return Err(GithubBuilderError::Dangling);
+            }
+        } else if def.lookup("gitea").is_some() {
+            if let Some(gitea_idx) = idxs.gitea {
+                gitea_idx
+            } else {
+                return Err(GithubBuilderError::Dangling);
+            }
         } else {
-            None
+            return Err(GithubBuilderError::Dangling);
         };
-        let mut pipelines = StaticPipelinesConfig::new();
-        for (_name, def) in config_projects.iter() {
-            let mut pipeline_id = PipelineId(pipelines.0.len() as i32);
-            let mut ci_idxs = Vec::new();
-            for (&ci_id, &(ci_type, ci_pipeline_id)) in &ci_to_pipeline {
-                if ci_pipeline_id == pipeline_id {
-                    let ci_idx = match ci_type {
-                        CiType::Jenkins => jenkins_idx,
-                        CiType::GithubStatus => github_status_idx,
-                    };
-                    let ci_idx = if let Some(ci_idx) = ci_idx {
-                        ci_idx
-                    } else {
-                        return Err(GithubBuilderError::Dangling);
-                    };
-                    ci_idxs.push((ci_id, ci_idx));
-                }
+        let vcs_idx = if def.lookup("git").is_some() {
+            if let Some(git_idx) = idxs.git {
+                git_idx
+            } else {
+                return Err(GithubBuilderError::Dangling);
             }
-            if ci_idxs.len() == 0 {
+        } else if def.lookup("github").is_some() {
+            if let Some(github_git_idx) = idxs.github_git {
+                github_git_idx
+            } else {
                 return Err(GithubBuilderError::Dangling);
             }
-            let ui_idx = if def.lookup("github").is_some() {
-                if let Some(github_idx) = github_idx {
-                    github_idx
-                } else {
-                    return Err(GithubBuilderError::Dangling);
-                }
+        } else if def.lookup("gitlab").is_some() {
+            if let Some(gitlab_git_idx) = idxs.gitlab_git {
+                gitlab_git_idx
             } else {
                 return Err(GithubBuilderError::Dangling);
-            };
-            let vcs_idx = if def.lookup("git").is_some() {
-                if let Some(git_idx) = git_idx {
-                    git_idx
-                } else {
-                    return Err(GithubBuilderError::Dangling);
-                }
-            } else if def.lookup("github").is_some() {
-                if let Some(github_git_idx) = github_git_idx {
-                    github_git_idx
-                } else {
-                    return Err(GithubBuilderError::Dangling);
-                }
+            }
+        } else if def.lookup("gitea").is_some() {
+            if let Some(gitea_git_idx) = idxs.gitea_git {
+                gitea_git_idx
             } else {
                 return Err(GithubBuilderError::Dangling);
-            };
+            }
+        } else {
+            return Err(GithubBuilderError::Dangling);
+        };
+        let retry = match setup_retry(def) {
+            Ok(retry) => retry,
+            Err(e) => return Err(GithubBuilderError::Project(e)),
+        };
+        pipelines.0.push(PipelineConfig{
+            pipeline_id: pipeline_id,
+            ci: ci_idxs,
+            ui: ui_idx,
+            vcs: vcs_idx,
+            ci_names: ci_names,
+            retry: retry,
+        });
+    }
+    Ok(pipelines)
+}
+
+/// Everything the admin commands need from the config file: where the
+/// database is, and the name of every pipeline.
+/// Unlike `GithubBuilder::build_from_file`, this starts no workers.
+pub fn admin_from_file<P: AsRef<Path>>(path: P) -> Result<
+    (db::Builder, Box<view::PipelinesConfig>),
+    GithubBuilderError,
+> {
+    let config_main = try!(parse_file(path));
+    let config = match config_main.get("config") {
+        Some(config) => config,
+        None => return Err(GithubBuilderError::NoConfig),
+    };
+    let config_projects =
+        match config_main.get("projects").and_then(|c| c.as_table()) {
+            Some(config_projects) => config_projects,
+            None => return Err(GithubBuilderError::NoProjects),
+        };
+    let db_build = match db::Builder::from_str(db_path(config)) {
+        Ok(db_build) => db_build,
+        Err(e) => return Err(GithubBuilderError::Db(e)),
+    };
+    let mut ids = match db_build.open().and_then(|mut db| Ids::load(&mut db)) {
+        Ok(ids) => ids,
+        Err(e) => return Err(GithubBuilderError::Db(e)),
+    };
+    let mut view_pipelines = StaticViewPipelinesConfig::new();
+    for (name, pipeline_id) in try!(pipeline_names(config_projects, &mut ids)) {
+        view_pipelines.pipelines.insert(name, pipeline_id);
+    }
+    Ok((db_build, Box::new(view_pipelines)))
+}
+
+fn parse_file<P: AsRef<Path>>(path: P)
+        -> Result<toml::Table, GithubBuilderError> {
+    let path = path.as_ref();
+    let mut config_file = match File::open(&*path) {
+        Ok(config_file) => config_file,
+        Err(e) => return Err(GithubBuilderError::OpenFile(e)),
+    };
+    let mut config_string = String::new();
+    match config_file.read_to_string(&mut config_string) {
+        Ok(_) => {},
+        Err(e) => return Err(GithubBuilderError::ReadFile(e)),
+    }
+    let mut config = match toml::Parser::new(&config_string).parse() {
+        Some(config) => config,
+        None => return Err(GithubBuilderError::Parse),
+    };
+    match secret::resolve(&mut config).into_iter().next() {
+        Some(e) => Err(GithubBuilderError::Secret(e)),
+        None => Ok(config),
+    }
+}
+
+fn db_path(config: &toml::Value) -> &str {
+    config.lookup("db")
+        .and_then(|file| file.as_str())
+        .unwrap_or_else(|| "db.sqlite")
+}
+
+/// The view name of every pipeline, and its id.
+///
+/// Pipelines the bot hasn't seen yet are given the ids it would give them,
+/// as long as the two hand them out in the same order.
+fn pipeline_names(
+    config_projects: &toml::Table,
+    ids: &mut Ids,
+) -> Result<Vec<(String, PipelineId)>, GithubBuilderError> {
+    let mut names = Vec::new();
+    for (name, def) in config_projects.iter() {
+        if def.as_table().is_none() {
+            return Err(GithubBuilderError::Project(
+                SetupError::InvalidArg(ProjectArg::Project, Ty::Table)
+            ));
+        }
+        let targets = match project_targets(def) {
+            Ok(targets) => targets,
+            Err(e) => return Err(GithubBuilderError::Project(e)),
+        };
+        for target in &targets {
+            let view_name = target.view_name(name);
+            names.push((view_name.clone(), ids.pipeline_id(&view_name)));
             if def.lookup("try").is_some() {
-                pipelines.0.push(PipelineConfig{
-                    pipeline_id: pipeline_id,
-                    ci: ci_idxs.clone(),
-                    ui: ui_idx,
-                    vcs: vcs_idx,
-                });
+                let view_name = target.try_view_name(name);
+                names.push((view_name.clone(), ids.pipeline_id(&view_name)));
             }
-            pipelines.0.push(PipelineConfig{
-                pipeline_id: pipeline_id,
-                ci: ci_idxs,
-                ui: ui_idx,
-                vcs: vcs_idx,
-            });
-            pipeline_id.0 = pipeline_id.0 + 1;
-        }
-        let db_path = config.lookup("db")
-            .and_then(|file| file.as_str())
-            .unwrap_or_else(|| "db.sqlite");
-        let db_build = db::Builder::from_str(db_path)
-            .expect("to parse db path");
-        let db = db_build
-            .open()
-            .expect("to open up db");
-        Ok(GithubBuilder{
-            cis: cis,
-            uis: uis,
-            vcss: vcss,
-            view: view,
-            db: db,
-            pipelines: pipelines,
-        })
+        }
     }
+    Ok(names)
 }
 
 impl WorkerBuilder for GithubBuilder {
@@ -376,12 +1002,453 @@ impl WorkerBuilder for GithubBuilder {
                 vcss: self.vcss,
                 view: self.view,
                 pipelines: Box::new(self.pipelines),
+                reloader: self.reloader.map(|reloader| {
+                    Box::new(reloader) as Box<Reload>
+                }),
             },
             self.db,
         )
     }
 }
 
+/// Reads `[projects]` again when the config file changes.
+///
+/// New projects get new pipeline ids, and the ones that are still in the file
+/// keep theirs. A new project can only use the workers that were started
+/// with the bot, and changes outside of `[projects]` need a restart.
+///
+/// A removed project's pipelines are paused, and kept around until they are
+/// done building, so the results still get reported. Their queues are left in
+/// the database, and are picked back up if the project is added again.
+///
+/// It also lays each repo's own `.aelita.toml` over its project. A file with
+/// anything wrong with it is left out entirely, and what is wrong is shown
+/// on the project's dashboard pages.
+pub struct Reloader {
+    path: PathBuf,
+    /// The `[config]` section the workers were started with.
+    config: toml::Value,
+    projects: toml::Table,
+    /// Removed projects that are still building.
+    draining: toml::Table,
+    /// The pipelines of every project in `projects` or `draining`.
+    pipeline_ids: HashMap<String, Vec<PipelineId>>,
+    /// The pipelines that were paused because their project was removed.
+    paused: HashSet<PipelineId>,
+    /// The pipeline for each branch PRs go into, and its project's name.
+    targets: HashMap<PipelineId, String>,
+    repo_configs: RepoConfigs,
+    ids: Ids,
+    idxs: WorkerIdxs,
+    shared: SharedConfigs,
+}
+
+impl Reloader {
+    /// Build the configs, and hand them to the workers.
+    fn apply(
+        &mut self,
+        projects: toml::Table,
+        draining: toml::Table,
+        db: &mut DbBox,
+    ) -> Result<(), GithubBuilderError> {
+        let mut all = projects.clone();
+        for (name, def) in &draining {
+            all.insert(name.clone(), def.clone());
+        }
+        let mut ids = self.ids.clone();
+        let mut configs;
+        loop {
+            configs = try!(ProjectsConfigs::build(
+                &self.config,
+                &all,
+                &self.repo_configs,
+                &mut ids,
+            ));
+            match ids.save(db) {
+                Ok(false) => break,
+                // Build them again with the ids the database has.
+                Ok(true) => {}
+                // The ids are kept, and saved next time.
+                Err(e) => {
+                    warn!("Failed to save the new pipeline ids: {:?}", e);
+                    break;
+                }
+            }
+        }
+        let pipelines = try!(pipelines_config(
+            &configs.ci_to_pipeline,
+            &configs.project_pipelines,
+            &self.idxs,
+        ));
+        self.shared.replace(configs.workers);
+        self.shared.pipelines.replace(pipelines);
+        self.ids = ids;
+        self.projects = projects;
+        self.draining = draining;
+        self.pipeline_ids = configs.pipeline_ids;
+        self.targets = configs.targets;
+        Ok(())
+    }
+    fn project_pipelines(&self, name: &str) -> Vec<PipelineId> {
+        self.pipeline_ids.get(name).cloned().unwrap_or_else(Vec::new)
+    }
+    /// Check a repo's settings, and whether they fit its project.
+    fn check_repo_config(
+        &self,
+        name: &str,
+        contents: Option<String>,
+    ) -> Result<Option<toml::Table>, Vec<String>> {
+        let contents = match contents {
+            Some(contents) => contents,
+            None => return Ok(None),
+        };
+        let settings = match check::check_repo_config(&contents) {
+            Ok(settings) => settings,
+            Err(problems) => return Err(
+                problems.iter().map(ToString::to_string).collect()
+            ),
+        };
+        let on_github = self.projects.get(name)
+            .and_then(|def| def.lookup("github"))
+            .is_some();
+        if settings.contains_key("github") && !on_github {
+            return Err(vec!["github: the project is not on GitHub".to_owned()]);
+        }
+        Ok(Some(settings))
+    }
+}
+
+/// The settings each repo keeps in `vcs::REPO_CONFIG`, on each of the
+/// branches PRs go into, by the pipeline for the branch.
+struct RepoConfigs {
+    settings: HashMap<PipelineId, toml::Table>,
+    /// What is wrong with the file; its settings are left out until fixed
+    problems: HashMap<PipelineId, Vec<String>>,
+}
+
+impl RepoConfigs {
+    fn new() -> Self {
+        RepoConfigs{
+            settings: HashMap::new(),
+            problems: HashMap::new(),
+        }
+    }
+    /// A project's settings, with its target branch's laid over them.
+    fn layer(&self, def: &toml::Value, pipeline_id: PipelineId) -> toml::Value {
+        let mut def = def.clone();
+        if let Some(settings) = self.settings.get(&pipeline_id) {
+            layer_repo_config(&mut def, settings);
+        }
+        def
+    }
+    fn problems(&self, pipeline_id: PipelineId) -> &[String] {
+        self.problems.get(&pipeline_id).map_or(&[][..], |p| &p[..])
+    }
+}
+
+/// Lay a repo's settings over its project's. The status contexts it lists
+/// are added to the project's, so a repo can wait for more checks, but
+/// never for fewer than the project requires.
+fn layer_repo_config(def: &mut toml::Value, settings: &toml::Table) {
+    let mut settings = settings.clone();
+    let added = match settings.get_mut("github") {
+        Some(&mut toml::Value::Table(ref mut github)) => github.remove("status"),
+        _ => None,
+    };
+    layer(def, &settings);
+    let added = match added {
+        Some(toml::Value::Array(added)) => added,
+        Some(added) => vec![added],
+        None => return,
+    };
+    let mut contexts = match def.lookup("github.status") {
+        Some(&toml::Value::Array(ref contexts)) => contexts.clone(),
+        Some(context) => vec![context.clone()],
+        None => Vec::new(),
+    };
+    for context in added {
+        if !contexts.contains(&context) {
+            contexts.push(context);
+        }
+    }
+    let mut github = toml::Table::new();
+    github.insert("status".to_owned(), toml::Value::Array(contexts));
+    let mut status = toml::Table::new();
+    status.insert("github".to_owned(), toml::Value::Table(github));
+    layer(def, &status);
+}
+
+/// Lay a repo's settings over its project's, replacing only the keys that
+/// the repo sets.
+fn layer(def: &mut toml::Value, settings: &toml::Table) {
+    let def = match *def {
+        toml::Value::Table(ref mut def) => def,
+        _ => return,
+    };
+    for (key, value) in settings {
+        match *value {
+            toml::Value::Table(ref settings) => {
+                let entry = def.entry(key.clone()).or_insert_with(|| {
+                    toml::Value::Table(toml::Table::new())
+                });
+                if entry.as_table().is_some() {
+                    layer(entry, settings);
+                } else {
+                    *entry = value.clone();
+                }
+            }
+            _ => {
+                def.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+impl Reload for Reloader {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+    fn reload(&mut self, db: &mut DbBox) {
+        let config_main = match parse_file(&self.path) {
+            Ok(config_main) => config_main,
+            Err(e) => {
+                warn!("Failed to read {}: {:?}", self.path.display(), e);
+                return;
+            }
+        };
+        if config_main.get("config") != Some(&self.config) {
+            warn!("Changes to [config] are only picked up after a restart");
+        }
+        let projects =
+            match config_main.get("projects").and_then(|c| c.as_table()) {
+                Some(projects) => projects.clone(),
+                None => {
+                    warn!("Not reloading {}: no projects", self.path.display());
+                    return;
+                }
+            };
+        if projects == self.projects {
+            return;
+        }
+        let mut draining = self.draining.clone();
+        for name in projects.keys() {
+            draining.remove(name);
+        }
+        let mut removed = Vec::new();
+        for (name, def) in &self.projects {
+            if !projects.contains_key(name) {
+                draining.insert(name.clone(), def.clone());
+                removed.push(name.clone());
+            }
+        }
+        if let Err(e) = self.apply(projects, draining, db) {
+            warn!("Not reloading {}: {:?}", self.path.display(), e);
+            return;
+        }
+        let mut pause = Vec::new();
+        for name in &removed {
+            pause.extend(self.project_pipelines(name));
+        }
+        let mut resume = Vec::new();
+        for name in self.projects.keys() {
+            for pipeline_id in self.project_pipelines(name) {
+                if self.paused.remove(&pipeline_id) {
+                    resume.push(pipeline_id);
+                }
+            }
+        }
+        match db.transaction(PauseTransaction{
+            pause: pause,
+            resume: resume,
+        }) {
+            Ok(paused) => self.paused.extend(paused),
+            Err(e) => warn!("Failed to pause removed pipelines: {:?}", e),
+        }
+        info!(
+            "Reloaded {}: {} projects, {} removed ones still building",
+            self.path.display(),
+            self.projects.len(),
+            self.draining.len(),
+        );
+    }
+    fn drain(&mut self, db: &mut DbBox) {
+        if self.draining.is_empty() {
+            return;
+        }
+        let mut done = Vec::new();
+        for name in self.draining.keys() {
+            match db.transaction(RunningTransaction{
+                pipeline_ids: self.project_pipelines(name),
+            }) {
+                Ok(false) => done.push(name.clone()),
+                Ok(true) => {}
+                Err(e) => warn!("Failed to check on {}: {:?}", name, e),
+            }
+        }
+        if done.is_empty() {
+            return;
+        }
+        let mut draining = self.draining.clone();
+        for name in &done {
+            draining.remove(name);
+        }
+        let projects = self.projects.clone();
+        match self.apply(projects, draining, db) {
+            Ok(()) => info!("Removed {}", done.join(", ")),
+            Err(e) => warn!("Failed to remove {}: {:?}", done.join(", "), e),
+        }
+    }
+    fn repo_config_pipelines(&self) -> Vec<PipelineId> {
+        self.targets.iter()
+            .filter(|&(_, name)| self.projects.contains_key(name))
+            .map(|(&pipeline_id, _)| pipeline_id)
+            .collect()
+    }
+    fn repo_config(
+        &mut self,
+        pipeline_id: PipelineId,
+        contents: Option<String>,
+        db: &mut DbBox,
+    ) {
+        let name = match self.targets.get(&pipeline_id) {
+            Some(name) if self.projects.contains_key(name) => name.clone(),
+            _ => return,
+        };
+        let (settings, problems) =
+            match self.check_repo_config(&name, contents) {
+                Ok(settings) => (settings, Vec::new()),
+                Err(problems) => (None, problems),
+            };
+        for problem in &problems {
+            warn!(
+                "{} in {} ({:?}): {}",
+                vcs::REPO_CONFIG,
+                name,
+                pipeline_id,
+                problem,
+            );
+        }
+        if settings.as_ref() == self.repo_configs.settings.get(&pipeline_id) &&
+                &problems[..] == self.repo_configs.problems(pipeline_id) {
+            return;
+        }
+        match settings {
+            Some(settings) =>
+                self.repo_configs.settings.insert(pipeline_id, settings),
+            None => self.repo_configs.settings.remove(&pipeline_id),
+        };
+        if problems.is_empty() {
+            self.repo_configs.problems.remove(&pipeline_id);
+        } else {
+            self.repo_configs.problems.insert(pipeline_id, problems);
+        }
+        let projects = self.projects.clone();
+        let draining = self.draining.clone();
+        if let Err(e) = self.apply(projects.clone(), draining.clone(), db) {
+            // Like a status check, without a [config.github.status].
+            warn!(
+                "{} in {} ({:?}) does not fit: {:?}",
+                vcs::REPO_CONFIG,
+                name,
+                pipeline_id,
+                e,
+            );
+            self.repo_configs.settings.remove(&pipeline_id);
+            self.repo_configs.problems.insert(
+                pipeline_id,
+                vec![format!("cannot be used: {:?}", e)],
+            );
+            if let Err(e) = self.apply(projects, draining, db) {
+                warn!("Failed to rebuild {}: {:?}", name, e);
+                return;
+            }
+        }
+        info!(
+            "Read the settings of {} ({:?}) from {}",
+            name,
+            pipeline_id,
+            vcs::REPO_CONFIG,
+        );
+    }
+}
+
+/// Pause the pipelines of removed projects, and resume the ones that were
+/// added back. Returns the pipelines it paused, leaving out the ones that
+/// were paused already.
+struct PauseTransaction {
+    pause: Vec<PipelineId>,
+    resume: Vec<PipelineId>,
+}
+
+impl Transaction for PauseTransaction {
+    type Return = Vec<PipelineId>;
+    fn run<D: Db>(
+        self,
+        db: &mut D
+    ) -> Result<Self::Return, Box<Error + Send + Sync>> {
+        let mut paused = Vec::new();
+        for pipeline_id in self.pause {
+            if !try!(db.is_paused(pipeline_id)) {
+                try!(db.set_paused(pipeline_id, true));
+                paused.push(pipeline_id);
+            }
+        }
+        for pipeline_id in self.resume {
+            try!(db.set_paused(pipeline_id, false));
+        }
+        Ok(paused)
+    }
+}
+
+struct ListIdsTransaction;
+
+impl Transaction for ListIdsTransaction {
+    type Return = Vec<ConfigIdEntry>;
+    fn run<D: Db>(
+        self,
+        db: &mut D
+    ) -> Result<Self::Return, Box<Error + Send + Sync>> {
+        db.list_config_ids()
+    }
+}
+
+struct AddIdsTransaction {
+    entries: Vec<ConfigIdEntry>,
+}
+
+impl Transaction for AddIdsTransaction {
+    type Return = ();
+    fn run<D: Db>(
+        self,
+        db: &mut D
+    ) -> Result<Self::Return, Box<Error + Send + Sync>> {
+        for entry in self.entries {
+            try!(db.add_config_id(entry));
+        }
+        Ok(())
+    }
+}
+
+/// Whether any of the pipelines is in the middle of a build.
+struct RunningTransaction {
+    pipeline_ids: Vec<PipelineId>,
+}
+
+impl Transaction for RunningTransaction {
+    type Return = bool;
+    fn run<D: Db>(
+        self,
+        db: &mut D
+    ) -> Result<Self::Return, Box<Error + Send + Sync>> {
+        for pipeline_id in self.pipeline_ids {
+            if try!(db.peek_running(pipeline_id)).is_some() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
 // Convenience functions for semantic parsing.
 
 macro_rules! toml_arg {
@@ -455,16 +1522,56 @@ macro_rules! toml_arg_default {
 
 // Everything under the [config] section.
 
-fn setup_github(config: &toml::Value, projects: StaticGithubProjectsConfig)
-        -> Result<github::Worker, SetupError<GithubArg>> {
+fn setup_github(
+    config: &toml::Value,
+    projects: Shared<StaticGithubProjectsConfig>,
+) -> Result<github::Worker, SetupError<GithubArg>> {
+    let credentials = match try!(github_credentials(
+        config,
+        "github",
+        GithubArg::Token,
+        GithubArg::AppId,
+        GithubArg::PrivateKey
+    )) {
+        Some(credentials) => credentials,
+        None => return Err(SetupError::NotFoundArg(GithubArg::Token)),
+    };
+    let checks = toml_arg_default!(config, "github", "checks", Boolean,
+        GithubArg::Checks, false
+    );
+    if let (true, &github_auth::Credentials::Token(_)) =
+            (checks, &credentials) {
+        warn!("The Checks API needs a GitHub App, not an access token");
+        return Err(SetupError::NotFoundArg(GithubArg::AppId));
+    }
     Ok(github::Worker::new(
         toml_arg!(config, "github", "listen", String, GithubArg::Listen),
-        toml_arg_default!(config, "github", "host", String, GithubArg::Host,
-            "https://api.github.com"
+        github_auth::Auth::new(
+            "ui_github",
+            toml_arg_default!(config, "github", "host", String, GithubArg::Host,
+                "https://api.github.com"
+            ),
+            credentials
+        ),
+        toml_arg!(config, "github", "user", String, GithubArg::User),
+        toml_arg!(config, "github", "secret", String, GithubArg::Secret),
+        toml_arg_default!(
+            config,
+            "github",
+            "require_sha256",
+            Boolean,
+            GithubArg::RequireSha256,
+            false
+        ),
+        checks,
+        toml_arg_default!(
+            config,
+            "github",
+            "status_comment",
+            Boolean,
+            GithubArg::StatusComment,
+            false
         ),
-        toml_arg!(config, "github", "token", String, GithubArg::Token),
-        toml_arg!(config, "github", "user", String, GithubArg::User),
-        toml_arg!(config, "github", "secret", String, GithubArg::Secret),
         Box::new(projects),
         db::Builder::from_str(
             &toml_arg_default!(config, "github", "db", String, GithubArg::Db,
@@ -472,12 +1579,54 @@ fn setup_github(config: &toml::Value, projects: StaticGithubProjectsConfig)
                     .unwrap_or("db.sqlite").to_owned()
             )[..]
         ).expect("the DB to open"),
+        match config.lookup("github.cache_ttl") {
+            Some(&toml::Value::Integer(ttl)) if ttl > 0 => Some(ttl),
+            Some(_) => return Err(SetupError::InvalidArg(
+                GithubArg::CacheTtl,
+                Ty::Integer,
+            )),
+            None => None,
+        },
+    ))
+}
+
+fn setup_gitlab(
+    config: &toml::Value,
+    projects: Shared<StaticGitlabProjectsConfig>,
+) -> Result<gitlab::Worker, SetupError<GitlabArg>> {
+    Ok(gitlab::Worker::new(
+        toml_arg!(config, "gitlab", "listen", String, GitlabArg::Listen),
+        gitlab_api::Api::new(
+            toml_arg_default!(config, "gitlab", "host", String, GitlabArg::Host,
+                "https://gitlab.com"
+            ),
+            toml_arg!(config, "gitlab", "token", String, GitlabArg::Token),
+        ),
+        toml_arg!(config, "gitlab", "user", String, GitlabArg::User),
+        toml_arg!(config, "gitlab", "secret", String, GitlabArg::Secret),
+        Box::new(projects),
+    ))
+}
+
+fn setup_gitea(
+    config: &toml::Value,
+    projects: Shared<StaticGiteaProjectsConfig>,
+) -> Result<gitea::Worker, SetupError<GiteaArg>> {
+    Ok(gitea::Worker::new(
+        toml_arg!(config, "gitea", "listen", String, GiteaArg::Listen),
+        gitea_api::Api::new(
+            toml_arg!(config, "gitea", "host", String, GiteaArg::Host),
+            toml_arg!(config, "gitea", "token", String, GiteaArg::Token),
+        ),
+        toml_arg!(config, "gitea", "user", String, GiteaArg::User),
+        toml_arg!(config, "gitea", "secret", String, GiteaArg::Secret),
+        Box::new(projects),
     ))
 }
 
 fn setup_github_status(
     config: &toml::Value,
-    pipelines: StaticGithubStatusPipelinesConfig
+    pipelines: Shared<StaticGithubStatusPipelinesConfig>
 ) -> Result<github_status::Worker, SetupError<GithubStatusArg>> {
     Ok(github_status::Worker::new(
         toml_arg!(
@@ -501,13 +1650,161 @@ fn setup_github_status(
                 GithubStatusArg::Secret
             )
         ),
+        toml_arg_default!(
+            config,
+            "github.status",
+            "require_sha256",
+            Boolean,
+            GithubStatusArg::RequireSha256,
+            toml_arg_default!(
+                config,
+                "github",
+                "require_sha256",
+                Boolean,
+                GithubStatusArg::RequireSha256,
+                false
+            )
+        ),
         Box::new(pipelines),
+        db::Builder::from_str(
+            &toml_arg_default!(
+                config,
+                "github.status",
+                "db",
+                String,
+                GithubStatusArg::Db,
+                config.lookup("github.db")
+                    .or_else(|| config.lookup("db"))
+                    .and_then(toml::Value::as_str)
+                    .unwrap_or("db.sqlite").to_owned()
+            )[..]
+        ).expect("the DB to open"),
     ))
 }
 
+fn setup_retry(
+    def: &toml::Value,
+) -> Result<RetryConfig, SetupError<ProjectArg>> {
+    let count = match def.lookup("retry") {
+        Some(count) => match count.as_integer() {
+            Some(count) if count >= 0 => count as u32,
+            _ => return Err(SetupError::InvalidArg(
+                ProjectArg::Retry,
+                Ty::Integer,
+            )),
+        },
+        None => 0,
+    };
+    let only_flaky = match def.lookup("retry_only_flaky") {
+        Some(only_flaky) => match only_flaky.as_bool() {
+            Some(only_flaky) => only_flaky,
+            None => return Err(SetupError::InvalidArg(
+                ProjectArg::RetryOnlyFlaky,
+                Ty::Boolean,
+            )),
+        },
+        None => false,
+    };
+    Ok(RetryConfig{
+        count: count,
+        only_flaky: only_flaky,
+    })
+}
+
+/// One of the branches a project takes PRs into.
+/// Each one gets its own pipelines, with their own staging branches.
+struct Target {
+    branch: String,
+    /// Whether it's the first one, which keeps the names it would have
+    /// if it were the only one
+    first: bool,
+}
+
+/// The branches a target's merges are built on, by whichever backend
+/// merges them: the `git` section's, or else the front-end's own.
+fn staging_branches(
+    def: &toml::Value,
+    front_end: &str,
+    target: &Target,
+    has_try: bool,
+) -> Vec<String> {
+    let section = if def.lookup("git").is_some() { "git" } else { front_end };
+    let branch = |key: String, default: &str| {
+        target.staging_branch(
+            def.lookup(&key)
+                .and_then(toml::Value::as_str)
+                .unwrap_or(default)
+                .to_owned()
+        )
+    };
+    let mut branches =
+        vec![branch(format!("{}.staging_branch", section), "staging")];
+    if has_try {
+        branches.push(branch(format!("try.{}.branch", section), "trying"));
+    }
+    branches
+}
+
+impl Target {
+    fn staging_branch(&self, staging_branch: String) -> String {
+        if self.first {
+            staging_branch
+        } else {
+            format!("{}-{}", staging_branch, self.branch)
+        }
+    }
+    fn view_name(&self, name: &str) -> String {
+        if self.first {
+            name.to_owned()
+        } else {
+            format!("{}/{}", name, self.branch)
+        }
+    }
+    fn try_view_name(&self, name: &str) -> String {
+        format!("{}/try", self.view_name(name))
+    }
+}
+
+/// The branches in `branches`, or else the project's master branch.
+fn project_targets(
+    def: &toml::Value,
+) -> Result<Vec<Target>, SetupError<ProjectArg>> {
+    let branches = match def.lookup("branches") {
+        Some(&toml::Value::Array(ref branches)) if !branches.is_empty() =>
+            try!(string_array(branches, ProjectArg::Branches)),
+        Some(_) => return Err(SetupError::InvalidArg(
+            ProjectArg::Branches,
+            Ty::Array,
+        )),
+        None => {
+            let vcs = if def.lookup("git").is_some() {
+                "git"
+            } else if def.lookup("github").is_some() {
+                "github"
+            } else if def.lookup("gitlab").is_some() {
+                "gitlab"
+            } else {
+                "gitea"
+            };
+            match def.lookup(&format!("{}.master_branch", vcs)) {
+                Some(&toml::Value::String(ref branch)) => vec![branch.clone()],
+                Some(_) => return Err(SetupError::InvalidArg(
+                    ProjectArg::MasterBranch,
+                    Ty::String,
+                )),
+                None => vec!["master".to_owned()],
+            }
+        }
+    };
+    Ok(branches.into_iter().enumerate().map(|(i, branch)| Target{
+        branch: branch,
+        first: i == 0,
+    }).collect())
+}
+
 fn setup_jenkins(
     config: &toml::Value,
-    pipelines: StaticJenkinsPipelinesConfig
+    pipelines: Shared<StaticJenkinsPipelinesConfig>
 ) -> Result<jenkins::Worker, SetupError<JenkinsArg>> {
     let user = if let Some(user) = config.lookup("jenkins.user") {
         if let Some(user) = user.as_str() {
@@ -540,9 +1837,82 @@ fn setup_jenkins(
     ))
 }
 
+fn setup_webhook(
+    config: &toml::Value,
+    pipelines: Shared<StaticWebhookPipelinesConfig>
+) -> Result<webhook::Worker, SetupError<WebhookArg>> {
+    let url = match config.lookup("webhook.url") {
+        Some(&toml::Value::String(ref url)) => Some(url.clone()),
+        Some(_) =>
+            return Err(SetupError::InvalidArg(WebhookArg::Url, Ty::String)),
+        None => None,
+    };
+    let fields = webhook::Fields{
+        name: toml_arg_default!(config, "webhook", "name", String,
+            WebhookArg::Name,
+            "/name"
+        ),
+        commit: toml_arg_default!(config, "webhook", "commit", String,
+            WebhookArg::Commit,
+            "/commit"
+        ),
+        state: toml_arg_default!(config, "webhook", "state", String,
+            WebhookArg::State,
+            "/state"
+        ),
+        url: url,
+        started: try!(string_array(
+            &toml_arg_default!(config, "webhook", "started", Array,
+                WebhookArg::Started,
+                vec![toml::Value::String("started".to_owned())]
+            ),
+            WebhookArg::Started,
+        )),
+        succeeded: try!(string_array(
+            &toml_arg_default!(config, "webhook", "succeeded", Array,
+                WebhookArg::Succeeded,
+                vec![toml::Value::String("success".to_owned())]
+            ),
+            WebhookArg::Succeeded,
+        )),
+        failed: try!(string_array(
+            &toml_arg_default!(config, "webhook", "failed", Array,
+                WebhookArg::Failed,
+                vec![
+                    toml::Value::String("failure".to_owned()),
+                    toml::Value::String("error".to_owned()),
+                ]
+            ),
+            WebhookArg::Failed,
+        )),
+    };
+    Ok(webhook::Worker::new(
+        toml_arg!(config, "webhook", "listen", String, WebhookArg::Listen),
+        toml_arg!(config, "webhook", "secret", String, WebhookArg::Secret),
+        toml_arg_default!(config, "webhook", "signature_header", String,
+            WebhookArg::SignatureHeader,
+            "X-Hub-Signature"
+        ),
+        fields,
+        Box::new(pipelines),
+    ))
+}
+
+fn string_array<T: Debug>(values: &[toml::Value], arg: T)
+        -> Result<Vec<String>, SetupError<T>> {
+    let mut ret_val = Vec::new();
+    for value in values {
+        match *value {
+            toml::Value::String(ref value) => ret_val.push(value.clone()),
+            _ => return Err(SetupError::InvalidArg(arg, Ty::String)),
+        }
+    }
+    Ok(ret_val)
+}
+
 fn setup_git(
     config: &toml::Value,
-    pipelines: StaticGitPipelinesConfig
+    pipelines: Shared<StaticGitPipelinesConfig>
 ) -> Result<git::Worker, SetupError<GitArg>> {
     Ok(git::Worker::new(
         toml_arg_default!(config, "git", "executable",
@@ -550,7 +1920,13 @@ fn setup_git(
             "git"
         ),
         toml_arg_default!(config, "git", "name", String, GitArg::Name,
-            toml_arg!(config, "github", "user", String, GitArg::Name)
+            match config.lookup("github.user")
+                    .or_else(|| config.lookup("gitlab.user"))
+                    .or_else(|| config.lookup("gitea.user"))
+                    .and_then(toml::Value::as_str) {
+                Some(s) => s.to_owned(),
+                None => return Err(SetupError::NotFoundArg(GitArg::Name)),
+            }
         ),
         toml_arg_default!(config, "git", "email", String, GitArg::Email,
             match config.lookup("github.user").and_then(toml::Value::as_str) {
@@ -564,39 +1940,212 @@ fn setup_git(
 
 fn setup_github_git(
     config: &toml::Value,
-    pipelines: StaticGithubGitPipelinesConfig
+    pipelines: Shared<StaticGithubGitPipelinesConfig>
 ) -> Result<github_git::Worker, SetupError<GithubGitArg>> {
-    Ok(github_git::Worker::new(
-        toml_arg_default!(
+    if config.lookup("github.git").is_none() {
+        return Err(SetupError::NotFoundConfig);
+    }
+    let credentials = match try!(github_credentials(
+        config,
+        "github.git",
+        GithubGitArg::Token,
+        GithubGitArg::AppId,
+        GithubGitArg::PrivateKey
+    )) {
+        Some(credentials) => credentials,
+        None => match try!(github_credentials(
             config,
-            "github.git",
-            "host",
-            String,
-            GithubGitArg::Host,
+            "github",
+            GithubGitArg::Token,
+            GithubGitArg::AppId,
+            GithubGitArg::PrivateKey
+        )) {
+            Some(credentials) => credentials,
+            None => return Err(SetupError::NotFoundArg(GithubGitArg::Token)),
+        },
+    };
+    Ok(github_git::Worker::new(
+        github_auth::Auth::new(
+            "vcs_github",
             toml_arg_default!(
                 config,
-                "github",
+                "github.git",
                 "host",
                 String,
                 GithubGitArg::Host,
-                "https://api.github.com"
-            )
+                toml_arg_default!(
+                    config,
+                    "github",
+                    "host",
+                    String,
+                    GithubGitArg::Host,
+                    "https://api.github.com"
+                )
+            ),
+            credentials
         ),
-        toml_arg_default!(
-            config,
-            "github.git",
-            "token",
-            String,
-            GithubGitArg::Token,
-            toml_arg!(config, "github", "token", String, GithubGitArg::Token)
+        Box::new(pipelines),
+    ))
+}
+
+fn setup_gitlab_git(
+    config: &toml::Value,
+    pipelines: Shared<StaticGitlabGitPipelinesConfig>
+) -> Result<gitlab_git::Worker, SetupError<GitlabGitArg>> {
+    Ok(gitlab_git::Worker::new(
+        gitlab_api::Api::new(
+            toml_arg_default!(
+                config,
+                "gitlab",
+                "host",
+                String,
+                GitlabGitArg::Host,
+                "https://gitlab.com"
+            ),
+            toml_arg!(config, "gitlab", "token", String, GitlabGitArg::Token),
         ),
+        match setup_bare_git(config, "gitlab.user") {
+            Ok(git) => git,
+            Err(e) => return Err(wrap_arg(e, GitlabGitArg::Git)),
+        },
         Box::new(pipelines),
     ))
 }
 
+fn setup_gitea_git(
+    config: &toml::Value,
+    pipelines: Shared<StaticGiteaGitPipelinesConfig>
+) -> Result<gitea_git::Worker, SetupError<GiteaGitArg>> {
+    // The front-end's host and token are used, unless the
+    // `gitea.git` section says otherwise.
+    let host = match config.lookup("gitea.git.host") {
+        Some(&toml::Value::String(ref host)) => host.clone(),
+        Some(_) => return Err(
+            SetupError::InvalidArg(GiteaGitArg::Host, Ty::String)
+        ),
+        None => toml_arg!(config, "gitea", "host", String, GiteaGitArg::Host),
+    };
+    let token = match config.lookup("gitea.git.token") {
+        Some(&toml::Value::String(ref token)) => token.clone(),
+        Some(_) => return Err(
+            SetupError::InvalidArg(GiteaGitArg::Token, Ty::String)
+        ),
+        None =>
+            toml_arg!(config, "gitea", "token", String, GiteaGitArg::Token),
+    };
+    Ok(gitea_git::Worker::new(
+        gitea_api::Api::new(host, token),
+        match setup_bare_git(config, "gitea.user") {
+            Ok(git) => git,
+            Err(e) => return Err(wrap_arg(e, GiteaGitArg::Git)),
+        },
+        Box::new(pipelines),
+    ))
+}
+
+/// For the backends that merge through the front-end's own git server.
+/// Merges are committed as the `git` section says, even if no project is
+/// set up to use a local clone, so the section need not be there.
+fn setup_bare_git(
+    config: &toml::Value,
+    user_key: &str,
+) -> Result<git::Bare, SetupError<GitArg>> {
+    let executable = try!(optional_string(
+        config,
+        "git.executable",
+        GitArg::Executable,
+    )).unwrap_or_else(|| "git".to_owned());
+    let name = match try!(optional_string(config, "git.name", GitArg::Name)) {
+        Some(name) => name,
+        None => match try!(optional_string(config, user_key, GitArg::Name)) {
+            Some(name) => name,
+            None => return Err(SetupError::NotFoundArg(GitArg::Name)),
+        },
+    };
+    let email = match try!(optional_string(config, "git.email", GitArg::Email)) {
+        Some(email) => email,
+        None => return Err(SetupError::NotFoundArg(GitArg::Email)),
+    };
+    let path = try!(optional_string(config, "git.path", GitArg::Path))
+        .unwrap_or_else(|| "cache/".to_owned());
+    Ok(git::Bare::new(executable, name, email, path))
+}
+
+/// A string setting that doesn't need its section to be there.
+fn optional_string<A: Debug>(
+    config: &toml::Value,
+    key: &str,
+    arg: A,
+) -> Result<Option<String>, SetupError<A>> {
+    match config.lookup(key) {
+        Some(&toml::Value::String(ref value)) => Ok(Some(value.clone())),
+        Some(_) => Err(SetupError::InvalidArg(arg, Ty::String)),
+        None => Ok(None),
+    }
+}
+
+/// Report a problem with one section's setting as part of another's.
+fn wrap_arg<A: Debug, B: Debug, F: Fn(A) -> B>(
+    e: SetupError<A>,
+    wrap: F,
+) -> SetupError<B> {
+    match e {
+        SetupError::NotFoundConfig => SetupError::NotFoundConfig,
+        SetupError::NotTableConfig => SetupError::NotTableConfig,
+        SetupError::NotFoundArg(arg) => SetupError::NotFoundArg(wrap(arg)),
+        SetupError::InvalidArg(arg, ty) =>
+            SetupError::InvalidArg(wrap(arg), ty),
+    }
+}
+
+/// Read either a personal access token,
+/// or a GitHub App ID and the path to its private key, from a section.
+/// Returns `None` if the section has neither.
+fn github_credentials<A>(
+    config: &toml::Value,
+    section: &str,
+    token_arg: A,
+    app_id_arg: A,
+    private_key_arg: A
+) -> Result<Option<github_auth::Credentials>, SetupError<A>> {
+    let section = match config.lookup(section) {
+        Some(section) => section,
+        None => return Ok(None),
+    };
+    if let Some(token) = section.lookup("token") {
+        return match token.as_str() {
+            Some(token) =>
+                Ok(Some(github_auth::Credentials::Token(token.to_owned()))),
+            None => Err(SetupError::InvalidArg(token_arg, Ty::String)),
+        };
+    }
+    let app_id = match section.lookup("app_id") {
+        Some(app_id) => match app_id.as_integer() {
+            Some(app_id) if app_id > 0 => app_id as u64,
+            _ => return Err(SetupError::InvalidArg(app_id_arg, Ty::Integer)),
+        },
+        None => return Ok(None),
+    };
+    let path = match section.lookup("private_key") {
+        Some(path) => match path.as_str() {
+            Some(path) => path,
+            None => {
+                return Err(SetupError::InvalidArg(private_key_arg, Ty::String))
+            }
+        },
+        None => return Err(SetupError::NotFoundArg(private_key_arg)),
+    };
+    let mut key = Vec::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_end(&mut key)) {
+        warn!("Failed to read GitHub App private key {}: {:?}", path, e);
+        return Err(SetupError::InvalidArg(private_key_arg, Ty::String));
+    }
+    Ok(Some(github_auth::Credentials::App(app_id, key)))
+}
+
 fn setup_view(
     config: &toml::Value,
-    pipelines: StaticViewPipelinesConfig
+    pipelines: Shared<StaticViewPipelinesConfig>
 ) -> Result<view::Worker, SetupError<ViewArg>> {
     let auth = if let Some(auth) = config.lookup("view.auth") {
         if auth.as_table().is_none() {
@@ -623,6 +2172,14 @@ fn setup_view(
     } else {
         view::Auth::None
     };
+    let api_token = match config.lookup("view.api_token") {
+        Some(&toml::Value::String(ref api_token)) => Some(api_token.clone()),
+        Some(_) => return Err(SetupError::InvalidArg(
+            ViewArg::ApiToken,
+            Ty::String
+        )),
+        None => None,
+    };
     Ok(view::Worker::new(
         toml_arg!(config, "view", "listen", String, ViewArg::Listen),
         db::Builder::from_str(
@@ -632,6 +2189,7 @@ fn setup_view(
         Box::new(pipelines),
         toml_arg!(config, "view", "secret", String, ViewArg::Secret),
         auth,
+        api_token,
     ))
 }
 
@@ -645,105 +2203,301 @@ impl StaticPipelinesConfig {
     }
 }
 
-impl PipelinesConfig for StaticPipelinesConfig {
-    fn by_pipeline_id(&self, id: PipelineId) -> PipelineConfig {
-        for cfg in &self.0 {
-            if cfg.pipeline_id == id {
-                return cfg.clone();
-            }
-        }
-        panic!("Invalid pipeline ID: {:?}", id);
-    }
-    fn by_ci_id(&self, id: CiId) -> PipelineConfig {
-        for cfg in &self.0 {
-            for &ci in &cfg.ci {
-                if ci.0 == id {
-                    return cfg.clone();
-                }
-            }
-        }
-        panic!("Invalid ci ID: {:?}", id);
+impl PipelinesConfig for StaticPipelinesConfig {
+    fn by_pipeline_id(&self, id: PipelineId) -> Option<PipelineConfig> {
+        for cfg in &self.0 {
+            if cfg.pipeline_id == id {
+                return Some(cfg.clone());
+            }
+        }
+        None
+    }
+    fn by_ci_id(&self, id: CiId) -> Option<PipelineConfig> {
+        for cfg in &self.0 {
+            for &ci in &cfg.ci {
+                if ci.0 == id {
+                    return Some(cfg.clone());
+                }
+            }
+        }
+        None
+    }
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// The pipelines for each of a front-end's repos, one set for each
+/// target branch.
+struct StaticProjectsConfig<R, P>(HashMap<R, Vec<P>>);
+
+type StaticGithubProjectsConfig =
+    StaticProjectsConfig<github::Repo, github::RepoPipelines>;
+type StaticGitlabProjectsConfig =
+    StaticProjectsConfig<gitlab::Repo, ui::RepoPipelines>;
+type StaticGiteaProjectsConfig =
+    StaticProjectsConfig<gitea::Repo, ui::RepoPipelines>;
+
+impl<R, P> StaticProjectsConfig<R, P>
+        where R: Clone + Eq + Hash, P: Clone + ui::TargetPipelines {
+    fn new() -> Self {
+        StaticProjectsConfig(HashMap::new())
+    }
+    fn by_repo(&self, repo: &R) -> Vec<P> {
+        self.0.get(repo).map(Clone::clone).unwrap_or(Vec::new())
+    }
+    fn by_pipeline(&self, pipeline_id: PipelineId)
+            -> Option<(R, ui::PipelineType)> {
+        for (repo, all_pipelines) in self.0.iter() {
+            for pipelines in all_pipelines {
+                if pipelines.pipeline_id() == pipeline_id {
+                    return Some((repo.clone(), ui::PipelineType::Stage));
+                }
+                if pipelines.try_pipeline_id() == Some(pipeline_id) {
+                    return Some((repo.clone(), ui::PipelineType::Try));
+                }
+            }
+        }
+        return None;
+    }
+}
+
+impl<R: Eq + Hash> StaticProjectsConfig<R, ui::RepoPipelines> {
+    /// Add a target branch's pipelines to a repo, for the front-ends
+    /// that keep nothing else for it. `front_end` names the table with
+    /// the front-end's settings, like `"gitlab"`.
+    fn add_pipelines(
+        &mut self,
+        repo: R,
+        def: &toml::Value,
+        front_end: &str,
+        target: &Target,
+        pipeline_id: PipelineId,
+        try_pipeline_id: Option<PipelineId>,
+    ) {
+        self.0.entry(repo).or_insert_with(Vec::new).push(
+            ui::RepoPipelines{
+                branch: Some(target.branch.clone()),
+                pipeline_id: pipeline_id,
+                try_pipeline_id: try_pipeline_id,
+                staging_branches: staging_branches(
+                    def,
+                    front_end,
+                    target,
+                    try_pipeline_id.is_some(),
+                ),
+            }
+        );
+    }
+}
+
+impl StaticGithubProjectsConfig {
+    fn add_project(
+        &mut self,
+        name: &str,
+        config: &toml::Value,
+        def: &toml::Value,
+        target: &Target,
+        pipeline_id: PipelineId,
+        try_pipeline_id: Option<PipelineId>,
+    ) -> Result<(), SetupError<GithubProjectArg>> {
+        let repo = github::Repo{
+            owner: toml_arg_default!(
+                config,
+                "github",
+                "owner",
+                String,
+                GithubProjectArg::Owner,
+                toml_arg!(
+                    def,
+                    "github",
+                    "owner",
+                    String,
+                    GithubProjectArg::Owner
+                )
+            ),
+            repo: toml_arg_default!(
+                def,
+                "github",
+                "repo",
+                String,
+                GithubProjectArg::Repo,
+                name
+            )
+        };
+        self.0.entry(repo).or_insert_with(Vec::new).push(
+            github::RepoPipelines{
+                branch: Some(target.branch.clone()),
+                pipeline_id: pipeline_id,
+                try_pipeline_id: try_pipeline_id,
+                review_approval: match def.lookup("github.review_approval") {
+                    Some(&toml::Value::Boolean(review_approval)) =>
+                        review_approval,
+                    Some(_) => return Err(SetupError::InvalidArg(
+                        GithubProjectArg::ReviewApproval,
+                        Ty::Boolean,
+                    )),
+                    None => false,
+                },
+                labels: github::Labels{
+                    approved: try!(github_label(def, "approved")),
+                    testing: try!(github_label(def, "testing")),
+                    failed: try!(github_label(def, "failed")),
+                    merge_conflict: try!(github_label(def, "merge_conflict")),
+                },
+            }
+        );
+        Ok(())
+    }
+}
+
+/// Read one of the labels in `[projects.NAME.github.labels]`.
+fn github_label(
+    def: &toml::Value,
+    state: &str
+) -> Result<Option<String>, SetupError<GithubProjectArg>> {
+    match def.lookup(&format!("github.labels.{}", state)) {
+        Some(&toml::Value::String(ref label)) => Ok(Some(label.clone())),
+        Some(_) => Err(SetupError::InvalidArg(
+            GithubProjectArg::Labels,
+            Ty::String,
+        )),
+        None => Ok(None),
+    }
+}
+
+impl github::ProjectsConfig for StaticGithubProjectsConfig {
+    fn pipelines_by_repo(
+        &self,
+        repo: &github::Repo
+    ) -> Vec<github::RepoPipelines> {
+        self.by_repo(repo)
+    }
+    fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+            -> Option<(github::Repo, github::PipelineType)> {
+        self.by_pipeline(pipeline_id)
+    }
+}
+
+impl StaticGitlabProjectsConfig {
+    fn add_project(
+        &mut self,
+        name: &str,
+        config: &toml::Value,
+        def: &toml::Value,
+        target: &Target,
+        pipeline_id: PipelineId,
+        try_pipeline_id: Option<PipelineId>,
+    ) -> Result<(), SetupError<GitlabProjectArg>> {
+        let namespace = toml_arg_default!(
+            def,
+            "gitlab",
+            "namespace",
+            String,
+            GitlabProjectArg::Namespace,
+            toml_arg!(
+                config,
+                "gitlab",
+                "namespace",
+                String,
+                GitlabProjectArg::Namespace
+            )
+        );
+        let project = toml_arg_default!(
+            def,
+            "gitlab",
+            "project",
+            String,
+            GitlabProjectArg::Project,
+            name
+        );
+        let repo = gitlab::Repo{
+            path: format!("{}/{}", namespace, project),
+        };
+        self.add_pipelines(
+            repo,
+            def,
+            "gitlab",
+            target,
+            pipeline_id,
+            try_pipeline_id,
+        );
+        Ok(())
+    }
+}
+
+impl gitlab::ProjectsConfig for StaticGitlabProjectsConfig {
+    fn pipelines_by_repo(
+        &self,
+        repo: &gitlab::Repo
+    ) -> Vec<ui::RepoPipelines> {
+        self.by_repo(repo)
     }
-    fn len(&self) -> usize {
-        self.0.len()
+    fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+            -> Option<(gitlab::Repo, gitlab::PipelineType)> {
+        self.by_pipeline(pipeline_id)
     }
 }
 
-struct StaticGithubProjectsConfig(
-    HashMap<github::Repo, github::RepoPipelines>
-);
-
-impl StaticGithubProjectsConfig {
-    fn new() -> Self {
-        StaticGithubProjectsConfig(HashMap::new())
-    }
+impl StaticGiteaProjectsConfig {
     fn add_project(
         &mut self,
         name: &str,
         config: &toml::Value,
         def: &toml::Value,
-        pipeline_id: PipelineId
-    ) -> Result<(), SetupError<GithubProjectArg>> {
-        self.0.insert(
-            github::Repo{
-                owner: toml_arg_default!(
+        target: &Target,
+        pipeline_id: PipelineId,
+        try_pipeline_id: Option<PipelineId>,
+    ) -> Result<(), SetupError<GiteaProjectArg>> {
+        let repo = gitea::Repo{
+            owner: toml_arg_default!(
+                def,
+                "gitea",
+                "owner",
+                String,
+                GiteaProjectArg::Owner,
+                toml_arg!(
                     config,
-                    "github",
+                    "gitea",
                     "owner",
                     String,
-                    GithubProjectArg::Owner,
-                    toml_arg!(
-                        def,
-                        "github",
-                        "owner",
-                        String,
-                        GithubProjectArg::Owner
-                    )
-                ),
-                repo: toml_arg_default!(
-                    def,
-                    "github",
-                    "repo",
-                    String,
-                    GithubProjectArg::Repo,
-                    name
+                    GiteaProjectArg::Owner
                 )
-            },
-            github::RepoPipelines{
-                pipeline_id: pipeline_id,
-                try_pipeline_id: if def.lookup("try").is_some() {
-                    Some(PipelineId(pipeline_id.0 + 1))
-                } else {
-                    None
-                },
-            }
+            ),
+            repo: toml_arg_default!(
+                def,
+                "gitea",
+                "repo",
+                String,
+                GiteaProjectArg::Repo,
+                name
+            ),
+        };
+        self.add_pipelines(
+            repo,
+            def,
+            "gitea",
+            target,
+            pipeline_id,
+            try_pipeline_id,
         );
         Ok(())
     }
 }
 
-impl github::ProjectsConfig for StaticGithubProjectsConfig {
+impl gitea::ProjectsConfig for StaticGiteaProjectsConfig {
     fn pipelines_by_repo(
         &self,
-        repo: &github::Repo
-    ) -> Option<github::RepoPipelines> {
-        self.0.get(repo).map(Clone::clone)
+        repo: &gitea::Repo
+    ) -> Vec<ui::RepoPipelines> {
+        self.by_repo(repo)
     }
     fn repo_by_pipeline(&self, pipeline_id: PipelineId)
-            -> Option<(github::Repo, github::PipelineType)> {
-        for (repo, pipelines) in self.0.iter() {
-            if pipelines.pipeline_id == pipeline_id {
-                return Some((repo.clone(), github::PipelineType::Stage));
-            }
-            if pipelines.try_pipeline_id == Some(pipeline_id) {
-                return Some((repo.clone(), github::PipelineType::Try));
-            }
-        }
-        return None;
+            -> Option<(gitea::Repo, gitea::PipelineType)> {
+        self.by_pipeline(pipeline_id)
     }
 }
 
-
 struct StaticGithubStatusPipelinesConfig(
     HashMap<CiId, github_status::Repo>
 );
@@ -758,8 +2512,8 @@ impl StaticGithubStatusPipelinesConfig {
         config: &toml::Value,
         def: &toml::Value,
         pipeline_id: PipelineId,
-        ci_id: &mut CiId,
-        ci_to_pipeline: &mut HashMap<CiId, (CiType, PipelineId)>,
+        ids: &mut Ids,
+        ci_to_pipeline: &mut HashMap<CiId, (CiType, PipelineId, String)>,
     ) -> Result<(), SetupError<GithubStatusProjectArg>> {
         match def.lookup("github.status") {
             Some(gh) => match gh {
@@ -770,7 +2524,7 @@ impl StaticGithubStatusPipelinesConfig {
                         def,
                         context,
                         pipeline_id,
-                        ci_id,
+                        ids,
                         ci_to_pipeline,
                     )
                 }
@@ -783,7 +2537,7 @@ impl StaticGithubStatusPipelinesConfig {
                                 def,
                                 context,
                                 pipeline_id,
-                                ci_id,
+                                ids,
                                 ci_to_pipeline,
                             ))
                         } else {
@@ -807,8 +2561,8 @@ impl StaticGithubStatusPipelinesConfig {
         def: &toml::Value,
         context: &str,
         pipeline_id: PipelineId,
-        ci_id: &mut CiId,
-        ci_to_pipeline: &mut HashMap<CiId, (CiType, PipelineId)>,
+        ids: &mut Ids,
+        ci_to_pipeline: &mut HashMap<CiId, (CiType, PipelineId, String)>,
     ) -> Result<(), SetupError<GithubStatusProjectArg>> {
         let repo = github_status::Repo{
             owner: toml_arg_default!(
@@ -835,9 +2589,12 @@ impl StaticGithubStatusPipelinesConfig {
             ),
             context: context.to_owned(),
         };
-        self.0.entry(*ci_id).or_insert(repo);
-        ci_to_pipeline.insert(*ci_id, (CiType::GithubStatus, pipeline_id));
-        ci_id.0 += 1;
+        let ci_id = ids.ci_id(pipeline_id, CiType::GithubStatus, context);
+        self.0.entry(ci_id).or_insert(repo);
+        ci_to_pipeline.insert(
+            ci_id,
+            (CiType::GithubStatus, pipeline_id, context.to_owned()),
+        );
         Ok(())
     }
 }
@@ -876,8 +2633,8 @@ impl StaticJenkinsPipelinesConfig {
         config: &toml::Value,
         def: &toml::Value,
         pipeline_id: PipelineId,
-        ci_id: &mut CiId,
-        ci_to_pipeline: &mut HashMap<CiId, (CiType, PipelineId)>,
+        ids: &mut Ids,
+        ci_to_pipeline: &mut HashMap<CiId, (CiType, PipelineId, String)>,
     ) -> Result<(), SetupError<JenkinsProjectArg>> {
         match def.lookup("jenkins") {
             Some(gh) => match gh {
@@ -888,7 +2645,7 @@ impl StaticJenkinsPipelinesConfig {
                         def,
                         jenkins_def,
                         pipeline_id,
-                        ci_id,
+                        ids,
                         ci_to_pipeline,
                     )
                 }
@@ -900,7 +2657,7 @@ impl StaticJenkinsPipelinesConfig {
                             def,
                             jenkins_def,
                             pipeline_id,
-                            ci_id,
+                            ids,
                             ci_to_pipeline,
                         ))
                     }
@@ -918,8 +2675,8 @@ impl StaticJenkinsPipelinesConfig {
         _def: &toml::Value,
         jenkins_def: &toml::Value,
         pipeline_id: PipelineId,
-        ci_id: &mut CiId,
-        ci_to_pipeline: &mut HashMap<CiId, (CiType, PipelineId)>,
+        ids: &mut Ids,
+        ci_to_pipeline: &mut HashMap<CiId, (CiType, PipelineId, String)>,
     ) -> Result<(), SetupError<JenkinsProjectArg>> {
         let job = jenkins::Job{
             name: toml_arg_default!(
@@ -938,9 +2695,12 @@ impl StaticJenkinsPipelinesConfig {
                 JenkinsProjectArg::Token
             ),
         };
-        self.0.entry(*ci_id).or_insert(job);
-        ci_to_pipeline.insert(*ci_id, (CiType::Jenkins, pipeline_id));
-        ci_id.0 += 1;
+        let ci_id = ids.ci_id(pipeline_id, CiType::Jenkins, &job.name);
+        ci_to_pipeline.insert(
+            ci_id,
+            (CiType::Jenkins, pipeline_id, job.name.clone()),
+        );
+        self.0.entry(ci_id).or_insert(job);
         Ok(())
     }
 }
@@ -962,61 +2722,303 @@ impl jenkins::PipelinesConfig for StaticJenkinsPipelinesConfig {
 }
 
 
+struct StaticWebhookPipelinesConfig(
+    HashMap<CiId, webhook::Job>
+);
+
+impl StaticWebhookPipelinesConfig {
+    fn new() -> Self {
+        StaticWebhookPipelinesConfig(HashMap::new())
+    }
+    fn add_pipeline(
+        &mut self,
+        name: &str,
+        config: &toml::Value,
+        def: &toml::Value,
+        pipeline_id: PipelineId,
+        ids: &mut Ids,
+        ci_to_pipeline: &mut HashMap<CiId, (CiType, PipelineId, String)>,
+    ) -> Result<(), SetupError<WebhookProjectArg>> {
+        match def.lookup("webhook") {
+            Some(wh) => match wh {
+                webhook_def @ &toml::Value::Table(_) => {
+                    self.add_item(
+                        name,
+                        config,
+                        def,
+                        webhook_def,
+                        pipeline_id,
+                        ids,
+                        ci_to_pipeline,
+                    )
+                }
+                &toml::Value::Array(ref webhook_defs) => {
+                    for webhook_def in webhook_defs {
+                        try!(self.add_item(
+                            name,
+                            config,
+                            def,
+                            webhook_def,
+                            pipeline_id,
+                            ids,
+                            ci_to_pipeline,
+                        ))
+                    }
+                    Ok(())
+                }
+                _ => Err(SetupError::NotTableConfig)
+            },
+            None => Err(SetupError::NotFoundConfig),
+        }
+    }
+    fn add_item(
+        &mut self,
+        name: &str,
+        _config: &toml::Value,
+        _def: &toml::Value,
+        webhook_def: &toml::Value,
+        pipeline_id: PipelineId,
+        ids: &mut Ids,
+        ci_to_pipeline: &mut HashMap<CiId, (CiType, PipelineId, String)>,
+    ) -> Result<(), SetupError<WebhookProjectArg>> {
+        let token = match webhook_def.lookup("token") {
+            Some(&toml::Value::String(ref token)) => Some(token.clone()),
+            Some(_) => return Err(SetupError::InvalidArg(
+                WebhookProjectArg::Token,
+                Ty::String,
+            )),
+            None => None,
+        };
+        let job = webhook::Job{
+            name: toml_arg_default!(
+                webhook_def,
+                "",
+                "name",
+                String,
+                WebhookProjectArg::Name,
+                name
+            ),
+            url: toml_arg!(
+                webhook_def,
+                "",
+                "url",
+                String,
+                WebhookProjectArg::Url
+            ),
+            body: toml_arg_default!(
+                webhook_def,
+                "",
+                "body",
+                String,
+                WebhookProjectArg::Body,
+                r#"{"commit":"{{commit}}","name":"{{name}}"}"#
+            ),
+            token: token,
+        };
+        let ci_id = ids.ci_id(pipeline_id, CiType::Webhook, &job.name);
+        ci_to_pipeline.insert(
+            ci_id,
+            (CiType::Webhook, pipeline_id, job.name.clone()),
+        );
+        self.0.entry(ci_id).or_insert(job);
+        Ok(())
+    }
+}
+
+impl webhook::PipelinesConfig for StaticWebhookPipelinesConfig {
+    fn job_by_id(&self, id: CiId)
+            -> Option<webhook::Job> {
+        return self.0.get(&id).map(Clone::clone)
+    }
+    fn ids_by_job_name(&self, job_name: &str) -> Vec<CiId> {
+        let mut ret_val = vec![];
+        for (id, i_job) in self.0.iter() {
+            if job_name == i_job.name {
+                ret_val.push(*id)
+            }
+        }
+        ret_val
+    }
+}
+
+
 struct StaticGitPipelinesConfig(
     HashMap<PipelineId, git::Repo>
 );
 
-impl StaticGitPipelinesConfig {
+impl StaticGitPipelinesConfig {
+    fn new() -> Self {
+        StaticGitPipelinesConfig(HashMap::new())
+    }
+    fn add_pipeline(
+        &mut self,
+        name: &str,
+        config: &toml::Value,
+        def: &toml::Value,
+        target: &Target,
+        pipeline_id: PipelineId,
+        is_try: bool,
+    ) -> Result<(), SetupError<GitProjectArg>> {
+        let repo = git::Repo{
+            path: toml_arg_default!(
+                config,
+                "git",
+                "path",
+                String,
+                GitProjectArg::Path,
+                "cache/"
+            ) + &toml_arg_default!(
+                def,
+                "git",
+                "path",
+                String,
+                GitProjectArg::Path,
+                name
+            ),
+            origin: toml_arg!(
+                def,
+                "git",
+                "origin",
+                String,
+                GitProjectArg::Origin
+            ),
+            master_branch: target.branch.clone(),
+            staging_branch: target.staging_branch(toml_arg_default!(
+                def,
+                if is_try { "try.git" } else { "git" },
+                if is_try { "branch" } else { "staging_branch" },
+                String,
+                GitProjectArg::StagingBranch,
+                if is_try { "trying" } else { "staging" }
+            )),
+            push_to_master: !is_try
+        };
+        self.0.entry(pipeline_id).or_insert(repo);
+        Ok(())
+    }
+}
+
+impl git::PipelinesConfig for StaticGitPipelinesConfig {
+    fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+            -> Option<git::Repo> {
+        return self.0.get(&pipeline_id).map(Clone::clone)
+    }
+}
+
+
+struct StaticGithubGitPipelinesConfig(
+    HashMap<PipelineId, github_git::Repo>
+);
+
+impl StaticGithubGitPipelinesConfig {
+    fn new() -> Self {
+        StaticGithubGitPipelinesConfig(HashMap::new())
+    }
+    fn add_pipeline(
+        &mut self,
+        name: &str,
+        config: &toml::Value,
+        def: &toml::Value,
+        target: &Target,
+        pipeline_id: PipelineId,
+        is_try: bool,
+    ) -> Result<(), SetupError<GithubGitProjectArg>> {
+        let repo = github_git::Repo{
+            owner: toml_arg_default!(
+                config,
+                "github",
+                "owner",
+                String,
+                GithubGitProjectArg::Owner,
+                toml_arg!(
+                    def,
+                    "github",
+                    "owner",
+                    String,
+                    GithubGitProjectArg::Owner
+                )
+            ),
+            repo: toml_arg_default!(
+                def,
+                "github",
+                "repo",
+                String,
+                GithubGitProjectArg::Repo,
+                name
+            ),
+            master_branch: target.branch.clone(),
+            staging_branch: target.staging_branch(toml_arg_default!(
+                def,
+                if is_try { "try.github" } else { "github" },
+                if is_try { "branch" } else { "staging_branch" },
+                String,
+                GithubGitProjectArg::StagingBranch,
+                if is_try { "trying" } else { "staging" }
+            )),
+            push_to_master: !is_try
+        };
+        self.0.entry(pipeline_id).or_insert(repo);
+        Ok(())
+    }
+}
+
+impl github_git::PipelinesConfig for StaticGithubGitPipelinesConfig {
+    fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+            -> Option<github_git::Repo> {
+        return self.0.get(&pipeline_id).map(Clone::clone)
+    }
+}
+
+struct StaticGitlabGitPipelinesConfig(
+    HashMap<PipelineId, gitlab_git::Repo>
+);
+
+impl StaticGitlabGitPipelinesConfig {
     fn new() -> Self {
-        StaticGitPipelinesConfig(HashMap::new())
+        StaticGitlabGitPipelinesConfig(HashMap::new())
     }
     fn add_pipeline(
         &mut self,
         name: &str,
         config: &toml::Value,
         def: &toml::Value,
+        target: &Target,
         pipeline_id: PipelineId,
         is_try: bool,
-    ) -> Result<(), SetupError<GitProjectArg>> {
-        let repo = git::Repo{
-            path: toml_arg_default!(
+    ) -> Result<(), SetupError<GitlabGitProjectArg>> {
+        let namespace = toml_arg_default!(
+            def,
+            "gitlab",
+            "namespace",
+            String,
+            GitlabGitProjectArg::Namespace,
+            toml_arg!(
                 config,
-                "git",
-                "path",
-                String,
-                GitProjectArg::Path,
-                "cache/"
-            ) + &toml_arg_default!(
-                def,
-                "git",
-                "path",
-                String,
-                GitProjectArg::Path,
-                name
-            ),
-            origin: toml_arg!(
-                def,
-                "git",
-                "origin",
-                String,
-                GitProjectArg::Origin
-            ),
-            master_branch: toml_arg_default!(
-                def,
-                "git",
-                "master_branch",
+                "gitlab",
+                "namespace",
                 String,
-                GitProjectArg::MasterBranch,
-                "master"
-            ),
-            staging_branch: toml_arg_default!(
+                GitlabGitProjectArg::Namespace
+            )
+        );
+        let project = toml_arg_default!(
+            def,
+            "gitlab",
+            "project",
+            String,
+            GitlabGitProjectArg::Project,
+            name
+        );
+        let repo = gitlab_git::Repo{
+            path: format!("{}/{}", namespace, project),
+            master_branch: target.branch.clone(),
+            staging_branch: target.staging_branch(toml_arg_default!(
                 def,
-                if is_try { "try.git" } else { "git" },
+                if is_try { "try.gitlab" } else { "gitlab" },
                 if is_try { "branch" } else { "staging_branch" },
                 String,
-                GitProjectArg::StagingBranch,
+                GitlabGitProjectArg::StagingBranch,
                 if is_try { "trying" } else { "staging" }
-            ),
+            )),
             push_to_master: !is_try
         };
         self.0.entry(pipeline_id).or_insert(repo);
@@ -1024,69 +3026,62 @@ impl StaticGitPipelinesConfig {
     }
 }
 
-impl git::PipelinesConfig for StaticGitPipelinesConfig {
+impl gitlab_git::PipelinesConfig for StaticGitlabGitPipelinesConfig {
     fn repo_by_pipeline(&self, pipeline_id: PipelineId)
-            -> Option<git::Repo> {
+            -> Option<gitlab_git::Repo> {
         return self.0.get(&pipeline_id).map(Clone::clone)
     }
 }
 
-
-struct StaticGithubGitPipelinesConfig(
-    HashMap<PipelineId, github_git::Repo>
+struct StaticGiteaGitPipelinesConfig(
+    HashMap<PipelineId, gitea_git::Repo>
 );
 
-impl StaticGithubGitPipelinesConfig {
+impl StaticGiteaGitPipelinesConfig {
     fn new() -> Self {
-        StaticGithubGitPipelinesConfig(HashMap::new())
+        StaticGiteaGitPipelinesConfig(HashMap::new())
     }
     fn add_pipeline(
         &mut self,
         name: &str,
         config: &toml::Value,
         def: &toml::Value,
+        target: &Target,
         pipeline_id: PipelineId,
         is_try: bool,
-    ) -> Result<(), SetupError<GithubGitProjectArg>> {
-        let repo = github_git::Repo{
+    ) -> Result<(), SetupError<GiteaGitProjectArg>> {
+        let repo = gitea_git::Repo{
             owner: toml_arg_default!(
-                config,
-                "github",
+                def,
+                "gitea",
                 "owner",
                 String,
-                GithubGitProjectArg::Owner,
+                GiteaGitProjectArg::Owner,
                 toml_arg!(
-                    def,
-                    "github",
+                    config,
+                    "gitea",
                     "owner",
                     String,
-                    GithubGitProjectArg::Owner
+                    GiteaGitProjectArg::Owner
                 )
             ),
             repo: toml_arg_default!(
                 def,
-                "github",
+                "gitea",
                 "repo",
                 String,
-                GithubGitProjectArg::Repo,
+                GiteaGitProjectArg::Repo,
                 name
             ),
-            master_branch: toml_arg_default!(
-                def,
-                "github",
-                "master_branch",
-                String,
-                GithubGitProjectArg::MasterBranch,
-                "master"
-            ),
-            staging_branch: toml_arg_default!(
+            master_branch: target.branch.clone(),
+            staging_branch: target.staging_branch(toml_arg_default!(
                 def,
-                if is_try { "try.github" } else { "github" },
+                if is_try { "try.gitea" } else { "gitea" },
                 if is_try { "branch" } else { "staging_branch" },
                 String,
-                GithubGitProjectArg::StagingBranch,
+                GiteaGitProjectArg::StagingBranch,
                 if is_try { "trying" } else { "staging" }
-            ),
+            )),
             push_to_master: !is_try
         };
         self.0.entry(pipeline_id).or_insert(repo);
@@ -1094,18 +3089,26 @@ impl StaticGithubGitPipelinesConfig {
     }
 }
 
-impl github_git::PipelinesConfig for StaticGithubGitPipelinesConfig {
+impl gitea_git::PipelinesConfig for StaticGiteaGitPipelinesConfig {
     fn repo_by_pipeline(&self, pipeline_id: PipelineId)
-            -> Option<github_git::Repo> {
+            -> Option<gitea_git::Repo> {
         return self.0.get(&pipeline_id).map(Clone::clone)
     }
 }
 
-struct StaticViewPipelinesConfig(HashMap<String, PipelineId>);
+struct StaticViewPipelinesConfig{
+    pipelines: HashMap<String, PipelineId>,
+    cis: HashMap<PipelineId, Vec<(CiId, String)>>,
+    problems: HashMap<PipelineId, Vec<String>>,
+}
 
 impl StaticViewPipelinesConfig {
     fn new() -> Self {
-        StaticViewPipelinesConfig(HashMap::new())
+        StaticViewPipelinesConfig{
+            pipelines: HashMap::new(),
+            cis: HashMap::new(),
+            problems: HashMap::new(),
+        }
     } 
     fn add_pipeline(
         &mut self,
@@ -1114,17 +3117,260 @@ impl StaticViewPipelinesConfig {
         _def: &toml::Value,
         pipeline_id: PipelineId,
     ) -> Result<(), SetupError<ViewProjectArg>> {
-        self.0.insert(name.to_owned(), pipeline_id);
+        self.pipelines.insert(name.to_owned(), pipeline_id);
         Ok(())
     }
+    fn add_ci(&mut self, pipeline_id: PipelineId, ci_id: CiId, name: &str) {
+        self.cis.entry(pipeline_id)
+            .or_insert_with(Vec::new)
+            .push((ci_id, name.to_owned()));
+    }
+    fn add_problems(&mut self, pipeline_id: PipelineId, problems: &[String]) {
+        self.problems.entry(pipeline_id)
+            .or_insert_with(Vec::new)
+            .extend(problems.iter().cloned());
+    }
 }
 
 impl view::PipelinesConfig for StaticViewPipelinesConfig {
     fn pipeline_by_name(&self, name: &str) -> Option<PipelineId> {
-        self.0.get(name).map(|x| *x)
+        self.pipelines.get(name).map(|x| *x)
+    }
+    fn all(&self) -> Vec<(Cow<str>, PipelineId)> {
+        self.pipelines.iter()
+            .map(|x| (Cow::Borrowed(&x.0[..]), *x.1))
+            .collect()
+    }
+    fn cis_by_pipeline(&self, pipeline_id: PipelineId)
+            -> Vec<(CiId, Cow<str>)>
+    {
+        self.cis.get(&pipeline_id).map(|cis| {
+            cis.iter().map(|x| (x.0, Cow::Borrowed(&x.1[..]))).collect()
+        }).unwrap_or(Vec::new())
+    }
+    fn problems(&self, pipeline_id: PipelineId) -> Vec<Cow<str>> {
+        self.problems.get(&pipeline_id).map(|problems| {
+            problems.iter().map(|x| Cow::Borrowed(&x[..])).collect()
+        }).unwrap_or(Vec::new())
+    }
+}
+
+// Sharing the configs with the workers, so they can be reloaded.
+
+/// A config that the workers read, and the reloader replaces.
+struct Shared<T>(Arc<RwLock<T>>);
+
+impl<T> Shared<T> {
+    fn new(value: T) -> Self {
+        Shared(Arc::new(RwLock::new(value)))
+    }
+    fn read(&self) -> RwLockReadGuard<T> {
+        self.0.read().expect("config lock to not be poisoned")
+    }
+    fn replace(&self, value: T) {
+        *self.0.write().expect("config lock to not be poisoned") = value;
+    }
+}
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Shared(self.0.clone())
+    }
+}
+
+/// Every config handed to a worker.
+#[derive(Clone)]
+struct SharedConfigs {
+    pipelines: Shared<StaticPipelinesConfig>,
+    github_projects: Shared<StaticGithubProjectsConfig>,
+    gitlab_projects: Shared<StaticGitlabProjectsConfig>,
+    gitea_projects: Shared<StaticGiteaProjectsConfig>,
+    github_status_pipelines: Shared<StaticGithubStatusPipelinesConfig>,
+    jenkins_pipelines: Shared<StaticJenkinsPipelinesConfig>,
+    webhook_pipelines: Shared<StaticWebhookPipelinesConfig>,
+    git_pipelines: Shared<StaticGitPipelinesConfig>,
+    github_git_pipelines: Shared<StaticGithubGitPipelinesConfig>,
+    gitlab_git_pipelines: Shared<StaticGitlabGitPipelinesConfig>,
+    gitea_git_pipelines: Shared<StaticGiteaGitPipelinesConfig>,
+    view_pipelines: Shared<StaticViewPipelinesConfig>,
+}
+
+impl SharedConfigs {
+    fn new() -> Self {
+        SharedConfigs{
+            pipelines: Shared::new(StaticPipelinesConfig::new()),
+            github_projects: Shared::new(StaticGithubProjectsConfig::new()),
+            gitlab_projects: Shared::new(StaticGitlabProjectsConfig::new()),
+            gitea_projects: Shared::new(StaticGiteaProjectsConfig::new()),
+            github_status_pipelines:
+                Shared::new(StaticGithubStatusPipelinesConfig::new()),
+            jenkins_pipelines:
+                Shared::new(StaticJenkinsPipelinesConfig::new()),
+            webhook_pipelines:
+                Shared::new(StaticWebhookPipelinesConfig::new()),
+            git_pipelines: Shared::new(StaticGitPipelinesConfig::new()),
+            github_git_pipelines:
+                Shared::new(StaticGithubGitPipelinesConfig::new()),
+            gitlab_git_pipelines:
+                Shared::new(StaticGitlabGitPipelinesConfig::new()),
+            gitea_git_pipelines:
+                Shared::new(StaticGiteaGitPipelinesConfig::new()),
+            view_pipelines: Shared::new(StaticViewPipelinesConfig::new()),
+        }
+    }
+    /// Hand new configs to the workers, except for `pipelines`,
+    /// which is built after the workers are.
+    fn replace(&self, workers: WorkerConfigs) {
+        self.github_projects.replace(workers.github_projects);
+        self.gitlab_projects.replace(workers.gitlab_projects);
+        self.gitea_projects.replace(workers.gitea_projects);
+        self.github_status_pipelines.replace(workers.github_status_pipelines);
+        self.jenkins_pipelines.replace(workers.jenkins_pipelines);
+        self.webhook_pipelines.replace(workers.webhook_pipelines);
+        self.git_pipelines.replace(workers.git_pipelines);
+        self.github_git_pipelines.replace(workers.github_git_pipelines);
+        self.gitlab_git_pipelines.replace(workers.gitlab_git_pipelines);
+        self.gitea_git_pipelines.replace(workers.gitea_git_pipelines);
+        self.view_pipelines.replace(workers.view_pipelines);
+    }
+}
+
+impl PipelinesConfig for Shared<StaticPipelinesConfig> {
+    fn by_pipeline_id(&self, id: PipelineId) -> Option<PipelineConfig> {
+        self.read().by_pipeline_id(id)
+    }
+    fn by_ci_id(&self, id: CiId) -> Option<PipelineConfig> {
+        self.read().by_ci_id(id)
+    }
+    fn len(&self) -> usize {
+        self.read().len()
+    }
+}
+
+impl github::ProjectsConfig for Shared<StaticGithubProjectsConfig> {
+    fn pipelines_by_repo(
+        &self,
+        repo: &github::Repo
+    ) -> Vec<github::RepoPipelines> {
+        github::ProjectsConfig::pipelines_by_repo(&*self.read(), repo)
+    }
+    fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+            -> Option<(github::Repo, github::PipelineType)> {
+        github::ProjectsConfig::repo_by_pipeline(&*self.read(), pipeline_id)
+    }
+}
+
+impl gitlab::ProjectsConfig for Shared<StaticGitlabProjectsConfig> {
+    fn pipelines_by_repo(
+        &self,
+        repo: &gitlab::Repo
+    ) -> Vec<ui::RepoPipelines> {
+        gitlab::ProjectsConfig::pipelines_by_repo(&*self.read(), repo)
+    }
+    fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+            -> Option<(gitlab::Repo, gitlab::PipelineType)> {
+        gitlab::ProjectsConfig::repo_by_pipeline(&*self.read(), pipeline_id)
+    }
+}
+
+impl gitea::ProjectsConfig for Shared<StaticGiteaProjectsConfig> {
+    fn pipelines_by_repo(
+        &self,
+        repo: &gitea::Repo
+    ) -> Vec<ui::RepoPipelines> {
+        gitea::ProjectsConfig::pipelines_by_repo(&*self.read(), repo)
+    }
+    fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+            -> Option<(gitea::Repo, gitea::PipelineType)> {
+        gitea::ProjectsConfig::repo_by_pipeline(&*self.read(), pipeline_id)
+    }
+}
+
+impl github_status::PipelinesConfig
+        for Shared<StaticGithubStatusPipelinesConfig> {
+    fn repo_by_id(&self, id: CiId) -> Option<github_status::Repo> {
+        github_status::PipelinesConfig::repo_by_id(&*self.read(), id)
+    }
+    fn ids_by_repo(&self, repo: &github_status::Repo) -> Vec<CiId> {
+        github_status::PipelinesConfig::ids_by_repo(&*self.read(), repo)
+    }
+}
+
+impl jenkins::PipelinesConfig for Shared<StaticJenkinsPipelinesConfig> {
+    fn job_by_id(&self, id: CiId) -> Option<jenkins::Job> {
+        jenkins::PipelinesConfig::job_by_id(&*self.read(), id)
+    }
+    fn ids_by_job_name(&self, job_name: &str) -> Vec<CiId> {
+        jenkins::PipelinesConfig::ids_by_job_name(&*self.read(), job_name)
+    }
+}
+
+impl webhook::PipelinesConfig for Shared<StaticWebhookPipelinesConfig> {
+    fn job_by_id(&self, id: CiId) -> Option<webhook::Job> {
+        webhook::PipelinesConfig::job_by_id(&*self.read(), id)
+    }
+    fn ids_by_job_name(&self, job_name: &str) -> Vec<CiId> {
+        webhook::PipelinesConfig::ids_by_job_name(&*self.read(), job_name)
+    }
+}
+
+impl git::PipelinesConfig for Shared<StaticGitPipelinesConfig> {
+    fn repo_by_pipeline(&self, pipeline_id: PipelineId) -> Option<git::Repo> {
+        git::PipelinesConfig::repo_by_pipeline(&*self.read(), pipeline_id)
+    }
+}
+
+impl github_git::PipelinesConfig for Shared<StaticGithubGitPipelinesConfig> {
+    fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+            -> Option<github_git::Repo> {
+        github_git::PipelinesConfig::repo_by_pipeline(
+            &*self.read(),
+            pipeline_id,
+        )
+    }
+}
+
+impl gitlab_git::PipelinesConfig for Shared<StaticGitlabGitPipelinesConfig> {
+    fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+            -> Option<gitlab_git::Repo> {
+        gitlab_git::PipelinesConfig::repo_by_pipeline(
+            &*self.read(),
+            pipeline_id,
+        )
+    }
+}
+
+impl gitea_git::PipelinesConfig for Shared<StaticGiteaGitPipelinesConfig> {
+    fn repo_by_pipeline(&self, pipeline_id: PipelineId)
+            -> Option<gitea_git::Repo> {
+        gitea_git::PipelinesConfig::repo_by_pipeline(&*self.read(), pipeline_id)
+    }
+}
+
+impl view::PipelinesConfig for Shared<StaticViewPipelinesConfig> {
+    fn pipeline_by_name(&self, name: &str) -> Option<PipelineId> {
+        view::PipelinesConfig::pipeline_by_name(&*self.read(), name)
     }
     fn all(&self) -> Vec<(Cow<str>, PipelineId)> {
-        self.0.iter().map(|x| (Cow::Borrowed(&x.0[..]), *x.1)).collect()
+        view::PipelinesConfig::all(&*self.read()).into_iter()
+            .map(|(name, pipeline_id)| {
+                (Cow::Owned(name.into_owned()), pipeline_id)
+            })
+            .collect()
+    }
+    fn cis_by_pipeline(&self, pipeline_id: PipelineId)
+            -> Vec<(CiId, Cow<str>)>
+    {
+        view::PipelinesConfig::cis_by_pipeline(&*self.read(), pipeline_id)
+            .into_iter()
+            .map(|(ci_id, name)| (ci_id, Cow::Owned(name.into_owned())))
+            .collect()
+    }
+    fn problems(&self, pipeline_id: PipelineId) -> Vec<Cow<str>> {
+        view::PipelinesConfig::problems(&*self.read(), pipeline_id)
+            .into_iter()
+            .map(|problem| Cow::Owned(problem.into_owned()))
+            .collect()
     }
 }
 
@@ -1140,6 +3386,10 @@ quick_error! {
             cause(err)
         }
         Parse {}
+        Secret(err: SecretError) {
+            cause(err)
+        }
+        Db(err: Box<Error + Send + Sync>) {}
         NoConfig {}
         NoProjects {}
         NoConfigGithub {}
@@ -1147,18 +3397,33 @@ quick_error! {
         Github(err: SetupError<GithubArg>) {
             cause(err)
         }
+        Gitlab(err: SetupError<GitlabArg>) {
+            cause(err)
+        }
+        Gitea(err: SetupError<GiteaArg>) {
+            cause(err)
+        }
         GithubStatus(err: SetupError<GithubStatusArg>) {
             cause(err)
         }
         Jenkins(err: SetupError<JenkinsArg>) {
             cause(err)
         }
+        Webhook(err: SetupError<WebhookArg>) {
+            cause(err)
+        }
         Git(err: SetupError<GitArg>) {
             cause(err)
         }
         GithubGit(err: SetupError<GithubGitArg>) {
             cause(err)
         }
+        GitlabGit(err: SetupError<GitlabGitArg>) {
+            cause(err)
+        }
+        GiteaGit(err: SetupError<GiteaGitArg>) {
+            cause(err)
+        }
         View(err: SetupError<ViewArg>) {
             cause(err)
         }
@@ -1168,18 +3433,33 @@ quick_error! {
         GithubProject(err: SetupError<GithubProjectArg>) {
             cause(err)
         }
+        GitlabProject(err: SetupError<GitlabProjectArg>) {
+            cause(err)
+        }
+        GiteaProject(err: SetupError<GiteaProjectArg>) {
+            cause(err)
+        }
         GithubStatusProject(err: SetupError<GithubStatusProjectArg>) {
             cause(err)
         }
         JenkinsProject(err: SetupError<JenkinsProjectArg>) {
             cause(err)
         }
+        WebhookProject(err: SetupError<WebhookProjectArg>) {
+            cause(err)
+        }
         GitProject(err: SetupError<GitProjectArg>) {
             cause(err)
         }
         GithubGitProject(err: SetupError<GithubGitProjectArg>) {
             cause(err)
         }
+        GitlabGitProject(err: SetupError<GitlabGitProjectArg>) {
+            cause(err)
+        }
+        GiteaGitProject(err: SetupError<GiteaGitProjectArg>) {
+            cause(err)
+        }
         ViewProject(err: SetupError<ViewProjectArg>) {
             cause(err)
         }
@@ -1202,15 +3482,41 @@ pub enum GithubArg {
     Listen,
     Host,
     Token,
+    AppId,
+    PrivateKey,
     User,
     Secret,
+    RequireSha256,
+    Checks,
+    StatusComment,
     Db,
+    CacheTtl,
+}
+
+#[derive(Debug)]
+pub enum GitlabArg {
+    Listen,
+    Host,
+    Token,
+    User,
+    Secret,
+}
+
+#[derive(Debug)]
+pub enum GiteaArg {
+    Listen,
+    Host,
+    Token,
+    User,
+    Secret,
 }
 
 #[derive(Debug)]
 pub enum GithubStatusArg {
     Listen,
     Secret,
+    RequireSha256,
+    Db,
 }
 
 #[derive(Debug)]
@@ -1220,17 +3526,48 @@ pub enum JenkinsArg {
     Token,
 }
 
+#[derive(Debug)]
+pub enum WebhookArg {
+    Listen,
+    Secret,
+    SignatureHeader,
+    Name,
+    Commit,
+    State,
+    Url,
+    Started,
+    Succeeded,
+    Failed,
+}
+
 #[derive(Debug)]
 pub enum GitArg {
     Executable,
     Name,
     Email,
+    Path,
 }
 
 #[derive(Debug)]
 pub enum GithubGitArg {
     Host,
     Token,
+    AppId,
+    PrivateKey,
+}
+
+#[derive(Debug)]
+pub enum GitlabGitArg {
+    Host,
+    Token,
+    Git(GitArg),
+}
+
+#[derive(Debug)]
+pub enum GiteaGitArg {
+    Host,
+    Token,
+    Git(GitArg),
 }
 
 #[derive(Debug)]
@@ -1242,17 +3579,36 @@ pub enum ViewArg {
     AuthGithubAppId,
     AuthGithubAppSecret,
     AuthGithubOrganization,
+    ApiToken,
 }
 
 #[derive(Debug)]
 pub enum ProjectArg {
     Project,
+    Branches,
+    MasterBranch,
+    Retry,
+    RetryOnlyFlaky,
 }
 
 #[derive(Debug)]
 pub enum GithubProjectArg {
     Owner,
     Repo,
+    ReviewApproval,
+    Labels,
+}
+
+#[derive(Debug)]
+pub enum GitlabProjectArg {
+    Namespace,
+    Project,
+}
+
+#[derive(Debug)]
+pub enum GiteaProjectArg {
+    Owner,
+    Repo,
 }
 
 #[derive(Debug)]
@@ -1268,11 +3624,18 @@ pub enum JenkinsProjectArg {
     Token,
 }
 
+#[derive(Debug)]
+pub enum WebhookProjectArg {
+    Name,
+    Url,
+    Body,
+    Token,
+}
+
 #[derive(Debug)]
 pub enum GitProjectArg {
     Path,
     Origin,
-    MasterBranch,
     StagingBranch,
 }
 
@@ -1280,7 +3643,20 @@ pub enum GitProjectArg {
 pub enum GithubGitProjectArg {
     Owner,
     Repo,
-    MasterBranch,
+    StagingBranch,
+}
+
+#[derive(Debug)]
+pub enum GitlabGitProjectArg {
+    Namespace,
+    Project,
+    StagingBranch,
+}
+
+#[derive(Debug)]
+pub enum GiteaGitProjectArg {
+    Owner,
+    Repo,
     StagingBranch,
 }
 
@@ -1310,4 +3686,110 @@ impl<T: Any + Debug> Display for SetupError<T> {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         Display::fmt(self.description(), fmt)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use ci::CiId;
+    use db::{ConfigIdEntry, Db, DbBox};
+    use db::sqlite::SqliteDb;
+    use pipeline::PipelineId;
+    use super::{CiType, Ids, layer, layer_repo_config};
+    use toml;
+
+    #[test]
+    fn ids_stay_with_their_names() {
+        let mut ids = Ids::new();
+        ids.start();
+        assert_eq!(ids.pipeline_id("a"), PipelineId(0));
+        assert_eq!(ids.pipeline_id("c"), PipelineId(1));
+        assert_eq!(ids.ci_id(PipelineId(0), CiType::Jenkins, "a"), CiId(0));
+        assert_eq!(ids.ci_id(PipelineId(0), CiType::Jenkins, "a"), CiId(1));
+        assert_eq!(ids.unsaved.len(), 4);
+        ids.unsaved.clear();
+        // A project added in front of the others doesn't move them.
+        ids.start();
+        assert_eq!(ids.pipeline_id("b"), PipelineId(2));
+        assert_eq!(ids.pipeline_id("a"), PipelineId(0));
+        assert_eq!(ids.ci_id(PipelineId(0), CiType::Jenkins, "a"), CiId(0));
+        assert_eq!(ids.ci_id(PipelineId(0), CiType::Jenkins, "a"), CiId(1));
+        assert_eq!(ids.ci_id(PipelineId(0), CiType::Webhook, "a"), CiId(2));
+        assert_eq!(ids.pipeline_id("c"), PipelineId(1));
+        assert_eq!(ids.unsaved.len(), 2);
+    }
+
+    #[test]
+    fn ids_saved_first_by_another_process_win() {
+        let mut db = DbBox::Sqlite(SqliteDb::open(":memory:").unwrap());
+        db.add_config_id(ConfigIdEntry{
+            name: "a".to_owned(),
+            pipeline_id: PipelineId(3),
+            ci_id: None,
+        }).unwrap();
+        // Loaded before the other process saved "a".
+        let mut ids = Ids::new();
+        ids.start();
+        assert_eq!(ids.pipeline_id("a"), PipelineId(0));
+        assert_eq!(ids.pipeline_id("b"), PipelineId(1));
+        assert!(ids.save(&mut db).unwrap());
+        ids.start();
+        assert_eq!(ids.pipeline_id("a"), PipelineId(3));
+        assert_eq!(ids.pipeline_id("b"), PipelineId(1));
+        assert!(!ids.save(&mut db).unwrap());
+    }
+
+    #[test]
+    fn repo_settings_are_laid_over() {
+        let parse = |text| toml::Parser::new(text).parse().expect("toml");
+        let mut def = toml::Value::Table(parse(r#"
+            retry = 1
+            [github]
+            owner = "o"
+            labels = { approved = "a", failed = "f" }
+        "#));
+        layer(&mut def, &parse(r#"
+            retry = 3
+            [github]
+            status = "ci"
+            labels = { failed = "broken" }
+        "#));
+        assert_eq!(def, toml::Value::Table(parse(r#"
+            retry = 3
+            [github]
+            owner = "o"
+            status = "ci"
+            labels = { approved = "a", failed = "broken" }
+        "#)));
+    }
+
+    #[test]
+    fn repo_status_contexts_are_only_added() {
+        let parse = |text| toml::Parser::new(text).parse().expect("toml");
+        let mut def = toml::Value::Table(parse(r#"
+            [github]
+            owner = "o"
+            status = "ci/jenkins"
+        "#));
+        layer_repo_config(&mut def, &parse(r#"
+            [github]
+            status = ["ci/travis", "ci/jenkins"]
+            review_approval = true
+        "#));
+        assert_eq!(def, toml::Value::Table(parse(r#"
+            [github]
+            owner = "o"
+            status = ["ci/jenkins", "ci/travis"]
+            review_approval = true
+        "#)));
+        layer_repo_config(&mut def, &parse(r#"
+            [github]
+            status = []
+        "#));
+        assert_eq!(
+            def.lookup("github.status"),
+            Some(&toml::Value::Array(vec![
+                toml::Value::String("ci/jenkins".to_owned()),
+                toml::Value::String("ci/travis".to_owned()),
+            ])),
+        );
+    }
+}
@@ -7,6 +7,7 @@ use pipeline::WorkerManager;
 use pipeline::WorkerThread;
 use std::error::Error;
 use ui::{self, github};
+use util::github_auth;
 use vcs::{self, git};
 use vcs::github as github_git;
 use view;
@@ -87,8 +88,42 @@ impl GithubBuilder {
     }
 }
 
+/// Everything the admin commands need from the environment: where the
+/// database is, and the name of every pipeline. This starts no workers.
+pub fn admin_from_os_env() -> Result<
+    (db::Builder, Box<view::PipelinesConfig>),
+    GithubBuilderError,
+> {
+    let env = |var: &str| {
+        let var = "AELITA_".to_owned() + var;
+        ::std::env::var(&var[..]).ok()
+    };
+    let db_key = try_env!(env, "PIPELINE_DB", PipelineDb);
+    let db_builder = match db::Builder::from_str(&db_key[..]) {
+        Ok(db_builder) => db_builder,
+        Err(e) => return Err(GithubBuilderError::DbConnect(e)),
+    };
+    let pj_key = try_env!(env, "PROJECT_DB", ProjectDb);
+    let pj_builder = match db::Builder::from_str(&pj_key[..]) {
+        Ok(pj_builder) => pj_builder,
+        Err(e) => return Err(GithubBuilderError::PjConnect(e)),
+    };
+    let pipelines: Box<view::PipelinesConfig> = match pj_builder {
+        db::Builder::Sqlite(d) =>
+            Box::new(try!(sqlite::ViewPipelinesConfig::new(d))),
+        db::Builder::Postgres(d) =>
+            Box::new(try!(postgres::ViewPipelinesConfig::new(d))),
+    };
+    Ok((db_builder, pipelines))
+}
+
 impl WorkerBuilder for GithubBuilder {
     fn start(self) -> (WorkerManager, DbBox) {
+        // There are no projects to lay them over, and nothing to reload.
+        warn!(
+            "Repos' own {} is not read with the environment config",
+            vcs::REPO_CONFIG,
+        );
         (
             WorkerManager {
                 cis: vec![self.ci],
@@ -96,6 +131,7 @@ impl WorkerBuilder for GithubBuilder {
                 vcss: vec![self.vcs],
                 view: Some(self.view),
                 pipelines: self.pipelines,
+                reloader: None,
             },
             self.db,
         )
@@ -122,14 +158,47 @@ fn setup_github<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
         Ok(gh_builder) => gh_builder,
         Err(e) => return Err(GithubBuilderError::GhConnect(e)),
     };
+    // A GitHub App ID and its PEM-encoded private key take precedence
+    // over a personal access token.
+    let credentials = match env("UI_GITHUB_APP_ID") {
+        Some(app_id) => github_auth::Credentials::App(
+            match app_id.parse() {
+                Ok(app_id) => app_id,
+                Err(_) => return Err(GithubBuilderError::InvalidKey(
+                    GithubBuilderKey::UiGithubAppId
+                )),
+            },
+            try_env!(env, "UI_GITHUB_APP_KEY", UiGithubAppKey).into_bytes(),
+        ),
+        None => github_auth::Credentials::Token(
+            try_env!(env, "UI_GITHUB_TOKEN", UiGithubToken)
+        ),
+    };
     Ok(WorkerThread::start(github::Worker::new(
         try_env!(env, "UI_GITHUB_LISTEN", UiGithubListen),
-        try_env!(env, "UI_GITHUB_HOST", UiGithubHost),
-        try_env!(env, "UI_GITHUB_TOKEN", UiGithubToken),
+        github_auth::Auth::new(
+            "ui_github",
+            try_env!(env, "UI_GITHUB_HOST", UiGithubHost),
+            credentials
+        ),
         try_env!(env, "UI_GITHUB_USER", UiGithubUser),
         try_env!(env, "UI_GITHUB_SECRET", UiGithubSecret),
+        env("UI_GITHUB_REQUIRE_SHA256")
+            .map(|require_sha256| require_sha256 == "true")
+            .unwrap_or(false),
+        env("UI_GITHUB_CHECKS").map(|checks| checks == "true").unwrap_or(false),
+        env("UI_GITHUB_STATUS_COMMENT")
+            .map(|status_comment| status_comment == "true")
+            .unwrap_or(false),
         projects,
         gh_builder,
+        match env("UI_GITHUB_CACHE_TTL").map(|ttl| ttl.parse::<i64>()) {
+            Some(Ok(ttl)) if ttl > 0 => Some(ttl),
+            Some(_) => return Err(GithubBuilderError::InvalidKey(
+                GithubBuilderKey::UiGithubCacheTtl
+            )),
+            None => None,
+        },
     )))
 }
 
@@ -179,6 +248,12 @@ fn setup_github_status<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
         Ok(pj_builder) => pj_builder,
         Err(e) => return Err(GithubBuilderError::PjConnect(e)),
     };
+    // Delivery ids go in the project db, unless told otherwise.
+    let gh_key = env("CI_GITHUB_DB").unwrap_or_else(|| pj_key.clone());
+    let gh_builder = match db::Builder::from_str(&gh_key[..]) {
+        Ok(gh_builder) => gh_builder,
+        Err(e) => return Err(GithubBuilderError::GhConnect(e)),
+    };
     let pipelines: Box<github_status::PipelinesConfig> = match pj_builder {
         db::Builder::Sqlite(d) =>
             Box::new(try!(sqlite::GithubStatusPipelinesConfig::new(d))),
@@ -188,7 +263,11 @@ fn setup_github_status<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
     Ok(WorkerThread::start(github_status::Worker::new(
         try_env!(env, "CI_GITHUB_LISTEN", CiGithubListen),
         try_env!(env, "CI_GITHUB_SECRET", CiGithubSecret),
+        env("CI_GITHUB_REQUIRE_SHA256")
+            .map(|require_sha256| require_sha256 == "true")
+            .unwrap_or(false),
         pipelines,
+        gh_builder,
     )))
 }
 
@@ -218,9 +297,26 @@ fn setup_github_git<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
         db::Builder::Postgres(d) =>
             Box::new(try!(postgres::GithubGitPipelinesConfig::new(d))),
     };
+    let credentials = match env("VCS_GITHUB_APP_ID") {
+        Some(app_id) => github_auth::Credentials::App(
+            match app_id.parse() {
+                Ok(app_id) => app_id,
+                Err(_) => return Err(GithubBuilderError::InvalidKey(
+                    GithubBuilderKey::VcsGithubAppId
+                )),
+            },
+            try_env!(env, "VCS_GITHUB_APP_KEY", VcsGithubAppKey).into_bytes(),
+        ),
+        None => github_auth::Credentials::Token(
+            try_env!(env, "VCS_GITHUB_TOKEN", VcsGithubToken)
+        ),
+    };
     Ok(WorkerThread::start(github_git::Worker::new(
-        try_env!(env, "VCS_GITHUB_HOST", VcsGithubHost),
-        try_env!(env, "VCS_GITHUB_TOKEN", VcsGithubToken),
+        github_auth::Auth::new(
+            "vcs_github",
+            try_env!(env, "VCS_GITHUB_HOST", VcsGithubHost),
+            credentials
+        ),
         pipelines,
     )))
 }
@@ -274,15 +370,17 @@ fn setup_view<F: Fn(&str) -> Option<String>>(env: &F) -> Result<
         pipelines,
         try_env!(env, "VIEW_SECRET", ViewSecret),
         view::Auth::None,
+        env("VIEW_API_TOKEN"),
     )))
 }
 
 mod sqlite {
     use config::PipelinesConfig as TPipelinesConfig;
-    use config::PipelineConfig;
+    use config::{PipelineConfig, RetryConfig};
     use pipeline::PipelineId;
     use rusqlite::Connection;
     use std::borrow::Cow;
+    use std::collections::HashMap;
     use std::error::Error;
     use std::path::PathBuf;
     use std::sync::Mutex;
@@ -315,6 +413,19 @@ mod sqlite {
                     ci_id INTEGER PRIMARY KEY,
                     pipeline_id INTEGER
                 );
+                -- The CI names come from these; they're made here too, because
+                -- only the ones for the CI in use get made by its own config.
+                CREATE TABLE IF NOT EXISTS twelvef_jenkins_pipelines (
+                    ci_id INTEGER PRIMARY KEY,
+                    name TEXT,
+                    token TEXT
+                );
+                CREATE TABLE IF NOT EXISTS twelvef_github_status_pipelines (
+                    ci_id INTEGER PRIMARY KEY,
+                    owner TEXT,
+                    repo TEXT,
+                    context TEXT
+                );
             "###));
             Ok(PipelinesConfig{
                 conn: Mutex::new(conn),
@@ -322,32 +433,46 @@ mod sqlite {
         }
     }
     impl TPipelinesConfig for PipelinesConfig {
-        fn by_pipeline_id(&self, pipeline_id: PipelineId) -> PipelineConfig {
+        fn by_pipeline_id(&self, pipeline_id: PipelineId)
+                -> Option<PipelineConfig> {
             let mut ci = Vec::new();
+            let mut ci_names = HashMap::new();
             let conn = self.conn.lock().unwrap();
             let sql = r###"
-                SELECT ci_id
-                FROM twelvef_config_pipeline_ci
-                WHERE pipeline_id = ?
+                SELECT c.ci_id, COALESCE(j.name, s.context)
+                FROM twelvef_config_pipeline_ci c
+                LEFT JOIN twelvef_jenkins_pipelines j
+                    ON j.ci_id = c.ci_id
+                LEFT JOIN twelvef_github_status_pipelines s
+                    ON s.ci_id = c.ci_id
+                WHERE c.pipeline_id = ?
             "###;
             let mut stmt = conn.prepare(&sql)
                 .expect("Prepare pipeline ci map query");
             let rows = stmt
-                .query_map(&[ &pipeline_id.0 ], |row| row.get::<_, i32>(0))
+                .query_map(&[ &pipeline_id.0 ], |row| {
+                    (row.get::<_, i32>(0), row.get::<_, Option<String>>(1))
+                })
                 .expect("Get pipeline ci map");
             for row in rows {
-                ci.push((CiId(row.expect("Get pipeline value")), 0));
+                let (ci_id, name) = row.expect("Get pipeline value");
+                ci.push((CiId(ci_id), 0));
+                if let Some(name) = name {
+                    ci_names.insert(CiId(ci_id), name);
+                }
             }
             let ui = 0;
             let vcs = 0;
-            PipelineConfig{
+            Some(PipelineConfig{
                 pipeline_id: pipeline_id,
                 ci: ci,
                 ui: ui,
                 vcs: vcs,
-            }
+                ci_names: ci_names,
+                retry: RetryConfig::default(),
+            })
         }
-        fn by_ci_id(&self, ci_id: CiId) -> PipelineConfig {
+        fn by_ci_id(&self, ci_id: CiId) -> Option<PipelineConfig> {
             let pipeline_id = {
                 let conn = self.conn.lock().unwrap();
                 let sql = r###"
@@ -360,7 +485,10 @@ mod sqlite {
                 let mut rows = stmt
                     .query_map(&[ &ci_id.0 ], |row| row.get::<_, i32>(0))
                     .expect("Get ci pipeline map");
-                rows.next().map(|row| row.expect("SQLite to work")).unwrap()
+                match rows.next() {
+                    Some(row) => row.expect("SQLite to work"),
+                    None => return None,
+                }
             };
             let pipeline_id = PipelineId(pipeline_id);
             self.by_pipeline_id(pipeline_id)
@@ -379,6 +507,23 @@ mod sqlite {
             rows.next().map(|row| row.expect("SQLite to work")).unwrap()
         }
     }
+    const GITHUB_PROJECTS_COLUMNS: &'static str = r###"
+        pipeline_id INTEGER PRIMARY KEY,
+        try_pipeline_id INTEGER NULL,
+        owner TEXT,
+        repo TEXT,
+        branch TEXT NULL,
+        review_approval BOOLEAN NOT NULL DEFAULT 0,
+        label_approved TEXT NULL,
+        label_testing TEXT NULL,
+        label_failed TEXT NULL,
+        label_merge_conflict TEXT NULL,
+        UNIQUE (owner, repo, branch)
+    "###;
+    const GITHUB_PROJECTS_COPIED: &'static str = r###"
+        pipeline_id, try_pipeline_id, owner, repo, branch, review_approval,
+        label_approved, label_testing, label_failed, label_merge_conflict
+    "###;
     pub struct GithubProjectsConfig {
         conn: Mutex<Connection>,
     }
@@ -387,14 +532,62 @@ mod sqlite {
                 -> Result<Self, Box<Error + Send + Sync + 'static>>
         {
             let conn = try!(Connection::open(path));
+            try!(conn.execute_batch(&format!(
+                "CREATE TABLE IF NOT EXISTS twelvef_github_projects ({});",
+                GITHUB_PROJECTS_COLUMNS,
+            )));
+            try!(::db::sqlite::add_missing_columns(
+                &conn,
+                "twelvef_github_projects",
+                &[
+                    ("branch", "TEXT NULL"),
+                    ("review_approval", "BOOLEAN NOT NULL DEFAULT 0"),
+                    ("label_approved", "TEXT NULL"),
+                    ("label_testing", "TEXT NULL"),
+                    ("label_failed", "TEXT NULL"),
+                    ("label_merge_conflict", "TEXT NULL"),
+                ],
+            ));
+            // Older tables only allow one row per repo. SQLite can't change
+            // a UNIQUE constraint in place, so copy them into a new table.
+            let schema = {
+                let mut stmt = try!(conn.prepare(r###"
+                    SELECT sql
+                    FROM sqlite_master
+                    WHERE type = 'table' AND name = 'twelvef_github_projects'
+                "###));
+                let mut rows = try!(stmt.query_map(&[], |row| {
+                    row.get::<_, String>(0)
+                }));
+                match rows.next() {
+                    Some(row) => try!(row),
+                    None => String::new(),
+                }
+            };
+            if !schema.contains("UNIQUE (owner, repo, branch)") {
+                try!(conn.execute_batch(&format!(r###"
+                    BEGIN;
+                    CREATE TABLE twelvef_github_projects_new ({columns});
+                    INSERT INTO twelvef_github_projects_new
+                        ({copied})
+                        SELECT {copied}
+                        FROM twelvef_github_projects;
+                    DROP TABLE twelvef_github_projects;
+                    ALTER TABLE twelvef_github_projects_new
+                        RENAME TO twelvef_github_projects;
+                    COMMIT;
+                "###,
+                    columns = GITHUB_PROJECTS_COLUMNS,
+                    copied = GITHUB_PROJECTS_COPIED,
+                )));
+            }
+            // NULL never equals NULL, so the constraint above lets a repo
+            // have any number of pipelines that take every branch.
             try!(conn.execute_batch(r###"
-                CREATE TABLE IF NOT EXISTS twelvef_github_projects (
-                    pipeline_id INTEGER PRIMARY KEY,
-                    try_pipeline_id INTEGER NULL,
-                    owner TEXT,
-                    repo TEXT,
-                    UNIQUE (owner, repo)
-                );
+                CREATE UNIQUE INDEX IF NOT EXISTS
+                    twelvef_github_projects_any_branch
+                    ON twelvef_github_projects (owner, repo)
+                    WHERE branch IS NULL;
             "###));
             Ok(GithubProjectsConfig{
                 conn: Mutex::new(conn),
@@ -403,27 +596,38 @@ mod sqlite {
     }
     impl TGithubProjectsConfig for GithubProjectsConfig {
         fn pipelines_by_repo(&self, repo: &github::Repo)
-                -> Option<github::RepoPipelines>
+                -> Vec<github::RepoPipelines>
         {
             let conn = self.conn.lock().unwrap();
             let sql = r###"
-                SELECT pipeline_id, try_pipeline_id
+                SELECT pipeline_id, try_pipeline_id, review_approval,
+                    label_approved, label_testing, label_failed,
+                    label_merge_conflict, branch
                 FROM twelvef_github_projects
                 WHERE owner = ? AND repo = ?
+                ORDER BY pipeline_id
             "###;
             let mut stmt = conn.prepare(&sql)
                 .expect("prepare pipelines query");
-            let mut rows = stmt
+            let rows = stmt
                 .query_map(&[&repo.owner, &repo.repo], |row| {
                     github::RepoPipelines{
+                        branch: row.get::<_, Option<String>>(7),
                         pipeline_id:
                             PipelineId(row.get::<_, i32>(0)),
                         try_pipeline_id:
                             row.get::<_, Option<i32>>(1).map(PipelineId),
+                        review_approval: row.get::<_, bool>(2),
+                        labels: github::Labels{
+                            approved: row.get::<_, Option<String>>(3),
+                            testing: row.get::<_, Option<String>>(4),
+                            failed: row.get::<_, Option<String>>(5),
+                            merge_conflict: row.get::<_, Option<String>>(6),
+                        },
                     }
                 })
                 .expect("get pipelines");
-            rows.next().map(|row| row.expect("sqlite to work"))
+            rows.map(|row| row.expect("sqlite to work")).collect()
         }
         fn repo_by_pipeline(&self, pipeline_id: PipelineId)
                 -> Option<(github::Repo, github::PipelineType)>
@@ -754,16 +958,37 @@ mod sqlite {
             let rows = rows.map(|row| row.expect("sqlite to work")).collect();
             rows
         }
+        fn cis_by_pipeline(&self, pipeline_id: PipelineId)
+                -> Vec<(CiId, Cow<str>)>
+        {
+            let conn = self.conn.lock().unwrap();
+            let sql = r###"
+                SELECT ci_id
+                FROM twelvef_config_pipeline_ci
+                WHERE pipeline_id = ?
+            "###;
+            let mut stmt = conn.prepare(&sql)
+                .expect("prepare pipeline ci query");
+            let rows = stmt
+                .query_map(&[&pipeline_id.0], |row| {
+                    let ci_id = row.get::<_, i32>(0);
+                    (CiId(ci_id), Cow::Owned(ci_id.to_string()))
+                })
+                .expect("get pipeline cis");
+            let rows = rows.map(|row| row.expect("sqlite to work")).collect();
+            rows
+        }
     }
 }
 
 mod postgres {
     use config::PipelinesConfig as TPipelinesConfig;
-    use config::PipelineConfig;
+    use config::{PipelineConfig, RetryConfig};
     use pipeline::PipelineId;
     use postgres::{Connection, TlsMode};
     use postgres::params::{ConnectParams, IntoConnectParams};
     use std::borrow::Cow;
+    use std::collections::HashMap;
     use std::error::Error;
     use ui::github::{self, ProjectsConfig as TGithubProjectsConfig};
     use ci::CiId;
@@ -796,6 +1021,19 @@ mod postgres {
                     ci_id SERIAL PRIMARY KEY,
                     pipeline_id SERIAL
                 );
+                -- The CI names come from these; they're made here too, because
+                -- only the ones for the CI in use get made by its own config.
+                CREATE TABLE IF NOT EXISTS twelvef_jenkins_pipelines (
+                    ci_id SERIAL PRIMARY KEY,
+                    name TEXT,
+                    token TEXT
+                );
+                CREATE TABLE IF NOT EXISTS twelvef_github_status_pipelines (
+                    ci_id SERIAL PRIMARY KEY,
+                    owner TEXT,
+                    repo TEXT,
+                    context TEXT
+                );
             "###));
             Ok(result)
         }
@@ -804,35 +1042,45 @@ mod postgres {
         }
     }
     impl TPipelinesConfig for PipelinesConfig {
-        fn by_pipeline_id(&self, pipeline_id: PipelineId) -> PipelineConfig {
+        fn by_pipeline_id(&self, pipeline_id: PipelineId)
+                -> Option<PipelineConfig> {
             retry!{{
                 let mut ci = Vec::new();
+                let mut ci_names = HashMap::new();
                 let conn = retry_unwrap!(self.conn());
                 let sql = r###"
-                    SELECT ci_id
-                    FROM twelvef_config_pipeline_ci
-                    WHERE pipeline_id = $1
+                    SELECT c.ci_id, COALESCE(j.name, s.context)
+                    FROM twelvef_config_pipeline_ci c
+                    LEFT JOIN twelvef_jenkins_pipelines j
+                        ON j.ci_id = c.ci_id
+                    LEFT JOIN twelvef_github_status_pipelines s
+                        ON s.ci_id = c.ci_id
+                    WHERE c.pipeline_id = $1
                 "###;
                 let stmt = retry_unwrap!(conn.prepare(&sql));
                 let rows = retry_unwrap!(
                     stmt.query(&[ &pipeline_id.0 ])
                 );
-                let rows = rows.iter();
-                let rows = rows.map(|row| row.get::<_, i32>(0));
-                for row in rows {
-                    ci.push((CiId(row), 0));
+                for row in rows.iter() {
+                    let ci_id = CiId(row.get::<_, i32>(0));
+                    ci.push((ci_id, 0));
+                    if let Some(name) = row.get::<_, Option<String>>(1) {
+                        ci_names.insert(ci_id, name);
+                    }
                 }
                 let ui = 0;
                 let vcs = 0;
-                PipelineConfig{
+                Some(PipelineConfig{
                     pipeline_id: pipeline_id,
                     ci: ci,
                     ui: ui,
                     vcs: vcs,
-                }
+                    ci_names: ci_names,
+                    retry: RetryConfig::default(),
+                })
             }}
         }
-        fn by_ci_id(&self, ci_id: CiId) -> PipelineConfig {
+        fn by_ci_id(&self, ci_id: CiId) -> Option<PipelineConfig> {
             let pipeline_id = (|| {
                 retry!{{
                     let conn = retry_unwrap!(self.conn());
@@ -847,10 +1095,10 @@ mod postgres {
                     );
                     let rows = rows.iter();
                     let mut rows = rows.map(|row| PipelineId(row.get::<_, i32>(0)));
-                    rows.next().unwrap()
+                    rows.next()
                 }}
             })();
-            self.by_pipeline_id(pipeline_id)
+            pipeline_id.and_then(|pipeline_id| self.by_pipeline_id(pipeline_id))
         }
         fn len(&self) -> usize {
             let conn = self.conn().unwrap();
@@ -883,9 +1131,31 @@ mod postgres {
                     pipeline_id INTEGER PRIMARY KEY,
                     try_pipeline_id INTEGER NULL,
                     owner TEXT,
-                    repo TEXT,
-                    UNIQUE (owner, repo)
+                    repo TEXT
                 );
+                ALTER TABLE twelvef_github_projects
+                    DROP CONSTRAINT IF EXISTS
+                    twelvef_github_projects_owner_repo_key;
+                ALTER TABLE twelvef_github_projects
+                    ADD COLUMN IF NOT EXISTS branch TEXT NULL;
+                CREATE UNIQUE INDEX IF NOT EXISTS
+                    twelvef_github_projects_owner_repo_branch
+                    ON twelvef_github_projects (owner, repo, branch);
+                CREATE UNIQUE INDEX IF NOT EXISTS
+                    twelvef_github_projects_any_branch
+                    ON twelvef_github_projects (owner, repo)
+                    WHERE branch IS NULL;
+                ALTER TABLE twelvef_github_projects
+                    ADD COLUMN IF NOT EXISTS review_approval BOOLEAN
+                    NOT NULL DEFAULT FALSE;
+                ALTER TABLE twelvef_github_projects
+                    ADD COLUMN IF NOT EXISTS label_approved TEXT NULL;
+                ALTER TABLE twelvef_github_projects
+                    ADD COLUMN IF NOT EXISTS label_testing TEXT NULL;
+                ALTER TABLE twelvef_github_projects
+                    ADD COLUMN IF NOT EXISTS label_failed TEXT NULL;
+                ALTER TABLE twelvef_github_projects
+                    ADD COLUMN IF NOT EXISTS label_merge_conflict TEXT NULL;
             "###));
             Ok(result)
         }
@@ -895,29 +1165,40 @@ mod postgres {
     }
     impl TGithubProjectsConfig for GithubProjectsConfig {
         fn pipelines_by_repo(&self, repo: &github::Repo)
-                -> Option<github::RepoPipelines>
+                -> Vec<github::RepoPipelines>
         {
             retry!{{
                 let conn = retry_unwrap!(self.conn());
                 let sql = r###"
-                    SELECT pipeline_id, try_pipeline_id
+                    SELECT pipeline_id, try_pipeline_id, review_approval,
+                        label_approved, label_testing, label_failed,
+                        label_merge_conflict, branch
                     FROM twelvef_github_projects
                     WHERE owner = $1 AND repo = $2
+                    ORDER BY pipeline_id
                 "###;
                 let stmt = retry_unwrap!(conn.prepare(&sql));
                 let rows = retry_unwrap!(
                     stmt.query(&[&repo.owner, &repo.repo])
                 );
                 let rows = rows.iter();
-                let mut rows = rows.map(|row| {
+                let rows = rows.map(|row| {
                     github::RepoPipelines{
+                        branch: row.get::<_, Option<String>>(7),
                         pipeline_id:
                             PipelineId(row.get::<_, i32>(0)),
                         try_pipeline_id:
                             row.get::<_, Option<i32>>(1).map(PipelineId),
+                        review_approval: row.get::<_, bool>(2),
+                        labels: github::Labels{
+                            approved: row.get::<_, Option<String>>(3),
+                            testing: row.get::<_, Option<String>>(4),
+                            failed: row.get::<_, Option<String>>(5),
+                            merge_conflict: row.get::<_, Option<String>>(6),
+                        },
                     }
                 });
-                rows.next()
+                rows.collect()
             }}
         }
         fn repo_by_pipeline(&self, pipeline_id: PipelineId)
@@ -1281,6 +1562,27 @@ mod postgres {
                 rows
             }}
         }
+        fn cis_by_pipeline(&self, pipeline_id: PipelineId)
+                -> Vec<(CiId, Cow<str>)>
+        {
+            retry!{{
+                let conn = retry_unwrap!(self.conn());
+                let sql = r###"
+                    SELECT ci_id
+                    FROM twelvef_config_pipeline_ci
+                    WHERE pipeline_id = $1
+                "###;
+                let stmt = retry_unwrap!(conn.prepare(&sql));
+                let rows = retry_unwrap!(stmt.query(&[&pipeline_id.0]));
+                let rows = rows.iter();
+                let rows = rows.map(|row| {
+                    let ci_id = row.get::<_, i32>(0);
+                    (CiId(ci_id), Cow::Owned(ci_id.to_string()))
+                });
+                let rows = rows.collect();
+                rows
+            }}
+        }
     }
 }
 
@@ -1313,8 +1615,11 @@ pub enum GithubBuilderKey {
     UiGithubListen,
     UiGithubHost,
     UiGithubToken,
+    UiGithubAppId,
+    UiGithubAppKey,
     UiGithubUser,
     UiGithubSecret,
+    UiGithubCacheTtl,
     CiJenkinsListen,
     CiJenkinsHost,
     CiJenkinsUser,
@@ -1323,9 +1628,82 @@ pub enum GithubBuilderKey {
     CiGithubSecret,
     VcsGithubHost,
     VcsGithubToken,
+    VcsGithubAppId,
+    VcsGithubAppKey,
     VcsGitExecutable,
     VcsGitName,
     VcsGitEmail,
     ViewListen,
     ViewSecret,
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use ci::CiId;
+    use config::PipelinesConfig as TPipelinesConfig;
+    use pipeline::PipelineId;
+    use rusqlite::Connection;
+    use std::env;
+    use std::fs;
+    use super::sqlite::{GithubProjectsConfig, PipelinesConfig};
+    use ui::github::{ProjectsConfig, Repo};
+
+    #[test]
+    fn sqlite_github_projects_gain_branches() {
+        let path = env::temp_dir().join("aelita-twelvef-branches.sqlite");
+        let _ = fs::remove_file(&path);
+        Connection::open(&path).unwrap().execute_batch(r###"
+            CREATE TABLE twelvef_github_projects (
+                pipeline_id INTEGER PRIMARY KEY,
+                try_pipeline_id INTEGER NULL,
+                owner TEXT,
+                repo TEXT,
+                UNIQUE (owner, repo)
+            );
+            INSERT INTO twelvef_github_projects (pipeline_id, owner, repo)
+                VALUES (1, 'o', 'r');
+        "###).unwrap();
+        GithubProjectsConfig::new(path.clone()).unwrap();
+        let conn = Connection::open(&path).unwrap();
+        conn.execute(r###"
+            INSERT INTO twelvef_github_projects
+                (pipeline_id, owner, repo, branch)
+                VALUES (2, 'o', 'r', 'beta')
+        "###, &[]).unwrap();
+        // Only one pipeline per repo can take every branch.
+        assert!(conn.execute(r###"
+            INSERT INTO twelvef_github_projects (pipeline_id, owner, repo)
+                VALUES (3, 'o', 'r')
+        "###, &[]).is_err());
+        let config = GithubProjectsConfig::new(path.clone()).unwrap();
+        let pipelines = config.pipelines_by_repo(&Repo{
+            owner: "o".to_owned(),
+            repo: "r".to_owned(),
+        });
+        assert_eq!(pipelines.len(), 2);
+        assert_eq!(pipelines[0].branch, None);
+        assert_eq!(pipelines[1].branch, Some("beta".to_owned()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn sqlite_pipelines_name_their_cis() {
+        let path = env::temp_dir().join("aelita-twelvef-ci-names.sqlite");
+        let _ = fs::remove_file(&path);
+        let config = PipelinesConfig::new(path.clone()).unwrap();
+        Connection::open(&path).unwrap().execute_batch(r###"
+            INSERT INTO twelvef_config_pipeline_ci (ci_id, pipeline_id)
+                VALUES (1, 1), (2, 1), (3, 1);
+            INSERT INTO twelvef_jenkins_pipelines (ci_id, name, token)
+                VALUES (1, 'build', 'token');
+            INSERT INTO twelvef_github_status_pipelines
+                (ci_id, owner, repo, context)
+                VALUES (2, 'o', 'r', 'ci/lint');
+        "###).unwrap();
+        let pipeline = config.by_pipeline_id(PipelineId(1)).unwrap();
+        assert_eq!(pipeline.ci.len(), 3);
+        assert_eq!(pipeline.ci_names.len(), 2);
+        assert_eq!(pipeline.ci_names[&CiId(1)], "build");
+        assert_eq!(pipeline.ci_names[&CiId(2)], "ci/lint");
+        fs::remove_file(&path).unwrap();
+    }
+}
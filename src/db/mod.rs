@@ -88,6 +88,38 @@ impl Db for DbBox {
             DbBox::Postgres(ref mut d) => d.list_queue(pipeline_id),
         }
     }
+    fn set_priority(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        priority: i32,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) =>
+                d.set_priority(pipeline_id, pr, priority),
+            DbBox::Postgres(ref mut d) =>
+                d.set_priority(pipeline_id, pr, priority),
+        }
+    }
+    fn set_paused(
+        &mut self,
+        pipeline_id: PipelineId,
+        paused: bool,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) => d.set_paused(pipeline_id, paused),
+            DbBox::Postgres(ref mut d) => d.set_paused(pipeline_id, paused),
+        }
+    }
+    fn is_paused(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) => d.is_paused(pipeline_id),
+            DbBox::Postgres(ref mut d) => d.is_paused(pipeline_id),
+        }
+    }
     fn put_running(
         &mut self,
         pipeline_id: PipelineId,
@@ -189,14 +221,13 @@ impl Db for DbBox {
     fn set_ci_state(
         &mut self,
         ci_id: CiId,
-        ci_state: CiState,
-        commit: &Commit,
+        entry: CiStateEntry,
     ) -> Result<(), Box<Error + Send + Sync>> {
         match *self {
             DbBox::Sqlite(ref mut d) =>
-                d.set_ci_state(ci_id, ci_state, commit),
+                d.set_ci_state(ci_id, entry),
             DbBox::Postgres(ref mut d) =>
-                d.set_ci_state(ci_id, ci_state, commit),
+                d.set_ci_state(ci_id, entry),
         }
     }
     fn clear_ci_state(
@@ -213,7 +244,7 @@ impl Db for DbBox {
     fn get_ci_state(
         &mut self,
         ci_id: CiId,
-    ) -> Result<Option<(CiState, Commit)>, Box<Error + Send + Sync>> {
+    ) -> Result<Option<CiStateEntry>, Box<Error + Send + Sync>> {
         match *self {
             DbBox::Sqlite(ref mut d) =>
                 d.get_ci_state(ci_id),
@@ -221,6 +252,78 @@ impl Db for DbBox {
                 d.get_ci_state(ci_id),
         }
     }
+    fn add_ci_failure(
+        &mut self,
+        ci_id: CiId,
+        commit: &Commit,
+        pull_commit: &Commit,
+        signatures: &[String],
+    ) -> Result<u32, Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) =>
+                d.add_ci_failure(ci_id, commit, pull_commit, signatures),
+            DbBox::Postgres(ref mut d) =>
+                d.add_ci_failure(ci_id, commit, pull_commit, signatures),
+        }
+    }
+    fn take_ci_failures(
+        &mut self,
+        ci_id: CiId,
+        commit: &Commit,
+    ) -> Result<Vec<String>, Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) => d.take_ci_failures(ci_id, commit),
+            DbBox::Postgres(ref mut d) => d.take_ci_failures(ci_id, commit),
+        }
+    }
+    fn clear_other_ci_failures(
+        &mut self,
+        ci_id: CiId,
+        pull_commit: &Commit,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) =>
+                d.clear_other_ci_failures(ci_id, pull_commit),
+            DbBox::Postgres(ref mut d) =>
+                d.clear_other_ci_failures(ci_id, pull_commit),
+        }
+    }
+    fn add_flaky(
+        &mut self,
+        ci_id: CiId,
+        signature: &str,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) => d.add_flaky(ci_id, signature),
+            DbBox::Postgres(ref mut d) => d.add_flaky(ci_id, signature),
+        }
+    }
+    fn list_flaky(
+        &mut self,
+        ci_id: CiId,
+    ) -> Result<Vec<FlakyEntry>, Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) => d.list_flaky(ci_id),
+            DbBox::Postgres(ref mut d) => d.list_flaky(ci_id),
+        }
+    }
+    fn add_config_id(
+        &mut self,
+        entry: ConfigIdEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) => d.add_config_id(entry),
+            DbBox::Postgres(ref mut d) => d.add_config_id(entry),
+        }
+    }
+    fn list_config_ids(
+        &mut self,
+    ) -> Result<Vec<ConfigIdEntry>, Box<Error + Send + Sync>> {
+        match *self {
+            DbBox::Sqlite(ref mut d) => d.list_config_ids(),
+            DbBox::Postgres(ref mut d) => d.list_config_ids(),
+        }
+    }
 }
 
 
@@ -245,6 +348,24 @@ pub trait Db: Sized {
         &mut self,
         PipelineId,
     ) -> Result<Vec<QueueEntry>, Box<Error + Send + Sync>>;
+    /// Change the priority of a queued PR.
+    /// Returns false if the PR is not in the queue.
+    fn set_priority(
+        &mut self,
+        PipelineId,
+        &Pr,
+        i32,
+    ) -> Result<bool, Box<Error + Send + Sync>>;
+    /// Stop (or start again) taking entries off the queue.
+    fn set_paused(
+        &mut self,
+        PipelineId,
+        bool,
+    ) -> Result<(), Box<Error + Send + Sync>>;
+    fn is_paused(
+        &mut self,
+        PipelineId,
+    ) -> Result<bool, Box<Error + Send + Sync>>;
     fn put_running(
         &mut self,
         PipelineId,
@@ -291,23 +412,72 @@ pub trait Db: Sized {
         &Pr,
         &Commit,
     ) -> Result<bool, Box<Error + Send + Sync>>;
-    /// Set the state of a CI job.
+    /// Set the state of a CI job, replacing any previous one.
     fn set_ci_state(
         &mut self,
         CiId,
-        CiState,
-        &Commit,
+        CiStateEntry,
     ) -> Result<(), Box<Error + Send + Sync>>;
-    /// Set the state of a CI job.
+    /// Forget the state of a CI job.
     fn clear_ci_state(
         &mut self,
         CiId,
     ) -> Result<(), Box<Error + Send + Sync>>;
-    /// Get a count of completed / remaining CI jobs.
+    /// Get the last known state of a CI job.
     fn get_ci_state(
         &mut self,
         CiId,
-    ) -> Result<Option<(CiState, Commit)>, Box<Error + Send + Sync>>;
+    ) -> Result<Option<CiStateEntry>, Box<Error + Send + Sync>>;
+    /// Record what failed when a CI job built a merge commit,
+    /// along with the pull commit that went into it.
+    /// Returns how many times the job has now failed on that merge commit.
+    ///
+    /// The signatures must not be empty; use the job name
+    /// if there is nothing more specific.
+    fn add_ci_failure(
+        &mut self,
+        CiId,
+        &Commit,
+        &Commit,
+        &[String],
+    ) -> Result<u32, Box<Error + Send + Sync>>;
+    /// Get everything that failed while a CI job built a merge commit,
+    /// without duplicates, and forget it, so it is only counted once.
+    fn take_ci_failures(
+        &mut self,
+        CiId,
+        &Commit,
+    ) -> Result<Vec<String>, Box<Error + Send + Sync>>;
+    /// Forget the failures of a CI job,
+    /// except the ones from building this pull commit.
+    fn clear_other_ci_failures(
+        &mut self,
+        CiId,
+        &Commit,
+    ) -> Result<(), Box<Error + Send + Sync>>;
+    /// Remember that a failure went away when its job was retried.
+    fn add_flaky(
+        &mut self,
+        CiId,
+        &str,
+    ) -> Result<(), Box<Error + Send + Sync>>;
+    /// List the failures of a CI job that are known to be flaky.
+    fn list_flaky(
+        &mut self,
+        CiId,
+    ) -> Result<Vec<FlakyEntry>, Box<Error + Send + Sync>>;
+    /// Remember the id given to a pipeline or CI job from the config file.
+    ///
+    /// Does nothing if the pipeline's name or the CI job's id is already
+    /// remembered, since another bot may have saved it first.
+    fn add_config_id(
+        &mut self,
+        ConfigIdEntry,
+    ) -> Result<(), Box<Error + Send + Sync>>;
+    /// List every remembered id, with the CI jobs in the order of their ids.
+    fn list_config_ids(
+        &mut self,
+    ) -> Result<Vec<ConfigIdEntry>, Box<Error + Send + Sync>>;
 }
 
 pub trait Transaction {
@@ -318,11 +488,46 @@ pub trait Transaction {
     ) -> Result<Self::Return, Box<Error + Send + Sync>>;
 }
 
+/// Everything stored about one pipeline's queue, read in one transaction
+pub struct InfoTransaction {
+    pub pipeline_id: PipelineId,
+}
+
+impl Transaction for InfoTransaction {
+    type Return = PipelineInfo;
+    fn run<D: Db>(
+        self,
+        db: &mut D
+    ) -> Result<Self::Return, Box<Error + Send + Sync>> {
+        retry!{{
+            Ok(PipelineInfo{
+                paused: retry_unwrap!(db.is_paused(self.pipeline_id)),
+                pending: retry_unwrap!(db.list_pending(self.pipeline_id)),
+                queue: retry_unwrap!(db.list_queue(self.pipeline_id)),
+                running: retry_unwrap!(db.peek_running(self.pipeline_id)),
+            })
+        }}
+    }
+}
+
+/// What `InfoTransaction` returns
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PipelineInfo {
+    pub paused: bool,
+    pub pending: Vec<PendingEntry>,
+    pub queue: Vec<QueueEntry>,
+    pub running: Option<RunningEntry>,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(i32)]
 pub enum CiState {
     Succeeded = 1,
     Failed = 2,
+    Queued = 3,
+    Running = 4,
+    /// Stopped being waited for, because another job failed
+    Canceled = 5,
 }
 
 impl CiState {
@@ -330,11 +535,49 @@ impl CiState {
         match this {
             1 => CiState::Succeeded,
             2 => CiState::Failed,
+            3 => CiState::Queued,
+            4 => CiState::Running,
+            5 => CiState::Canceled,
             x => panic!("Invalid CI state: {}", x),
         }
     }
 }
 
+/// The state of a single CI job
+///
+/// Times are in seconds since the Unix epoch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CiStateEntry {
+    pub state: CiState,
+    pub commit: Commit,
+    pub url: Option<Url>,
+    pub started: Option<i64>,
+    pub finished: Option<i64>,
+}
+
+/// A failure that has gone away on retry at least once
+///
+/// `last_seen` is in seconds since the Unix epoch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlakyEntry {
+    pub signature: String,
+    pub count: i64,
+    pub last_seen: i64,
+}
+
+/// The id a pipeline or CI job from the config file was given
+///
+/// Pipelines keep their ids when projects are added or removed,
+/// so the rest of the state store doesn't end up pointing at the wrong one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigIdEntry {
+    /// The pipeline's name in the view; for a CI job, its type and name
+    pub name: String,
+    pub pipeline_id: PipelineId,
+    /// `None` for the pipeline itself
+    pub ci_id: Option<CiId>,
+}
+
 /// An item not yet in the build queue
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct PendingEntry {
@@ -342,6 +585,8 @@ pub struct PendingEntry {
     pub pr: Pr,
     pub title: String,
     pub url: Url,
+    /// Drafts cannot be approved until they are marked ready
+    pub draft: bool,
 }
 
 /// An item in the build queue
@@ -350,6 +595,8 @@ pub struct QueueEntry {
     pub commit: Commit,
     pub pr: Pr,
     pub message: String,
+    /// Higher priorities are built first; ties go in approval order
+    pub priority: i32,
 }
 
 /// An item in the build queue that is currently running
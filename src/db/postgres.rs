@@ -1,7 +1,8 @@
 // This file is released under the same terms as Rust itself.
 
 use ci::CiId;
-use db::{self, CiState, Db, PendingEntry, QueueEntry, RunningEntry};
+use db::{self, CiState, CiStateEntry, ConfigIdEntry, Db};
+use db::{FlakyEntry, PendingEntry, QueueEntry, RunningEntry};
 use hyper::Url;
 use postgres::{Connection, TlsMode};
 use postgres::params::{ConnectParams, IntoConnectParams};
@@ -9,6 +10,7 @@ use postgres::transaction::Transaction as PgTransaction;
 use std::error::Error;
 use ui::Pr;
 use pipeline::PipelineId;
+use util;
 use vcs::Commit;
 
 pub struct PostgresDb {
@@ -26,14 +28,41 @@ impl PostgresDb {
             CREATE TABLE IF NOT EXISTS ci_state (
                 ci_id SERIAL PRIMARY KEY,
                 ci_state INTEGER,
-                ci_commit TEXT
+                ci_commit TEXT,
+                ci_url TEXT,
+                started_at BIGINT,
+                finished_at BIGINT
+            );
+            ALTER TABLE ci_state ADD COLUMN IF NOT EXISTS ci_url TEXT;
+            ALTER TABLE ci_state ADD COLUMN IF NOT EXISTS started_at BIGINT;
+            ALTER TABLE ci_state ADD COLUMN IF NOT EXISTS finished_at BIGINT;
+            CREATE TABLE IF NOT EXISTS ci_failure (
+                ci_id INTEGER,
+                built_commit TEXT,
+                pull_commit TEXT,
+                attempt INTEGER,
+                signature TEXT
+            );
+            ALTER TABLE ci_failure ADD COLUMN IF NOT EXISTS pull_commit TEXT;
+            CREATE TABLE IF NOT EXISTS flaky (
+                ci_id INTEGER,
+                signature TEXT,
+                seen_count BIGINT,
+                last_seen BIGINT,
+                PRIMARY KEY (ci_id, signature)
             );
             CREATE TABLE IF NOT EXISTS queue (
                 id SERIAL PRIMARY KEY,
                 pipeline_id INTEGER,
                 pr TEXT,
                 message TEXT,
-                pull_commit TEXT
+                pull_commit TEXT,
+                priority INTEGER NOT NULL DEFAULT 0
+            );
+            ALTER TABLE queue
+                ADD COLUMN IF NOT EXISTS priority INTEGER NOT NULL DEFAULT 0;
+            CREATE TABLE IF NOT EXISTS paused (
+                pipeline_id INTEGER PRIMARY KEY
             );
             CREATE TABLE IF NOT EXISTS running (
                 pipeline_id INTEGER PRIMARY KEY,
@@ -52,6 +81,22 @@ impl PostgresDb {
                 title TEXT,
                 url TEXT
             );
+            ALTER TABLE pending
+                ADD COLUMN IF NOT EXISTS draft BOOLEAN NOT NULL DEFAULT FALSE;
+            CREATE TABLE IF NOT EXISTS config_id (
+                name TEXT,
+                pipeline_id INTEGER,
+                ci_id INTEGER UNIQUE
+            );
+            -- Two bots starting at once could both have saved a pipeline;
+            -- keep the first one's id, so there is one row per name.
+            DELETE FROM config_id AS a
+                USING config_id AS b
+                WHERE a.ci_id IS NULL AND b.ci_id IS NULL
+                    AND a.name = b.name AND a.ctid > b.ctid;
+            CREATE UNIQUE INDEX IF NOT EXISTS config_id_pipeline_name
+                ON config_id (name)
+                WHERE ci_id IS NULL;
         "###));
         Ok(result)
     }
@@ -104,6 +149,39 @@ impl Db for PostgresDb {
         ).list_queue(pipeline_id);
         result
     }
+    fn set_priority(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        priority: i32,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).set_priority(pipeline_id, pr, priority);
+        result
+    }
+    fn set_paused(
+        &mut self,
+        pipeline_id: PipelineId,
+        paused: bool,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).set_paused(pipeline_id, paused);
+        result
+    }
+    fn is_paused(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).is_paused(pipeline_id);
+        result
+    }
     fn put_running(
         &mut self,
         pipeline_id: PipelineId,
@@ -204,13 +282,12 @@ impl Db for PostgresDb {
     fn set_ci_state(
         &mut self,
         ci_id: CiId,
-        ci_state: CiState,
-        commit: &Commit,
+        entry: CiStateEntry,
     ) -> Result<(), Box<Error + Send + Sync>> {
         let conn = try!(self.conn());
         let result = PostgresTransaction::new(
             try!(conn.transaction())
-        ).set_ci_state(ci_id, ci_state, commit);
+        ).set_ci_state(ci_id, entry);
         result
     }
     fn clear_ci_state(
@@ -226,13 +303,88 @@ impl Db for PostgresDb {
     fn get_ci_state(
         &mut self,
         ci_id: CiId,
-    ) -> Result<Option<(CiState, Commit)>, Box<Error + Send + Sync>> {
+    ) -> Result<Option<CiStateEntry>, Box<Error + Send + Sync>> {
         let conn = try!(self.conn());
         let result = PostgresTransaction::new(
             try!(conn.transaction())
         ).get_ci_state(ci_id);
         result
     }
+    fn add_ci_failure(
+        &mut self,
+        ci_id: CiId,
+        commit: &Commit,
+        pull_commit: &Commit,
+        signatures: &[String],
+    ) -> Result<u32, Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).add_ci_failure(ci_id, commit, pull_commit, signatures);
+        result
+    }
+    fn take_ci_failures(
+        &mut self,
+        ci_id: CiId,
+        commit: &Commit,
+    ) -> Result<Vec<String>, Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).take_ci_failures(ci_id, commit);
+        result
+    }
+    fn clear_other_ci_failures(
+        &mut self,
+        ci_id: CiId,
+        pull_commit: &Commit,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).clear_other_ci_failures(ci_id, pull_commit);
+        result
+    }
+    fn add_flaky(
+        &mut self,
+        ci_id: CiId,
+        signature: &str,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).add_flaky(ci_id, signature);
+        result
+    }
+    fn list_flaky(
+        &mut self,
+        ci_id: CiId,
+    ) -> Result<Vec<FlakyEntry>, Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).list_flaky(ci_id);
+        result
+    }
+    fn add_config_id(
+        &mut self,
+        entry: ConfigIdEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).add_config_id(entry);
+        result
+    }
+    fn list_config_ids(
+        &mut self,
+    ) -> Result<Vec<ConfigIdEntry>, Box<Error + Send + Sync>> {
+        let conn = try!(self.conn());
+        let result = PostgresTransaction::new(
+            try!(conn.transaction())
+        ).list_config_ids();
+        result
+    }
 }
 
 
@@ -252,17 +404,19 @@ impl<'a> Db for PostgresTransaction<'a> {
     fn push_queue(
         &mut self,
         pipeline_id: PipelineId,
-        QueueEntry{pr, commit, message}: QueueEntry
+        QueueEntry{pr, commit, message, priority}: QueueEntry
     ) -> Result<(), Box<Error + Send + Sync>> {
         let sql = r###"
-            INSERT INTO queue (pr, pipeline_id, pull_commit, message)
-            VALUES ($1, $2, $3, $4)
+            INSERT INTO queue
+                (pr, pipeline_id, pull_commit, message, priority)
+            VALUES ($1, $2, $3, $4, $5)
         "###;
         try!(self.conn.execute(sql, &[
             &pr.as_str(),
             &pipeline_id.0,
             &commit.as_str(),
             &message,
+            &priority,
         ]));
         Ok(())
     }
@@ -273,10 +427,10 @@ impl<'a> Db for PostgresTransaction<'a> {
         let trans = try!(self.conn
             .transaction());
         let sql = r###"
-            SELECT id, pr, pull_commit, message
+            SELECT id, pr, pull_commit, message, priority
             FROM queue
             WHERE pipeline_id = $1
-            ORDER BY id ASC LIMIT 1
+            ORDER BY priority DESC, id ASC LIMIT 1
         "###;
         let item = {
             let stmt = try!(trans.prepare(sql));
@@ -288,6 +442,7 @@ impl<'a> Db for PostgresTransaction<'a> {
                     pr: Pr::from(row.get::<_, String>(1)),
                     commit: Commit::from(row.get::<_, String>(2)),
                     message: row.get::<_, String>(3),
+                    priority: row.get::<_, i32>(4),
                 },
             ));
             rows.next()
@@ -306,10 +461,10 @@ impl<'a> Db for PostgresTransaction<'a> {
         pipeline_id: PipelineId,
     ) -> Result<Vec<QueueEntry>, Box<Error + Send + Sync>> {
         let sql = r###"
-            SELECT pr, pull_commit, message
+            SELECT pr, pull_commit, message, priority
             FROM queue
             WHERE pipeline_id = $1
-            ORDER BY id ASC
+            ORDER BY priority DESC, id ASC
         "###;
         let stmt = try!(self.conn.prepare(&sql));
         let rows = try!(stmt.query(&[&pipeline_id.0]));
@@ -318,10 +473,59 @@ impl<'a> Db for PostgresTransaction<'a> {
             pr: Pr::from(row.get::<_, String>(0)),
             commit: Commit::from(row.get::<_, String>(1)),
             message: row.get::<_, String>(2),
+            priority: row.get::<_, i32>(3),
         });
         let rows: Vec<QueueEntry> = rows.collect();
         Ok(rows)
     }
+    fn set_priority(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        priority: i32,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
+        let sql = r###"
+            UPDATE queue
+            SET priority = $1
+            WHERE pipeline_id = $2 AND pr = $3
+        "###;
+        let affected_rows = try!(self.conn.execute(sql, &[
+            &priority,
+            &pipeline_id.0,
+            &pr.as_str(),
+        ]));
+        Ok(affected_rows != 0)
+    }
+    fn set_paused(
+        &mut self,
+        pipeline_id: PipelineId,
+        paused: bool,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let sql = if paused {
+            r###"
+                INSERT INTO paused (pipeline_id)
+                VALUES ($1)
+                ON CONFLICT DO NOTHING
+            "###
+        } else {
+            r###"
+                DELETE FROM paused WHERE pipeline_id = $1
+            "###
+        };
+        try!(self.conn.execute(sql, &[&pipeline_id.0]));
+        Ok(())
+    }
+    fn is_paused(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT pipeline_id FROM paused WHERE pipeline_id = $1
+        "###;
+        let stmt = try!(self.conn.prepare(&sql));
+        let rows = try!(stmt.query(&[&pipeline_id.0]));
+        Ok(!rows.is_empty())
+    }
     fn put_running(
         &mut self,
         pipeline_id: PipelineId,
@@ -435,8 +639,9 @@ impl<'a> Db for PostgresTransaction<'a> {
             &entry.pr.as_str(),
         ]));
         let sql = r###"
-            INSERT INTO pending (pipeline_id, pr, pull_commit, title, url)
-            VALUES ($1, $2, $3, $4, $5)
+            INSERT INTO pending
+                (pipeline_id, pr, pull_commit, title, url, draft)
+            VALUES ($1, $2, $3, $4, $5, $6)
         "###;
         try!(trans.execute(sql, &[
             &pipeline_id.0,
@@ -444,6 +649,7 @@ impl<'a> Db for PostgresTransaction<'a> {
             &entry.commit.as_str(),
             &entry.title,
             &entry.url.as_str(),
+            &entry.draft,
         ]));
         try!(trans.commit());
         Ok(())
@@ -455,7 +661,7 @@ impl<'a> Db for PostgresTransaction<'a> {
     ) -> Result<Option<PendingEntry>, Box<Error + Send + Sync>> {
         let trans = try!(self.conn.transaction());
         let sql = r###"
-            SELECT id, pr, pull_commit, title, url
+            SELECT id, pr, pull_commit, title, url, draft
             FROM pending
             WHERE pipeline_id = $1 AND pr = $2
         "###;
@@ -471,6 +677,7 @@ impl<'a> Db for PostgresTransaction<'a> {
                 commit: Commit::from(row.get::<_, String>(2)),
                 title: row.get(3),
                 url: Url::parse(&row.get::<_, String>(4)).unwrap(),
+                draft: row.get(5),
             }));
             rows.next()
         };
@@ -489,7 +696,7 @@ impl<'a> Db for PostgresTransaction<'a> {
         pr: &Pr,
     ) -> Result<Option<PendingEntry>, Box<Error + Send + Sync>> {
         let sql = r###"
-            SELECT pr, pull_commit, title, url
+            SELECT pr, pull_commit, title, url, draft
             FROM pending
             WHERE pipeline_id = $1 AND pr = $2
         "###;
@@ -504,6 +711,7 @@ impl<'a> Db for PostgresTransaction<'a> {
             commit: Commit::from(row.get::<_, String>(1)),
             title: row.get(2),
             url: Url::parse(&row.get::<_, String>(3)).unwrap(),
+            draft: row.get(4),
         });
         Ok(rows.next())
     }
@@ -512,7 +720,7 @@ impl<'a> Db for PostgresTransaction<'a> {
         pipeline_id: PipelineId,
     ) -> Result<Vec<PendingEntry>, Box<Error + Send + Sync>> {
         let sql = r###"
-            SELECT pr, pull_commit, title, url
+            SELECT pr, pull_commit, title, url, draft
             FROM pending
             WHERE pipeline_id = $1
         "###;
@@ -524,6 +732,7 @@ impl<'a> Db for PostgresTransaction<'a> {
             commit: Commit::from(row.get::<_, String>(1)),
             title: row.get(2),
             url: Url::parse(&row.get::<_, String>(3)).unwrap(),
+            draft: row.get(4),
         });
         let rows: Vec<PendingEntry> = rows.collect();
         Ok(rows)
@@ -582,20 +791,26 @@ impl<'a> Db for PostgresTransaction<'a> {
     fn set_ci_state(
         &mut self,
         ci_id: CiId,
-        ci_state: CiState,
-        commit: &Commit,
+        entry: CiStateEntry,
     ) -> Result<(), Box<Error + Send + Sync>> {
         let sql = r###"
-            INSERT INTO ci_state (ci_id, ci_state, ci_commit)
-            VALUES ($1, $2, $3)
+            INSERT INTO ci_state
+                (ci_id, ci_state, ci_commit, ci_url, started_at, finished_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
             ON CONFLICT (ci_id) DO UPDATE SET
                 ci_state = $2,
-                ci_commit = $3
+                ci_commit = $3,
+                ci_url = $4,
+                started_at = $5,
+                finished_at = $6
         "###;
         try!(self.conn.execute(sql, &[
             &ci_id.0,
-            &(ci_state as i32),
-            &commit.as_str(),
+            &(entry.state as i32),
+            &entry.commit.as_str(),
+            &entry.url.as_ref().map(Url::as_str),
+            &entry.started,
+            &entry.finished,
         ]));
         Ok(())
     }
@@ -615,21 +830,170 @@ impl<'a> Db for PostgresTransaction<'a> {
     fn get_ci_state(
         &mut self,
         ci_id: CiId,
-    ) -> Result<Option<(CiState, Commit)>, Box<Error + Send + Sync>> {
+    ) -> Result<Option<CiStateEntry>, Box<Error + Send + Sync>> {
         let sql = r###"
             SELECT 
-                ci_state, ci_commit
+                ci_state, ci_commit, ci_url, started_at, finished_at
             FROM ci_state
             WHERE ci_id = $1
         "###;
         let stmt = try!(self.conn.prepare(&sql));
         let rows = try!(stmt.query(&[ &ci_id.0 ]));
         let rows = rows.iter();
-        let mut rows = rows.map(|row| (
-            CiState::from_i32(row.get::<_, i32>(0)),
-            Commit::from(row.get::<_, String>(1)),
-        ));
+        let mut rows = rows.map(|row| CiStateEntry {
+            state: CiState::from_i32(row.get::<_, i32>(0)),
+            commit: Commit::from(row.get::<_, String>(1)),
+            url: row.get::<_, Option<String>>(2)
+                .and_then(|url| Url::parse(&url).ok()),
+            started: row.get(3),
+            finished: row.get(4),
+        });
         let value = rows.next();
         Ok(value)
     }
-}
\ No newline at end of file
+    fn add_ci_failure(
+        &mut self,
+        ci_id: CiId,
+        commit: &Commit,
+        pull_commit: &Commit,
+        signatures: &[String],
+    ) -> Result<u32, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT COALESCE(MAX(attempt), 0)
+            FROM ci_failure
+            WHERE ci_id = $1 AND built_commit = $2
+        "###;
+        let attempt = {
+            let stmt = try!(self.conn.prepare(&sql));
+            let rows = try!(stmt.query(&[ &ci_id.0, &commit.as_str() ]));
+            let attempt = rows.iter().next()
+                .map(|row| row.get::<_, i32>(0))
+                .unwrap_or(0);
+            attempt + 1
+        };
+        let sql = r###"
+            INSERT INTO ci_failure
+                (ci_id, built_commit, pull_commit, attempt, signature)
+            VALUES ($1, $2, $3, $4, $5)
+        "###;
+        for signature in signatures {
+            try!(self.conn.execute(sql, &[
+                &ci_id.0,
+                &commit.as_str(),
+                &pull_commit.as_str(),
+                &attempt,
+                signature,
+            ]));
+        }
+        Ok(attempt as u32)
+    }
+    fn take_ci_failures(
+        &mut self,
+        ci_id: CiId,
+        commit: &Commit,
+    ) -> Result<Vec<String>, Box<Error + Send + Sync>> {
+        let sql = r###"
+            DELETE FROM ci_failure
+            WHERE ci_id = $1 AND built_commit = $2
+            RETURNING signature
+        "###;
+        let stmt = try!(self.conn.prepare(&sql));
+        let rows = try!(stmt.query(&[ &ci_id.0, &commit.as_str() ]));
+        let mut result: Vec<String> = Vec::new();
+        for row in rows.iter() {
+            let signature: String = row.get(0);
+            if !result.contains(&signature) {
+                result.push(signature);
+            }
+        }
+        Ok(result)
+    }
+    fn clear_other_ci_failures(
+        &mut self,
+        ci_id: CiId,
+        pull_commit: &Commit,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let sql = r###"
+            DELETE FROM ci_failure
+            WHERE ci_id = $1 AND pull_commit IS DISTINCT FROM $2
+        "###;
+        try!(self.conn.execute(sql, &[
+            &ci_id.0,
+            &pull_commit.as_str(),
+        ]));
+        Ok(())
+    }
+    fn add_flaky(
+        &mut self,
+        ci_id: CiId,
+        signature: &str,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let sql = r###"
+            INSERT INTO flaky (ci_id, signature, seen_count, last_seen)
+            VALUES ($1, $2, 1, $3)
+            ON CONFLICT (ci_id, signature) DO UPDATE SET
+                seen_count = flaky.seen_count + 1,
+                last_seen = $3
+        "###;
+        try!(self.conn.execute(sql, &[
+            &ci_id.0,
+            &signature,
+            &util::now_secs(),
+        ]));
+        Ok(())
+    }
+    fn list_flaky(
+        &mut self,
+        ci_id: CiId,
+    ) -> Result<Vec<FlakyEntry>, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT signature, seen_count, last_seen
+            FROM flaky
+            WHERE ci_id = $1
+            ORDER BY seen_count DESC, signature ASC
+        "###;
+        let stmt = try!(self.conn.prepare(&sql));
+        let rows = try!(stmt.query(&[ &ci_id.0 ]));
+        let rows = rows.iter();
+        let rows = rows.map(|row| FlakyEntry {
+            signature: row.get(0),
+            count: row.get(1),
+            last_seen: row.get(2),
+        });
+        Ok(rows.collect())
+    }
+    fn add_config_id(
+        &mut self,
+        ConfigIdEntry{name, pipeline_id, ci_id}: ConfigIdEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let sql = r###"
+            INSERT INTO config_id (name, pipeline_id, ci_id)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+        "###;
+        try!(self.conn.execute(sql, &[
+            &name,
+            &pipeline_id.0,
+            &ci_id.map(|ci_id| ci_id.0),
+        ]));
+        Ok(())
+    }
+    fn list_config_ids(
+        &mut self,
+    ) -> Result<Vec<ConfigIdEntry>, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT name, pipeline_id, ci_id
+            FROM config_id
+            ORDER BY ci_id ASC
+        "###;
+        let stmt = try!(self.conn.prepare(&sql));
+        let rows = try!(stmt.query(&[]));
+        let rows = rows.iter();
+        let rows = rows.map(|row| ConfigIdEntry {
+            name: row.get(0),
+            pipeline_id: PipelineId(row.get(1)),
+            ci_id: row.get::<_, Option<i32>>(2).map(CiId),
+        });
+        Ok(rows.collect())
+    }
+}
@@ -1,7 +1,8 @@
 // This file is released under the same terms as Rust itself.
 
 use ci::CiId;
-use db::{self, CiState, Db, PendingEntry, QueueEntry, RunningEntry};
+use db::{self, CiState, CiStateEntry, ConfigIdEntry, Db};
+use db::{FlakyEntry, PendingEntry, QueueEntry, RunningEntry};
 use hyper::Url;
 use pipeline::PipelineId;
 use rusqlite::{self, Connection};
@@ -9,6 +10,7 @@ use std::convert::AsRef;
 use std::error::Error;
 use std::path::Path;
 use ui::Pr;
+use util;
 use vcs::Commit;
 
 pub struct SqliteDb {
@@ -22,14 +24,35 @@ impl SqliteDb {
             CREATE TABLE IF NOT EXISTS ci_state (
                 ci_id INTEGER,
                 ci_state INTEGER,
-                ci_commit STRING
+                ci_commit STRING,
+                ci_url TEXT,
+                started_at INTEGER,
+                finished_at INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS ci_failure (
+                ci_id INTEGER,
+                built_commit TEXT,
+                pull_commit TEXT,
+                attempt INTEGER,
+                signature TEXT
+            );
+            CREATE TABLE IF NOT EXISTS flaky (
+                ci_id INTEGER,
+                signature TEXT,
+                seen_count INTEGER,
+                last_seen INTEGER,
+                PRIMARY KEY (ci_id, signature)
             );
             CREATE TABLE IF NOT EXISTS queue (
                 id INTEGER PRIMARY KEY,
                 pipeline_id INTEGER,
                 pr TEXT,
                 message TEXT,
-                pull_commit TEXT
+                pull_commit TEXT,
+                priority INTEGER NOT NULL DEFAULT 0
+            );
+            CREATE TABLE IF NOT EXISTS paused (
+                pipeline_id INTEGER PRIMARY KEY
             );
             CREATE TABLE IF NOT EXISTS running (
                 pipeline_id INTEGER PRIMARY KEY,
@@ -46,15 +69,78 @@ impl SqliteDb {
                 pr TEXT,
                 pull_commit TEXT,
                 title TEXT,
-                url TEXT
+                url TEXT,
+                draft BOOLEAN NOT NULL DEFAULT 0
+            );
+            CREATE TABLE IF NOT EXISTS config_id (
+                name TEXT,
+                pipeline_id INTEGER,
+                ci_id INTEGER UNIQUE
             );
+            -- Two bots starting at once could both have saved a pipeline;
+            -- keep the first one's id, so there is one row per name.
+            DELETE FROM config_id
+                WHERE ci_id IS NULL AND rowid NOT IN (
+                    SELECT MIN(rowid) FROM config_id
+                    WHERE ci_id IS NULL
+                    GROUP BY name
+                );
+            CREATE UNIQUE INDEX IF NOT EXISTS config_id_pipeline_name
+                ON config_id (name)
+                WHERE ci_id IS NULL;
         "###));
+        try!(add_missing_columns(&conn, "ci_state", &[
+            ("ci_url", "TEXT"),
+            ("started_at", "INTEGER"),
+            ("finished_at", "INTEGER"),
+        ]));
+        try!(add_missing_columns(&conn, "ci_failure", &[
+            ("pull_commit", "TEXT"),
+        ]));
+        try!(add_missing_columns(&conn, "queue", &[
+            ("priority", "INTEGER NOT NULL DEFAULT 0"),
+        ]));
+        try!(add_missing_columns(&conn, "pending", &[
+            ("draft", "BOOLEAN NOT NULL DEFAULT 0"),
+        ]));
         Ok(SqliteDb{
             conn: conn,
         })
     }
 }
 
+/// Bring a table created by an older version up to date.
+///
+/// SQLite has no `ADD COLUMN IF NOT EXISTS`,
+/// so look at what's there first.
+pub fn add_missing_columns(
+    conn: &Connection,
+    table: &str,
+    columns: &[(&str, &str)],
+) -> rusqlite::Result<()> {
+    let existing = {
+        let sql = format!("PRAGMA table_info({})", table);
+        let mut stmt = try!(conn.prepare(&sql));
+        let rows = try!(stmt.query_map(&[], |row| row.get::<_, String>(1)));
+        let mut existing = Vec::new();
+        for row in rows {
+            existing.push(try!(row));
+        }
+        existing
+    };
+    for &(name, ty) in columns {
+        if !existing.iter().any(|column| column == name) {
+            try!(conn.execute_batch(&format!(
+                "ALTER TABLE {} ADD COLUMN {} {};",
+                table,
+                name,
+                ty,
+            )));
+        }
+    }
+    Ok(())
+}
+
 
 impl Db for SqliteDb {
     fn transaction<T: db::Transaction>(
@@ -93,6 +179,33 @@ impl Db for SqliteDb {
             try!(self.conn.transaction())
         ).list_queue(pipeline_id)
     }
+    fn set_priority(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        priority: i32,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).set_priority(pipeline_id, pr, priority)
+    }
+    fn set_paused(
+        &mut self,
+        pipeline_id: PipelineId,
+        paused: bool,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).set_paused(pipeline_id, paused)
+    }
+    fn is_paused(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).is_paused(pipeline_id)
+    }
     fn put_running(
         &mut self,
         pipeline_id: PipelineId,
@@ -175,12 +288,11 @@ impl Db for SqliteDb {
     fn set_ci_state(
         &mut self,
         ci_id: CiId,
-        ci_state: CiState,
-        commit: &Commit,
+        entry: CiStateEntry,
     ) -> Result<(), Box<Error + Send + Sync>> {
         SqliteTransaction::new(
             try!(self.conn.transaction())
-        ).set_ci_state(ci_id, ci_state, commit)
+        ).set_ci_state(ci_id, entry)
     }
     fn clear_ci_state(
         &mut self,
@@ -193,11 +305,72 @@ impl Db for SqliteDb {
     fn get_ci_state(
         &mut self,
         ci_id: CiId,
-    ) -> Result<Option<(CiState, Commit)>, Box<Error + Send + Sync>> {
+    ) -> Result<Option<CiStateEntry>, Box<Error + Send + Sync>> {
         SqliteTransaction::new(
             try!(self.conn.transaction())
         ).get_ci_state(ci_id)
     }
+    fn add_ci_failure(
+        &mut self,
+        ci_id: CiId,
+        commit: &Commit,
+        pull_commit: &Commit,
+        signatures: &[String],
+    ) -> Result<u32, Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).add_ci_failure(ci_id, commit, pull_commit, signatures)
+    }
+    fn take_ci_failures(
+        &mut self,
+        ci_id: CiId,
+        commit: &Commit,
+    ) -> Result<Vec<String>, Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).take_ci_failures(ci_id, commit)
+    }
+    fn clear_other_ci_failures(
+        &mut self,
+        ci_id: CiId,
+        pull_commit: &Commit,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).clear_other_ci_failures(ci_id, pull_commit)
+    }
+    fn add_flaky(
+        &mut self,
+        ci_id: CiId,
+        signature: &str,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).add_flaky(ci_id, signature)
+    }
+    fn list_flaky(
+        &mut self,
+        ci_id: CiId,
+    ) -> Result<Vec<FlakyEntry>, Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).list_flaky(ci_id)
+    }
+    fn add_config_id(
+        &mut self,
+        entry: ConfigIdEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).add_config_id(entry)
+    }
+    fn list_config_ids(
+        &mut self,
+    ) -> Result<Vec<ConfigIdEntry>, Box<Error + Send + Sync>> {
+        SqliteTransaction::new(
+            try!(self.conn.transaction())
+        ).list_config_ids()
+    }
 }
 
 
@@ -217,17 +390,19 @@ impl<'a> Db for SqliteTransaction<'a> {
     fn push_queue(
         &mut self,
         pipeline_id: PipelineId,
-        QueueEntry{pr, commit, message}: QueueEntry,
+        QueueEntry{pr, commit, message, priority}: QueueEntry,
     ) -> Result<(), Box<Error + Send + Sync>> {
         let sql = r###"
-            INSERT INTO queue (pr, pipeline_id, pull_commit, message)
-            VALUES (?, ?, ?, ?)
+            INSERT INTO queue
+                (pr, pipeline_id, pull_commit, message, priority)
+            VALUES (?, ?, ?, ?, ?)
         "###;
         try!(self.conn.execute(sql, &[
             &pr.as_str(),
             &pipeline_id.0,
             &commit.as_str(),
             &message,
+            &priority,
         ]));
         Ok(())
     }
@@ -236,10 +411,10 @@ impl<'a> Db for SqliteTransaction<'a> {
         pipeline_id: PipelineId,
     ) -> Result<Option<QueueEntry>, Box<Error + Send + Sync>> {
         let sql = r###"
-            SELECT id, pr, pull_commit, message
+            SELECT id, pr, pull_commit, message, priority
             FROM queue
             WHERE pipeline_id = ?
-            ORDER BY id ASC LIMIT 1
+            ORDER BY priority DESC, id ASC LIMIT 1
         "###;
         let item = {
             let mut stmt = try!(self.conn.prepare(sql));
@@ -250,6 +425,7 @@ impl<'a> Db for SqliteTransaction<'a> {
                     pr: Pr::from(row.get::<_, String>(1)),
                     commit: Commit::from(row.get::<_, String>(2)),
                     message: row.get::<_, String>(3),
+                    priority: row.get::<_, i32>(4),
                 },
             )));
             rows.next()
@@ -271,16 +447,17 @@ impl<'a> Db for SqliteTransaction<'a> {
         pipeline_id: PipelineId,
     ) -> Result<Vec<QueueEntry>, Box<Error + Send + Sync>> {
         let sql = r###"
-            SELECT pr, pull_commit, message
+            SELECT pr, pull_commit, message, priority
             FROM queue
             WHERE pipeline_id = ?
-            ORDER BY id ASC
+            ORDER BY priority DESC, id ASC
         "###;
         let mut stmt = try!(self.conn.prepare(&sql));
         let rows = try!(stmt.query_map(&[&pipeline_id.0], |row| QueueEntry {
                 pr: Pr::from(row.get::<_, String>(0)),
                 commit: Commit::from(row.get::<_, String>(1)),
                 message: row.get::<_, String>(2),
+                priority: row.get::<_, i32>(3),
             })
         );
         let mut v = vec![];
@@ -292,6 +469,56 @@ impl<'a> Db for SqliteTransaction<'a> {
         }
         Ok(v)
     }
+    fn set_priority(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        priority: i32,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
+        let sql = r###"
+            UPDATE queue
+            SET priority = ?
+            WHERE pipeline_id = ? AND pr = ?
+        "###;
+        let affected_rows = try!(self.conn.execute(sql, &[
+            &priority,
+            &pipeline_id.0,
+            &pr.as_str(),
+        ]));
+        Ok(affected_rows != 0)
+    }
+    fn set_paused(
+        &mut self,
+        pipeline_id: PipelineId,
+        paused: bool,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let sql = if paused {
+            r###"
+                INSERT OR IGNORE INTO paused (pipeline_id)
+                VALUES (?)
+            "###
+        } else {
+            r###"
+                DELETE FROM paused WHERE pipeline_id = ?
+            "###
+        };
+        try!(self.conn.execute(sql, &[&pipeline_id.0]));
+        Ok(())
+    }
+    fn is_paused(
+        &mut self,
+        pipeline_id: PipelineId,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT COUNT(*) FROM paused WHERE pipeline_id = ?
+        "###;
+        let count: i64 = try!(self.conn.query_row(
+            sql,
+            &[&pipeline_id.0],
+            |row| row.get(0),
+        ));
+        Ok(count != 0)
+    }
     fn put_running(
         &mut self,
         pipeline_id: PipelineId,
@@ -403,8 +630,9 @@ impl<'a> Db for SqliteTransaction<'a> {
             &entry.pr.as_str(),
         ]).expect("Remove pending entry");
         let sql = r###"
-            INSERT INTO pending (pipeline_id, pr, pull_commit, title, url)
-            VALUES (?, ?, ?, ?, ?)
+            INSERT INTO pending
+                (pipeline_id, pr, pull_commit, title, url, draft)
+            VALUES (?, ?, ?, ?, ?, ?)
         "###;
         try!(self.conn.execute(sql, &[
             &pipeline_id.0,
@@ -412,6 +640,7 @@ impl<'a> Db for SqliteTransaction<'a> {
             &entry.commit.as_str(),
             &entry.title,
             &entry.url.as_str(),
+            &entry.draft,
         ]));
         Ok(())
     }
@@ -421,7 +650,7 @@ impl<'a> Db for SqliteTransaction<'a> {
         pr: &Pr,
     ) -> Result<Option<PendingEntry>, Box<Error + Send + Sync>> {
         let sql = r###"
-            SELECT id, pr, pull_commit, title, url
+            SELECT id, pr, pull_commit, title, url, draft
             FROM pending
             WHERE pipeline_id = ? AND pr = ?
         "###;
@@ -436,6 +665,7 @@ impl<'a> Db for SqliteTransaction<'a> {
                     commit: Commit::from(row.get::<_, String>(2)),
                     title: row.get(3),
                     url: Url::parse(&row.get::<_, String>(4)).unwrap(),
+                    draft: row.get(5),
                 }))
             );
             match rows.next() {
@@ -460,7 +690,7 @@ impl<'a> Db for SqliteTransaction<'a> {
         pr: &Pr,
     ) -> Result<Option<PendingEntry>, Box<Error + Send + Sync>> {
         let sql = r###"
-            SELECT pr, pull_commit, title, url
+            SELECT pr, pull_commit, title, url, draft
             FROM pending
             WHERE pipeline_id = ? AND pr = ?
         "###;
@@ -474,6 +704,7 @@ impl<'a> Db for SqliteTransaction<'a> {
                 commit: Commit::from(row.get::<_, String>(1)),
                 title: row.get(2),
                 url: Url::parse(&row.get::<_, String>(3)).unwrap(),
+                draft: row.get(4),
             })
         );
         match rows.next() {
@@ -487,7 +718,7 @@ impl<'a> Db for SqliteTransaction<'a> {
         pipeline_id: PipelineId,
     ) -> Result<Vec<PendingEntry>, Box<Error + Send + Sync>> {
         let sql = r###"
-            SELECT pr, pull_commit, title, url
+            SELECT pr, pull_commit, title, url, draft
             FROM pending
             WHERE pipeline_id = ?
         "###;
@@ -497,6 +728,7 @@ impl<'a> Db for SqliteTransaction<'a> {
                 commit: Commit::from(row.get::<_, String>(1)),
                 title: row.get(2),
                 url: Url::parse(&row.get::<_, String>(3)).unwrap(),
+                draft: row.get(4),
             })
         );
         let mut v = vec![];
@@ -562,23 +794,30 @@ impl<'a> Db for SqliteTransaction<'a> {
     fn set_ci_state(
         &mut self,
         ci_id: CiId,
-        ci_state: CiState,
-        commit: &Commit,
+        entry: CiStateEntry,
     ) -> Result<(), Box<Error + Send + Sync>> {
+        // There's no key on ci_id, so REPLACE would not replace anything.
+        try!(self.clear_ci_state(ci_id));
         let sql = r###"
-            REPLACE INTO ci_state
+            INSERT INTO ci_state
                 (
                     ci_id,
                     ci_state,
-                    ci_commit
+                    ci_commit,
+                    ci_url,
+                    started_at,
+                    finished_at
                 )
             VALUES
-                (?, ?, ?)
+                (?, ?, ?, ?, ?, ?)
         "###;
         try!(self.conn.execute(sql, &[
             &ci_id.0,
-            &(ci_state as i32),
-            &commit.as_str(),
+            &(entry.state as i32),
+            &entry.commit.as_str(),
+            &entry.url.as_ref().map(Url::as_str),
+            &entry.started,
+            &entry.finished,
         ]));
         Ok(())
     }
@@ -598,9 +837,9 @@ impl<'a> Db for SqliteTransaction<'a> {
     fn get_ci_state(
         &mut self,
         ci_id: CiId,
-    ) -> Result<Option<(CiState, Commit)>, Box<Error + Send + Sync>> {
+    ) -> Result<Option<CiStateEntry>, Box<Error + Send + Sync>> {
         let sql = r###"
-            SELECT ci_state, ci_commit
+            SELECT ci_state, ci_commit, ci_url, started_at, finished_at
             FROM ci_state
             WHERE ci_id = ?
         "###;
@@ -609,10 +848,14 @@ impl<'a> Db for SqliteTransaction<'a> {
             let mut rows = try!(stmt
                 .query_map(&[
                     &ci_id.0,
-                ], |row| (
-                    CiState::from_i32(row.get::<_, i32>(0)),
-                    Commit::from(row.get::<_, String>(1)),
-                ))
+                ], |row| CiStateEntry {
+                    state: CiState::from_i32(row.get::<_, i32>(0)),
+                    commit: Commit::from(row.get::<_, String>(1)),
+                    url: row.get::<_, Option<String>>(2)
+                        .and_then(|url| Url::parse(&url).ok()),
+                    started: row.get(3),
+                    finished: row.get(4),
+                })
             );
             match rows.next() {
                 Some(Err(e)) => return Err(e.into()),
@@ -622,4 +865,178 @@ impl<'a> Db for SqliteTransaction<'a> {
         };
         Ok(entry)
     }
-}
\ No newline at end of file
+    fn add_ci_failure(
+        &mut self,
+        ci_id: CiId,
+        commit: &Commit,
+        pull_commit: &Commit,
+        signatures: &[String],
+    ) -> Result<u32, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT COALESCE(MAX(attempt), 0)
+            FROM ci_failure
+            WHERE ci_id = ? AND built_commit = ?
+        "###;
+        let attempt = {
+            let mut stmt = try!(self.conn.prepare(&sql));
+            let mut rows = try!(stmt.query_map(&[
+                &ci_id.0,
+                &commit.as_str(),
+            ], |row| row.get::<_, i32>(0)));
+            match rows.next() {
+                Some(Err(e)) => return Err(e.into()),
+                Some(Ok(attempt)) => attempt + 1,
+                None => 1,
+            }
+        };
+        let sql = r###"
+            INSERT INTO ci_failure
+                (ci_id, built_commit, pull_commit, attempt, signature)
+            VALUES (?, ?, ?, ?, ?)
+        "###;
+        for signature in signatures {
+            try!(self.conn.execute(sql, &[
+                &ci_id.0,
+                &commit.as_str(),
+                &pull_commit.as_str(),
+                &attempt,
+                signature,
+            ]));
+        }
+        Ok(attempt as u32)
+    }
+    fn take_ci_failures(
+        &mut self,
+        ci_id: CiId,
+        commit: &Commit,
+    ) -> Result<Vec<String>, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT DISTINCT signature
+            FROM ci_failure
+            WHERE ci_id = ? AND built_commit = ?
+        "###;
+        let mut result = Vec::new();
+        {
+            let mut stmt = try!(self.conn.prepare(&sql));
+            let rows = try!(stmt.query_map(&[
+                &ci_id.0,
+                &commit.as_str(),
+            ], |row| row.get::<_, String>(0)));
+            for row in rows {
+                result.push(try!(row));
+            }
+        }
+        let sql = r###"
+            DELETE FROM ci_failure
+            WHERE ci_id = ? AND built_commit = ?
+        "###;
+        try!(self.conn.execute(sql, &[
+            &ci_id.0,
+            &commit.as_str(),
+        ]));
+        Ok(result)
+    }
+    fn clear_other_ci_failures(
+        &mut self,
+        ci_id: CiId,
+        pull_commit: &Commit,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let sql = r###"
+            DELETE FROM ci_failure
+            WHERE ci_id = ? AND pull_commit IS NOT ?
+        "###;
+        try!(self.conn.execute(sql, &[
+            &ci_id.0,
+            &pull_commit.as_str(),
+        ]));
+        Ok(())
+    }
+    fn add_flaky(
+        &mut self,
+        ci_id: CiId,
+        signature: &str,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let now = util::now_secs();
+        let sql = r###"
+            UPDATE flaky
+            SET seen_count = seen_count + 1, last_seen = ?
+            WHERE ci_id = ? AND signature = ?
+        "###;
+        let updated = try!(self.conn.execute(sql, &[
+            &now,
+            &ci_id.0,
+            &signature,
+        ]));
+        if updated == 0 {
+            let sql = r###"
+                INSERT INTO flaky (ci_id, signature, seen_count, last_seen)
+                VALUES (?, ?, 1, ?)
+            "###;
+            try!(self.conn.execute(sql, &[
+                &ci_id.0,
+                &signature,
+                &now,
+            ]));
+        }
+        Ok(())
+    }
+    fn list_flaky(
+        &mut self,
+        ci_id: CiId,
+    ) -> Result<Vec<FlakyEntry>, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT signature, seen_count, last_seen
+            FROM flaky
+            WHERE ci_id = ?
+            ORDER BY seen_count DESC, signature ASC
+        "###;
+        let mut stmt = try!(self.conn.prepare(&sql));
+        let rows = try!(stmt.query_map(&[
+            &ci_id.0,
+        ], |row| FlakyEntry {
+            signature: row.get(0),
+            count: row.get(1),
+            last_seen: row.get(2),
+        }));
+        let mut result = Vec::new();
+        for row in rows {
+            result.push(try!(row));
+        }
+        Ok(result)
+    }
+    fn add_config_id(
+        &mut self,
+        ConfigIdEntry{name, pipeline_id, ci_id}: ConfigIdEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let sql = r###"
+            INSERT OR IGNORE INTO config_id (name, pipeline_id, ci_id)
+            VALUES (?, ?, ?)
+        "###;
+        try!(self.conn.execute(sql, &[
+            &name,
+            &pipeline_id.0,
+            &ci_id.map(|ci_id| ci_id.0),
+        ]));
+        Ok(())
+    }
+    fn list_config_ids(
+        &mut self,
+    ) -> Result<Vec<ConfigIdEntry>, Box<Error + Send + Sync>> {
+        let sql = r###"
+            SELECT name, pipeline_id, ci_id
+            FROM config_id
+            ORDER BY ci_id ASC
+        "###;
+        let mut stmt = try!(self.conn.prepare(&sql));
+        let rows = try!(stmt.query_map(&[], |row| ConfigIdEntry {
+            name: row.get(0),
+            pipeline_id: PipelineId(row.get(1)),
+            ci_id: row.get::<_, Option<i32>>(2).map(CiId),
+        }));
+        let mut result = Vec::new();
+        for row in rows {
+            result.push(try!(row));
+        }
+        Ok(result)
+    }
+}
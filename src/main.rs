@@ -34,6 +34,7 @@ extern crate void;
 
 #[macro_use] mod util;
 mod ci;
+mod cli;
 mod config;
 mod db;
 mod pipeline;
@@ -42,20 +43,41 @@ mod view;
 mod vcs;
 
 use config::WorkerBuilder;
-use db::Db;
-use pipeline::{Ci, Event, GetPipelineId, Pipeline, Ui, Vcs};
-use std::borrow::Cow;
+use db::{Db, DbBox};
+use pipeline::{Ci, Event, GetPipelineId, Pipeline, PipelineId, Ui, Vcs};
+use pipeline::WorkerManager;
 use std::env::args;
 use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// How often to look for changes to the config file, in seconds.
+const RELOAD_POLL_SEC: u64 = 5;
 
 fn main() {
     env_logger::init().unwrap();
-    let mut args = args();
-    let _ = args.next(); // ignore executable name
-    let config_path = args.next()
-        .map(Cow::Owned)
-        .unwrap_or(Cow::Borrowed("config.toml"));
-    let config_path = &*config_path;
+    let mut args: Vec<String> = args().skip(1).collect(); // skip executable
+    // The config path comes first, and defaults to config.toml.
+    let config_path = match args.first() {
+        Some(arg) if !cli::is_command(arg) => Some(arg.clone()),
+        _ => None,
+    };
+    let config_path = match config_path {
+        Some(config_path) => {
+            args.remove(0);
+            config_path
+        }
+        None => "config.toml".to_owned(),
+    };
+    let config_path = &config_path[..];
+    // Anything after it is an admin command, to run instead of the bot.
+    if !args.is_empty() {
+        process::exit(cli::run(config_path, &args));
+    }
     if config_path == "-12" {
         let worker_builder =
             config::twelvef::GithubBuilder::build_from_os_env()
@@ -71,7 +93,14 @@ fn main() {
 
 fn run_workers<B: WorkerBuilder>(builder: B) -> ! {
     use std::sync::mpsc::{Select, Handle};
-    let (workers, mut db) = builder.start();
+    let (mut workers, mut db) = builder.start();
+    let mut reloader = workers.reloader.take();
+    let recv_reload = reloader.as_ref().map(|reloader| {
+        watch_config(reloader.path().to_owned())
+    });
+    if let Some(ref reloader) = reloader {
+        fetch_repo_configs(&workers, &reloader.repo_config_pipelines());
+    }
     debug!(
         "Created {} pipelines, {} CIs, {} UIs, and {} VCSs (View: {})",
         workers.pipelines.len(),
@@ -102,21 +131,46 @@ fn run_workers<B: WorkerBuilder>(builder: B) -> ! {
         for h in &mut ci_handles { h.add(); }
         for h in &mut ui_handles { h.add(); }
         for h in &mut vcs_handles { h.add(); }
+        let mut reload_handle: Option<Handle<()>> =
+            recv_reload.as_ref().map(|recv| select.handle(recv));
         if let Some(ref mut h) = view_handle { h.add(); }
+        if let Some(ref mut h) = reload_handle { h.add(); }
         let mut pending: Option<Event> = None;
         'outer: loop {
             if let Some(event) = pending.take() {
-                let pipeline_id = event.pipeline_id(&*workers.pipelines);
-                let pipeline = workers.pipeline_by_id(pipeline_id);
-                if let Some(pipeline) = pipeline {
-                    let result = db.transaction(PipelineTransaction{
-                        pipeline: pipeline,
-                        event: event,
-                    });
-                    if let Err(e) = result {
-                        warn!("Event handling failed: {:?}", e);
+                match event {
+                    Event::VcsEvent(vcs::Event::FetchedRepoConfig(
+                        pipeline_id,
+                        contents,
+                    )) => {
+                        if let Some(ref mut reloader) = reloader {
+                            reloader.repo_config(pipeline_id, contents, &mut db);
+                        }
+                    }
+                    event => {
+                        let moved = match event {
+                            Event::VcsEvent(vcs::Event::MovedToMaster(
+                                pipeline_id,
+                                _,
+                            )) |
+                            Event::UiEvent(ui::Event::Pushed(pipeline_id)) =>
+                                Some(pipeline_id),
+                            _ => None,
+                        };
+                        handle_event(&workers, &mut db, event);
+                        // The target branch has the repo's settings on it.
+                        if let (Some(pipeline_id), Some(reloader)) =
+                                (moved, reloader.as_ref()) {
+                            if reloader.repo_config_pipelines()
+                                    .contains(&pipeline_id) {
+                                fetch_repo_configs(&workers, &[pipeline_id]);
+                            }
+                        }
                     }
                 }
+                if let Some(ref mut reloader) = reloader {
+                    reloader.drain(&mut db);
+                }
             }
             let id = select.wait();
             for h in &mut ci_handles {
@@ -137,10 +191,83 @@ fn run_workers<B: WorkerBuilder>(builder: B) -> ! {
                     continue 'outer;
                 }
             }
+            if let Some(ref mut h) = view_handle {
+                if h.id() == id {
+                    pending = h.recv().map(|event| match event {
+                        view::Event::UiEvent(event) => Event::UiEvent(event),
+                    }).ok();
+                    continue 'outer;
+                }
+            }
+            if let Some(ref mut h) = reload_handle {
+                if h.id() == id {
+                    if h.recv().is_ok() {
+                        if let Some(ref mut reloader) = reloader {
+                            reloader.reload(&mut db);
+                            reloader.drain(&mut db);
+                            fetch_repo_configs(
+                                &workers,
+                                &reloader.repo_config_pipelines(),
+                            );
+                        }
+                    }
+                    continue 'outer;
+                }
+            }
         }
     }
 }
 
+fn handle_event(workers: &WorkerManager, db: &mut DbBox, event: Event) {
+    let pipeline = event.pipeline_id(&*workers.pipelines)
+        .and_then(|pipeline_id| workers.pipeline_by_id(pipeline_id));
+    if let Some(pipeline) = pipeline {
+        let result = db.transaction(PipelineTransaction{
+            pipeline: pipeline,
+            event: event,
+        });
+        if let Err(e) = result {
+            warn!("Event handling failed: {:?}", e);
+        }
+    } else {
+        info!("Dropped an event for a pipeline that is gone");
+    }
+}
+
+/// Ask the pipelines' VCS workers for the settings their repos keep.
+fn fetch_repo_configs(workers: &WorkerManager, pipeline_ids: &[PipelineId]) {
+    for &pipeline_id in pipeline_ids {
+        let worker = workers.pipelines.by_pipeline_id(pipeline_id)
+            .and_then(|config| workers.vcss.get(config.vcs));
+        if let Some(worker) = worker {
+            worker.send_msg.send(vcs::Message::FetchRepoConfig(pipeline_id))
+                .unwrap();
+        }
+    }
+}
+
+/// Send a message whenever the file's modification time changes.
+fn watch_config(path: PathBuf) -> Receiver<()> {
+    fn modified(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+    }
+    let (send, recv) = channel();
+    thread::spawn(move || {
+        let mut last = modified(&path);
+        loop {
+            thread::sleep(Duration::new(RELOAD_POLL_SEC, 0));
+            let current = modified(&path);
+            if current != last {
+                last = current;
+                if send.send(()).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    recv
+}
+
 struct PipelineTransaction<'cntx, C, U, V>
     where C: Ci + 'cntx,
           U: Ui + 'cntx,
@@ -159,8 +286,6 @@ impl<'cntx, C, U, V> db::Transaction
         mut self,
         db: &mut D
     ) -> Result<(), Box<Error + Send + Sync>> {
-        use std::thread;
-        use std::time::Duration;
         use util::{MIN_DELAY_SEC, MAX_DELAY_SEC};
         let mut delay = Duration::new(MIN_DELAY_SEC, 0);
         let max = Duration::new(MAX_DELAY_SEC, 0);
@@ -1,12 +1,15 @@
 // This file is released under the same terms as Rust itself.
 
 use ci::{self, CiId};
-use config::{PipelineConfig, PipelinesConfig};
-use db::{CiState, Db, PendingEntry, QueueEntry, RunningEntry};
+use config::{PipelineConfig, PipelinesConfig, Reload, RetryConfig};
+use db::{CiState, CiStateEntry, Db, PendingEntry, QueueEntry, RunningEntry};
+use hyper::Url;
+use std::collections::HashMap;
 use std::error::Error;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::thread;
 use ui::{self, Pr};
+use util;
 use vcs::{self, Commit, Remote};
 use view;
 
@@ -28,6 +31,8 @@ pub struct WorkerManager {
         view::Message,
     >>,
     pub pipelines: Box<PipelinesConfig>,
+    /// Set when the pipelines can change without a restart.
+    pub reloader: Option<Box<Reload>>,
 }
 
 impl WorkerManager {
@@ -42,13 +47,17 @@ impl WorkerManager {
             WorkerThread<vcs::Event, vcs::Message>,
         >
     > {
-        let PipelineConfig{ci, ui, vcs, pipeline_id: _} = self.pipelines.by_pipeline_id(pipeline_id);
+        let PipelineConfig{ci, ui, vcs, ci_names, retry, pipeline_id: _} =
+            match self.pipelines.by_pipeline_id(pipeline_id) {
+                Some(config) => config,
+                None => return None,
+            };
         if let (Some(ui), Some(vcs)) = (
             self.uis.get(ui),
             self.vcss.get(vcs)
         ) {
             let ci = ci.iter().flat_map(|&(id, idx)| self.cis.get(idx).map(|ci| (id, ci))).collect();
-            Some(Pipeline::new(pipeline_id, ci, ui, vcs))
+            Some(Pipeline::new(pipeline_id, ci, ci_names, retry, ui, vcs))
         } else {
             None
         }
@@ -94,6 +103,8 @@ impl Ci for WorkerThread<ci::Event, ci::Message> {
 
 pub trait Ui {
     fn send_result(&self, PipelineId, Pr, ui::Status);
+    fn send_ci_result(&self, PipelineId, Pr, ui::CiResult);
+    fn send_queue_position(&self, PipelineId, Pr, usize);
 }
 
 impl Ui for WorkerThread<ui::Event, ui::Message> {
@@ -106,6 +117,27 @@ impl Ui for WorkerThread<ui::Event, ui::Message> {
         self.send_msg.send(ui::Message::SendResult(pipeline_id, pr, status))
             .unwrap();
     }
+    fn send_ci_result(
+        &self,
+        pipeline_id: PipelineId,
+        pr: Pr,
+        result: ui::CiResult,
+    ) {
+        self.send_msg.send(ui::Message::SendCiResult(pipeline_id, pr, result))
+            .unwrap();
+    }
+    fn send_queue_position(
+        &self,
+        pipeline_id: PipelineId,
+        pr: Pr,
+        position: usize,
+    ) {
+        self.send_msg.send(ui::Message::SendQueuePosition(
+            pipeline_id,
+            pr,
+            position,
+        )).unwrap();
+    }
 }
 
 pub trait Vcs {
@@ -155,6 +187,8 @@ where C: Ci + 'cntx,
 {
     pub id: PipelineId,
     pub ci: Vec<(CiId, &'cntx C)>,
+    pub ci_names: HashMap<CiId, String>,
+    pub retry: RetryConfig,
     pub ui: &'cntx U,
     pub vcs: &'cntx V,
 }
@@ -167,11 +201,14 @@ pub enum Event {
 }
 
 pub trait GetPipelineId {
-    fn pipeline_id<C: PipelinesConfig + ?Sized>(&self, config: &C) -> PipelineId;
+    /// `None` if the event is for a pipeline that is no longer configured.
+    fn pipeline_id<C: PipelinesConfig + ?Sized>(&self, config: &C)
+        -> Option<PipelineId>;
 }
 
 impl GetPipelineId for Event {
-    fn pipeline_id<C: PipelinesConfig + ?Sized>(&self, config: &C) -> PipelineId {
+    fn pipeline_id<C: PipelinesConfig + ?Sized>(&self, config: &C)
+            -> Option<PipelineId> {
         match *self {
             Event::UiEvent(ref e) => e.pipeline_id(config),
             Event::CiEvent(ref e) => e.pipeline_id(config),
@@ -188,12 +225,16 @@ where C: Ci + 'cntx,
     pub fn new(
         id: PipelineId,
         ci: Vec<(CiId, &'cntx C)>,
+        ci_names: HashMap<CiId, String>,
+        retry: RetryConfig,
         ui: &'cntx U,
         vcs: &'cntx V,
     ) -> Self {
         Pipeline {
             id: id,
             ci: ci,
+            ci_names: ci_names,
+            retry: retry,
             ui: ui,
             vcs: vcs,
         }
@@ -211,37 +252,47 @@ where C: Ci + 'cntx,
                 message,
             )) => {
                 assert_eq!(&pipeline_id, &self.id);
-                let commit = match (
-                    commit,
-                    try!(db.peek_pending_by_pr(self.id, &pr))
-                        .map(|p| p.commit),
-                ) {
-                    (Some(reviewed_pr), Some(current_pr)) => {
-                        if reviewed_pr != current_pr {
+                let pending = try!(db.peek_pending_by_pr(self.id, &pr));
+                let draft = pending.as_ref().map(|p| p.draft).unwrap_or(false);
+                let commit = if draft {
+                    self.ui.send_result(
+                        self.id,
+                        pr.clone(),
+                        ui::Status::Draft,
+                    );
+                    None
+                } else {
+                    match (
+                        commit,
+                        pending.map(|p| p.commit),
+                    ) {
+                        (Some(reviewed_pr), Some(current_pr)) => {
+                            if reviewed_pr != current_pr {
+                                self.ui.send_result(
+                                    self.id,
+                                    pr.clone(),
+                                    ui::Status::Invalidated,
+                                );
+                                None
+                            } else {
+                                Some(reviewed_pr)
+                            }
+                        }
+                        (Some(reviewed_pr), None) => {
+                            Some(reviewed_pr)
+                        }
+                        (None, Some(current_pr)) => {
+                            Some(current_pr)
+                        }
+                        (None, None) => {
                             self.ui.send_result(
                                 self.id,
                                 pr.clone(),
-                                ui::Status::Invalidated,
+                                ui::Status::NoCommit,
                             );
                             None
-                        } else {
-                            Some(reviewed_pr)
                         }
                     }
-                    (Some(reviewed_pr), None) => {
-                        Some(reviewed_pr)
-                    }
-                    (None, Some(current_pr)) => {
-                        Some(current_pr)
-                    }
-                    (None, None) => {
-                        self.ui.send_result(
-                            self.id,
-                            pr.clone(),
-                            ui::Status::NoCommit,
-                        );
-                        None
-                    }
                 };
                 if let Some(commit) = commit {
                     self.ui.send_result(
@@ -254,11 +305,13 @@ where C: Ci + 'cntx,
                         commit: commit,
                         pr: pr,
                         message: message,
+                        priority: 0,
                     }));
+                    try!(self.send_queue_positions(db));
                 }
             },
             Event::UiEvent(ui::Event::Opened(
-                pipeline_id, pr, commit, title, url
+                pipeline_id, pr, commit, title, url, draft
             )) => {
                 assert_eq!(&pipeline_id, &self.id);
                 try!(db.add_pending(self.id, PendingEntry{
@@ -266,10 +319,11 @@ where C: Ci + 'cntx,
                     pr: pr,
                     title: title,
                     url: url,
+                    draft: draft,
                 }));
             },
             Event::UiEvent(ui::Event::Changed(
-                pipeline_id, pr, commit, title, url
+                pipeline_id, pr, commit, title, url, draft
             )) => {
                 assert_eq!(&pipeline_id, &self.id);
                 if try!(db.cancel_by_pr_different_commit(
@@ -282,23 +336,69 @@ where C: Ci + 'cntx,
                         pr.clone(),
                         ui::Status::Invalidated,
                     );
+                    try!(self.send_queue_positions(db));
+                } else if draft && try!(self.is_queued(db, &pr)) {
+                    // Converted back to a draft after it was approved.
+                    try!(db.cancel_by_pr(self.id, &pr));
+                    self.ui.send_result(
+                        self.id,
+                        pr.clone(),
+                        ui::Status::Draft,
+                    );
+                    try!(self.send_queue_positions(db));
                 }
                 try!(db.add_pending(self.id, PendingEntry{
                     commit: commit,
                     pr: pr,
                     title: title,
                     url: url,
+                    draft: draft,
                 }));
             },
             Event::UiEvent(ui::Event::Closed(pipeline_id, pr)) => {
                 assert_eq!(&pipeline_id, &self.id);
                 try!(db.take_pending_by_pr(self.id, &pr));
                 try!(db.cancel_by_pr(self.id, &pr));
+                try!(self.send_queue_positions(db));
+            },
+            Event::UiEvent(ui::Event::Retargeted(pipeline_id, pr)) => {
+                assert_eq!(&pipeline_id, &self.id);
+                let queued = try!(self.is_queued(db, &pr));
+                try!(db.take_pending_by_pr(self.id, &pr));
+                try!(db.cancel_by_pr(self.id, &pr));
+                if queued {
+                    self.ui.send_result(
+                        self.id,
+                        pr,
+                        ui::Status::Retargeted,
+                    );
+                }
+                try!(self.send_queue_positions(db));
             },
             Event::UiEvent(ui::Event::Canceled(pipeline_id, pr)) => {
                 assert_eq!(&pipeline_id, &self.id);
                 try!(db.cancel_by_pr(self.id, &pr));
+                try!(self.send_queue_positions(db));
+            },
+            Event::UiEvent(ui::Event::Prioritized(
+                pipeline_id,
+                pr,
+                priority,
+            )) => {
+                assert_eq!(&pipeline_id, &self.id);
+                if try!(db.set_priority(self.id, &pr, priority)) {
+                    try!(self.send_queue_positions(db));
+                } else {
+                    warn!("Reprioritized PR that is not in the queue");
+                }
+            },
+            Event::UiEvent(ui::Event::Paused(pipeline_id, paused)) => {
+                assert_eq!(&pipeline_id, &self.id);
+                try!(db.set_paused(self.id, paused));
             },
+            Event::UiEvent(ui::Event::Pushed(_)) => {
+                // The main loop reads the repo's settings again.
+            }
             Event::VcsEvent(vcs::Event::MergedToStaging(
                 pipeline_id,
                 pull_commit,
@@ -317,7 +417,21 @@ where C: Ci + 'cntx,
                     } else {
                         running.merge_commit = Some(merge_commit.clone());
                         for &(ci_id, ci) in &self.ci {
-                            try!(db.clear_ci_state(ci_id));
+                            // Failures from building this pull commit
+                            // before stay, in case this merge builds the
+                            // same merge commit again.
+                            try!(db.clear_other_ci_failures(
+                                ci_id,
+                                &pull_commit,
+                            ));
+                            try!(self.set_ci_state(
+                                db,
+                                ci_id,
+                                CiState::Queued,
+                                &running,
+                                &merge_commit,
+                                None,
+                            ));
                             ci.start_build(
                                 ci_id,
                                 merge_commit.clone(),
@@ -363,19 +477,27 @@ where C: Ci + 'cntx,
                 }
             },
             Event::CiEvent(ci::Event::BuildStarted(
-                _ci_id,
+                ci_id,
                 building_commit,
                 url,
             )) => {
                 if let Some(running) = try!(db.peek_running(self.id)) {
-                    if let Some(merged_commit) = running.merge_commit {
-                        if merged_commit != building_commit {
+                    if let Some(ref merged_commit) = running.merge_commit {
+                        if merged_commit != &building_commit {
                             warn!("Building a different commit");
                         } else if running.canceled {
                             // Drop it on the floor. It's canceled.
                         } else if running.built {
                             warn!("Got CI build started after done building!");
                         } else {
+                            try!(self.set_ci_state(
+                                db,
+                                ci_id,
+                                CiState::Running,
+                                &running,
+                                &building_commit,
+                                url.clone(),
+                            ));
                             self.ui.send_result(
                                 self.id,
                                 running.pr.clone(),
@@ -394,9 +516,10 @@ where C: Ci + 'cntx,
                 }
             },
             Event::CiEvent(ci::Event::BuildFailed(
-                _ci_id,
+                ci_id,
                 built_commit,
                 url,
+                summary,
             )) => {
                 if let Some(running) = try!(db.take_running(self.id)) {
                     if let Some(ref merged_commit) = running.merge_commit {
@@ -409,13 +532,30 @@ where C: Ci + 'cntx,
                             warn!("Got duplicate BuildFailed event");
                             // Put it back
                             try!(db.put_running(self.id, running.clone()));
+                        } else if try!(self.retry_failed_build(
+                            db,
+                            ci_id,
+                            &running,
+                            &built_commit,
+                            &summary,
+                        )) {
+                            // Put it back, to wait for the new build.
+                            try!(db.put_running(self.id, running.clone()));
                         } else {
-                            for &(ci_id, _) in &self.ci {
-                                if let Some((_state, commit)) = try!(db.get_ci_state(ci_id)) {
-                                    assert_eq!(built_commit, commit);
-                                    try!(db.clear_ci_state(ci_id));
-                                }
-                            }
+                            try!(self.set_ci_state(
+                                db,
+                                ci_id,
+                                CiState::Failed,
+                                &running,
+                                &built_commit,
+                                url.clone(),
+                            ));
+                            try!(self.cancel_other_cis(
+                                db,
+                                ci_id,
+                                &running,
+                                &built_commit,
+                            ));
                             self.ui.send_result(
                                 self.id,
                                 running.pr.clone(),
@@ -423,6 +563,7 @@ where C: Ci + 'cntx,
                                     running.pull_commit.clone(),
                                     merged_commit.clone(),
                                     url,
+                                    summary,
                                 ),
                             );
                         }
@@ -450,16 +591,29 @@ where C: Ci + 'cntx,
                             // Put it back.
                             try!(db.put_running(self.id, running.clone()));
                         } else {
-                            try!(db.set_ci_state(
+                            try!(self.set_ci_state(
+                                db,
                                 ci_id,
                                 CiState::Succeeded,
+                                &running,
                                 &built_commit,
+                                url.clone(),
                             ));
+                            // Anything that failed on this merge commit
+                            // before it passed is flaky. The failures are
+                            // forgotten once counted, so later builds of
+                            // the same commit don't count them again.
+                            for signature in try!(db.take_ci_failures(
+                                ci_id,
+                                &built_commit,
+                            )) {
+                                try!(db.add_flaky(ci_id, &signature));
+                            }
                             let not_succeeded_count = self.ci.iter()
                                 .filter(|&&(ci_id, _)| retry!{{
-                                    if let Some((state, commit)) = retry_unwrap!(db.get_ci_state(ci_id)) {
-                                        assert_eq!(built_commit, commit);
-                                        state != CiState::Succeeded
+                                    if let Some(entry) = retry_unwrap!(db.get_ci_state(ci_id)) {
+                                        assert_eq!(built_commit, entry.commit);
+                                        entry.state != CiState::Succeeded
                                     } else {
                                         true
                                     }
@@ -550,8 +704,13 @@ where C: Ci + 'cntx,
                     warn!("VCS move event with no queued PR");
                 }
             }
+            Event::VcsEvent(vcs::Event::FetchedRepoConfig(..)) => {
+                // The repo's settings go to the config, not the pipeline.
+            }
         }
-        if try!(db.peek_running(self.id)).is_none() {
+        // A paused pipeline finishes what it is building, but starts nothing.
+        if try!(db.peek_running(self.id)).is_none() &&
+                !try!(db.is_paused(self.id)) {
             if let Some(next) = try!(db.pop_queue(self.id)) {
                 self.vcs.merge_to_staging(
                     self.id,
@@ -567,8 +726,165 @@ where C: Ci + 'cntx,
                     canceled: false,
                     built: false,
                 }));
+                try!(self.send_queue_positions(db));
+            }
+        }
+        Ok(())
+    }
+    /// Tell the UI where every waiting PR is in the queue.
+    fn send_queue_positions<D: Db>(
+        &self,
+        db: &mut D,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        for (i, entry) in try!(db.list_queue(self.id)).into_iter().enumerate() {
+            self.ui.send_queue_position(self.id, entry.pr, i + 1);
+        }
+        Ok(())
+    }
+    /// Whether the PR is waiting in the queue, or being built.
+    fn is_queued<D: Db>(
+        &self,
+        db: &mut D,
+        pr: &Pr,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
+        if try!(db.list_queue(self.id)).iter().any(|entry| &entry.pr == pr) {
+            return Ok(true);
+        }
+        Ok(match try!(db.peek_running(self.id)) {
+            Some(running) => &running.pr == pr && !running.canceled,
+            None => false,
+        })
+    }
+    /// Record a failed CI job, and start it again if the retry settings
+    /// allow it. Returns true if the job was restarted.
+    fn retry_failed_build<D: Db>(
+        &self,
+        db: &mut D,
+        ci_id: CiId,
+        running: &RunningEntry,
+        built_commit: &Commit,
+        summary: &ci::FailureSummary,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
+        // Failures without test names are tracked by the job name.
+        let signatures = if summary.tests.is_empty() {
+            vec![self.ci_name(ci_id)]
+        } else {
+            summary.tests.clone()
+        };
+        let attempts = try!(db.add_ci_failure(
+            ci_id,
+            built_commit,
+            &running.pull_commit,
+            &signatures,
+        ));
+        if attempts > self.retry.count {
+            return Ok(false);
+        }
+        if self.retry.only_flaky {
+            let flaky = try!(db.list_flaky(ci_id));
+            let all_flaky = signatures.iter().all(|signature| {
+                flaky.iter().any(|entry| &entry.signature == signature)
+            });
+            if !all_flaky {
+                return Ok(false);
             }
         }
+        let ci = match self.ci.iter().find(|&&(id, _)| id == ci_id) {
+            Some(&(_, ci)) => ci,
+            None => return Ok(false),
+        };
+        info!("Retrying CI {:?}, attempt {}", ci_id, attempts);
+        try!(self.set_ci_state(
+            db,
+            ci_id,
+            CiState::Queued,
+            running,
+            built_commit,
+            None,
+        ));
+        ci.start_build(ci_id, built_commit.clone());
+        Ok(true)
+    }
+    /// Once one job fails, the others' results don't matter any more.
+    /// Mark the ones that haven't finished as canceled.
+    fn cancel_other_cis<D: Db>(
+        &self,
+        db: &mut D,
+        failed_ci_id: CiId,
+        running: &RunningEntry,
+        built_commit: &Commit,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        for &(ci_id, _) in &self.ci {
+            if ci_id == failed_ci_id {
+                continue;
+            }
+            let unfinished = match try!(db.get_ci_state(ci_id)) {
+                Some(entry) => entry.commit == *built_commit && (
+                    entry.state == CiState::Queued ||
+                    entry.state == CiState::Running
+                ),
+                None => false,
+            };
+            if unfinished {
+                try!(self.set_ci_state(
+                    db,
+                    ci_id,
+                    CiState::Canceled,
+                    running,
+                    built_commit,
+                    None,
+                ));
+            }
+        }
+        Ok(())
+    }
+    fn ci_name(&self, ci_id: CiId) -> String {
+        self.ci_names.get(&ci_id)
+            .cloned()
+            .unwrap_or_else(|| ci_id.0.to_string())
+    }
+    /// Record the state of one CI job and tell the UI about it.
+    fn set_ci_state<D: Db>(
+        &self,
+        db: &mut D,
+        ci_id: CiId,
+        state: CiState,
+        running: &RunningEntry,
+        merge_commit: &Commit,
+        url: Option<Url>,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let now = util::now_secs();
+        let previous = match try!(db.get_ci_state(ci_id)) {
+            Some(ref entry) if entry.commit == *merge_commit => Some(entry.clone()),
+            _ => None,
+        };
+        let (started, finished) = match state {
+            CiState::Queued => (None, None),
+            CiState::Running => (Some(now), None),
+            CiState::Succeeded | CiState::Failed | CiState::Canceled => (
+                previous.as_ref().and_then(|entry| entry.started),
+                Some(now),
+            ),
+        };
+        let url = url.or(previous.and_then(|entry| entry.url));
+        let entry = CiStateEntry{
+            state: state,
+            commit: merge_commit.clone(),
+            url: url,
+            started: started,
+            finished: finished,
+        };
+        try!(db.set_ci_state(ci_id, entry.clone()));
+        let name = self.ci_name(ci_id);
+        self.ui.send_ci_result(
+            self.id,
+            running.pr.clone(),
+            ui::CiResult{
+                name: name,
+                pull_commit: running.pull_commit.clone(),
+                state: entry,
+            },
+        );
         Ok(())
     }
 }
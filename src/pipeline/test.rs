@@ -1,8 +1,10 @@
 // This file is released under the same terms as Rust itself.
 
 use super::{Ci, Vcs, Ui};
-use ci::{self, CiId};
-use db::{CiState, Db, PendingEntry, QueueEntry, RunningEntry};
+use ci::{self, CiId, FailureSummary};
+use config::RetryConfig;
+use db::{CiState, CiStateEntry, ConfigIdEntry, Db, FlakyEntry, PendingEntry};
+use db::{QueueEntry, RunningEntry};
 use hyper::Url;
 use hyper::client::IntoUrl;
 use pipeline::{Event, Pipeline, PipelineId};
@@ -15,18 +17,26 @@ use vcs::{self, Commit, Remote};
 
 struct MemoryDb {
     queue: VecDeque<QueueEntry>,
+    paused: bool,
     running: Option<RunningEntry>,
     pending: Vec<PendingEntry>,
-    cis: HashMap<CiId, (CiState, Commit)>,
+    cis: HashMap<CiId, CiStateEntry>,
+    failures: Vec<(CiId, Commit, Commit, u32, String)>,
+    flaky: HashMap<(CiId, String), i64>,
+    config_ids: Vec<ConfigIdEntry>,
 }
 
 impl MemoryDb {
     fn new() -> Self {
         MemoryDb{
             queue: VecDeque::new(),
+            paused: false,
             running: None,
             pending: Vec::new(),
             cis: HashMap::new(),
+            failures: Vec::new(),
+            flaky: HashMap::new(),
+            config_ids: Vec::new(),
         }
     }
 }
@@ -37,7 +47,10 @@ impl Db for MemoryDb {
         _: PipelineId,
         entry: QueueEntry,
     ) -> Result<(), Box<Error + Send + Sync>> {
-        self.queue.push_back(entry);
+        let i = self.queue.iter()
+            .position(|queued| queued.priority < entry.priority)
+            .unwrap_or(self.queue.len());
+        self.queue.insert(i, entry);
         Ok(())
     }
     fn pop_queue(
@@ -50,7 +63,38 @@ impl Db for MemoryDb {
         &mut self,
         _: PipelineId,
     ) -> Result<Vec<QueueEntry>, Box<Error + Send + Sync>> {
-        unimplemented!()
+        Ok(self.queue.iter().cloned().collect())
+    }
+    fn set_priority(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        priority: i32,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
+        let queue = mem::replace(&mut self.queue, VecDeque::new());
+        let mut found = false;
+        for mut entry in queue {
+            if entry.pr == *pr {
+                entry.priority = priority;
+                found = true;
+            }
+            try!(self.push_queue(pipeline_id, entry));
+        }
+        Ok(found)
+    }
+    fn set_paused(
+        &mut self,
+        _: PipelineId,
+        paused: bool,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        self.paused = paused;
+        Ok(())
+    }
+    fn is_paused(
+        &mut self,
+        _: PipelineId,
+    ) -> Result<bool, Box<Error + Send + Sync>> {
+        Ok(self.paused)
     }
     fn put_running(
         &mut self,
@@ -161,10 +205,9 @@ impl Db for MemoryDb {
     fn set_ci_state(
         &mut self,
         ci_id: CiId,
-        ci_state: CiState,
-        commit: &Commit,
+        entry: CiStateEntry,
     ) -> Result<(), Box<Error + Send + Sync>> {
-        self.cis.insert(ci_id, (ci_state, commit.clone()));
+        self.cis.insert(ci_id, entry);
         Ok(())
     }
     fn clear_ci_state(
@@ -177,18 +220,106 @@ impl Db for MemoryDb {
     fn get_ci_state(
         &mut self,
         ci_id: CiId,
-    ) -> Result<Option<(CiState, Commit)>, Box<Error + Send + Sync>> {
+    ) -> Result<Option<CiStateEntry>, Box<Error + Send + Sync>> {
         Ok(self.cis.get(&ci_id).cloned())
     }
+    fn add_ci_failure(
+        &mut self,
+        ci_id: CiId,
+        commit: &Commit,
+        pull_commit: &Commit,
+        signatures: &[String],
+    ) -> Result<u32, Box<Error + Send + Sync>> {
+        let attempt = self.failures.iter()
+            .filter(|f| f.0 == ci_id && &f.1 == commit)
+            .map(|f| f.3)
+            .max()
+            .unwrap_or(0) + 1;
+        for signature in signatures {
+            self.failures.push((
+                ci_id,
+                commit.clone(),
+                pull_commit.clone(),
+                attempt,
+                signature.clone(),
+            ));
+        }
+        Ok(attempt)
+    }
+    fn take_ci_failures(
+        &mut self,
+        ci_id: CiId,
+        commit: &Commit,
+    ) -> Result<Vec<String>, Box<Error + Send + Sync>> {
+        let mut result: Vec<String> = Vec::new();
+        for f in &self.failures {
+            if f.0 == ci_id && &f.1 == commit && !result.contains(&f.4) {
+                result.push(f.4.clone());
+            }
+        }
+        self.failures.retain(|f| f.0 != ci_id || &f.1 != commit);
+        Ok(result)
+    }
+    fn clear_other_ci_failures(
+        &mut self,
+        ci_id: CiId,
+        pull_commit: &Commit,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        self.failures.retain(|f| f.0 != ci_id || &f.2 == pull_commit);
+        Ok(())
+    }
+    fn add_flaky(
+        &mut self,
+        ci_id: CiId,
+        signature: &str,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        *self.flaky.entry((ci_id, signature.to_owned())).or_insert(0) += 1;
+        Ok(())
+    }
+    fn list_flaky(
+        &mut self,
+        ci_id: CiId,
+    ) -> Result<Vec<FlakyEntry>, Box<Error + Send + Sync>> {
+        Ok(self.flaky.iter()
+            .filter(|&(key, _)| key.0 == ci_id)
+            .map(|(key, &count)| FlakyEntry{
+                signature: key.1.clone(),
+                count: count,
+                last_seen: 0,
+            })
+            .collect())
+    }
+    fn add_config_id(
+        &mut self,
+        entry: ConfigIdEntry,
+    ) -> Result<(), Box<Error + Send + Sync>> {
+        let taken = self.config_ids.iter().any(|e| match entry.ci_id {
+            None => e.ci_id.is_none() && e.name == entry.name,
+            ci_id => e.ci_id == ci_id,
+        });
+        if !taken {
+            self.config_ids.push(entry);
+        }
+        Ok(())
+    }
+    fn list_config_ids(
+        &mut self,
+    ) -> Result<Vec<ConfigIdEntry>, Box<Error + Send + Sync>> {
+        Ok(self.config_ids.clone())
+    }
 }
 
 struct MemoryUi {
     results: Vec<(Pr, ui::Status)>,
+    ci_results: Vec<(Pr, ui::CiResult)>,
+    queue_positions: Vec<(Pr, usize)>,
 }
 impl MemoryUi {
     fn new() -> RefCell<MemoryUi> {
         RefCell::new(MemoryUi{
             results: Vec::new(),
+            ci_results: Vec::new(),
+            queue_positions: Vec::new(),
         })
     }
 }
@@ -201,6 +332,22 @@ impl Ui for RefCell<MemoryUi> {
     ) {
         self.borrow_mut().results.push((pr, status));
     }
+    fn send_ci_result(
+        &self,
+        _: PipelineId,
+        pr: Pr,
+        result: ui::CiResult,
+    ) {
+        self.borrow_mut().ci_results.push((pr, result));
+    }
+    fn send_queue_position(
+        &self,
+        _: PipelineId,
+        pr: Pr,
+        position: usize,
+    ) {
+        self.borrow_mut().queue_positions.push((pr, position));
+    }
 }
 
 struct MemoryVcs {
@@ -258,6 +405,26 @@ fn handle_event(
         ui: ui,
         vcs: vcs,
         ci: vec![(CiId(1), ci)],
+        ci_names: HashMap::new(),
+        retry: RetryConfig::default(),
+        id: PipelineId(0),
+    }.handle_event(db, event).unwrap();
+}
+
+fn handle_event_retry(
+    ui: &mut RefCell<MemoryUi>,
+    vcs: &mut RefCell<MemoryVcs>,
+    ci: &mut RefCell<MemoryCi>,
+    db: &mut MemoryDb,
+    retry: RetryConfig,
+    event: Event,
+) {
+    Pipeline{
+        ui: ui,
+        vcs: vcs,
+        ci: vec![(CiId(1), ci)],
+        ci_names: HashMap::new(),
+        retry: retry,
         id: PipelineId(0),
     }.handle_event(db, event).unwrap();
 }
@@ -274,6 +441,8 @@ fn handle_event_2_ci(
         ui: ui,
         vcs: vcs,
         ci: vec![(CiId(1), ci1), (CiId(2), ci2)],
+        ci_names: HashMap::new(),
+        retry: RetryConfig::default(),
         id: PipelineId(0),
     }.handle_event(db, event).unwrap();
 }
@@ -366,6 +535,7 @@ fn handle_add_to_queue_by_pending_some() {
             memory_commit_a(),
             "".to_owned(),
             Url::parse("http://www.com/").unwrap(),
+            false,
         )),
     );
     handle_event(
@@ -385,6 +555,178 @@ fn handle_add_to_queue_by_pending_some() {
     assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_a());
 }
 
+#[test]
+fn handle_add_to_queue_draft() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Opened(
+            PipelineId(0),
+            memory_pr_a(),
+            memory_commit_a(),
+            "".to_owned(),
+            Url::parse("http://www.com/").unwrap(),
+            true,
+        )),
+    );
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Approved(
+            PipelineId(0),
+            memory_pr_a(),
+            Some(memory_commit_a()),
+            "Message!".to_owned(),
+        )),
+    );
+    assert!(db.running.is_none());
+    assert!(db.queue.is_empty());
+    assert!(vcs.borrow().staging.is_none());
+    assert_eq!(ui.borrow().results[0].1, ui::Status::Draft);
+    // Once it is ready for review, it can be approved.
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Changed(
+            PipelineId(0),
+            memory_pr_a(),
+            memory_commit_a(),
+            "".to_owned(),
+            Url::parse("http://www.com/").unwrap(),
+            false,
+        )),
+    );
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Approved(
+            PipelineId(0),
+            memory_pr_a(),
+            Some(memory_commit_a()),
+            "Message!".to_owned(),
+        )),
+    );
+    assert_eq!(db.running.unwrap().pull_commit, memory_commit_a());
+    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_a());
+}
+
+#[test]
+fn handle_converted_to_draft() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Opened(
+            PipelineId(0),
+            memory_pr_a(),
+            memory_commit_a(),
+            "".to_owned(),
+            Url::parse("http://www.com/").unwrap(),
+            false,
+        )),
+    );
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Approved(
+            PipelineId(0),
+            memory_pr_a(),
+            None,
+            "Message!".to_owned(),
+        )),
+    );
+    assert!(!db.running.as_ref().unwrap().canceled);
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Changed(
+            PipelineId(0),
+            memory_pr_a(),
+            memory_commit_a(),
+            "".to_owned(),
+            Url::parse("http://www.com/").unwrap(),
+            true,
+        )),
+    );
+    assert!(db.running.as_ref().unwrap().canceled);
+    assert_eq!(
+        ui.borrow().results.last().unwrap(),
+        &(memory_pr_a(), ui::Status::Draft)
+    );
+}
+
+#[test]
+fn handle_retargeted() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Opened(
+            PipelineId(0),
+            memory_pr_a(),
+            memory_commit_a(),
+            "".to_owned(),
+            Url::parse("http://www.com/").unwrap(),
+            false,
+        )),
+    );
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Approved(
+            PipelineId(0),
+            memory_pr_a(),
+            None,
+            "Message!".to_owned(),
+        )),
+    );
+    assert!(!db.running.as_ref().unwrap().canceled);
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Retargeted(
+            PipelineId(0),
+            memory_pr_a(),
+        )),
+    );
+    assert!(db.running.as_ref().unwrap().canceled);
+    assert!(db.pending.is_empty());
+    assert_eq!(
+        ui.borrow().results.last().unwrap(),
+        &(memory_pr_a(), ui::Status::Retargeted)
+    );
+}
+
 #[test]
 fn handle_add_to_queue_by_pending_changed() {
     let mut ui = MemoryUi::new();
@@ -402,6 +744,7 @@ fn handle_add_to_queue_by_pending_changed() {
             memory_commit_a(),
             "".to_owned(),
             Url::parse("http://www.com/").unwrap(),
+            false,
         )),
     );
     handle_event(
@@ -415,6 +758,7 @@ fn handle_add_to_queue_by_pending_changed() {
             memory_commit_b(),
             "".to_owned(),
             Url::parse("http://www.com/").unwrap(),
+            false,
         )),
     );
     handle_event(
@@ -606,6 +950,7 @@ fn handle_merge_failed_notify_user_merge_next_commit() {
         commit: memory_commit_c(),
         pr: memory_pr_b(),
         message: "M!".to_owned(),
+        priority: 0,
     }).unwrap();
     handle_event(
         &mut ui,
@@ -706,6 +1051,10 @@ fn handle_ci_failed_notify_user() {
             CiId(1),
             memory_commit_b(),
             None,
+            FailureSummary{
+                tests: vec!["tests::it_works".to_owned()],
+                log: None,
+            },
         ))
     );
     assert!(db.running.is_none());
@@ -718,6 +1067,10 @@ fn handle_ci_failed_notify_user() {
             memory_commit_a(),
             memory_commit_b(),
             None,
+            FailureSummary{
+                tests: vec!["tests::it_works".to_owned()],
+                log: None,
+            },
         ))]
     );
 }
@@ -740,6 +1093,7 @@ fn handle_ci_failed_notify_user_next_commit() {
         commit: memory_commit_c(),
         pr: memory_pr_b(),
         message: "M!".to_owned(),
+        priority: 0,
     }).unwrap();
     vcs.borrow_mut().staging = Some(memory_commit_b());
     handle_event(
@@ -751,6 +1105,7 @@ fn handle_ci_failed_notify_user_next_commit() {
             CiId(1),
             memory_commit_b(),
             None,
+            FailureSummary::default(),
         ))
     );
     assert_eq!(db.running.unwrap(), RunningEntry{
@@ -770,6 +1125,7 @@ fn handle_ci_failed_notify_user_next_commit() {
             memory_commit_a(),
             memory_commit_b(),
             None,
+            FailureSummary::default(),
         ))]
     );
 }
@@ -974,6 +1330,7 @@ fn handle_move_failed_notify_user_next_commit() {
         commit: memory_commit_c(),
         pr: memory_pr_b(),
         message: "M!".to_owned(),
+        priority: 0,
     }).unwrap();
     vcs.borrow_mut().staging = Some(memory_commit_b());
     handle_event(
@@ -1061,6 +1418,7 @@ fn handle_move_succeeded_notify_user_next_commit() {
         commit: memory_commit_c(),
         pr: memory_pr_b(),
         message: "M!".to_owned(),
+        priority: 0,
     }).unwrap();
     vcs.borrow_mut().staging = Some(memory_commit_b());
     handle_event(
@@ -1154,6 +1512,7 @@ fn handle_ui_changed_cancel() {
             memory_commit_c(),
             "".to_owned(),
             Url::parse("http://www.com/").unwrap(),
+            false,
         ))
     );
     assert_eq!(db.running.unwrap(), RunningEntry{
@@ -1191,6 +1550,7 @@ fn handle_ui_changed_no_real_change() {
             memory_commit_a(),
             "".to_owned(),
             Url::parse("http://www.com/").unwrap(),
+            false,
         ))
     );
     assert_eq!(db.running.unwrap(), RunningEntry{
@@ -1221,6 +1581,7 @@ fn handle_ui_changed_cancel_queue() {
         commit: memory_commit_c(),
         pr: memory_pr_b(),
         message: "MSG!".to_owned(),
+        priority: 0,
     }).unwrap();
     handle_event(
         &mut ui,
@@ -1233,6 +1594,7 @@ fn handle_ui_changed_cancel_queue() {
             memory_commit_d(),
             "".to_owned(),
             Url::parse("http://www.com/").unwrap(),
+            false,
         ))
     );
     assert_eq!(db.running.unwrap(), RunningEntry{
@@ -1264,6 +1626,7 @@ fn handle_ui_changed_no_real_change_queue() {
         commit: memory_commit_c(),
         pr: memory_pr_b(),
         message: "MSG!".to_owned(),
+        priority: 0,
     }).unwrap();
     handle_event(
         &mut ui,
@@ -1276,6 +1639,7 @@ fn handle_ui_changed_no_real_change_queue() {
             memory_commit_c(),
             "".to_owned(),
             Url::parse("http://www.com/").unwrap(),
+            false,
         ))
     );
     assert_eq!(db.running.unwrap(), RunningEntry{
@@ -1290,6 +1654,7 @@ fn handle_ui_changed_no_real_change_queue() {
         commit: memory_commit_c(),
         pr: memory_pr_b(),
         message: "MSG!".to_owned(),
+        priority: 0,
     });
 }
 
@@ -2001,6 +2366,7 @@ fn handle_runthrough_2_ci_first_failed() {
             CiId(1),
             memory_commit_b(),
             None,
+            FailureSummary::default(),
         ))
     );
     assert_eq!(db.running, Some(RunningEntry{
@@ -2028,6 +2394,7 @@ fn handle_runthrough_2_ci_first_failed() {
             memory_commit_a(),
             memory_commit_b(),
             None,
+            FailureSummary::default(),
         )),
     ]);
     // The second CI successfully built it. It should not affect the state of anything.
@@ -2060,6 +2427,7 @@ fn handle_runthrough_2_ci_first_failed() {
             memory_commit_a(),
             memory_commit_b(),
             None,
+            FailureSummary::default(),
         )),
     ]);
     // The second one is now merged into staging; let's start building.
@@ -2101,6 +2469,7 @@ fn handle_runthrough_2_ci_first_failed() {
             memory_commit_a(),
             memory_commit_b(),
             None,
+            FailureSummary::default(),
         )),
         (memory_pr_c(), ui::Status::StartingBuild(
             memory_commit_c(),
@@ -2254,6 +2623,7 @@ fn handle_runthrough_2_ci_second_failed() {
             CiId(2),
             memory_commit_b(),
             None,
+            FailureSummary::default(),
         ))
     );
     assert_eq!(db.running, Some(RunningEntry{
@@ -2281,6 +2651,7 @@ fn handle_runthrough_2_ci_second_failed() {
             memory_commit_a(),
             memory_commit_b(),
             None,
+            FailureSummary::default(),
         )),
     ]);
     // The second one is now merged into staging; let's start building.
@@ -2322,10 +2693,596 @@ fn handle_runthrough_2_ci_second_failed() {
             memory_commit_a(),
             memory_commit_b(),
             None,
+            FailureSummary::default(),
         )),
         (memory_pr_c(), ui::Status::StartingBuild(
             memory_commit_c(),
             memory_commit_d(),
         )),
     ]);
-}
\ No newline at end of file
+}
+
+#[test]
+fn handle_ci_states_2_ci() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci1 = MemoryCi::new();
+    let mut ci2 = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    let url = "http://ci.example.com/1".into_url().unwrap();
+    handle_event_2_ci(
+        &mut ui,
+        &mut vcs,
+        &mut ci1,
+        &mut ci2,
+        &mut db,
+        Event::UiEvent(ui::Event::Approved(
+            PipelineId(0),
+            memory_pr_a(),
+            Some(memory_commit_a()),
+            "MSG!".to_owned(),
+        ))
+    );
+    handle_event_2_ci(
+        &mut ui,
+        &mut vcs,
+        &mut ci1,
+        &mut ci2,
+        &mut db,
+        Event::VcsEvent(vcs::Event::MergedToStaging(
+            PipelineId(0),
+            memory_commit_a(),
+            memory_commit_b(),
+        ))
+    );
+    // Both jobs are waiting to start.
+    assert_eq!(db.cis[&CiId(1)].state, CiState::Queued);
+    assert_eq!(db.cis[&CiId(2)].state, CiState::Queued);
+    handle_event_2_ci(
+        &mut ui,
+        &mut vcs,
+        &mut ci1,
+        &mut ci2,
+        &mut db,
+        Event::CiEvent(ci::Event::BuildStarted(
+            CiId(1),
+            memory_commit_b(),
+            Some(url.clone()),
+        ))
+    );
+    assert_eq!(db.cis[&CiId(1)].state, CiState::Running);
+    assert!(db.cis[&CiId(1)].started.is_some());
+    assert_eq!(db.cis[&CiId(2)].state, CiState::Queued);
+    // The first job failed. Its state sticks around, along with its URL.
+    handle_event_2_ci(
+        &mut ui,
+        &mut vcs,
+        &mut ci1,
+        &mut ci2,
+        &mut db,
+        Event::CiEvent(ci::Event::BuildFailed(
+            CiId(1),
+            memory_commit_b(),
+            None,
+            FailureSummary::default(),
+        ))
+    );
+    let entry = db.cis[&CiId(1)].clone();
+    assert_eq!(entry.state, CiState::Failed);
+    assert_eq!(entry.commit, memory_commit_b());
+    assert_eq!(entry.url, Some(url));
+    assert!(entry.started.is_some());
+    assert!(entry.finished.is_some());
+    let states: Vec<_> = ui.borrow().ci_results.iter()
+        .map(|&(ref pr, ref result)| (
+            pr.clone(),
+            result.name.clone(),
+            result.pull_commit.clone(),
+            result.state.state,
+        ))
+        .collect();
+    assert_eq!(states, vec![
+        (memory_pr_a(), "1".to_owned(), memory_commit_a(), CiState::Queued),
+        (memory_pr_a(), "2".to_owned(), memory_commit_a(), CiState::Queued),
+        (memory_pr_a(), "1".to_owned(), memory_commit_a(), CiState::Running),
+        (memory_pr_a(), "1".to_owned(), memory_commit_a(), CiState::Failed),
+        (memory_pr_a(), "2".to_owned(), memory_commit_a(), CiState::Canceled),
+    ]);
+    // The job that was still queued is not left hanging.
+    let entry = db.cis[&CiId(2)].clone();
+    assert_eq!(entry.state, CiState::Canceled);
+    assert_eq!(entry.commit, memory_commit_b());
+    assert!(entry.finished.is_some());
+}
+
+#[test]
+fn handle_ci_failed_retry_marks_flaky() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    let retry = RetryConfig{
+        count: 1,
+        only_flaky: false,
+    };
+    db.put_running(PipelineId(0), RunningEntry{
+        pull_commit: memory_commit_a(),
+        merge_commit: Some(memory_commit_b()),
+        pr: memory_pr_a(),
+        message: "MSG!".to_owned(),
+        canceled: false,
+        built: false,
+    }).unwrap();
+    vcs.borrow_mut().staging = Some(memory_commit_b());
+    handle_event_retry(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        retry,
+        Event::CiEvent(ci::Event::BuildFailed(
+            CiId(1),
+            memory_commit_b(),
+            None,
+            FailureSummary{
+                tests: vec!["tests::sometimes".to_owned()],
+                log: None,
+            },
+        ))
+    );
+    assert!(db.running.is_some());
+    assert_eq!(ci.borrow().build, Some(memory_commit_b()));
+    assert_eq!(db.cis[&CiId(1)].state, CiState::Queued);
+    assert!(ui.borrow().results.is_empty());
+    handle_event_retry(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        retry,
+        Event::CiEvent(ci::Event::BuildSucceeded(
+            CiId(1),
+            memory_commit_b(),
+            None,
+        ))
+    );
+    assert!(db.running.as_ref().unwrap().built);
+    assert_eq!(vcs.borrow().master, Some(memory_commit_b()));
+    assert_eq!(
+        db.list_flaky(CiId(1)).unwrap(),
+        vec![FlakyEntry{
+            signature: "tests::sometimes".to_owned(),
+            count: 1,
+            last_seen: 0,
+        }]
+    );
+    // Counted once; a later build of the same commit doesn't count it again.
+    assert!(db.failures.is_empty());
+}
+
+#[test]
+fn handle_ci_failed_retry_only_flaky() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    let retry = RetryConfig{
+        count: 2,
+        only_flaky: true,
+    };
+    let running = RunningEntry{
+        pull_commit: memory_commit_a(),
+        merge_commit: Some(memory_commit_b()),
+        pr: memory_pr_a(),
+        message: "MSG!".to_owned(),
+        canceled: false,
+        built: false,
+    };
+    let summary = FailureSummary{
+        tests: vec!["tests::sometimes".to_owned()],
+        log: None,
+    };
+    db.put_running(PipelineId(0), running.clone()).unwrap();
+    vcs.borrow_mut().staging = Some(memory_commit_b());
+    handle_event_retry(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        retry,
+        Event::CiEvent(ci::Event::BuildFailed(
+            CiId(1),
+            memory_commit_b(),
+            None,
+            summary.clone(),
+        ))
+    );
+    // Not known to be flaky yet, so it fails right away.
+    assert!(db.running.is_none());
+    assert!(ci.borrow().build.is_none());
+    assert_eq!(
+        ui.borrow().results,
+        vec![(memory_pr_a(), ui::Status::Failure(
+            memory_commit_a(),
+            memory_commit_b(),
+            None,
+            summary.clone(),
+        ))]
+    );
+    db.add_flaky(CiId(1), "tests::sometimes").unwrap();
+    db.put_running(PipelineId(0), running).unwrap();
+    handle_event_retry(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        retry,
+        Event::CiEvent(ci::Event::BuildFailed(
+            CiId(1),
+            memory_commit_b(),
+            None,
+            summary,
+        ))
+    );
+    assert!(db.running.is_some());
+    assert_eq!(ci.borrow().build, Some(memory_commit_b()));
+    assert_eq!(ui.borrow().results.len(), 1);
+}
+
+#[test]
+fn handle_ci_failed_retry_only_flaky_learns_from_manual_retry() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    let retry = RetryConfig{
+        count: 1,
+        only_flaky: true,
+    };
+    let summary = FailureSummary{
+        tests: vec!["tests::sometimes".to_owned()],
+        log: None,
+    };
+    db.put_running(PipelineId(0), RunningEntry{
+        pull_commit: memory_commit_a(),
+        merge_commit: Some(memory_commit_b()),
+        pr: memory_pr_a(),
+        message: "MSG!".to_owned(),
+        canceled: false,
+        built: false,
+    }).unwrap();
+    vcs.borrow_mut().staging = Some(memory_commit_b());
+    handle_event_retry(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        retry,
+        Event::CiEvent(ci::Event::BuildFailed(
+            CiId(1),
+            memory_commit_b(),
+            None,
+            summary.clone(),
+        ))
+    );
+    // Not known to be flaky yet, so it fails right away.
+    assert!(db.running.is_none());
+    assert!(ci.borrow().build.is_none());
+    // Somebody retries it by hand, and it passes on the same merge commit.
+    db.put_running(PipelineId(0), RunningEntry{
+        pull_commit: memory_commit_a(),
+        merge_commit: None,
+        pr: memory_pr_a(),
+        message: "MSG!".to_owned(),
+        canceled: false,
+        built: false,
+    }).unwrap();
+    handle_event_retry(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        retry,
+        Event::VcsEvent(vcs::Event::MergedToStaging(
+            PipelineId(0),
+            memory_commit_a(),
+            memory_commit_b(),
+        ))
+    );
+    assert_eq!(ci.borrow().build, Some(memory_commit_b()));
+    handle_event_retry(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        retry,
+        Event::CiEvent(ci::Event::BuildSucceeded(
+            CiId(1),
+            memory_commit_b(),
+            None,
+        ))
+    );
+    let flaky = db.list_flaky(CiId(1)).unwrap();
+    assert_eq!(flaky.len(), 1);
+    assert_eq!(flaky[0].signature, "tests::sometimes");
+    // The next time it fails, on another PR, it gets retried on its own.
+    db.running = None;
+    db.put_running(PipelineId(0), RunningEntry{
+        pull_commit: memory_commit_c(),
+        merge_commit: None,
+        pr: memory_pr_b(),
+        message: "MSG!".to_owned(),
+        canceled: false,
+        built: false,
+    }).unwrap();
+    vcs.borrow_mut().staging = Some(memory_commit_d());
+    handle_event_retry(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        retry,
+        Event::VcsEvent(vcs::Event::MergedToStaging(
+            PipelineId(0),
+            memory_commit_c(),
+            memory_commit_d(),
+        ))
+    );
+    ci.borrow_mut().build = None;
+    handle_event_retry(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        retry,
+        Event::CiEvent(ci::Event::BuildFailed(
+            CiId(1),
+            memory_commit_d(),
+            None,
+            summary,
+        ))
+    );
+    assert!(db.running.is_some());
+    assert_eq!(ci.borrow().build, Some(memory_commit_d()));
+}
+
+#[test]
+fn handle_ci_failed_not_flaky_when_master_moved() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    let retry = RetryConfig{
+        count: 0,
+        only_flaky: false,
+    };
+    db.put_running(PipelineId(0), RunningEntry{
+        pull_commit: memory_commit_a(),
+        merge_commit: Some(memory_commit_b()),
+        pr: memory_pr_a(),
+        message: "MSG!".to_owned(),
+        canceled: false,
+        built: false,
+    }).unwrap();
+    vcs.borrow_mut().staging = Some(memory_commit_b());
+    handle_event_retry(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        retry,
+        Event::CiEvent(ci::Event::BuildFailed(
+            CiId(1),
+            memory_commit_b(),
+            None,
+            FailureSummary{
+                tests: vec!["tests::broken_by_master".to_owned()],
+                log: None,
+            },
+        ))
+    );
+    assert!(db.running.is_none());
+    // Retried by hand after master moved, so it merges to something else,
+    // and what is built is not the code that failed.
+    db.put_running(PipelineId(0), RunningEntry{
+        pull_commit: memory_commit_a(),
+        merge_commit: None,
+        pr: memory_pr_a(),
+        message: "MSG!".to_owned(),
+        canceled: false,
+        built: false,
+    }).unwrap();
+    vcs.borrow_mut().staging = Some(memory_commit_c());
+    handle_event_retry(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        retry,
+        Event::VcsEvent(vcs::Event::MergedToStaging(
+            PipelineId(0),
+            memory_commit_a(),
+            memory_commit_c(),
+        ))
+    );
+    handle_event_retry(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        retry,
+        Event::CiEvent(ci::Event::BuildSucceeded(
+            CiId(1),
+            memory_commit_c(),
+            None,
+        ))
+    );
+    assert!(db.running.as_ref().unwrap().built);
+    assert!(db.list_flaky(CiId(1)).unwrap().is_empty());
+}
+
+#[test]
+fn handle_queue_positions() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    // The first PR goes straight from the queue to running.
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Approved(
+            PipelineId(0),
+            memory_pr_a(),
+            Some(memory_commit_a()),
+            "MSG!".to_owned(),
+        ))
+    );
+    assert_eq!(ui.borrow().queue_positions, vec![
+        (memory_pr_a(), 1),
+    ]);
+    // The next two have to wait.
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Approved(
+            PipelineId(0),
+            memory_pr_c(),
+            Some(memory_commit_c()),
+            "MSG!".to_owned(),
+        ))
+    );
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Approved(
+            PipelineId(0),
+            memory_pr_b(),
+            Some(memory_commit_b()),
+            "MSG!".to_owned(),
+        ))
+    );
+    assert_eq!(ui.borrow().queue_positions, vec![
+        (memory_pr_a(), 1),
+        (memory_pr_c(), 1),
+        (memory_pr_c(), 1),
+        (memory_pr_b(), 2),
+    ]);
+    // Canceling one moves the others up.
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Canceled(
+            PipelineId(0),
+            memory_pr_c(),
+        ))
+    );
+    assert_eq!(ui.borrow().queue_positions, vec![
+        (memory_pr_a(), 1),
+        (memory_pr_c(), 1),
+        (memory_pr_c(), 1),
+        (memory_pr_b(), 2),
+        (memory_pr_b(), 1),
+    ]);
+}
+
+#[test]
+fn handle_prioritized() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    db.put_running(PipelineId(0), RunningEntry{
+        pull_commit: memory_commit_a(),
+        merge_commit: None,
+        pr: memory_pr_a(),
+        message: "MSG!".to_owned(),
+        canceled: false,
+        built: false,
+    }).unwrap();
+    for &(ref pr, ref commit) in &[
+        (memory_pr_b(), memory_commit_b()),
+        (memory_pr_c(), memory_commit_c()),
+    ] {
+        handle_event(
+            &mut ui,
+            &mut vcs,
+            &mut ci,
+            &mut db,
+            Event::UiEvent(ui::Event::Approved(
+                PipelineId(0),
+                pr.clone(),
+                Some(commit.clone()),
+                "MSG!".to_owned(),
+            ))
+        );
+    }
+    ui.borrow_mut().queue_positions.clear();
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Prioritized(
+            PipelineId(0),
+            memory_pr_c(),
+            10,
+        ))
+    );
+    assert_eq!(db.queue[0].pr, memory_pr_c());
+    assert_eq!(db.queue[0].priority, 10);
+    assert_eq!(db.queue[1].pr, memory_pr_b());
+    assert_eq!(ui.borrow().queue_positions, vec![
+        (memory_pr_c(), 1),
+        (memory_pr_b(), 2),
+    ]);
+}
+
+#[test]
+fn handle_paused() {
+    let mut ui = MemoryUi::new();
+    let mut vcs = MemoryVcs::new();
+    let mut ci = MemoryCi::new();
+    let mut db = MemoryDb::new();
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Paused(PipelineId(0), true))
+    );
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Approved(
+            PipelineId(0),
+            memory_pr_a(),
+            Some(memory_commit_a()),
+            "MSG!".to_owned(),
+        ))
+    );
+    // Approved, but left waiting in the queue.
+    assert!(db.running.is_none());
+    assert_eq!(db.queue[0].pr, memory_pr_a());
+    assert!(vcs.borrow().staging.is_none());
+    handle_event(
+        &mut ui,
+        &mut vcs,
+        &mut ci,
+        &mut db,
+        Event::UiEvent(ui::Event::Paused(PipelineId(0), false))
+    );
+    assert_eq!(db.running.unwrap().pull_commit, memory_commit_a());
+    assert!(db.queue.is_empty());
+    assert_eq!(vcs.borrow().staging.as_ref().unwrap(), &memory_commit_a());
+}
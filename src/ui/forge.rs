@@ -0,0 +1,192 @@
+// This file is released under the same terms as Rust itself.
+
+//! What the GitLab and Gitea front-ends share. Their webhooks, commands
+//! and commit statuses work the same way; only the names differ.
+
+use hyper;
+use hyper::Url;
+use hyper::buffer::BufReader;
+use hyper::header::Headers;
+use hyper::net::{HttpListener, NetworkListener, NetworkStream};
+use hyper::server::{Request, Response};
+use hyper::status::StatusCode;
+use pipeline::PipelineId;
+use serde::Deserialize;
+use serde_json::{self, from_slice as json_from_slice};
+use std::io::BufWriter;
+use std::sync::mpsc::Sender;
+use ui::{self, comments, Pr, RepoPipelines};
+use vcs::Commit;
+
+/// Accept webhooks forever, and pass each one to `handle`.
+pub fn run_webhook<F>(listen: &str, handle: F)
+        where F: Fn(Request, Response) {
+    let mut listener = HttpListener::new(listen)
+        .expect("webhook");
+    while let Ok(mut stream) = listener.accept() {
+        let addr = stream.peer_addr()
+            .expect("webhook client address");
+        let mut stream_clone = stream.clone();
+        let mut buf_read = BufReader::new(
+            &mut stream_clone as &mut NetworkStream
+        );
+        let mut buf_write = BufWriter::new(&mut stream);
+        let req = match Request::new(&mut buf_read, addr) {
+            Ok(req) => req,
+            Err(e) => {
+                warn!("Invalid webhook HTTP: {:?}", e);
+                continue;
+            }
+        };
+        let mut head = Headers::new();
+        let res = Response::new(&mut buf_write, &mut head);
+        handle(req, res);
+    }
+}
+
+/// Answer a webhook with an empty body.
+pub fn respond(mut res: Response, status: StatusCode, what: &str) {
+    *res.status_mut() = status;
+    if let Err(e) = res.send(&[]) {
+        warn!("Failed to send response to {}: {:?}", what, e);
+    }
+}
+
+/// Parse a webhook's body, and answer it, as a bad request if the body
+/// isn't what was expected.
+pub fn parse_hook<T: Deserialize>(body: &[u8], res: Response, what: &str)
+        -> Option<T> {
+    match json_from_slice::<T>(body) {
+        Ok(desc) => {
+            respond(res, StatusCode::NoContent, what);
+            Some(desc)
+        }
+        Err(e) => {
+            warn!("Got invalid {}: {:?}", what, e);
+            respond(res, StatusCode::BadRequest, what);
+            None
+        }
+    }
+}
+
+/// What a PR webhook says happened, if it's something the pipelines
+/// care about.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PrAction {
+    Opened,
+    Changed,
+    Closed,
+}
+
+/// Tell the pipelines for the PR's target branch what happened to it.
+pub fn send_pr_action(
+    send_event: &Sender<ui::Event>,
+    action: PrAction,
+    repo_pipelines: &RepoPipelines,
+    pr: Pr,
+    commit: Commit,
+    title: String,
+    url: Url,
+    draft: bool,
+) {
+    if let Some(pipeline_id) = repo_pipelines.try_pipeline_id {
+        send_event.send(pr_event(
+            action,
+            pipeline_id,
+            pr.clone(),
+            commit.clone(),
+            title.clone(),
+            url.clone(),
+            // Drafts can still be tried.
+            false,
+        )).expect("Pipeline to be there");
+    }
+    send_event.send(pr_event(
+        action,
+        repo_pipelines.pipeline_id,
+        pr,
+        commit,
+        title,
+        url,
+        draft,
+    )).expect("Pipeline to be there");
+}
+
+fn pr_event(
+    action: PrAction,
+    pipeline_id: PipelineId,
+    pr: Pr,
+    commit: Commit,
+    title: String,
+    url: Url,
+    draft: bool,
+) -> ui::Event {
+    match action {
+        PrAction::Opened =>
+            ui::Event::Opened(pipeline_id, pr, commit, title, url, draft),
+        PrAction::Changed =>
+            ui::Event::Changed(pipeline_id, pr, commit, title, url, draft),
+        PrAction::Closed => ui::Event::Closed(pipeline_id, pr),
+    }
+}
+
+/// Send the event for a command from a comment. `merge_message` makes
+/// the merge commit's message, given the name of whoever approved it.
+pub fn send_command<F>(
+    send_event: &Sender<ui::Event>,
+    command: comments::Command,
+    repo_pipelines: &RepoPipelines,
+    pr: Pr,
+    merge_message: F,
+) where F: FnOnce(&str) -> String {
+    let (pipeline_id, approval) = match command {
+        comments::Command::Approved(user, commit) =>
+            (Some(repo_pipelines.pipeline_id), Some((user, commit))),
+        comments::Command::Canceled =>
+            (Some(repo_pipelines.pipeline_id), None),
+        comments::Command::TryApproved(user, commit) =>
+            (repo_pipelines.try_pipeline_id, Some((user, commit))),
+        comments::Command::TryCanceled =>
+            (repo_pipelines.try_pipeline_id, None),
+    };
+    let pipeline_id = match pipeline_id {
+        Some(pipeline_id) => pipeline_id,
+        None => {
+            info!("Got try command for repo without try; do nothing");
+            return;
+        }
+    };
+    let event = match approval {
+        Some((user, commit)) => ui::Event::Approved(
+            pipeline_id,
+            pr,
+            commit,
+            merge_message(user),
+        ),
+        None => ui::Event::Canceled(
+            pipeline_id,
+            pr,
+        ),
+    };
+    send_event.send(event).expect("Command: Pipeline error");
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum RequestError {
+        /// HTTP-level error
+        HttpStatus(status: StatusCode) {}
+        /// HTTP-level error
+        Http(err: hyper::error::Error) {
+            cause(err)
+            from()
+        }
+        /// JSON error
+        Json(err: serde_json::error::Error) {
+            cause(err)
+            from()
+        }
+        /// Repo not found for pipeline
+        Pipeline(pipeline_id: PipelineId) {}
+    }
+}
@@ -0,0 +1,694 @@
+// This file is released under the same terms as Rust itself.
+
+//! The Gitea pull request front-end. Forgejo is the same.
+//!
+//! The webhook payloads are shaped like GitHub's, but come with
+//! `X-Gitea-Event`, and are signed with a bare hex HMAC-SHA256 in
+//! `X-Gitea-Signature`. Commands are parsed the same way as on GitHub, and
+//! as there, only users with write access to the repo can give them.
+
+use crossbeam;
+use db;
+use hyper::Url;
+use hyper::method::Method;
+use hyper::server::{Request, Response};
+use hyper::status::StatusCode;
+use pipeline::{self, PipelineId};
+use serde_json::{self, from_reader as json_from_reader};
+use std::iter;
+use std::sync::mpsc::{Sender, Receiver};
+use ui::{self, comments, Pr, RepoPipelines};
+use ui::{handle_pr_retarget, is_staging_branch, pipelines_for_branch};
+use ui::{handle_push, render_comment, render_status};
+use ui::render_unmanaged_branch;
+use ui::forge::{self, PrAction, RequestError};
+use util::gitea_api::Api;
+use util::github_headers::{self, get_header};
+use vcs::Commit;
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Repo {
+    pub owner: String,
+    pub repo: String,
+}
+
+pub use ui::PipelineType;
+
+pub trait ProjectsConfig: Send + Sync + 'static {
+    /// The pipelines for each of the repo's target branches.
+    /// It is empty if the repo is not managed at all.
+    fn pipelines_by_repo(&self, &Repo) -> Vec<RepoPipelines>;
+    fn repo_by_pipeline(&self, PipelineId) -> Option<(Repo, PipelineType)>;
+}
+
+pub struct Worker {
+    listen: String,
+    api: Api,
+    user: String,
+    user_ident: String,
+    secret: String,
+    projects: Box<ProjectsConfig>,
+}
+
+impl Worker {
+    pub fn new(
+        listen: String,
+        api: Api,
+        user: String,
+        secret: String,
+        projects: Box<ProjectsConfig>,
+    ) -> Worker {
+        let user_ident = format!("@{}", user);
+        Worker {
+            listen: listen,
+            api: api,
+            user: user,
+            user_ident: user_ident,
+            secret: secret,
+            projects: projects,
+        }
+    }
+}
+
+// JSON API structs
+#[derive(Deserialize, Serialize)]
+struct UserDesc {
+    login: String,
+}
+#[derive(Deserialize, Serialize)]
+struct RepositoryDesc {
+    name: String,
+    owner: UserDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct PushDesc {
+    // ref is a reserved word.
+    #[serde(rename="ref")]
+    git_ref: String,
+    repository: RepositoryDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct PrBranchDesc {
+    // ref is a reserved word.
+    #[serde(rename="ref")]
+    git_ref: String,
+    sha: String,
+}
+#[derive(Deserialize, Serialize)]
+struct PrDesc {
+    number: u32,
+    title: String,
+    state: String,
+    html_url: String,
+    base: PrBranchDesc,
+    head: PrBranchDesc,
+    draft: Option<bool>,
+}
+#[derive(Deserialize, Serialize)]
+struct ChangeFromDesc {
+    from: String,
+}
+#[derive(Deserialize, Serialize)]
+struct PrChangesDesc {
+    // ref is a reserved word.
+    #[serde(rename="ref")]
+    git_ref: Option<ChangeFromDesc>,
+}
+#[derive(Deserialize, Serialize)]
+struct PullRequestDesc {
+    action: String,
+    pull_request: PrDesc,
+    repository: RepositoryDesc,
+    changes: Option<PrChangesDesc>,
+}
+#[derive(Deserialize, Serialize)]
+struct IssuePullRequestDesc {
+    merged: bool,
+}
+#[derive(Deserialize, Serialize)]
+struct IssueDesc {
+    number: u32,
+    title: String,
+    body: Option<String>,
+    state: String,
+    user: UserDesc,
+    pull_request: Option<IssuePullRequestDesc>,
+}
+#[derive(Deserialize, Serialize)]
+struct CommentBodyDesc {
+    user: UserDesc,
+    body: String,
+}
+#[derive(Deserialize, Serialize)]
+struct CommentDesc {
+    action: String,
+    issue: IssueDesc,
+    comment: CommentBodyDesc,
+    repository: RepositoryDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct PostCommentDesc {
+    body: String,
+}
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct StatusDesc {
+    state: String,
+    target_url: Option<String>,
+    description: String,
+    context: String,
+}
+#[derive(Deserialize, Serialize)]
+struct PermissionDesc {
+    permission: String,
+}
+#[derive(Deserialize, Serialize)]
+struct PrBaseDesc {
+    base: PrBranchDesc,
+}
+
+impl pipeline::Worker<ui::Event, ui::Message> for Worker {
+    fn run(
+        &self,
+        recv_msg: Receiver<ui::Message>,
+        mut send_event: Sender<ui::Event>
+    ) {
+        crossbeam::scope(|scope| {
+            let s2 = &*self;
+            let send_event_2 = send_event.clone();
+            scope.spawn(move || {
+                s2.run_webhook(send_event_2);
+            });
+            loop {
+                s2.handle_message(
+                    recv_msg.recv().expect("Pipeline went away"),
+                    &mut send_event,
+                );
+            }
+        })
+    }
+}
+
+impl Worker {
+    fn run_webhook(
+        &self,
+        send_event: Sender<ui::Event>,
+    ) {
+        forge::run_webhook(&self.listen, |req, res| {
+            self.handle_webhook(req, res, &send_event);
+        });
+    }
+
+    fn handle_webhook(
+        &self,
+        mut req: Request,
+        res: Response,
+        send_event: &Sender<ui::Event>
+    ) {
+        let x_gitea_event = get_header(&req.headers, "X-Gitea-Event");
+        let body = github_headers::parse_sha256_body(
+            &mut req,
+            "X-Gitea-Signature",
+            self.secret.as_bytes(),
+        );
+        let body = match body {
+            Some(body) => body,
+            None => {
+                forge::respond(res, StatusCode::Forbidden, "bad webhook");
+                return;
+            }
+        };
+        match &x_gitea_event[..] {
+            b"pull_request" => {
+                let desc = forge::parse_hook::<PullRequestDesc>(
+                    &body,
+                    res,
+                    "PR message",
+                );
+                if let Some(desc) = desc {
+                    info!(
+                        "Got PR message for #{}: {}",
+                        desc.pull_request.number,
+                        desc.action,
+                    );
+                    self.handle_pr(send_event, desc);
+                }
+            }
+            b"push" => {
+                let desc = forge::parse_hook::<PushDesc>(&body, res, "push");
+                if let Some(desc) = desc {
+                    let repo = Repo{
+                        owner: desc.repository.owner.login,
+                        repo: desc.repository.name,
+                    };
+                    let all_pipelines = self.projects.pipelines_by_repo(&repo);
+                    handle_push(send_event, all_pipelines, &desc.git_ref);
+                }
+            }
+            b"issue_comment" | b"pull_request_comment" => {
+                let desc = forge::parse_hook::<CommentDesc>(
+                    &body,
+                    res,
+                    "comment",
+                );
+                if let Some(desc) = desc {
+                    self.handle_comment(send_event, desc);
+                }
+            }
+            e => {
+                info!("Got unknown event {:?}", String::from_utf8_lossy(e));
+                forge::respond(res, StatusCode::NoContent, "Gitea");
+            }
+        }
+    }
+
+    fn handle_pr(
+        &self,
+        send_event: &Sender<ui::Event>,
+        desc: PullRequestDesc,
+    ) {
+        let repo = Repo{
+            owner: desc.repository.owner.login,
+            repo: desc.repository.name,
+        };
+        let pull_request = desc.pull_request;
+        let pr = Pr::from(pull_request.number.to_string());
+        let all_pipelines = self.projects.pipelines_by_repo(&repo);
+        if all_pipelines.is_empty() {
+            warn!(
+                "Got bad repo {:?}",
+                repo
+            );
+            return;
+        }
+        if is_staging_branch(&all_pipelines, &pull_request.head.git_ref) {
+            info!(
+                "PR is from staging branch {}; do nothing",
+                pull_request.head.git_ref
+            );
+            return;
+        }
+        let base = pull_request.base.git_ref;
+        let old_base = desc.changes.and_then(|changes| changes.git_ref);
+        if let Some(old_base) = old_base {
+            handle_pr_retarget(
+                send_event,
+                &all_pipelines,
+                &pr,
+                &old_base.from,
+                &base,
+            );
+        }
+        let repo_pipelines = match pipelines_for_branch(all_pipelines, &base) {
+            Some(repo_pipelines) => repo_pipelines,
+            None => {
+                info!("PR targets unmanaged branch {}; do nothing", base);
+                return;
+            }
+        };
+        let url = match Url::parse(&pull_request.html_url) {
+            Ok(url) => url,
+            Err(e) => {
+                warn!("PR #{} has invalid URL: {:?}", pr, e);
+                return;
+            }
+        };
+        let action = match &desc.action[..] {
+            "closed" => PrAction::Closed,
+            "opened" | "reopened" => PrAction::Opened,
+            "synchronized" | "edited" => PrAction::Changed,
+            _ => return,
+        };
+        forge::send_pr_action(
+            send_event,
+            action,
+            &repo_pipelines,
+            pr,
+            Commit::from(pull_request.head.sha),
+            pull_request.title,
+            url,
+            pull_request.draft.unwrap_or(false),
+        );
+    }
+
+    fn handle_comment(
+        &self,
+        send_event: &Sender<ui::Event>,
+        desc: CommentDesc,
+    ) {
+        let body = &desc.comment.body;
+        let user = &desc.comment.user.login;
+        if desc.action != "created" {
+            info!("Comment was not just made; do nothing");
+            return;
+        }
+        if desc.issue.pull_request.is_none() {
+            info!("Got issue comment; do nothing");
+            return;
+        }
+        if *user == self.user {
+            info!("Comment is my own; do nothing");
+            return;
+        }
+        if !body.contains(&self.user_ident) {
+            info!("Comment does not mention me; do nothing");
+            return;
+        }
+        if desc.issue.state == "closed" {
+            info!("Comment is for closed PR; do nothing");
+            return;
+        }
+        info!("Got pull request comment");
+        let repo = Repo{
+            owner: desc.repository.owner.login.clone(),
+            repo: desc.repository.name.clone(),
+        };
+        let pr = Pr::from(desc.issue.number.to_string());
+        let all_pipelines = self.projects.pipelines_by_repo(&repo);
+        if all_pipelines.is_empty() {
+            warn!(
+                "Got bad repo {:?}",
+                repo
+            );
+            return;
+        }
+        let allowed = self.user_has_write(&repo, user)
+            .unwrap_or_else(|e| {
+                warn!("Failed to check if {} has permission: {:?}", user, e);
+                false
+            });
+        if !allowed {
+            info!("Got mentioned by not-permitted user");
+        } else if let Some(command) = comments::parse(body, user) {
+            // The comment hook doesn't say where the PR goes.
+            let base = match self.pr_base(&repo, &pr) {
+                Ok(base) => base,
+                Err(e) => {
+                    warn!("Failed to get the base branch of {}: {:?}", pr, e);
+                    return;
+                }
+            };
+            let unmanaged_comment =
+                render_unmanaged_branch(&all_pipelines, "PR", &base);
+            match pipelines_for_branch(all_pipelines, &base) {
+                Some(repo_pipelines) => forge::send_command(
+                    send_event,
+                    command,
+                    &repo_pipelines,
+                    pr,
+                    |user| merge_message(&desc.issue, user),
+                ),
+                None => {
+                    info!("Got command for PR into unmanaged branch");
+                    let result =
+                        self.post_comment(&repo, &pr, unmanaged_comment);
+                    if let Err(e) = result {
+                        warn!("Failed to comment on {}: {:?}", pr, e);
+                    }
+                }
+            }
+        } else {
+            info!("Pull request comment is not a command");
+        }
+    }
+
+    fn handle_message(
+        &self,
+        msg: ui::Message,
+        _: &mut Sender<ui::Event>,
+    ) {
+        match msg {
+            ui::Message::SendResult(pipeline_id, pr, status) => {
+                let result = self.send_result_to_pr(pipeline_id, &pr, &status);
+                if let Err(e) = result {
+                    warn!("Failed to send {:?} to pr {}: {:?}", status, pr, e)
+                }
+            }
+            ui::Message::SendCiResult(pipeline_id, pr, ci_result) => {
+                let result = self.send_ci_result_to_pr(pipeline_id, &ci_result);
+                if let Err(e) = result {
+                    warn!(
+                        "Failed to send {:?} to pr {}: {:?}",
+                        ci_result,
+                        pr,
+                        e,
+                    )
+                }
+            }
+            // There's nowhere to show it but a comment, which would be spam.
+            ui::Message::SendQueuePosition(_, _, _) => {}
+        }
+    }
+
+    fn send_result_to_pr(
+        &self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        status: &ui::Status,
+    ) -> Result<(), RequestError> {
+        let (repo, pipeline_type) =
+            match self.projects.repo_by_pipeline(pipeline_id) {
+                Some(result) => result,
+                None => {
+                    return Err(RequestError::Pipeline(pipeline_id));
+                }
+            };
+        if let Some(body) = render_comment(status, "PR", "Base") {
+            try!(self.post_comment(&repo, pr, body));
+        }
+        let context = match pipeline_type {
+            PipelineType::Stage => "continuous-integration/aelita",
+            PipelineType::Try => "continuous-integration/aelita/try",
+        };
+        if let Some(commit_status) = render_status(status) {
+            let status_body = StatusDesc {
+                state: commit_status.state.github_name().to_owned(),
+                target_url: commit_status.url.map(ToString::to_string),
+                description: commit_status.description,
+                context: context.to_owned(),
+            };
+            let pull_commit = commit_status.pull_commit;
+            try!(self.post_status(&repo, pull_commit, &status_body));
+            if let Some(merge_commit) = commit_status.merge_commit {
+                try!(self.post_status(&repo, merge_commit, &status_body));
+            }
+        }
+        Ok(())
+    }
+
+    fn send_ci_result_to_pr(
+        &self,
+        pipeline_id: PipelineId,
+        ci_result: &ui::CiResult,
+    ) -> Result<(), RequestError> {
+        let (repo, pipeline_type) =
+            match self.projects.repo_by_pipeline(pipeline_id) {
+                Some(result) => result,
+                None => {
+                    return Err(RequestError::Pipeline(pipeline_id));
+                }
+            };
+        let context = match pipeline_type {
+            PipelineType::Stage => "continuous-integration/aelita/job",
+            PipelineType::Try => "continuous-integration/aelita/try/job",
+        };
+        let entry = &ci_result.state;
+        let (state, description) = match entry.state {
+            db::CiState::Queued => ("pending", "Queued"),
+            db::CiState::Running => ("pending", "Running"),
+            db::CiState::Succeeded => ("success", "Passed"),
+            db::CiState::Failed => ("failure", "Failed"),
+            db::CiState::Canceled => ("error", "Canceled"),
+        };
+        let status_body = StatusDesc {
+            state: state.to_owned(),
+            target_url: entry.url.as_ref().map(ToString::to_string),
+            description: description.to_owned(),
+            context: format!("{}/{}", context, ci_result.name),
+        };
+        try!(self.post_status(&repo, &ci_result.pull_commit, &status_body));
+        try!(self.post_status(&repo, &entry.commit, &status_body));
+        Ok(())
+    }
+
+    /// Gitea works out the user's permission from collaborators, teams
+    /// and ownership, so one request covers all of them.
+    fn user_has_write(
+        &self,
+        repo: &Repo,
+        user: &str,
+    ) -> Result<bool, RequestError> {
+        let path = format!(
+            "/repos/{}/{}/collaborators/{}/permission",
+            repo.owner,
+            repo.repo,
+            user,
+        );
+        let resp = try!(self.api.send(Method::Get, &path, None));
+        if resp.status == StatusCode::NotFound ||
+                resp.status == StatusCode::Forbidden {
+            return Ok(false);
+        }
+        if !resp.status.is_success() {
+            return Err(RequestError::HttpStatus(resp.status));
+        }
+        let permission: PermissionDesc = try!(json_from_reader(resp));
+        Ok(permission_has_write(&permission.permission))
+    }
+
+    fn pr_base(
+        &self,
+        repo: &Repo,
+        pr: &Pr,
+    ) -> Result<String, RequestError> {
+        let path = format!(
+            "/repos/{}/{}/pulls/{}",
+            repo.owner,
+            repo.repo,
+            pr,
+        );
+        let resp = try!(self.api.send(Method::Get, &path, None));
+        if !resp.status.is_success() {
+            return Err(RequestError::HttpStatus(resp.status));
+        }
+        let desc: PrBaseDesc = try!(json_from_reader(resp));
+        Ok(desc.base.git_ref)
+    }
+
+    fn post_comment(
+        &self,
+        repo: &Repo,
+        pr: &Pr,
+        body: String,
+    ) -> Result<(), RequestError> {
+        let path = format!(
+            "/repos/{}/{}/issues/{}/comments",
+            repo.owner,
+            repo.repo,
+            pr,
+        );
+        let body = try!(serde_json::to_string(&PostCommentDesc{
+            body: body,
+        }));
+        let resp = try!(self.api.send(Method::Post, &path, Some(&body)));
+        if !resp.status.is_success() {
+            return Err(RequestError::HttpStatus(resp.status));
+        }
+        Ok(())
+    }
+
+    fn post_status(
+        &self,
+        repo: &Repo,
+        commit: &Commit,
+        status_body: &StatusDesc,
+    ) -> Result<(), RequestError> {
+        let path = format!(
+            "/repos/{}/{}/statuses/{}",
+            repo.owner,
+            repo.repo,
+            commit,
+        );
+        let body = try!(serde_json::to_string(status_body));
+        let resp = try!(self.api.send(Method::Post, &path, Some(&body)));
+        if !resp.status.is_success() {
+            return Err(RequestError::HttpStatus(resp.status));
+        }
+        Ok(())
+    }
+}
+
+fn permission_has_write(permission: &str) -> bool {
+    match permission {
+        "write" | "admin" | "owner" => true,
+        _ => false,
+    }
+}
+
+fn merge_message(issue: &IssueDesc, user: &str) -> String {
+    format!(
+        "{}\n\nMerge #{} a=@{} r=@{}\n{}\n\n{}",
+        issue.title,
+        issue.number,
+        issue.user.login,
+        user,
+        iter::repeat('_').take(72).collect::<String>(),
+        issue.body.as_ref().map(|x| &x[..]).unwrap_or(""),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::from_str as json_from_str;
+    use super::{CommentDesc, PullRequestDesc};
+    use super::{merge_message, permission_has_write};
+
+    #[test]
+    fn test_parse_pull_request_hook() {
+        let desc: PullRequestDesc = json_from_str(r#"{
+            "action": "edited",
+            "number": 3,
+            "pull_request": {
+                "id": 12,
+                "number": 3,
+                "user": {"id": 1, "login": "contributor"},
+                "title": "Fix it",
+                "body": "",
+                "state": "open",
+                "html_url": "https://gitea.example.com/owner/repo/pulls/3",
+                "merged": false,
+                "base": {"label": "master", "ref": "master",
+                         "sha": "e16d1eca074ae29ac1812e14316e96f3117d0675"},
+                "head": {"label": "fix", "ref": "fix",
+                         "sha": "55016813274e906e4cbfed97be83e19e6cd93d91"}
+            },
+            "changes": {"ref": {"from": "release"}},
+            "repository": {
+                "id": 1,
+                "name": "repo",
+                "full_name": "owner/repo",
+                "owner": {"id": 2, "login": "owner"}
+            }
+        }"#).unwrap();
+        assert_eq!(desc.repository.owner.login, "owner");
+        assert_eq!(desc.pull_request.base.git_ref, "master");
+        assert!(desc.pull_request.draft.is_none());
+        assert_eq!(desc.changes.unwrap().git_ref.unwrap().from, "release");
+    }
+
+    #[test]
+    fn test_parse_comment_hook() {
+        let desc: CommentDesc = json_from_str(r#"{
+            "action": "created",
+            "issue": {
+                "number": 3,
+                "user": {"id": 1, "login": "contributor"},
+                "title": "Fix it",
+                "body": "It was broken",
+                "state": "open",
+                "pull_request": {"merged": false, "merged_at": null}
+            },
+            "comment": {
+                "user": {"id": 3, "login": "reviewer"},
+                "body": "@aelita r+"
+            },
+            "repository": {
+                "name": "repo",
+                "owner": {"login": "owner"}
+            },
+            "is_pull": true
+        }"#).unwrap();
+        assert!(desc.issue.pull_request.is_some());
+        assert_eq!(
+            merge_message(&desc.issue, "reviewer").lines().nth(2),
+            Some("Merge #3 a=@contributor r=@reviewer"),
+        );
+    }
+
+    #[test]
+    fn test_permission_has_write() {
+        assert!(permission_has_write("owner"));
+        assert!(permission_has_write("write"));
+        assert!(!permission_has_write("read"));
+        assert!(!permission_has_write("none"));
+    }
+}
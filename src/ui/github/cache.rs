@@ -1,14 +1,28 @@
 // This file is released under the same terms as Rust itself.
 
 use db::Builder;
+use db::sqlite::add_missing_columns;
 use rusqlite::{self, Connection};
 use postgres::{Connection as PgConnection, TlsMode};
 use postgres::params::{ConnectParams, IntoConnectParams};
 use std::convert::AsRef;
 use std::error::Error;
 use std::path::Path;
+use ui::Pr;
 use ui::github::TeamId;
 use pipeline::PipelineId;
+use util;
+
+/// The one comment on a PR that gets edited as its status changes
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct StatusComment {
+    pub id: i64,
+    /// One markdown line per status change, oldest first
+    pub history: Vec<String>,
+    pub attempts: u32,
+    /// Who approved the PR, until the pipeline confirms it
+    pub approver: Option<String>,
+}
 
 pub fn from_builder(builder: &Builder)
         -> Result<Cache, Box<Error + Send + Sync>> {
@@ -47,10 +61,21 @@ impl Cache {
             Cache::Postgres(ref mut c) => c.teams_with_write(pipeline_id),
         }
     }
-    pub fn is_org(&mut self, pipeline_id: PipelineId) -> Option<bool> {
-        match *self {
+    /// Whether the pipeline's repo belongs to an organization,
+    /// or `None` if we don't know, or only knew before `fresh_since`.
+    pub fn is_org(
+        &mut self,
+        pipeline_id: PipelineId,
+        fresh_since: i64,
+    ) -> Option<bool> {
+        let is_org = match *self {
             Cache::Sqlite(ref mut c) => c.is_org(pipeline_id),
             Cache::Postgres(ref mut c) => c.is_org(pipeline_id),
+        };
+        match is_org {
+            Some((is_org, updated_at)) if updated_at >= fresh_since =>
+                Some(is_org),
+            _ => None,
         }
     }
     pub fn set_is_org(&mut self, pipeline_id: PipelineId, is_org: bool) {
@@ -59,6 +84,44 @@ impl Cache {
             Cache::Postgres(ref mut c) => c.set_is_org(pipeline_id, is_org),
         }
     }
+    /// Forget everything about who can write to the pipeline's repo,
+    /// so that it gets loaded again the next time it's needed.
+    pub fn invalidate(&mut self, pipeline_id: PipelineId) {
+        match *self {
+            Cache::Sqlite(ref mut c) => c.invalidate(pipeline_id),
+            Cache::Postgres(ref mut c) => c.invalidate(pipeline_id),
+        }
+    }
+    /// Every pipeline that there is anything cached for.
+    pub fn cached_pipelines(&mut self) -> Vec<PipelineId> {
+        match *self {
+            Cache::Sqlite(ref mut c) => c.cached_pipelines(),
+            Cache::Postgres(ref mut c) => c.cached_pipelines(),
+        }
+    }
+    pub fn status_comment(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+    ) -> Option<StatusComment> {
+        match *self {
+            Cache::Sqlite(ref mut c) => c.status_comment(pipeline_id, pr),
+            Cache::Postgres(ref mut c) => c.status_comment(pipeline_id, pr),
+        }
+    }
+    pub fn set_status_comment(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        comment: &StatusComment,
+    ) {
+        match *self {
+            Cache::Sqlite(ref mut c) =>
+                c.set_status_comment(pipeline_id, pr, comment),
+            Cache::Postgres(ref mut c) =>
+                c.set_status_comment(pipeline_id, pr, comment),
+        }
+    }
 }
 
 pub struct Sqlite {
@@ -76,9 +139,25 @@ impl Sqlite {
             );
             CREATE TABLE IF NOT EXISTS github_is_org (
                 pipeline_id INTEGER PRIMARY KEY,
-                is_org BOOLEAN
+                is_org BOOLEAN,
+                updated_at INTEGER NOT NULL DEFAULT 0
+            );
+            CREATE TABLE IF NOT EXISTS github_status_comment (
+                pipeline_id INTEGER,
+                pr TEXT,
+                comment_id INTEGER,
+                history TEXT,
+                attempts INTEGER,
+                approver TEXT,
+                UNIQUE (pipeline_id, pr)
             )
         "###));
+        try!(add_missing_columns(&conn, "github_is_org", &[
+            ("updated_at", "INTEGER NOT NULL DEFAULT 0"),
+        ]));
+        try!(add_missing_columns(&conn, "github_status_comment", &[
+            ("approver", "TEXT"),
+        ]));
         Ok(Sqlite{
             conn: conn,
         })
@@ -132,28 +211,100 @@ impl Sqlite {
             .collect();
         ret_val
     }
-    fn is_org(&mut self, pipeline_id: PipelineId) -> Option<bool> {
+    fn is_org(&mut self, pipeline_id: PipelineId) -> Option<(bool, i64)> {
         let sql = r###"
-            SELECT is_org
+            SELECT is_org, updated_at
             FROM github_is_org
             WHERE pipeline_id = ?
         "###;
         let mut stmt = self.conn.prepare(&sql)
             .expect("Prepare peek-running query");
         let mut rows = stmt
-            .query_map(&[&pipeline_id.0], |row| row.get::<_, bool>(0))
+            .query_map(&[&pipeline_id.0], |row| {
+                (row.get::<_, bool>(0), row.get::<_, i64>(1))
+            })
             .expect("Get is-org");
         rows.next().map(|row| row.expect("SQLite to work"))
     }
     fn set_is_org(&mut self, pipeline_id: PipelineId, is_org: bool) {
         let sql = r###"
-            REPLACE INTO github_is_org (pipeline_id, is_org)
-            VALUES (?, ?)
+            REPLACE INTO github_is_org (pipeline_id, is_org, updated_at)
+            VALUES (?, ?, ?)
         "###;
         self.conn.execute(sql, &[
-            &pipeline_id.0, &is_org
+            &pipeline_id.0, &is_org, &util::now_secs()
         ]).expect("to set is_org");
     }
+    fn invalidate(&mut self, pipeline_id: PipelineId) {
+        let trans = self.conn
+            .transaction()
+            .expect("Start invalidate transaction");
+        trans.execute(r###"
+            DELETE FROM github_is_org
+            WHERE pipeline_id = ?
+        "###, &[&pipeline_id.0]).expect("to clear is_org");
+        trans.execute(r###"
+            DELETE FROM github_teams_with_write
+            WHERE pipeline_id = ?
+        "###, &[&pipeline_id.0]).expect("to clear teams");
+        trans.commit().expect("Commit invalidate transaction");
+    }
+    fn cached_pipelines(&mut self) -> Vec<PipelineId> {
+        let sql = r###"
+            SELECT pipeline_id
+            FROM github_is_org
+        "###;
+        let mut stmt = self.conn.prepare(&sql)
+            .expect("Prepare cached pipelines query");
+        let rows = stmt
+            .query_map(&[], |row| PipelineId(row.get::<_, i32>(0)))
+            .expect("Get cached pipelines");
+        rows.map(|row| row.expect("SQLite to work")).collect()
+    }
+    fn status_comment(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+    ) -> Option<StatusComment> {
+        let sql = r###"
+            SELECT comment_id, history, attempts, approver
+            FROM github_status_comment
+            WHERE pipeline_id = ? AND pr = ?
+        "###;
+        let mut stmt = self.conn.prepare(&sql)
+            .expect("Prepare status comment query");
+        let mut rows = stmt
+            .query_map(&[&pipeline_id.0, &pr.as_str()], |row| {
+                StatusComment{
+                    id: row.get::<_, i64>(0),
+                    history: split_history(&row.get::<_, String>(1)),
+                    attempts: row.get::<_, i32>(2) as u32,
+                    approver: row.get::<_, Option<String>>(3),
+                }
+            })
+            .expect("Get status comment");
+        rows.next().map(|row| row.expect("SQLite to work"))
+    }
+    fn set_status_comment(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        comment: &StatusComment,
+    ) {
+        let sql = r###"
+            REPLACE INTO github_status_comment
+                (pipeline_id, pr, comment_id, history, attempts, approver)
+            VALUES (?, ?, ?, ?, ?, ?)
+        "###;
+        self.conn.execute(sql, &[
+            &pipeline_id.0,
+            &pr.as_str(),
+            &comment.id,
+            &comment.history.join("\n"),
+            &(comment.attempts as i32),
+            &comment.approver,
+        ]).expect("to set status comment");
+    }
 }
 
 pub struct Postgres {
@@ -176,7 +327,19 @@ impl Postgres {
             CREATE TABLE IF NOT EXISTS github_is_org (
                 pipeline_id INTEGER PRIMARY KEY,
                 is_org BOOLEAN
-            )
+            );
+            ALTER TABLE github_is_org
+                ADD COLUMN IF NOT EXISTS updated_at BIGINT NOT NULL DEFAULT 0;
+            CREATE TABLE IF NOT EXISTS github_status_comment (
+                pipeline_id INTEGER,
+                pr TEXT,
+                comment_id BIGINT,
+                history TEXT,
+                attempts INTEGER,
+                UNIQUE (pipeline_id, pr)
+            );
+            ALTER TABLE github_status_comment
+                ADD COLUMN IF NOT EXISTS approver TEXT;
         "###));
         Ok(result)
     }
@@ -234,10 +397,10 @@ impl Postgres {
             ret_val
         }}
     }
-    fn is_org(&mut self, pipeline_id: PipelineId) -> Option<bool> {
+    fn is_org(&mut self, pipeline_id: PipelineId) -> Option<(bool, i64)> {
         retry!{{
             let sql = r###"
-                SELECT is_org
+                SELECT is_org, updated_at
                 FROM github_is_org
                 WHERE pipeline_id = $1
             "###;
@@ -245,21 +408,141 @@ impl Postgres {
             let stmt = retry_unwrap!(conn.prepare(&sql));
             let rows = retry_unwrap!(stmt.query(&[&pipeline_id.0]));
             let rows = rows.iter();
-            let mut rows = rows.map(|row| row.get::<_, bool>(0));
+            let mut rows = rows.map(|row| {
+                (row.get::<_, bool>(0), row.get::<_, i64>(1))
+            });
             rows.next()
         }}
     }
     fn set_is_org(&mut self, pipeline_id: PipelineId, is_org: bool) {
         retry!{{
             let sql = r###"
-                INSERT INTO github_is_org (pipeline_id, is_org)
-                VALUES ($1, $2)
-                ON CONFLICT (pipeline_id) DO UPDATE SET is_org = $2
+                INSERT INTO github_is_org (pipeline_id, is_org, updated_at)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (pipeline_id)
+                    DO UPDATE SET is_org = $2, updated_at = $3
             "###;
             let conn = retry_unwrap!(self.conn());
             retry_unwrap!(conn.execute(sql, &[
-                &pipeline_id.0, &is_org
+                &pipeline_id.0, &is_org, &util::now_secs()
             ]));
         }}
     }
-}
\ No newline at end of file
+    fn invalidate(&mut self, pipeline_id: PipelineId) {
+        retry!{{
+            let conn = retry_unwrap!(self.conn());
+            let trans = retry_unwrap!(conn.transaction());
+            retry_unwrap!(trans.execute(r###"
+                DELETE FROM github_is_org
+                WHERE pipeline_id = $1
+            "###, &[&pipeline_id.0]));
+            retry_unwrap!(trans.execute(r###"
+                DELETE FROM github_teams_with_write
+                WHERE pipeline_id = $1
+            "###, &[&pipeline_id.0]));
+            retry_unwrap!(trans.commit());
+        }}
+    }
+    fn cached_pipelines(&mut self) -> Vec<PipelineId> {
+        retry!{{
+            let sql = r###"
+                SELECT pipeline_id
+                FROM github_is_org
+            "###;
+            let conn = retry_unwrap!(self.conn());
+            let stmt = retry_unwrap!(conn.prepare(&sql));
+            let rows = retry_unwrap!(stmt.query(&[]));
+            let rows = rows.iter();
+            let rows = rows.map(|row| PipelineId(row.get::<_, i32>(0)));
+            let ret_val = rows.collect();
+            ret_val
+        }}
+    }
+    fn status_comment(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+    ) -> Option<StatusComment> {
+        retry!{{
+            let sql = r###"
+                SELECT comment_id, history, attempts, approver
+                FROM github_status_comment
+                WHERE pipeline_id = $1 AND pr = $2
+            "###;
+            let conn = retry_unwrap!(self.conn());
+            let stmt = retry_unwrap!(conn.prepare(&sql));
+            let rows = retry_unwrap!(stmt.query(
+                &[&pipeline_id.0, &pr.as_str()]
+            ));
+            let rows = rows.iter();
+            let mut rows = rows.map(|row| {
+                StatusComment{
+                    id: row.get::<_, i64>(0),
+                    history: split_history(&row.get::<_, String>(1)),
+                    attempts: row.get::<_, i32>(2) as u32,
+                    approver: row.get::<_, Option<String>>(3),
+                }
+            });
+            rows.next()
+        }}
+    }
+    fn set_status_comment(
+        &mut self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        comment: &StatusComment,
+    ) {
+        retry!{{
+            let sql = r###"
+                INSERT INTO github_status_comment
+                    (pipeline_id, pr, comment_id, history, attempts, approver)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                ON CONFLICT (pipeline_id, pr) DO UPDATE
+                    SET comment_id = $3, history = $4, attempts = $5,
+                        approver = $6
+            "###;
+            let conn = retry_unwrap!(self.conn());
+            retry_unwrap!(conn.execute(sql, &[
+                &pipeline_id.0,
+                &pr.as_str(),
+                &comment.id,
+                &comment.history.join("\n"),
+                &(comment.attempts as i32),
+                &comment.approver,
+            ]));
+        }}
+    }
+}
+
+fn split_history(history: &str) -> Vec<String> {
+    if history.is_empty() {
+        Vec::new()
+    } else {
+        history.split('\n').map(ToOwned::to_owned).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pipeline::PipelineId;
+    use super::{Cache, Sqlite};
+    use ui::github::TeamId;
+
+    #[test]
+    fn test_invalidate() {
+        let mut cache = Cache::Sqlite(Sqlite::open(":memory:").unwrap());
+        cache.set_is_org(PipelineId(1), true);
+        cache.set_teams_with_write(PipelineId(1), vec![TeamId(5)].into_iter());
+        cache.set_is_org(PipelineId(2), false);
+        assert_eq!(cache.is_org(PipelineId(1), 0), Some(true));
+        // Too old to trust.
+        assert_eq!(cache.is_org(PipelineId(1), i64::max_value()), None);
+        let mut cached = cache.cached_pipelines();
+        cached.sort_by_key(|pipeline_id| pipeline_id.0);
+        assert_eq!(cached, vec![PipelineId(1), PipelineId(2)]);
+        cache.invalidate(PipelineId(1));
+        assert_eq!(cache.is_org(PipelineId(1), 0), None);
+        assert!(cache.teams_with_write(PipelineId(1)).is_empty());
+        assert_eq!(cache.is_org(PipelineId(2), 0), Some(false));
+    }
+}
@@ -0,0 +1,209 @@
+// This file is released under the same terms as Rust itself.
+
+//! Check runs, for reporting through the Checks API instead of
+//! commit statuses.
+
+use ci::FailureSummary;
+use db::CiState;
+use hyper::Url;
+use ui::{CiResult, render_failure_summary};
+use vcs::Commit;
+use vcs::git::ToShortString;
+
+pub const NAME: &'static str = "aelita";
+pub const NAME_TRY: &'static str = "aelita/try";
+
+pub const ACTION_RETRY: &'static str = "retry";
+pub const ACTION_CANCEL: &'static str = "cancel";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Conclusion {
+    Success,
+    Failure,
+    Cancelled,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CheckStatus {
+    Queued,
+    InProgress,
+    Completed(Conclusion),
+}
+
+/// Everything we know about one check run, so that its summary can be
+/// rendered again whenever part of it changes.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CheckRun {
+    /// Assigned by GitHub once the check run has been created
+    pub id: Option<u64>,
+    pub pull_commit: Commit,
+    pub status: CheckStatus,
+    pub title: String,
+    pub queue_position: Option<usize>,
+    pub merge_commit: Option<Commit>,
+    pub url: Option<Url>,
+    pub cis: Vec<CiResult>,
+    pub failure: Option<FailureSummary>,
+}
+
+impl CheckRun {
+    pub fn new(pull_commit: Commit) -> CheckRun {
+        CheckRun {
+            id: None,
+            pull_commit: pull_commit,
+            status: CheckStatus::Queued,
+            title: "Approved".to_owned(),
+            queue_position: None,
+            merge_commit: None,
+            url: None,
+            cis: Vec::new(),
+            failure: None,
+        }
+    }
+    pub fn set_ci_result(&mut self, ci_result: CiResult) {
+        match self.cis.iter().position(|ci| ci.name == ci_result.name) {
+            Some(i) => self.cis[i] = ci_result,
+            None => {
+                self.cis.push(ci_result);
+                self.cis.sort_by(|a, b| a.name.cmp(&b.name));
+            }
+        }
+    }
+    pub fn status_str(&self) -> &'static str {
+        match self.status {
+            CheckStatus::Queued => "queued",
+            CheckStatus::InProgress => "in_progress",
+            CheckStatus::Completed(_) => "completed",
+        }
+    }
+    pub fn conclusion_str(&self) -> Option<&'static str> {
+        match self.status {
+            CheckStatus::Completed(Conclusion::Success) => Some("success"),
+            CheckStatus::Completed(Conclusion::Failure) => Some("failure"),
+            CheckStatus::Completed(Conclusion::Cancelled) => Some("cancelled"),
+            _ => None,
+        }
+    }
+    /// The buttons to show: a queued or running build can be canceled,
+    /// and a failed one can be retried.
+    /// Returns (label, description, identifier) triples.
+    pub fn actions(&self) -> Vec<(&'static str, &'static str, &'static str)> {
+        match self.status {
+            CheckStatus::Queued | CheckStatus::InProgress => vec![
+                ("Cancel", "Take this PR out of the queue", ACTION_CANCEL),
+            ],
+            CheckStatus::Completed(Conclusion::Failure) => vec![
+                ("Retry", "Put this PR back in the queue", ACTION_RETRY),
+            ],
+            CheckStatus::Completed(_) => vec![],
+        }
+    }
+    /// Render the markdown summary shown on the PR's checks tab.
+    pub fn summary(&self) -> String {
+        let mut result = String::new();
+        if let (CheckStatus::Queued, Some(position)) =
+                (self.status, self.queue_position) {
+            result.push_str(&format!("**Queue position:** {}\n\n", position));
+        }
+        result.push_str(&format!(
+            "**Pull commit:** {}\n\n",
+            &self.pull_commit.to_short_string()[..],
+        ));
+        if let Some(ref merge_commit) = self.merge_commit {
+            result.push_str(&format!(
+                "**Merge commit:** {}\n\n",
+                &merge_commit.to_short_string()[..],
+            ));
+        }
+        if !self.cis.is_empty() {
+            result.push_str("| Job | Status | Duration |\n");
+            result.push_str("| --- | --- | --- |\n");
+            for ci in &self.cis {
+                let state = match ci.state.state {
+                    CiState::Queued => "Queued",
+                    CiState::Running => "Running",
+                    CiState::Succeeded => "Passed",
+                    CiState::Failed => "Failed",
+                    CiState::Canceled => "Canceled",
+                };
+                let name = match ci.state.url {
+                    Some(ref url) => format!("[{}]({})", ci.name, url),
+                    None => ci.name.clone(),
+                };
+                let duration = match (ci.state.started, ci.state.finished) {
+                    (Some(started), Some(finished)) if finished >= started => {
+                        let secs = finished - started;
+                        format!("{}m {}s", secs / 60, secs % 60)
+                    }
+                    _ => String::new(),
+                };
+                result.push_str(&format!(
+                    "| {} | {} | {} |\n",
+                    name.replace('|', "\\|"),
+                    state,
+                    duration,
+                ));
+            }
+        }
+        if let Some(ref failure) = self.failure {
+            result.push_str(&render_failure_summary(failure));
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ci::FailureSummary;
+    use db::{CiState, CiStateEntry};
+    use super::{CheckRun, CheckStatus, Conclusion, ACTION_RETRY};
+    use ui::CiResult;
+    use vcs::Commit;
+
+    fn ci_result(name: &str, state: CiState) -> CiResult {
+        CiResult {
+            name: name.to_owned(),
+            pull_commit: Commit::from("a".to_owned()),
+            state: CiStateEntry {
+                state: state,
+                commit: Commit::from("b".to_owned()),
+                url: None,
+                started: Some(10),
+                finished: Some(75),
+            },
+        }
+    }
+
+    #[test]
+    fn summary_lists_jobs_in_order() {
+        let mut run = CheckRun::new(Commit::from("a".to_owned()));
+        run.status = CheckStatus::InProgress;
+        run.merge_commit = Some(Commit::from("b".to_owned()));
+        run.set_ci_result(ci_result("travis", CiState::Succeeded));
+        run.set_ci_result(ci_result("appveyor", CiState::Running));
+        run.set_ci_result(ci_result("travis", CiState::Failed));
+        assert_eq!(run.cis.len(), 2);
+        assert_eq!(run.summary(), "**Pull commit:** a\n\n\
+            **Merge commit:** b\n\n\
+            | Job | Status | Duration |\n\
+            | --- | --- | --- |\n\
+            | appveyor | Running | 1m 5s |\n\
+            | travis | Failed | 1m 5s |\n");
+    }
+
+    #[test]
+    fn failed_run_offers_retry() {
+        let mut run = CheckRun::new(Commit::from("a".to_owned()));
+        run.queue_position = Some(3);
+        assert!(run.summary().starts_with("**Queue position:** 3\n\n"));
+        run.status = CheckStatus::Completed(Conclusion::Failure);
+        run.failure = Some(FailureSummary {
+            tests: vec!["foo::bar".to_owned()],
+            log: None,
+        });
+        assert!(!run.summary().contains("Queue position"));
+        assert!(run.summary().contains("* `foo::bar`"));
+        assert_eq!(run.conclusion_str(), Some("failure"));
+        assert_eq!(run.actions()[0].2, ACTION_RETRY);
+    }
+}
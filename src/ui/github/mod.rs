@@ -1,6 +1,7 @@
 // This file is released under the same terms as Rust itself.
 
 mod cache;
+mod checks;
 
 use crossbeam;
 use db;
@@ -12,24 +13,31 @@ use hyper::net::{HttpListener, NetworkListener, NetworkStream};
 use hyper::server::{Request, Response};
 use hyper::status::StatusCode;
 use pipeline::{self, PipelineId};
-use rest::{authorization, Authorization, Client, Mime};
+use rest::Mime;
 use serde_json::{
     self,
     from_slice as json_from_slice,
 };
 use std;
-use std::borrow::Cow;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::io::BufWriter;
 use std::iter;
 use std::sync::Mutex;
 use std::sync::mpsc::{Sender, Receiver};
 use ui::{self, comments, Pr};
-use util::USER_AGENT;
+use ui::{render_comment, render_comment_line, render_failure_summary};
+use ui::{render_status, render_unmanaged_branch};
+use url::form_urlencoded::byte_serialize;
+use util;
+use util::github_auth::{self, Auth};
+use util::github_deliveries::{self, Deliveries};
 use util::github_headers;
 use vcs::Commit;
 use vcs::git::ToShortString;
 
+use self::cache::StatusComment;
+use self::checks::{CheckRun, CheckStatus, Conclusion};
+
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct TeamId(pub u32);
 
@@ -39,56 +47,133 @@ pub struct Repo {
     pub repo: String,
 }
 
+/// The pipelines for PRs into one of a repo's target branches.
 #[derive(Clone, Debug)]
 pub struct RepoPipelines {
+    /// The branch PRs have to target, or `None` to take PRs into any branch
+    pub branch: Option<String>,
     pub pipeline_id: PipelineId,
     pub try_pipeline_id: Option<PipelineId>,
+    /// Count an "Approved" review from someone with write access as `r+`
+    pub review_approval: bool,
+    pub labels: Labels,
+}
+
+impl ui::TargetPipelines for RepoPipelines {
+    fn branch(&self) -> Option<&str> {
+        self.branch.as_ref().map(|b| &b[..])
+    }
+    fn pipeline_id(&self) -> PipelineId {
+        self.pipeline_id
+    }
+    fn try_pipeline_id(&self) -> Option<PipelineId> {
+        self.try_pipeline_id
+    }
+}
+
+/// Labels to keep on a PR while it is in each state.
+/// `None` means that state has no label.
+#[derive(Clone, Debug, Default)]
+pub struct Labels {
+    /// Waiting in the queue
+    pub approved: Option<String>,
+    pub testing: Option<String>,
+    pub failed: Option<String>,
+    pub merge_conflict: Option<String>,
 }
 
-#[derive(Clone, Copy, Debug)]
-pub enum PipelineType {
-    Stage,
-    Try,
+impl Labels {
+    fn all(&self) -> Vec<&str> {
+        [&self.approved, &self.testing, &self.failed, &self.merge_conflict]
+            .iter()
+            .filter_map(|label| label.as_ref().map(|label| &label[..]))
+            .collect()
+    }
+    /// The label a PR should have, or `None` if it should have none of them.
+    /// Statuses that don't change the label return `Err`.
+    fn for_status(&self, status: &ui::Status) -> Result<Option<&str>, ()> {
+        let label = match *status {
+            ui::Status::Approved(_) => &self.approved,
+            ui::Status::StartingBuild(_, _) |
+            ui::Status::Testing(_, _, _) => &self.testing,
+            ui::Status::Failure(_, _, _, _) |
+            ui::Status::Unmoveable(_, _) => &self.failed,
+            ui::Status::Unmergeable(_) => &self.merge_conflict,
+            ui::Status::Success(_, _, _) |
+            ui::Status::Invalidated |
+            ui::Status::Draft |
+            ui::Status::Retargeted |
+            ui::Status::Completed(_, _) => return Ok(None),
+            ui::Status::NoCommit => return Err(()),
+        };
+        Ok(label.as_ref().map(|label| &label[..]))
+    }
 }
 
+pub use ui::PipelineType;
+
 pub trait ProjectsConfig: Send + Sync + 'static {
-    fn pipelines_by_repo(&self, &Repo) -> Option<RepoPipelines>;
+    /// The pipelines for each of the repo's target branches.
+    /// It is empty if the repo is not managed at all.
+    fn pipelines_by_repo(&self, &Repo) -> Vec<RepoPipelines>;
     fn repo_by_pipeline(&self, PipelineId) -> Option<(Repo, PipelineType)>;
 }
 
 pub struct Worker {
     listen: String,
     projects: Box<ProjectsConfig>,
-    client: Client<Authorization<authorization::Token>>,
+    auth: Auth,
     user_ident: String,
     secret: String,
+    /// Reject webhooks that are not signed with SHA-256
+    require_sha256: bool,
+    deliveries: Mutex<Deliveries>,
     cache: Mutex<cache::Cache>,
+    /// How long to trust cached permissions, in seconds, in case
+    /// a webhook that should have cleared them got lost
+    cache_ttl: Option<i64>,
+    /// Report through check runs instead of commit statuses
+    checks: bool,
+    check_runs: Mutex<HashMap<(PipelineId, Pr), CheckRun>>,
+    /// Keep one comment per PR up to date instead of posting new ones
+    status_comment: bool,
+    /// The branch each open PR targets, as of its last webhook
+    bases: Mutex<HashMap<(Repo, Pr), String>>,
 }
 
 impl Worker {
     pub fn new(
         listen: String,
-        host: String,
-        token: String,
+        auth: Auth,
         user: String,
         secret: String,
+        require_sha256: bool,
+        checks: bool,
+        status_comment: bool,
         projects: Box<ProjectsConfig>,
         cache_builder: db::Builder,
+        cache_ttl: Option<i64>,
     ) -> Worker {
         let user_ident = format!("@{}", user);
         Worker {
             listen: listen,
             projects: projects,
             user_ident: user_ident,
-            client: Client::new(USER_AGENT.to_owned())
-                .base(&host)
-                .authorization(Authorization(authorization::Token{
-                    token: token,
-                })),
+            auth: auth,
             secret: secret,
+            require_sha256: require_sha256,
+            deliveries: Mutex::new(
+                github_deliveries::from_builder(&cache_builder, "ui_github")
+                    .expect("to get a deliveries table")
+            ),
             cache: Mutex::new(
                 cache::from_builder(&cache_builder).expect("to get a cache")
             ),
+            cache_ttl: cache_ttl,
+            checks: checks,
+            check_runs: Mutex::new(HashMap::new()),
+            status_comment: status_comment,
+            bases: Mutex::new(HashMap::new()),
         }
     }
 }
@@ -117,6 +202,10 @@ struct PostCommentComment {
     body: String,
 }
 #[derive(Deserialize, Serialize)]
+struct CommentIdDesc {
+    id: i64,
+}
+#[derive(Deserialize, Serialize)]
 struct CommentDesc {
     issue: IssueCommentIssue,
     comment: IssueCommentComment,
@@ -146,6 +235,13 @@ struct PingDesc {
     zen: String,
 }
 #[derive(Deserialize, Serialize)]
+struct PushDesc {
+    // ref is a reserved word.
+    #[serde(rename="ref")]
+    git_ref: String,
+    repository: RepositoryDesc,
+}
+#[derive(Deserialize, Serialize)]
 struct TeamDesc {
     slug: String,
     id: u32,
@@ -165,18 +261,41 @@ struct PrBranchDesc {
     sha: String,
 }
 #[derive(Deserialize, Serialize)]
+struct PrBaseDesc {
+    // ref is a reserved word.
+    #[serde(rename="ref")]
+    git_ref: String,
+}
+#[derive(Deserialize, Serialize)]
 struct PrDesc {
     state: String,
     number: u32,
     head: PrBranchDesc,
+    base: PrBaseDesc,
     html_url: String,
     title: String,
+    draft: Option<bool>,
 }
 #[derive(Deserialize, Serialize)]
 struct PullRequestDesc {
     action: String,
     pull_request: PrDesc,
     repository: RepositoryDesc,
+    changes: Option<PrChangesDesc>,
+}
+#[derive(Deserialize, Serialize)]
+struct PrChangesDesc {
+    base: Option<PrBaseChangeDesc>,
+}
+#[derive(Deserialize, Serialize)]
+struct PrBaseChangeDesc {
+    // ref is a reserved word.
+    #[serde(rename="ref")]
+    git_ref: ChangeFromDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct ChangeFromDesc {
+    from: String,
 }
 #[derive(Deserialize, Serialize)]
 struct StatusDesc {
@@ -189,6 +308,114 @@ struct StatusDesc {
 struct TeamAddDesc {
     repository: RepositoryDesc,
 }
+#[derive(Deserialize, Serialize)]
+struct OrganizationDesc {
+    login: String,
+}
+/// The parts of `membership`, `team`, `organization` and `member` events
+/// needed to know which permissions might have changed.
+#[derive(Deserialize, Serialize)]
+struct PermissionChangeDesc {
+    action: String,
+    repository: Option<RepositoryDesc>,
+    organization: Option<OrganizationDesc>,
+}
+#[derive(Deserialize, Serialize)]
+struct ReviewPrDesc {
+    number: u32,
+    title: String,
+    body: Option<String>,
+    state: String,
+    html_url: String,
+    user: UserDesc,
+    base: PrBaseDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct PrTargetDesc {
+    base: PrBaseDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct ReviewDesc {
+    user: UserDesc,
+    body: Option<String>,
+    state: String,
+    commit_id: String,
+}
+#[derive(Deserialize, Serialize)]
+struct PrReviewDesc {
+    action: String,
+    review: ReviewDesc,
+    pull_request: ReviewPrDesc,
+    repository: RepositoryDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct PrReviewCommentDesc {
+    action: String,
+    comment: IssueCommentComment,
+    pull_request: ReviewPrDesc,
+    repository: RepositoryDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct LabelDesc {
+    name: String,
+}
+#[derive(Deserialize, Serialize)]
+struct PostLabelsDesc {
+    labels: Vec<String>,
+}
+#[derive(Deserialize, Serialize)]
+struct CheckRunDesc {
+    name: String,
+    head_sha: String,
+    external_id: Option<String>,
+}
+#[derive(Deserialize, Serialize)]
+struct RequestedActionDesc {
+    identifier: String,
+}
+#[derive(Deserialize, Serialize)]
+struct CheckRunEventDesc {
+    action: String,
+    check_run: CheckRunDesc,
+    requested_action: Option<RequestedActionDesc>,
+    repository: RepositoryDesc,
+    sender: UserDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct CheckRunOutputDesc {
+    title: String,
+    summary: String,
+}
+#[derive(Deserialize, Serialize)]
+struct CheckRunActionDesc {
+    label: String,
+    description: String,
+    identifier: String,
+}
+#[derive(Deserialize, Serialize)]
+struct PostCheckRunDesc {
+    name: String,
+    head_sha: String,
+    external_id: String,
+    status: String,
+    conclusion: Option<String>,
+    details_url: Option<String>,
+    output: CheckRunOutputDesc,
+    actions: Vec<CheckRunActionDesc>,
+}
+#[derive(Deserialize, Serialize)]
+struct CheckRunIdDesc {
+    id: u64,
+}
+#[derive(Deserialize, Serialize)]
+struct ExistingCheckRunDesc {
+    id: u64,
+    external_id: Option<String>,
+}
+#[derive(Deserialize, Serialize)]
+struct CheckRunListDesc {
+    check_runs: Vec<ExistingCheckRunDesc>,
+}
 
 impl pipeline::Worker<ui::Event, ui::Message> for Worker {
     fn run(
@@ -246,11 +473,42 @@ impl Worker {
         mut res: Response,
         send_event: &Sender<ui::Event>
     ) {
-        let head = github_headers::parse(&mut req, self.secret.as_bytes());
-        let (x_github_event, body) = match head {
+        let head = github_headers::parse(
+            &mut req,
+            self.secret.as_bytes(),
+            self.require_sha256,
+        );
+        let head = match head {
             Some(head) => head,
             None => return,
         };
+        if let Some(ref delivery) = head.delivery {
+            if self.deliveries.lock().unwrap().seen(delivery) {
+                info!("Got duplicate delivery {}; do nothing", delivery);
+                *res.status_mut() = StatusCode::NoContent;
+                if let Err(e) = res.send(&[]) {
+                    warn!("Failed to send response to duplicate: {:?}", e);
+                }
+                return;
+            }
+        }
+        let handled = self.handle_event(head.event, head.body, res, send_event);
+        // Only remember it once it has been handled, so that a delivery
+        // that failed partway is handled again when GitHub redelivers it.
+        if let (true, Some(delivery)) = (handled, head.delivery) {
+            self.deliveries.lock().unwrap().record(&delivery);
+        }
+    }
+
+    /// Returns false if the event could not be handled,
+    /// and is worth handling again if it's redelivered.
+    fn handle_event(
+        &self,
+        x_github_event: Vec<u8>,
+        body: Vec<u8>,
+        mut res: Response,
+        send_event: &Sender<ui::Event>
+    ) -> bool {
         match &x_github_event[..] {
             b"issue_comment" => {
                 if let Ok(desc) = json_from_slice::<CommentDesc>(&body) {
@@ -298,15 +556,45 @@ impl Worker {
                         repo: desc.repository.name,
                     };
                     let pr = Pr::from(desc.pull_request.number.to_string());
+                    let all_pipelines = self.projects.pipelines_by_repo(&repo);
+                    if all_pipelines.is_empty() {
+                        warn!(
+                            "Got bad repo {:?}",
+                            repo
+                        );
+                        return true;
+                    }
+                    let base = desc.pull_request.base.git_ref.clone();
+                    {
+                        let mut bases = self.bases.lock().unwrap();
+                        let key = (repo.clone(), pr.clone());
+                        if desc.action == "closed" {
+                            bases.remove(&key);
+                        } else {
+                            bases.insert(key, base.clone());
+                        }
+                    }
+                    let old_base = desc.changes.as_ref()
+                        .and_then(|changes| changes.base.as_ref())
+                        .map(|base| &base.git_ref.from[..]);
+                    if let Some(old_base) = old_base {
+                        ui::handle_pr_retarget(
+                            send_event,
+                            &all_pipelines,
+                            &pr,
+                            old_base,
+                            &base,
+                        );
+                    }
                     let repo_pipelines =
-                        match self.projects.pipelines_by_repo(&repo) {
+                        match pipelines_for_base(all_pipelines, Some(&base)) {
                             Some(repo_pipelines) => repo_pipelines,
                             None => {
-                                warn!(
-                                    "Got bad repo {:?}",
-                                    repo
+                                info!(
+                                    "PR targets unmanaged branch {}; do nothing",
+                                    base
                                 );
-                                return;
+                                return true;
                             }
                         };
                     let commit = Commit::from(
@@ -321,6 +609,8 @@ impl Worker {
                             pr.clone(),
                             desc.pull_request.title.clone(),
                             desc.pull_request.html_url.clone(),
+                            // Drafts can still be tried.
+                            false,
                         );
                     }
                     self.handle_pr_update(
@@ -331,6 +621,7 @@ impl Worker {
                         pr,
                         desc.pull_request.title,
                         desc.pull_request.html_url,
+                        desc.pull_request.draft.unwrap_or(false),
                     );
                 } else {
                     warn!("Got invalid PR message");
@@ -340,6 +631,77 @@ impl Worker {
                     }
                 }
             }
+            b"pull_request_review" => {
+                if let Ok(desc) = json_from_slice::<PrReviewDesc>(&body) {
+                    *res.status_mut() = StatusCode::NoContent;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to Github review: {:?}",
+                            e,
+                        );
+                    }
+                    if desc.action != "submitted" {
+                        info!("Review was not just submitted; do nothing");
+                    } else if desc.pull_request.state == "closed" {
+                        info!("Review is for closed PR; do nothing");
+                    } else {
+                        info!("Got pull request review");
+                        self.handle_pr_review(send_event, desc);
+                    }
+                } else {
+                    warn!("Got invalid review");
+                    *res.status_mut() = StatusCode::BadRequest;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to bad review: {:?}",
+                            e,
+                        );
+                    }
+                }
+            }
+            b"pull_request_review_comment" => {
+                if let Ok(desc) = json_from_slice::<PrReviewCommentDesc>(&body) {
+                    *res.status_mut() = StatusCode::NoContent;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to Github comment: {:?}",
+                            e,
+                        );
+                    }
+                    if desc.action != "created" {
+                        info!("Review comment was not just made; do nothing");
+                    } else if !desc.comment.body.contains(&self.user_ident) {
+                        info!("Comment does not mention me; do nothing");
+                    } else if desc.pull_request.state == "closed" {
+                        info!("Comment is for closed PR; do nothing");
+                    } else {
+                        info!("Got pull request review comment");
+                        let repo = Repo{
+                            owner: desc.repository.owner.login,
+                            repo: desc.repository.name,
+                        };
+                        let base = desc.pull_request.base.git_ref.clone();
+                        let issue = review_pr_to_issue(desc.pull_request);
+                        self.handle_pr_text(
+                            send_event,
+                            repo,
+                            &issue,
+                            Some(base),
+                            &desc.comment.user.login,
+                            &desc.comment.body,
+                        );
+                    }
+                } else {
+                    warn!("Got invalid review comment");
+                    *res.status_mut() = StatusCode::BadRequest;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to bad comment: {:?}",
+                            e,
+                        );
+                    }
+                }
+            }
             b"ping" => {
                 if let Ok(desc) = json_from_slice::<PingDesc>(&body) {
                     info!("Got Ping: {}", desc.zen);
@@ -352,6 +714,26 @@ impl Worker {
                     warn!("Failed to send response to Github ping: {:?}", e);
                 }
             }
+            b"push" => {
+                if let Ok(desc) = json_from_slice::<PushDesc>(&body) {
+                    *res.status_mut() = StatusCode::NoContent;
+                    if let Err(e) = res.send(&[]) {
+                        warn!("Failed to send response to Github push: {:?}", e);
+                    }
+                    let repo = Repo{
+                        owner: desc.repository.owner.login,
+                        repo: desc.repository.name,
+                    };
+                    let all_pipelines = self.projects.pipelines_by_repo(&repo);
+                    ui::handle_push(send_event, all_pipelines, &desc.git_ref);
+                } else {
+                    warn!("Got invalid push");
+                    *res.status_mut() = StatusCode::BadRequest;
+                    if let Err(e) = res.send(&[]) {
+                        warn!("Failed to send response to bad push: {:?}", e);
+                    }
+                }
+            }
             b"team_add" => {
                 if let Ok(desc) = json_from_slice::<TeamAddDesc>(&body) {
                     info!("Got team add event");
@@ -366,26 +748,25 @@ impl Worker {
                         owner: desc.repository.owner.login,
                         repo: desc.repository.name,
                     };
-                    let repo_pipelines =
-                        match self.projects.pipelines_by_repo(&repo) {
-                            Some(repo_pipelines) => repo_pipelines,
-                            None => {
-                                warn!("team add event for nonexistant repo");
-                                return;
-                            }
-                        };
+                    let all_pipelines = self.projects.pipelines_by_repo(&repo);
+                    if all_pipelines.is_empty() {
+                        warn!("team add event for nonexistant repo");
+                        return true;
+                    }
                     let mut cache = self.cache.lock().unwrap();
                     let teams = match self.get_all_teams_with_write(&repo) {
                         Ok(t) => t,
                         Err(e) => {
                             warn!("Failed to refresh teams: {:?}", e);
-                            return;
+                            return false;
                         }
                     };
-                    cache.set_teams_with_write(
-                        repo_pipelines.pipeline_id,
-                        teams.iter().cloned(),
-                    );
+                    for repo_pipelines in &all_pipelines {
+                        cache.set_teams_with_write(
+                            repo_pipelines.pipeline_id,
+                            teams.iter().cloned(),
+                        );
+                    }
                 } else {
                     warn!("Got invalid team add event");
                     *res.status_mut() = StatusCode::BadRequest;
@@ -397,6 +778,55 @@ impl Worker {
                     }
                 }
             }
+            b"membership" | b"team" | b"organization" | b"member" => {
+                if let Ok(desc) =
+                        json_from_slice::<PermissionChangeDesc>(&body) {
+                    info!(
+                        "Got {} event: {}",
+                        String::from_utf8_lossy(&x_github_event),
+                        desc.action,
+                    );
+                    *res.status_mut() = StatusCode::NoContent;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to Github event: {:?}",
+                            e,
+                        );
+                    }
+                    self.handle_permission_change(desc);
+                } else {
+                    warn!("Got invalid permission change event");
+                    *res.status_mut() = StatusCode::BadRequest;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to bad event: {:?}",
+                            e,
+                        );
+                    }
+                }
+            }
+            b"check_run" => {
+                if let Ok(desc) = json_from_slice::<CheckRunEventDesc>(&body) {
+                    info!("Got check run event: {}", desc.action);
+                    *res.status_mut() = StatusCode::NoContent;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to Github check run: {:?}",
+                            e,
+                        );
+                    }
+                    self.handle_check_run_event(send_event, desc);
+                } else {
+                    warn!("Got invalid check run event");
+                    *res.status_mut() = StatusCode::BadRequest;
+                    if let Err(e) = res.send(&[]) {
+                        warn!(
+                            "Failed to send response to bad check run: {:?}",
+                            e,
+                        );
+                    }
+                }
+            }
             e => {
                 *res.status_mut() = StatusCode::BadRequest;
                 if let Err(e) = res.send(&[]) {
@@ -411,6 +841,39 @@ impl Worker {
                 );
             }
         }
+        true
+    }
+
+    /// Forget cached permissions that an event might have made stale.
+    /// Events about a repo clear that repo; events about an organization
+    /// clear every repo it owns.
+    fn handle_permission_change(&self, desc: PermissionChangeDesc) {
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(repository) = desc.repository {
+            let repo = Repo{
+                owner: repository.owner.login,
+                repo: repository.name,
+            };
+            let all_pipelines = self.projects.pipelines_by_repo(&repo);
+            if !all_pipelines.is_empty() {
+                info!("Invalidating permissions for {:?}", repo);
+            }
+            for repo_pipelines in all_pipelines {
+                cache.invalidate(repo_pipelines.pipeline_id);
+                if let Some(try_pipeline_id) = repo_pipelines.try_pipeline_id {
+                    cache.invalidate(try_pipeline_id);
+                }
+            }
+        } else if let Some(organization) = desc.organization {
+            for pipeline_id in cache.cached_pipelines() {
+                let owner = self.projects.repo_by_pipeline(pipeline_id)
+                    .map(|(repo, _)| repo.owner);
+                if owner.as_ref() == Some(&organization.login) {
+                    info!("Invalidating permissions for {:?}", pipeline_id);
+                    cache.invalidate(pipeline_id);
+                }
+            }
+        }
     }
 
     fn handle_pr_update(
@@ -422,7 +885,16 @@ impl Worker {
         pr: Pr,
         title: String,
         html_url: String,
+        draft: bool,
     ) {
+        if action == "closed" {
+            self.check_runs.lock().unwrap().remove(&(pipeline_id, pr.clone()));
+            // A PR that is closed without merging keeps whatever state label
+            // it had, unless it's taken off here.
+            if let Err(e) = self.sync_labels(pipeline_id, &pr, None) {
+                warn!("Failed to clear labels for pr {}: {:?}", pr, e);
+            }
+        }
         let event = match action {
             "closed" => Some(ui::Event::Closed(
                 pipeline_id,
@@ -434,14 +906,18 @@ impl Worker {
                 commit,
                 title,
                 Url::parse(&html_url).unwrap(),
+                draft,
             )),
-            "synchronize" | "edited" => Some(ui::Event::Changed(
-                pipeline_id,
-                pr,
-                commit,
-                title,
-                Url::parse(&html_url).unwrap(),
-            )),
+            "synchronize" | "edited" |
+            "ready_for_review" | "converted_to_draft" =>
+                Some(ui::Event::Changed(
+                    pipeline_id,
+                    pr,
+                    commit,
+                    title,
+                    Url::parse(&html_url).unwrap(),
+                    draft,
+                )),
             _ => None,
         };
         if let Some(event) = event {
@@ -458,20 +934,45 @@ impl Worker {
             owner: desc.repository.owner.login,
             repo: desc.repository.name,
         };
-        let pr = Pr::from(desc.issue.number.to_string());
-        let repo_pipelines = match self.projects.pipelines_by_repo(&repo) {
-            Some(repo_pipelines) => repo_pipelines,
-            None => {
-                warn!(
-                    "Got bad repo {:?}",
-                    repo
-                );
+        self.handle_pr_text(
+            send_event,
+            repo,
+            &desc.issue,
+            None,
+            &desc.comment.user.login,
+            &desc.comment.body,
+        );
+    }
+
+    /// Run the command in a comment or review that mentions us.
+    /// The branch the PR targets is looked up if it isn't given.
+    fn handle_pr_text(
+        &self,
+        send_event: &Sender<ui::Event>,
+        repo: Repo,
+        issue: &IssueCommentIssue,
+        base: Option<String>,
+        user: &str,
+        body: &str,
+    ) {
+        let pr = Pr::from(issue.number.to_string());
+        let all_pipelines = self.projects.pipelines_by_repo(&repo);
+        if all_pipelines.is_empty() {
+            warn!(
+                "Got bad repo {:?}",
+                repo
+            );
+            return;
+        }
+        let base = match self.pr_base(&all_pipelines, &repo, &pr, base) {
+            Ok(base) => base,
+            Err(e) => {
+                warn!("Failed to get the base branch of {}: {:?}", pr, e);
                 return;
             }
         };
-        let user = &desc.comment.user.login;
-        let body = &desc.comment.body;
-        let pipeline_id = repo_pipelines.pipeline_id;
+        // Permissions are the same for every branch.
+        let pipeline_id = all_pipelines[0].pipeline_id;
         let allowed = self.user_has_write(user, &repo, pipeline_id)
             .unwrap_or_else(|e| {
                 warn!("Failed to check if {} has permission: {:?}", user, e);
@@ -479,31 +980,213 @@ impl Worker {
             });
         if !allowed {
             info!("Got mentioned by not-permitted user");
-        } else if let Some(command) = comments::parse(&body, user) {
-            self.handle_comment_command(
-                send_event,
-                command,
-                &desc.issue,
-                &repo_pipelines,
-                pr,
+        } else if let Some(command) = comments::parse(body, user) {
+            let target = base.as_ref().map(|base| &base[..]);
+            let unmanaged_comment = render_unmanaged_branch(
+                &all_pipelines,
+                "PR",
+                target.unwrap_or(""),
             );
+            match pipelines_for_base(all_pipelines, target) {
+                Some(repo_pipelines) => self.handle_comment_command(
+                    send_event,
+                    command,
+                    issue,
+                    &repo_pipelines,
+                    pr,
+                ),
+                None => {
+                    info!("Got command for PR into unmanaged branch");
+                    let result =
+                        self.post_comment(&repo, &pr, unmanaged_comment);
+                    if let Err(e) = result {
+                        warn!("Failed to comment on {}: {:?}", pr, e);
+                    }
+                }
+            }
         } else {
             info!("Pull request comment is not a command");
         }
     }
 
-    fn handle_comment_command(
+    fn handle_pr_review(
         &self,
         send_event: &Sender<ui::Event>,
-        command: comments::Command,
-        issue: &IssueCommentIssue,
-        repo_pipelines: &RepoPipelines,
-        pr: Pr,
+        desc: PrReviewDesc,
     ) {
-        match command {
-            comments::Command::Approved(user, commit) => {
-                self.handle_approved_pr(
-                    repo_pipelines.pipeline_id,
+        let repo = Repo{
+            owner: desc.repository.owner.login,
+            repo: desc.repository.name,
+        };
+        let base = desc.pull_request.base.git_ref.clone();
+        let issue = review_pr_to_issue(desc.pull_request);
+        let user = &desc.review.user.login;
+        if let Some(ref body) = desc.review.body {
+            if body.contains(&self.user_ident) {
+                // An explicit command wins over the review's verdict.
+                self.handle_pr_text(
+                    send_event,
+                    repo,
+                    &issue,
+                    Some(base),
+                    user,
+                    body,
+                );
+                return;
+            }
+        }
+        if desc.review.state.to_lowercase() != "approved" {
+            info!("Review is not an approval; do nothing");
+            return;
+        }
+        let all_pipelines = self.projects.pipelines_by_repo(&repo);
+        if all_pipelines.is_empty() {
+            warn!(
+                "Got bad repo {:?}",
+                repo
+            );
+            return;
+        }
+        let repo_pipelines = match pipelines_for_base(all_pipelines, Some(&base)) {
+            Some(repo_pipelines) => repo_pipelines,
+            None => {
+                info!("Review is for PR into unmanaged branch; do nothing");
+                return;
+            }
+        };
+        if !repo_pipelines.review_approval {
+            info!("Reviews do not count as approval here; do nothing");
+            return;
+        }
+        let pipeline_id = repo_pipelines.pipeline_id;
+        let allowed = self.user_has_write(user, &repo, pipeline_id)
+            .unwrap_or_else(|e| {
+                warn!("Failed to check if {} has permission: {:?}", user, e);
+                false
+            });
+        if !allowed {
+            info!("Got approved by not-permitted user");
+            return;
+        }
+        let pr = Pr::from(issue.number.to_string());
+        self.handle_approved_pr(
+            pipeline_id,
+            send_event,
+            &issue,
+            pr,
+            user,
+            Some(Commit::from(desc.review.commit_id)),
+        );
+    }
+
+    fn handle_check_run_event(
+        &self,
+        send_event: &Sender<ui::Event>,
+        desc: CheckRunEventDesc,
+    ) {
+        let action = match (&desc.action[..], desc.requested_action) {
+            ("rerequested", _) => checks::ACTION_RETRY.to_owned(),
+            ("requested_action", Some(requested)) => requested.identifier,
+            _ => {
+                info!("Check run event is not a request; do nothing");
+                return;
+            }
+        };
+        let repo = Repo{
+            owner: desc.repository.owner.login,
+            repo: desc.repository.name,
+        };
+        let all_pipelines = self.projects.pipelines_by_repo(&repo);
+        if all_pipelines.is_empty() {
+            warn!(
+                "Got bad repo {:?}",
+                repo
+            );
+            return;
+        }
+        let pr = match desc.check_run.external_id {
+            Some(ref pr) if !pr.is_empty() => Pr::from(pr.clone()),
+            _ => {
+                warn!("Check run has no PR number");
+                return;
+            }
+        };
+        let base = match self.pr_base(&all_pipelines, &repo, &pr, None) {
+            Ok(base) => base,
+            Err(e) => {
+                warn!("Failed to get the base branch of {}: {:?}", pr, e);
+                return;
+            }
+        };
+        let target = base.as_ref().map(|base| &base[..]);
+        let repo_pipelines = match pipelines_for_base(all_pipelines, target) {
+            Some(repo_pipelines) => repo_pipelines,
+            None => {
+                info!("Check run is for PR into unmanaged branch; do nothing");
+                return;
+            }
+        };
+        let pipeline_id = if desc.check_run.name == checks::NAME {
+            Some(repo_pipelines.pipeline_id)
+        } else if desc.check_run.name == checks::NAME_TRY {
+            repo_pipelines.try_pipeline_id
+        } else {
+            None
+        };
+        let pipeline_id = match pipeline_id {
+            Some(pipeline_id) => pipeline_id,
+            None => {
+                info!("Check run {} is not mine", desc.check_run.name);
+                return;
+            }
+        };
+        let user = &desc.sender.login;
+        let allowed = self.user_has_write(user, &repo, pipeline_id)
+            .unwrap_or_else(|e| {
+                warn!("Failed to check if {} has permission: {:?}", user, e);
+                false
+            });
+        if !allowed {
+            info!("Got check run request by not-permitted user");
+        } else if action == checks::ACTION_RETRY {
+            let issue = match self.get_issue(&repo, &pr) {
+                Ok(issue) => issue,
+                Err(e) => {
+                    warn!("Failed to get PR {} to retry: {:?}", pr, e);
+                    return;
+                }
+            };
+            self.handle_approved_pr(
+                pipeline_id,
+                send_event,
+                &issue,
+                pr,
+                user,
+                Some(Commit::from(desc.check_run.head_sha)),
+            );
+        } else if action == checks::ACTION_CANCEL {
+            self.handle_canceled_pr(
+                pipeline_id,
+                send_event,
+                pr,
+            );
+        } else {
+            warn!("Got unknown check run action {}", action);
+        }
+    }
+
+    fn handle_comment_command(
+        &self,
+        send_event: &Sender<ui::Event>,
+        command: comments::Command,
+        issue: &IssueCommentIssue,
+        repo_pipelines: &RepoPipelines,
+        pr: Pr,
+    ) {
+        match command {
+            comments::Command::Approved(user, commit) => {
+                self.handle_approved_pr(
+                    repo_pipelines.pipeline_id,
                     send_event,
                     issue,
                     pr,
@@ -560,6 +1243,22 @@ impl Worker {
             iter::repeat('_').take(72).collect::<String>(),
             issue.body.as_ref().map(|x| &x[..]).unwrap_or(""),
         );
+        if self.status_comment {
+            // Kept with the status comment, which try builds share,
+            // until the pipeline reports the approval.
+            let stage_pipeline_id = self.projects.repo_by_pipeline(pipeline_id)
+                .and_then(|(repo, _)| {
+                    self.pipelines_by_pipeline(&repo, pipeline_id)
+                })
+                .map(|repo_pipelines| repo_pipelines.pipeline_id);
+            if let Some(stage_pipeline_id) = stage_pipeline_id {
+                let mut cache = self.cache.lock().unwrap();
+                let mut comment = cache.status_comment(stage_pipeline_id, &pr)
+                    .unwrap_or_else(StatusComment::default);
+                comment.approver = Some(user.to_owned());
+                cache.set_status_comment(stage_pipeline_id, &pr, &comment);
+            }
+        }
         send_event.send(ui::Event::Approved(
             pipeline_id,
             pr,
@@ -574,6 +1273,23 @@ impl Worker {
         send_event: &Sender<ui::Event>,
         pr: Pr,
     ) {
+        if let Err(e) = self.sync_labels(pipeline_id, &pr, None) {
+            warn!("Failed to clear labels for pr {}: {:?}", pr, e);
+        }
+        if self.checks {
+            // The pipeline does not report cancellation, so do it here.
+            let result = self.update_check_run(pipeline_id, &pr, None, |run| {
+                if let CheckStatus::Completed(_) = run.status {
+                    return false;
+                }
+                run.status = CheckStatus::Completed(Conclusion::Cancelled);
+                run.title = "Canceled".to_owned();
+                true
+            });
+            if let Err(e) = result {
+                warn!("Failed to cancel check run for pr {}: {:?}", pr, e);
+            }
+        }
         send_event.send(ui::Event::Canceled(
             pipeline_id,
             pr,
@@ -592,6 +1308,35 @@ impl Worker {
                     warn!("Failed to send {:?} to pr {}: {:?}", status, pr, e)
                 }
             }
+            ui::Message::SendCiResult(pipeline_id, pr, ci_result) => {
+                let result =
+                    self.send_ci_result_to_pr(pipeline_id, &pr, &ci_result);
+                if let Err(e) = result {
+                    warn!(
+                        "Failed to send {:?} to pr {}: {:?}",
+                        ci_result,
+                        pr,
+                        e,
+                    )
+                }
+            }
+            ui::Message::SendQueuePosition(pipeline_id, pr, position) => {
+                if !self.checks {
+                    return;
+                }
+                let result = self.update_check_run(pipeline_id, &pr, None, |run| {
+                    let changed = run.queue_position != Some(position);
+                    run.queue_position = Some(position);
+                    changed && run.status == CheckStatus::Queued
+                });
+                if let Err(e) = result {
+                    warn!(
+                        "Failed to send queue position to pr {}: {:?}",
+                        pr,
+                        e,
+                    )
+                }
+            }
         }
     }
 
@@ -601,14 +1346,18 @@ impl Worker {
         repo: &Repo,
         pipeline_id: PipelineId,
     ) -> Result<bool, GithubRequestError> {
+        let fresh_since = match self.cache_ttl {
+            Some(ttl) => util::now_secs() - ttl,
+            None => 0,
+        };
         let mut cache = self.cache.lock().unwrap();
-        match cache.is_org(pipeline_id) {
+        match cache.is_org(pipeline_id, fresh_since) {
             Some(true) => {
                 info!("Using teams permission check");
                 let teams = cache.teams_with_write(pipeline_id);
                 let mut allowed = false;
                 for team in teams {
-                    if try!(self.user_is_member_of(user, team)) {
+                    if try!(self.user_is_member_of(user, repo, team)) {
                         allowed = true;
                         break;
                     }
@@ -650,203 +1399,669 @@ impl Worker {
                     return Err(GithubRequestError::Pipeline(pipeline_id));
                 }
             };
-        let comment_body = match *status {
-            ui::Status::Approved(_) => None,
-            ui::Status::StartingBuild(_, _) => None,
-            ui::Status::Testing(_, _, _) => None,
-            ui::Status::Success(_, _, ref url) => Some({
-                if let Some(ref url) = *url {
-                    Cow::Owned(format!(":+1: [Build succeeded]({})", url))
-                } else {
-                    Cow::Borrowed(":+1: Build succeeded")
-                }
-            }),
-            ui::Status::Failure(_, _, ref url) => Some({
-                if let Some(ref url) = *url {
-                    Cow::Owned(format!(":-1: [Build failed]({})", url))
-                } else {
-                    Cow::Borrowed(":-1: Build failed")
-                }
-            }),
-            ui::Status::Unmergeable(_) => Some(Cow::Borrowed(
-                ":x: Merge conflict!"
-            )),
-            ui::Status::Unmoveable(_, _) => Some(Cow::Borrowed(
-                ":scream: Internal error while fast-forward master"
-            )),
-            ui::Status::Invalidated => Some(Cow::Borrowed(
-                ":no_good: New commits added"
-            )),
-            ui::Status::NoCommit => Some(Cow::Borrowed(
-                ":scream: Internal error: no commit found for PR"
-            )),
-            ui::Status::Completed(_, _) => None,
-        };
+        let comment_body = render_comment(status, "PR", "Base");
+        if self.checks {
+            try!(self.send_status_to_check_run(pipeline_id, pr, status));
+        }
+        if self.status_comment {
+            try!(self.update_status_comment(
+                &repo,
+                pipeline_id,
+                pipeline_type,
+                pr,
+                status,
+            ));
+        }
+        if let Err(e) = self.sync_labels(pipeline_id, pr, Some(status)) {
+            warn!("Failed to update labels for pr {}: {:?}", pr, e);
+        }
         let context = match pipeline_type {
             PipelineType::Stage => "continuous-integration/aelita",
             PipelineType::Try => "continuous-integration/aelita/try",
-        }.to_owned();
-        let status = match *status {
-            ui::Status::Approved(ref pull_commit) => Some((
-                pull_commit,
-                None,
-                StatusDesc {
-                    state: "pending".to_owned(),
-                    target_url: None,
-                    description: format!(
-                        "Approved {}",
-                        pull_commit,
-                    ),
-                    context: context,
-                }
-            )),
-            ui::Status::StartingBuild(
-                ref pull_commit,
-                ref merge_commit,
-            ) => Some((
-                pull_commit,
-                Some(merge_commit),
-                StatusDesc {
-                    state: "pending".to_owned(),
-                    target_url: None,
-                    description: format!(
-                        "Testing {} with merge commit {}",
-                        &pull_commit.to_short_string()[..],
-                        &merge_commit.to_short_string()[..],
-                    ),
-                    context: context,
-                }
-            )),
-            ui::Status::Testing(
-                ref pull_commit,
-                ref merge_commit,
-                ref url,
-            ) => Some((
-                pull_commit,
-                Some(merge_commit),
-                StatusDesc {
-                    state: "pending".to_owned(),
-                    target_url: url.as_ref().map(ToString::to_string),
-                    description: format!(
-                        "Testing {} with merge commit {}",
-                        &pull_commit.to_short_string()[..],
-                        &merge_commit.to_short_string()[..],
-                    ),
-                    context: context,
-                }
-            )),
-            ui::Status::Success(
-                ref pull_commit,
-                ref merge_commit,
-                ref url,
-            ) => Some((
-                pull_commit,
-                Some(merge_commit),
-                StatusDesc {
-                    state: "success".to_owned(),
-                    target_url: url.as_ref().map(ToString::to_string),
-                    description: "Tests passed".to_owned(),
-                    context: context,
-                }
-            )),
-            ui::Status::Failure(
-                ref pull_commit,
-                ref merge_commit, 
-                ref url,
-            ) => Some((
-                pull_commit,
-                Some(merge_commit),
-                StatusDesc {
-                    state: "failure".to_owned(),
-                    target_url: url.as_ref().map(ToString::to_string),
-                    description: "Tests failed".to_owned(),
-                    context: context,
-                }
-            )),
-            ui::Status::Unmergeable(
-                ref pull_commit,
-            ) => Some((
-                pull_commit,
-                None,
-                StatusDesc {
-                    state: "failure".to_owned(),
-                    target_url: None,
-                    description: "Merge failed".to_owned(),
-                    context: context,
-                }
-            )),
-            ui::Status::Unmoveable(
-                ref pull_commit,
-                ref merge_commit,
-            ) => Some((
-                pull_commit,
-                Some(merge_commit),
-                StatusDesc {
-                    state: "error".to_owned(),
-                    target_url: None,
-                    description: "Merge failed".to_owned(),
-                    context: context,
-                }
-            )),
-            ui::Status::Invalidated | ui::Status::NoCommit => None,
-            ui::Status::Completed(_, _) => None,
         };
-        if let Some(comment_body) = comment_body {
+        if let (false, Some(comment_body)) = (self.status_comment, comment_body) {
+            try!(self.post_comment(&repo, pr, comment_body));
+        }
+        if let (false, Some(commit_status)) = (self.checks, render_status(status)) {
+            let status_body = StatusDesc {
+                state: commit_status.state.github_name().to_owned(),
+                target_url: commit_status.url.map(ToString::to_string),
+                description: commit_status.description,
+                context: context.to_owned(),
+            };
+            let pull_commit = commit_status.pull_commit;
+            try!(self.post_status(&repo, pull_commit, &status_body));
+            if let Some(merge_commit) = commit_status.merge_commit {
+                try!(self.post_status(&repo, merge_commit, &status_body));
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the status to the history in the PR's status comment,
+    /// and edit the comment, or post it if there isn't one yet.
+    fn update_status_comment(
+        &self,
+        repo: &Repo,
+        pipeline_id: PipelineId,
+        pipeline_type: PipelineType,
+        pr: &Pr,
+        status: &ui::Status,
+    ) -> Result<(), GithubRequestError> {
+        // Try builds share the comment with regular ones.
+        let stage_pipeline_id =
+            match self.pipelines_by_pipeline(repo, pipeline_id) {
+                Some(repo_pipelines) => repo_pipelines.pipeline_id,
+                None => return Err(GithubRequestError::Pipeline(pipeline_id)),
+            };
+        // Copy the comment out, so the lock isn't held while talking to
+        // GitHub.
+        let mut comment = self.cache.lock().unwrap()
+            .status_comment(stage_pipeline_id, pr)
+            .unwrap_or_else(StatusComment::default);
+        let approver = match *status {
+            ui::Status::Approved(_) => comment.approver.take(),
+            _ => None,
+        };
+        if let ui::Status::StartingBuild(_, _) = *status {
+            comment.attempts += 1;
+        }
+        let line = match render_status_line(
+            status,
+            approver.as_ref().map(|approver| &approver[..]),
+            comment.attempts,
+        ) {
+            Some(line) => line,
+            None => return Ok(()),
+        };
+        comment.history.push(match pipeline_type {
+            PipelineType::Stage => line,
+            PipelineType::Try => format!("Try: {}", line),
+        });
+        let len = comment.history.len();
+        if len > MAX_STATUS_HISTORY {
+            comment.history.drain(..len - MAX_STATUS_HISTORY);
+        }
+        let body = render_status_comment(&comment, status);
+        let client = try!(self.auth.client(&repo.owner, &repo.repo));
+        let body = PostCommentComment{
+            body: body,
+        };
+        if comment.id != 0 {
+            let url = format!(
+                "/repos/{}/{}/issues/comments/{}",
+                repo.owner,
+                repo.repo,
+                comment.id
+            );
+            let resp = try!(self.auth.rate_limit().send(true, || {
+                let req = try!(client.patch(&url).expect("url").json(&body));
+                let req = req.header(Self::accept(AcceptType::Regular));
+                Ok::<_, GithubRequestError>(try!(req.send()))
+            }, |resp| &resp.http));
+            if resp.http.status == StatusCode::NotFound {
+                // Someone deleted it; start a new one.
+                comment.id = 0;
+            } else if !resp.is_success() {
+                return Err(GithubRequestError::HttpStatus(resp.http.status))
+            }
+        }
+        if comment.id == 0 {
             let url = format!(
                 "/repos/{}/{}/issues/{}/comments",
                 repo.owner,
                 repo.repo,
                 pr
             );
-            let comment = PostCommentComment{
-                body: comment_body.into_owned(),
+            let resp = try!(self.auth.rate_limit().send(false, || {
+                let req = try!(client.post(&url).expect("url").json(&body));
+                let req = req.header(Self::accept(AcceptType::Regular));
+                Ok::<_, GithubRequestError>(try!(req.send()))
+            }, |resp| &resp.http));
+            if !resp.is_success() {
+                return Err(GithubRequestError::HttpStatus(resp.http.status))
+            }
+            let desc: CommentIdDesc = try!(resp.json());
+            comment.id = desc.id;
+        }
+        self.cache.lock().unwrap()
+            .set_status_comment(stage_pipeline_id, pr, &comment);
+        Ok(())
+    }
+
+    /// Swap the PR's state label for the one that goes with the status,
+    /// or take it off if there is no status.
+    fn sync_labels(
+        &self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        status: Option<&ui::Status>,
+    ) -> Result<(), GithubRequestError> {
+        let (repo, pipeline_type) =
+            match self.projects.repo_by_pipeline(pipeline_id) {
+                Some(result) => result,
+                None => {
+                    return Err(GithubRequestError::Pipeline(pipeline_id));
+                }
             };
-            let resp = try!(
-                try!(self.client.post(&url).expect("url").json(&comment))
+        if let PipelineType::Try = pipeline_type {
+            // Try builds don't go anywhere, so they don't get labels.
+            return Ok(());
+        }
+        let labels = match self.pipelines_by_pipeline(&repo, pipeline_id) {
+            Some(repo_pipelines) => repo_pipelines.labels,
+            None => return Err(GithubRequestError::Pipeline(pipeline_id)),
+        };
+        let managed = labels.all();
+        if managed.is_empty() {
+            return Ok(());
+        }
+        let wanted = match status.map(|status| labels.for_status(status)) {
+            Some(Ok(wanted)) => wanted,
+            Some(Err(())) => return Ok(()),
+            None => None,
+        };
+        let client = try!(self.auth.client(&repo.owner, &repo.repo));
+        let url = format!(
+            "/repos/{}/{}/issues/{}/labels",
+            repo.owner,
+            repo.repo,
+            pr
+        );
+        let resp = try!(self.auth.rate_limit().send(true, || {
+            client.get(&url).expect("valid url")
+                .header(Self::accept(AcceptType::Regular))
+                .send()
+        }, |resp| &resp.http));
+        if !resp.is_success() {
+            return Err(GithubRequestError::HttpStatus(resp.http.status))
+        }
+        let current: Vec<LabelDesc> = try!(resp.json());
+        // Only touch our own labels, one at a time, so that labels
+        // somebody else adds in the meantime are not overwritten.
+        if let Some(wanted) = wanted {
+            if !current.iter().any(|label| label.name == wanted) {
+                let body = PostLabelsDesc {
+                    labels: vec![wanted.to_owned()],
+                };
+                let resp = try!(self.auth.rate_limit().send(false, || {
+                    let req = try!(
+                        client.post(&url).expect("valid url").json(&body)
+                    );
+                    let req = req.header(Self::accept(AcceptType::Regular));
+                    Ok::<_, GithubRequestError>(try!(req.send()))
+                }, |resp| &resp.http));
+                if !resp.is_success() {
+                    return Err(GithubRequestError::HttpStatus(
+                        resp.http.status,
+                    ));
+                }
+            }
+        }
+        for label in current {
+            let unwanted = managed.contains(&&label.name[..]) &&
+                Some(&label.name[..]) != wanted;
+            if !unwanted {
+                continue;
+            }
+            let url = format!(
+                "{}/{}",
+                url,
+                byte_serialize(label.name.as_bytes()).collect::<String>(),
+            );
+            let resp = try!(self.auth.rate_limit().send(true, || {
+                client.delete(&url).expect("valid url")
                     .header(Self::accept(AcceptType::Regular))
                     .send()
-            );
-            if !resp.is_success() {
+            }, |resp| &resp.http));
+            // It's already gone if somebody else took it off.
+            if !resp.is_success() && resp.http.status != StatusCode::NotFound {
                 return Err(GithubRequestError::HttpStatus(resp.http.status))
             }
         }
-        if let Some(status) = status {
-            let (pull_commit, merge_commit, status_body) = status;
+        Ok(())
+    }
+
+    fn send_status_to_check_run(
+        &self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        status: &ui::Status,
+    ) -> Result<(), GithubRequestError> {
+        match *status {
+            ui::Status::Approved(ref pull_commit) => {
+                self.update_check_run(pipeline_id, pr, Some(pull_commit), |run| {
+                    *run = CheckRun::new(pull_commit.clone());
+                    true
+                })
+            }
+            ui::Status::StartingBuild(ref pull_commit, ref merge_commit) => {
+                self.update_check_run(pipeline_id, pr, Some(pull_commit), |run| {
+                    run.status = CheckStatus::InProgress;
+                    run.title = "Testing".to_owned();
+                    run.merge_commit = Some(merge_commit.clone());
+                    true
+                })
+            }
+            ui::Status::Testing(ref pull_commit, ref merge_commit, ref url) => {
+                self.update_check_run(pipeline_id, pr, Some(pull_commit), |run| {
+                    run.status = CheckStatus::InProgress;
+                    run.title = "Testing".to_owned();
+                    run.merge_commit = Some(merge_commit.clone());
+                    if url.is_some() {
+                        run.url = url.clone();
+                    }
+                    true
+                })
+            }
+            ui::Status::Success(ref pull_commit, ref merge_commit, ref url) => {
+                self.update_check_run(pipeline_id, pr, Some(pull_commit), |run| {
+                    run.status = CheckStatus::Completed(Conclusion::Success);
+                    run.title = "Tests passed".to_owned();
+                    run.merge_commit = Some(merge_commit.clone());
+                    if url.is_some() {
+                        run.url = url.clone();
+                    }
+                    true
+                })
+            }
+            ui::Status::Failure(
+                ref pull_commit,
+                ref merge_commit,
+                ref url,
+                ref summary,
+            ) => {
+                self.update_check_run(pipeline_id, pr, Some(pull_commit), |run| {
+                    run.status = CheckStatus::Completed(Conclusion::Failure);
+                    run.title = "Tests failed".to_owned();
+                    run.merge_commit = Some(merge_commit.clone());
+                    if url.is_some() {
+                        run.url = url.clone();
+                    }
+                    if !summary.is_empty() {
+                        run.failure = Some(summary.clone());
+                    }
+                    true
+                })
+            }
+            ui::Status::Unmergeable(ref pull_commit) => {
+                self.update_check_run(pipeline_id, pr, Some(pull_commit), |run| {
+                    run.status = CheckStatus::Completed(Conclusion::Failure);
+                    run.title = "Merge conflict".to_owned();
+                    true
+                })
+            }
+            ui::Status::Unmoveable(ref pull_commit, ref merge_commit) => {
+                self.update_check_run(pipeline_id, pr, Some(pull_commit), |run| {
+                    run.status = CheckStatus::Completed(Conclusion::Failure);
+                    run.title = "Failed to fast-forward master".to_owned();
+                    run.merge_commit = Some(merge_commit.clone());
+                    true
+                })
+            }
+            ui::Status::Invalidated => {
+                self.update_check_run(pipeline_id, pr, None, |run| {
+                    if let CheckStatus::Completed(_) = run.status {
+                        return false;
+                    }
+                    run.status = CheckStatus::Completed(Conclusion::Cancelled);
+                    run.title = "New commits added".to_owned();
+                    true
+                })
+            }
+            ui::Status::Draft => {
+                self.update_check_run(pipeline_id, pr, None, |run| {
+                    if let CheckStatus::Completed(_) = run.status {
+                        return false;
+                    }
+                    run.status = CheckStatus::Completed(Conclusion::Cancelled);
+                    run.title = "Converted to draft".to_owned();
+                    true
+                })
+            }
+            ui::Status::Retargeted => {
+                self.update_check_run(pipeline_id, pr, None, |run| {
+                    if let CheckStatus::Completed(_) = run.status {
+                        return false;
+                    }
+                    run.status = CheckStatus::Completed(Conclusion::Cancelled);
+                    run.title = "Base branch changed".to_owned();
+                    true
+                })
+            }
+            ui::Status::NoCommit | ui::Status::Completed(_, _) => Ok(()),
+        }
+    }
+
+    /// Change the check run for a PR, and send it to GitHub if the change
+    /// returns true. If a commit is given, and the check run is for some
+    /// other commit (or there isn't one yet), a new one is started.
+    fn update_check_run<F>(
+        &self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        pull_commit: Option<&Commit>,
+        change: F,
+    ) -> Result<(), GithubRequestError>
+        where F: FnOnce(&mut CheckRun) -> bool
+    {
+        let (repo, pipeline_type) =
+            match self.projects.repo_by_pipeline(pipeline_id) {
+                Some(result) => result,
+                None => {
+                    return Err(GithubRequestError::Pipeline(pipeline_id));
+                }
+            };
+        let key = (pipeline_id, pr.clone());
+        // Work on a copy, so the lock isn't held while talking to GitHub.
+        let mut run = {
+            let mut check_runs = self.check_runs.lock().unwrap();
+            if let Some(pull_commit) = pull_commit {
+                let stale = check_runs.get(&key)
+                    .map(|run| run.pull_commit != *pull_commit)
+                    .unwrap_or(true);
+                if stale {
+                    check_runs.insert(
+                        key.clone(),
+                        CheckRun::new(pull_commit.clone()),
+                    );
+                }
+            }
+            let run = match check_runs.get_mut(&key) {
+                Some(run) => run,
+                None => return Ok(()),
+            };
+            if !change(run) {
+                return Ok(());
+            }
+            run.clone()
+        };
+        let had_id = run.id.is_some();
+        try!(self.send_check_run(&repo, pipeline_type, pr, &mut run));
+        if !had_id {
+            // Keep the id GitHub gave it, unless a newer commit
+            // replaced the check run in the meantime.
+            let mut check_runs = self.check_runs.lock().unwrap();
+            if let Some(stored) = check_runs.get_mut(&key) {
+                if stored.pull_commit == run.pull_commit {
+                    stored.id = run.id;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn send_check_run(
+        &self,
+        repo: &Repo,
+        pipeline_type: PipelineType,
+        pr: &Pr,
+        run: &mut CheckRun,
+    ) -> Result<(), GithubRequestError> {
+        let client = try!(self.auth.client(&repo.owner, &repo.repo));
+        let name = match pipeline_type {
+            PipelineType::Stage => checks::NAME,
+            PipelineType::Try => checks::NAME_TRY,
+        };
+        if run.id.is_none() {
+            // We may have made one before restarting; don't add another.
+            run.id = try!(self.find_check_run(&client, repo, name, pr, run));
+        }
+        let body = PostCheckRunDesc {
+            name: name.to_owned(),
+            head_sha: run.pull_commit.to_string(),
+            external_id: pr.to_string(),
+            status: run.status_str().to_owned(),
+            conclusion: run.conclusion_str().map(|c| c.to_owned()),
+            details_url: run.url.as_ref().map(ToString::to_string),
+            output: CheckRunOutputDesc {
+                title: run.title.clone(),
+                summary: run.summary(),
+            },
+            actions: run.actions().into_iter()
+                .map(|(label, description, identifier)| CheckRunActionDesc {
+                    label: label.to_owned(),
+                    description: description.to_owned(),
+                    identifier: identifier.to_owned(),
+                })
+                .collect(),
+        };
+        let resp = if let Some(id) = run.id {
             let url = format!(
-                "/repos/{}/{}/statuses/{}",
+                "/repos/{}/{}/check-runs/{}",
                 repo.owner,
                 repo.repo,
-                pull_commit
+                id
             );
-            let resp = try!(
-                try!(self.client.post(&url).expect("url").json(&status_body))
-                    .header(Self::accept(AcceptType::Regular))
-                    .send()
+            try!(self.auth.rate_limit().send(true, || {
+                let req = try!(client.patch(&url).expect("url").json(&body));
+                let req = req.header(Self::accept(AcceptType::Checks));
+                Ok::<_, GithubRequestError>(try!(req.send()))
+            }, |resp| &resp.http))
+        } else {
+            let url = format!(
+                "/repos/{}/{}/check-runs",
+                repo.owner,
+                repo.repo
             );
-            if !resp.is_success() {
-                return Err(GithubRequestError::HttpStatus(resp.http.status))
-            }
-            if let Some(merge_commit) = merge_commit {
-                let url = format!(
-                    "/repos/{}/{}/statuses/{}",
-                    repo.owner,
-                    repo.repo,
-                    merge_commit
-                );
-                let resp = try!(
-                    try!(
-                        self.client.post(&url).expect("url")
-                            .json(&status_body)
-                    )
-                        .header(Self::accept(AcceptType::Regular))
-                        .send()
-                );
-                if !resp.is_success() {
-                    return Err(GithubRequestError::HttpStatus(
-                    	resp.http.status
-                    ))
+            try!(self.auth.rate_limit().send(false, || {
+                let req = try!(client.post(&url).expect("url").json(&body));
+                let req = req.header(Self::accept(AcceptType::Checks));
+                Ok::<_, GithubRequestError>(try!(req.send()))
+            }, |resp| &resp.http))
+        };
+        if !resp.is_success() {
+            return Err(GithubRequestError::HttpStatus(resp.http.status))
+        }
+        if run.id.is_none() {
+            let desc: CheckRunIdDesc = try!(resp.json());
+            run.id = Some(desc.id);
+        }
+        Ok(())
+    }
+
+    /// Look for a check run this bot already made for the PR's commit.
+    fn find_check_run(
+        &self,
+        client: &github_auth::Client,
+        repo: &Repo,
+        name: &str,
+        pr: &Pr,
+        run: &CheckRun,
+    ) -> Result<Option<u64>, GithubRequestError> {
+        let url = format!(
+            "/repos/{}/{}/commits/{}/check-runs?check_name={}",
+            repo.owner,
+            repo.repo,
+            run.pull_commit,
+            byte_serialize(name.as_bytes()).collect::<String>(),
+        );
+        let resp = try!(self.auth.rate_limit().send(true, || {
+            client.get(&url).expect("valid url")
+                .header(Self::accept(AcceptType::Checks))
+                .send()
+        }, |resp| &resp.http));
+        if !resp.is_success() {
+            return Err(GithubRequestError::HttpStatus(resp.http.status))
+        }
+        let list: CheckRunListDesc = try!(resp.json());
+        let external_id = pr.to_string();
+        Ok(list.check_runs.into_iter()
+            .find(|desc| desc.external_id.as_ref() == Some(&external_id))
+            .map(|desc| desc.id))
+    }
+
+    fn get_issue(
+        &self,
+        repo: &Repo,
+        pr: &Pr,
+    ) -> Result<IssueCommentIssue, GithubRequestError> {
+        let client = try!(self.auth.client(&repo.owner, &repo.repo));
+        let url = format!(
+            "/repos/{}/{}/issues/{}",
+            repo.owner,
+            repo.repo,
+            pr
+        );
+        let resp = try!(self.auth.rate_limit().send(true, || {
+            client.get(&url).expect("valid url")
+                .header(Self::accept(AcceptType::Regular))
+                .send()
+        }, |resp| &resp.http));
+        if !resp.is_success() {
+            return Err(GithubRequestError::HttpStatus(resp.http.status))
+        }
+        Ok(try!(resp.json()))
+    }
+
+    /// The branch a PR targets, looked up on GitHub unless it is given
+    /// or a webhook already said. Repos that take PRs into any branch
+    /// don't need it, so it's `None`.
+    fn pr_base(
+        &self,
+        all_pipelines: &[RepoPipelines],
+        repo: &Repo,
+        pr: &Pr,
+        base: Option<String>,
+    ) -> Result<Option<String>, GithubRequestError> {
+        if base.is_some() || all_pipelines.iter().all(|p| p.branch.is_none()) {
+            return Ok(base);
+        }
+        let key = (repo.clone(), pr.clone());
+        if let Some(base) = self.bases.lock().unwrap().get(&key) {
+            return Ok(Some(base.clone()));
+        }
+        let client = try!(self.auth.client(&repo.owner, &repo.repo));
+        let url = format!(
+            "/repos/{}/{}/pulls/{}",
+            repo.owner,
+            repo.repo,
+            pr
+        );
+        let resp = try!(self.auth.rate_limit().send(true, || {
+            client.get(&url).expect("valid url")
+                .header(Self::accept(AcceptType::Regular))
+                .send()
+        }, |resp| &resp.http));
+        if !resp.is_success() {
+            return Err(GithubRequestError::HttpStatus(resp.http.status))
+        }
+        let desc: PrTargetDesc = try!(resp.json());
+        self.bases.lock().unwrap().insert(key, desc.base.git_ref.clone());
+        Ok(Some(desc.base.git_ref))
+    }
+
+    /// The pipelines that a stage or try pipeline belongs with.
+    fn pipelines_by_pipeline(
+        &self,
+        repo: &Repo,
+        pipeline_id: PipelineId,
+    ) -> Option<RepoPipelines> {
+        self.projects.pipelines_by_repo(repo).into_iter().find(|p| {
+            p.pipeline_id == pipeline_id ||
+                p.try_pipeline_id == Some(pipeline_id)
+        })
+    }
+
+    fn post_comment(
+        &self,
+        repo: &Repo,
+        pr: &Pr,
+        body: String,
+    ) -> Result<(), GithubRequestError> {
+        let client = try!(self.auth.client(&repo.owner, &repo.repo));
+        let url = format!(
+            "/repos/{}/{}/issues/{}/comments",
+            repo.owner,
+            repo.repo,
+            pr
+        );
+        let comment = PostCommentComment{
+            body: body,
+        };
+        let resp = try!(self.auth.rate_limit().send(false, || {
+            let req = try!(client.post(&url).expect("url").json(&comment));
+            let req = req.header(Self::accept(AcceptType::Regular));
+            Ok::<_, GithubRequestError>(try!(req.send()))
+        }, |resp| &resp.http));
+        if !resp.is_success() {
+            return Err(GithubRequestError::HttpStatus(resp.http.status))
+        }
+        Ok(())
+    }
+
+    fn send_ci_result_to_pr(
+        &self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        ci_result: &ui::CiResult,
+    ) -> Result<(), GithubRequestError> {
+        if self.checks {
+            let pull_commit = Some(&ci_result.pull_commit);
+            return self.update_check_run(pipeline_id, pr, pull_commit, |run| {
+                run.set_ci_result(ci_result.clone());
+                true
+            });
+        }
+        let (repo, pipeline_type) =
+            match self.projects.repo_by_pipeline(pipeline_id) {
+                Some(result) => result,
+                None => {
+                    return Err(GithubRequestError::Pipeline(pipeline_id));
                 }
+            };
+        let context = match pipeline_type {
+            PipelineType::Stage => "continuous-integration/aelita/job",
+            PipelineType::Try => "continuous-integration/aelita/try/job",
+        };
+        let entry = &ci_result.state;
+        let duration = match (entry.started, entry.finished) {
+            (Some(started), Some(finished)) if finished >= started => {
+                let secs = finished - started;
+                format!(" in {}m {}s", secs / 60, secs % 60)
             }
+            _ => String::new(),
+        };
+        let (state, description) = match entry.state {
+            db::CiState::Queued => ("pending", "Queued".to_owned()),
+            db::CiState::Running => ("pending", "Running".to_owned()),
+            db::CiState::Succeeded =>
+                ("success", format!("Passed{}", duration)),
+            db::CiState::Failed =>
+                ("failure", format!("Failed{}", duration)),
+            db::CiState::Canceled => ("error", "Canceled".to_owned()),
+        };
+        let status_body = StatusDesc {
+            state: state.to_owned(),
+            target_url: entry.url.as_ref().map(ToString::to_string),
+            description: description,
+            context: format!("{}/{}", context, ci_result.name),
+        };
+        try!(self.post_status(&repo, &ci_result.pull_commit, &status_body));
+        try!(self.post_status(&repo, &entry.commit, &status_body));
+        Ok(())
+    }
+
+    fn post_status(
+        &self,
+        repo: &Repo,
+        commit: &Commit,
+        status_body: &StatusDesc,
+    ) -> Result<(), GithubRequestError> {
+        let client = try!(self.auth.client(&repo.owner, &repo.repo));
+        let url = format!(
+            "/repos/{}/{}/statuses/{}",
+            repo.owner,
+            repo.repo,
+            commit
+        );
+        let resp = try!(self.auth.rate_limit().send(false, || {
+            let req = try!(client.post(&url).expect("url").json(status_body));
+            let req = req.header(Self::accept(AcceptType::Regular));
+            Ok::<_, GithubRequestError>(try!(req.send()))
+        }, |resp| &resp.http));
+        if !resp.is_success() {
+            return Err(GithubRequestError::HttpStatus(resp.http.status))
         }
         Ok(())
     }
@@ -854,18 +2069,20 @@ impl Worker {
     fn user_is_member_of(
         &self,
         user: &str,
+        repo: &Repo,
         team: TeamId,
     ) -> Result<bool, GithubRequestError> {
+        let client = try!(self.auth.client(&repo.owner, &repo.repo));
         let url = format!(
             "/teams/{}/members/{}",
             team.0,
             user,
         );
-        let resp = try!(
-            self.client.get(&url).expect("valid url")
+        let resp = try!(self.auth.rate_limit().send(true, || {
+            client.get(&url).expect("valid url")
                 .header(Self::accept(AcceptType::Regular))
                 .send()
-        );
+        }, |resp| &resp.http));
         if resp.http.status == StatusCode::NotFound {
             Ok(false)
         } else if resp.is_success() {
@@ -880,17 +2097,18 @@ impl Worker {
         user: &str,
         repo: &Repo,
     ) -> Result<bool, GithubRequestError> {
+        let client = try!(self.auth.client(&repo.owner, &repo.repo));
         let url = format!(
             "/repos/{}/{}/collaborators/{}",
             repo.owner,
             repo.repo,
             user,
         );
-        let resp = try!(
-            self.client.get(&url).expect("valid url")
+        let resp = try!(self.auth.rate_limit().send(true, || {
+            client.get(&url).expect("valid url")
                 .header(Self::accept(AcceptType::Regular))
                 .send()
-        );
+        }, |resp| &resp.http));
         if resp.http.status == StatusCode::NotFound {
             Ok(false)
         } else if resp.is_success() {
@@ -904,16 +2122,17 @@ impl Worker {
         &self,
         repo: &Repo,
     ) -> Result<bool, GithubRequestError> {
+        let client = try!(self.auth.client(&repo.owner, &repo.repo));
         let url = format!(
             "/repos/{}/{}",
             repo.owner,
             repo.repo,
         );
-        let resp = try!(
-            self.client.get(&url).expect("valid url")
+        let resp = try!(self.auth.rate_limit().send(true, || {
+            client.get(&url).expect("valid url")
                 .header(Self::accept(AcceptType::Regular))
                 .send()
-        );
+        }, |resp| &resp.http));
         if resp.is_success() {
             let repo_desc: RepositoryDesc = try!(resp.json());
             Ok(match &repo_desc.owner.owner_type[..] {
@@ -936,15 +2155,16 @@ impl Worker {
         &self,
         repo: &Repo,
     ) -> Result<HashSet<TeamId>, GithubRequestError> {
+        let client = try!(self.auth.client(&repo.owner, &repo.repo));
         let url = format!(
             "/orgs/{}/teams",
             repo.owner,
         );
-        let resp = try!(
-            self.client.get(&url).expect("valid url")
+        let resp = try!(self.auth.rate_limit().send(true, || {
+            client.get(&url).expect("valid url")
                 .header(Self::accept(AcceptType::Regular))
                 .send()
-        );
+        }, |resp| &resp.http));
         if resp.is_success() {
             let all_teams: Vec<TeamDesc> = try!(resp.json());
             let mut writing_teams = HashSet::new();
@@ -955,11 +2175,11 @@ impl Worker {
                     repo.owner,
                     repo.repo
                 );
-                let resp = try!(
-                    self.client.get(&url).expect("valid url")
+                let resp = try!(self.auth.rate_limit().send(true, || {
+                    client.get(&url).expect("valid url")
                         .header(Self::accept(AcceptType::Repository))
                         .send()
-                );
+                }, |resp| &resp.http));
                 let team_repo: TeamRepoDesc = try!(resp.json());
                 if let Some(ref permissions) = team_repo.permissions {
                     if permissions.admin || permissions.push {
@@ -977,14 +2197,97 @@ impl Worker {
             AcceptType::Regular => "application/vnd.github.v3+json",
             AcceptType::Repository =>
                 "application/vnd.github.v3.repository+json",
+            AcceptType::Checks =>
+                "application/vnd.github.antiope-preview+json",
         }.parse().expect("hard-coded mimes to be valid");
         header::Accept(vec![qitem(mime)])
     }
 }
 
+/// The pipelines for PRs into `base`,
+/// or the first ones if the base isn't needed to tell them apart.
+fn pipelines_for_base(
+    all_pipelines: Vec<RepoPipelines>,
+    base: Option<&str>,
+) -> Option<RepoPipelines> {
+    match base {
+        Some(base) => ui::pipelines_for_branch(all_pipelines, base),
+        None => all_pipelines.into_iter().next(),
+    }
+}
+
+/// Review events carry the pull request, rather than the issue,
+/// but the same parts of it are needed to approve it.
+fn review_pr_to_issue(pr: ReviewPrDesc) -> IssueCommentIssue {
+    IssueCommentIssue {
+        number: pr.number,
+        title: pr.title,
+        body: pr.body,
+        pull_request: Some(IssueCommentPullRequest {
+            html_url: pr.html_url,
+        }),
+        state: pr.state,
+        user: pr.user,
+    }
+}
+
+/// The most status changes to keep in a status comment.
+const MAX_STATUS_HISTORY: usize = 50;
+
+/// One line of a status comment's history,
+/// or `None` if the status is not worth a line.
+fn render_status_line(
+    status: &ui::Status,
+    approver: Option<&str>,
+    attempt: u32,
+) -> Option<String> {
+    Some(match *status {
+        ui::Status::Approved(ref pull_commit) => match approver {
+            Some(approver) => format!(
+                ":hourglass: Approved `{}` by @{}",
+                &pull_commit.to_short_string()[..],
+                approver,
+            ),
+            None => format!(
+                ":hourglass: Approved `{}`",
+                &pull_commit.to_short_string()[..],
+            ),
+        },
+        ui::Status::StartingBuild(_, ref merge_commit) => format!(
+            ":construction: Attempt {}: testing merge commit `{}`",
+            attempt,
+            &merge_commit.to_short_string()[..],
+        ),
+        ui::Status::Testing(_, _, Some(ref url)) =>
+            format!(":construction: [Build running]({})", url),
+        ui::Status::Testing(_, _, None) => return None,
+        ui::Status::Completed(_, ref merge_commit) => format!(
+            ":white_check_mark: Done with `{}`",
+            &merge_commit.to_short_string()[..],
+        ),
+        // The failure summary goes at the end of the whole comment.
+        _ => return render_comment_line(status, "PR", "Base"),
+    })
+}
+
+/// The whole status comment. Only the latest failure gets its summary.
+fn render_status_comment(comment: &StatusComment, latest: &ui::Status) -> String {
+    let mut result = "**aelita status**\n\n".to_owned();
+    for line in &comment.history {
+        result.push_str("* ");
+        result.push_str(line);
+        result.push('\n');
+    }
+    if let ui::Status::Failure(_, _, _, ref summary) = *latest {
+        result.push_str(&render_failure_summary(summary));
+    }
+    result
+}
+
 enum AcceptType {
     Regular,
     Repository,
+    Checks,
 }
 
 quick_error! {
@@ -1007,7 +2310,137 @@ quick_error! {
             cause(err)
             from()
         }
+        /// Authentication error
+        Auth(err: github_auth::Error) {
+            cause(err)
+            from()
+        }
         /// Repo not found for pipeline
         Pipeline(pipeline_id: PipelineId) {}
     }
 }
+
+#[cfg(test)]
+mod test {
+    use ci::FailureSummary;
+    use pipeline::PipelineId;
+    use super::Labels;
+    use super::{RepoPipelines, pipelines_for_base};
+    use super::{render_status_comment, render_status_line};
+    use super::cache::StatusComment;
+    use ui::Status;
+    use vcs::Commit;
+
+    fn branch_pipelines(branch: Option<&str>, id: i32) -> RepoPipelines {
+        RepoPipelines{
+            branch: branch.map(ToOwned::to_owned),
+            pipeline_id: PipelineId(id),
+            try_pipeline_id: None,
+            review_approval: false,
+            labels: Labels::default(),
+        }
+    }
+
+    #[test]
+    fn test_pipelines_for_base() {
+        let all = vec![
+            branch_pipelines(Some("master"), 0),
+            branch_pipelines(Some("release"), 1),
+        ];
+        let found = pipelines_for_base(all.clone(), Some("release"));
+        assert_eq!(found.map(|p| p.pipeline_id), Some(PipelineId(1)));
+        let found = pipelines_for_base(all.clone(), Some("gh-pages"));
+        assert!(found.is_none());
+        // Pipelines without a branch take PRs into any of them.
+        let any = vec![branch_pipelines(None, 2)];
+        let found = pipelines_for_base(any.clone(), Some("gh-pages"));
+        assert_eq!(found.map(|p| p.pipeline_id), Some(PipelineId(2)));
+        let found = pipelines_for_base(any, None);
+        assert_eq!(found.map(|p| p.pipeline_id), Some(PipelineId(2)));
+    }
+
+    #[test]
+    fn test_labels_for_status() {
+        let labels = Labels{
+            approved: Some("S-waiting-on-bors".to_owned()),
+            testing: Some("S-testing".to_owned()),
+            failed: None,
+            merge_conflict: Some("S-merge-conflict".to_owned()),
+        };
+        let commit = Commit::from("a".to_owned());
+        assert_eq!(
+            labels.all(),
+            vec!["S-waiting-on-bors", "S-testing", "S-merge-conflict"]
+        );
+        assert_eq!(
+            labels.for_status(&Status::Approved(commit.clone())),
+            Ok(Some("S-waiting-on-bors"))
+        );
+        assert_eq!(
+            labels.for_status(&Status::Testing(
+                commit.clone(),
+                commit.clone(),
+                None,
+            )),
+            Ok(Some("S-testing"))
+        );
+        assert_eq!(
+            labels.for_status(&Status::Failure(
+                commit.clone(),
+                commit.clone(),
+                None,
+                FailureSummary::default(),
+            )),
+            Ok(None)
+        );
+        assert_eq!(labels.for_status(&Status::Invalidated), Ok(None));
+        assert_eq!(labels.for_status(&Status::NoCommit), Err(()));
+        assert_eq!(labels.for_status(&Status::Draft), Ok(None));
+        assert_eq!(labels.for_status(&Status::Retargeted), Ok(None));
+    }
+
+    #[test]
+    fn test_render_status_comment() {
+        let commit = Commit::from("abcdef".to_owned());
+        let approved = Status::Approved(commit.clone());
+        let failed = Status::Failure(
+            commit.clone(),
+            commit.clone(),
+            None,
+            FailureSummary{
+                tests: vec!["foo".to_owned()],
+                log: None,
+            },
+        );
+        let comment = StatusComment{
+            id: 1,
+            history: vec![
+                render_status_line(&approved, Some("bob"), 0).unwrap(),
+                render_status_line(&Status::StartingBuild(
+                    commit.clone(),
+                    commit.clone(),
+                ), None, 1).unwrap(),
+                render_status_line(&failed, None, 1).unwrap(),
+            ],
+            attempts: 1,
+            approver: None,
+        };
+        assert_eq!(
+            render_status_comment(&comment, &approved),
+            "**aelita status**\n\n\
+            * :hourglass: Approved `abcde` by @bob\n\
+            * :construction: Attempt 1: testing merge commit `abcde`\n\
+            * :-1: Build failed\n"
+        );
+        assert!(render_status_comment(&comment, &failed)
+            .contains("* `foo`"));
+        assert_eq!(
+            render_status_line(&Status::Testing(
+                commit.clone(),
+                commit.clone(),
+                None,
+            ), None, 1),
+            None
+        );
+    }
+}
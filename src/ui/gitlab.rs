@@ -0,0 +1,667 @@
+// This file is released under the same terms as Rust itself.
+
+//! The GitLab merge request front-end.
+//!
+//! Merge Request, Note and Push hooks come in, checked against the secret
+//! token GitLab sends as-is in `X-Gitlab-Token`. Results go back out as
+//! notes on the merge request, and as commit statuses.
+
+use crossbeam;
+use db;
+use hyper::Url;
+use hyper::method::Method;
+use hyper::server::{Request, Response};
+use hyper::status::StatusCode;
+use pipeline::{self, PipelineId};
+use serde_json::{self, from_reader as json_from_reader};
+use std::io::Read;
+use std::iter;
+use std::sync::mpsc::{Sender, Receiver};
+use ui::{self, comments, Pr, RepoPipelines, StatusState};
+use ui::{handle_pr_retarget, is_staging_branch, pipelines_for_branch};
+use ui::{handle_push, render_comment, render_status};
+use ui::render_unmanaged_branch;
+use ui::forge::{self, PrAction, RequestError};
+use util::crypto;
+use util::github_headers::get_header;
+use util::gitlab_api::{self, Api};
+use vcs::Commit;
+
+/// GitLab's access level for Developers, the least that may approve.
+const DEVELOPER_ACCESS: u32 = 30;
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Repo {
+    /// The project's full path, like `group/subgroup/project`
+    pub path: String,
+}
+
+impl Repo {
+    /// How the project is named in API paths.
+    fn api_id(&self) -> String {
+        gitlab_api::encode(&self.path)
+    }
+}
+
+pub use ui::PipelineType;
+
+pub trait ProjectsConfig: Send + Sync + 'static {
+    /// The pipelines for each of the project's target branches.
+    /// It is empty if the project is not managed at all.
+    fn pipelines_by_repo(&self, &Repo) -> Vec<RepoPipelines>;
+    fn repo_by_pipeline(&self, PipelineId) -> Option<(Repo, PipelineType)>;
+}
+
+pub struct Worker {
+    listen: String,
+    api: Api,
+    user: String,
+    user_ident: String,
+    secret: String,
+    projects: Box<ProjectsConfig>,
+}
+
+impl Worker {
+    pub fn new(
+        listen: String,
+        api: Api,
+        user: String,
+        secret: String,
+        projects: Box<ProjectsConfig>,
+    ) -> Worker {
+        let user_ident = format!("@{}", user);
+        Worker {
+            listen: listen,
+            api: api,
+            user: user,
+            user_ident: user_ident,
+            secret: secret,
+            projects: projects,
+        }
+    }
+}
+
+// JSON API structs
+#[derive(Deserialize, Serialize)]
+struct UserDesc {
+    id: u64,
+    username: String,
+}
+#[derive(Deserialize, Serialize)]
+struct ProjectDesc {
+    path_with_namespace: String,
+}
+#[derive(Deserialize, Serialize)]
+struct LastCommitDesc {
+    id: String,
+}
+#[derive(Deserialize, Serialize)]
+struct MergeRequestDesc {
+    iid: u32,
+    title: String,
+    description: Option<String>,
+    state: String,
+    target_branch: String,
+    source_branch: Option<String>,
+    url: String,
+    last_commit: Option<LastCommitDesc>,
+    /// Only in Merge Request Hooks
+    action: Option<String>,
+    draft: Option<bool>,
+    /// What older versions of GitLab call drafts
+    work_in_progress: Option<bool>,
+}
+#[derive(Deserialize, Serialize)]
+struct ChangeDesc {
+    previous: String,
+    current: String,
+}
+#[derive(Deserialize, Serialize)]
+struct MergeRequestChangesDesc {
+    target_branch: Option<ChangeDesc>,
+}
+#[derive(Deserialize, Serialize)]
+struct MergeRequestHookDesc {
+    user: UserDesc,
+    project: ProjectDesc,
+    object_attributes: MergeRequestDesc,
+    changes: Option<MergeRequestChangesDesc>,
+}
+#[derive(Deserialize, Serialize)]
+struct PushHookDesc {
+    // ref is a reserved word.
+    #[serde(rename="ref")]
+    git_ref: String,
+    project: ProjectDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct NoteDesc {
+    note: String,
+    noteable_type: String,
+}
+#[derive(Deserialize, Serialize)]
+struct NoteHookDesc {
+    user: UserDesc,
+    project: ProjectDesc,
+    object_attributes: NoteDesc,
+    merge_request: Option<MergeRequestDesc>,
+}
+#[derive(Deserialize, Serialize)]
+struct PostNoteDesc {
+    body: String,
+}
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct PostStatusDesc {
+    state: String,
+    name: String,
+    target_url: Option<String>,
+    description: String,
+}
+#[derive(Deserialize, Serialize)]
+struct MemberDesc {
+    access_level: u32,
+}
+
+impl pipeline::Worker<ui::Event, ui::Message> for Worker {
+    fn run(
+        &self,
+        recv_msg: Receiver<ui::Message>,
+        mut send_event: Sender<ui::Event>
+    ) {
+        crossbeam::scope(|scope| {
+            let s2 = &*self;
+            let send_event_2 = send_event.clone();
+            scope.spawn(move || {
+                s2.run_webhook(send_event_2);
+            });
+            loop {
+                s2.handle_message(
+                    recv_msg.recv().expect("Pipeline went away"),
+                    &mut send_event,
+                );
+            }
+        })
+    }
+}
+
+impl Worker {
+    fn run_webhook(
+        &self,
+        send_event: Sender<ui::Event>,
+    ) {
+        forge::run_webhook(&self.listen, |req, res| {
+            self.handle_webhook(req, res, &send_event);
+        });
+    }
+
+    fn handle_webhook(
+        &self,
+        mut req: Request,
+        res: Response,
+        send_event: &Sender<ui::Event>
+    ) {
+        let token = get_header(&req.headers, "X-Gitlab-Token");
+        if !crypto::verify_token(self.secret.as_bytes(), &token) {
+            warn!("Got incorrect token");
+            forge::respond(res, StatusCode::Forbidden, "bad webhook");
+            return;
+        }
+        let x_gitlab_event = get_header(&req.headers, "X-Gitlab-Event");
+        let mut body = Vec::new();
+        if let Err(e) = req.read_to_end(&mut body) {
+            warn!("Failed to read body: {:?}", e);
+            return;
+        }
+        match &x_gitlab_event[..] {
+            b"Merge Request Hook" => {
+                let desc = forge::parse_hook::<MergeRequestHookDesc>(
+                    &body,
+                    res,
+                    "MR message",
+                );
+                if let Some(desc) = desc {
+                    info!(
+                        "Got MR message for !{}: {}",
+                        desc.object_attributes.iid,
+                        desc.object_attributes.action.as_ref()
+                            .map(|action| &action[..])
+                            .unwrap_or(""),
+                    );
+                    self.handle_mr(send_event, desc);
+                }
+            }
+            b"Push Hook" => {
+                let desc = forge::parse_hook::<PushHookDesc>(&body, res, "push");
+                if let Some(desc) = desc {
+                    let repo = Repo{
+                        path: desc.project.path_with_namespace,
+                    };
+                    let all_pipelines = self.projects.pipelines_by_repo(&repo);
+                    handle_push(send_event, all_pipelines, &desc.git_ref);
+                }
+            }
+            b"Note Hook" => {
+                let desc = forge::parse_hook::<NoteHookDesc>(&body, res, "note");
+                if let Some(desc) = desc {
+                    self.handle_note(send_event, desc);
+                }
+            }
+            e => {
+                info!("Got unknown event {:?}", String::from_utf8_lossy(e));
+                forge::respond(res, StatusCode::NoContent, "GitLab");
+            }
+        }
+    }
+
+    fn handle_mr(
+        &self,
+        send_event: &Sender<ui::Event>,
+        desc: MergeRequestHookDesc,
+    ) {
+        let repo = Repo{
+            path: desc.project.path_with_namespace,
+        };
+        let mr = desc.object_attributes;
+        let pr = Pr::from(mr.iid.to_string());
+        let all_pipelines = self.projects.pipelines_by_repo(&repo);
+        if all_pipelines.is_empty() {
+            warn!(
+                "Got bad repo {:?}",
+                repo
+            );
+            return;
+        }
+        if let Some(ref source_branch) = mr.source_branch {
+            if is_staging_branch(&all_pipelines, source_branch) {
+                info!("MR is from staging branch {}; do nothing", source_branch);
+                return;
+            }
+        }
+        let retarget = desc.changes.and_then(|changes| changes.target_branch);
+        if let Some(retarget) = retarget {
+            handle_pr_retarget(
+                send_event,
+                &all_pipelines,
+                &pr,
+                &retarget.previous,
+                &retarget.current,
+            );
+        }
+        let repo_pipelines =
+            match pipelines_for_branch(all_pipelines, &mr.target_branch) {
+                Some(repo_pipelines) => repo_pipelines,
+                None => {
+                    info!(
+                        "MR targets unmanaged branch {}; do nothing",
+                        mr.target_branch
+                    );
+                    return;
+                }
+            };
+        let commit = match mr.last_commit {
+            Some(last_commit) => Commit::from(last_commit.id),
+            None => {
+                warn!("MR !{} has no commits", pr);
+                return;
+            }
+        };
+        let url = match Url::parse(&mr.url) {
+            Ok(url) => url,
+            Err(e) => {
+                warn!("MR !{} has invalid URL: {:?}", pr, e);
+                return;
+            }
+        };
+        let action = match mr.action.as_ref().map(|action| &action[..]) {
+            Some("close") | Some("merge") => PrAction::Closed,
+            Some("open") | Some("reopen") => PrAction::Opened,
+            Some("update") => PrAction::Changed,
+            _ => return,
+        };
+        forge::send_pr_action(
+            send_event,
+            action,
+            &repo_pipelines,
+            pr,
+            commit,
+            mr.title,
+            url,
+            mr.draft.or(mr.work_in_progress).unwrap_or(false),
+        );
+    }
+
+    fn handle_note(
+        &self,
+        send_event: &Sender<ui::Event>,
+        desc: NoteHookDesc,
+    ) {
+        let body = &desc.object_attributes.note;
+        let mr = match desc.merge_request {
+            Some(ref mr) if desc.object_attributes.noteable_type ==
+                    "MergeRequest" => mr,
+            _ => {
+                info!("Note is not on a merge request; do nothing");
+                return;
+            }
+        };
+        if desc.user.username == self.user {
+            info!("Note is my own; do nothing");
+            return;
+        }
+        if !body.contains(&self.user_ident) {
+            info!("Note does not mention me; do nothing");
+            return;
+        }
+        if mr.state != "opened" {
+            info!("Note is for closed MR; do nothing");
+            return;
+        }
+        info!("Got merge request note");
+        let repo = Repo{
+            path: desc.project.path_with_namespace.clone(),
+        };
+        let pr = Pr::from(mr.iid.to_string());
+        let all_pipelines = self.projects.pipelines_by_repo(&repo);
+        if all_pipelines.is_empty() {
+            warn!(
+                "Got bad repo {:?}",
+                repo
+            );
+            return;
+        }
+        let user = &desc.user.username;
+        let allowed = self.user_has_developer(&repo, desc.user.id)
+            .unwrap_or_else(|e| {
+                warn!("Failed to check if {} has permission: {:?}", user, e);
+                false
+            });
+        if !allowed {
+            info!("Got mentioned by not-permitted user");
+        } else if let Some(command) = comments::parse(body, user) {
+            let unmanaged_note = render_unmanaged_branch(
+                &all_pipelines,
+                "merge request",
+                &mr.target_branch,
+            );
+            match pipelines_for_branch(all_pipelines, &mr.target_branch) {
+                Some(repo_pipelines) => forge::send_command(
+                    send_event,
+                    command,
+                    &repo_pipelines,
+                    pr,
+                    |user| merge_message(mr, user),
+                ),
+                None => {
+                    info!("Got command for MR into unmanaged branch");
+                    if let Err(e) = self.post_note(&repo, &pr, unmanaged_note) {
+                        warn!("Failed to comment on !{}: {:?}", pr, e);
+                    }
+                }
+            }
+        } else {
+            info!("Note is not a command");
+        }
+    }
+
+    fn handle_message(
+        &self,
+        msg: ui::Message,
+        _: &mut Sender<ui::Event>,
+    ) {
+        match msg {
+            ui::Message::SendResult(pipeline_id, pr, status) => {
+                let result = self.send_result_to_mr(pipeline_id, &pr, &status);
+                if let Err(e) = result {
+                    warn!("Failed to send {:?} to MR !{}: {:?}", status, pr, e)
+                }
+            }
+            ui::Message::SendCiResult(pipeline_id, pr, ci_result) => {
+                let result = self.send_ci_result_to_mr(pipeline_id, &ci_result);
+                if let Err(e) = result {
+                    warn!(
+                        "Failed to send {:?} to MR !{}: {:?}",
+                        ci_result,
+                        pr,
+                        e,
+                    )
+                }
+            }
+            // There's nowhere to show it but a note, which would be spam.
+            ui::Message::SendQueuePosition(_, _, _) => {}
+        }
+    }
+
+    fn send_result_to_mr(
+        &self,
+        pipeline_id: PipelineId,
+        pr: &Pr,
+        status: &ui::Status,
+    ) -> Result<(), RequestError> {
+        let (repo, pipeline_type) =
+            match self.projects.repo_by_pipeline(pipeline_id) {
+                Some(result) => result,
+                None => {
+                    return Err(RequestError::Pipeline(pipeline_id));
+                }
+            };
+        if let Some(body) = render_comment(status, "MR", "Target") {
+            try!(self.post_note(&repo, pr, body));
+        }
+        let name = match pipeline_type {
+            PipelineType::Stage => "aelita",
+            PipelineType::Try => "aelita/try",
+        };
+        if let Some(commit_status) = render_status(status) {
+            let status_body = PostStatusDesc {
+                state: state_name(commit_status.state).to_owned(),
+                name: name.to_owned(),
+                target_url: commit_status.url.map(ToString::to_string),
+                description: commit_status.description,
+            };
+            let pull_commit = commit_status.pull_commit;
+            try!(self.post_status(&repo, pull_commit, &status_body));
+            if let Some(merge_commit) = commit_status.merge_commit {
+                try!(self.post_status(&repo, merge_commit, &status_body));
+            }
+        }
+        Ok(())
+    }
+
+    fn send_ci_result_to_mr(
+        &self,
+        pipeline_id: PipelineId,
+        ci_result: &ui::CiResult,
+    ) -> Result<(), RequestError> {
+        let (repo, pipeline_type) =
+            match self.projects.repo_by_pipeline(pipeline_id) {
+                Some(result) => result,
+                None => {
+                    return Err(RequestError::Pipeline(pipeline_id));
+                }
+            };
+        let name = match pipeline_type {
+            PipelineType::Stage => "aelita/job",
+            PipelineType::Try => "aelita/try/job",
+        };
+        let entry = &ci_result.state;
+        let (state, description) = match entry.state {
+            db::CiState::Queued => ("pending", "Queued"),
+            db::CiState::Running => ("running", "Running"),
+            db::CiState::Succeeded => ("success", "Passed"),
+            db::CiState::Failed => ("failed", "Failed"),
+            db::CiState::Canceled => ("canceled", "Canceled"),
+        };
+        let status_body = PostStatusDesc {
+            state: state.to_owned(),
+            name: format!("{}/{}", name, ci_result.name),
+            target_url: entry.url.as_ref().map(ToString::to_string),
+            description: description.to_owned(),
+        };
+        try!(self.post_status(&repo, &ci_result.pull_commit, &status_body));
+        try!(self.post_status(&repo, &entry.commit, &status_body));
+        Ok(())
+    }
+
+    fn user_has_developer(
+        &self,
+        repo: &Repo,
+        user_id: u64,
+    ) -> Result<bool, RequestError> {
+        let path = format!(
+            "/projects/{}/members/all/{}",
+            repo.api_id(),
+            user_id,
+        );
+        let resp = try!(self.api.send(Method::Get, &path, None));
+        if resp.status == StatusCode::NotFound {
+            return Ok(false);
+        }
+        if !resp.status.is_success() {
+            return Err(RequestError::HttpStatus(resp.status));
+        }
+        let member: MemberDesc = try!(json_from_reader(resp));
+        Ok(member.access_level >= DEVELOPER_ACCESS)
+    }
+
+    fn post_note(
+        &self,
+        repo: &Repo,
+        pr: &Pr,
+        body: String,
+    ) -> Result<(), RequestError> {
+        let path = format!(
+            "/projects/{}/merge_requests/{}/notes",
+            repo.api_id(),
+            pr,
+        );
+        let body = try!(serde_json::to_string(&PostNoteDesc{
+            body: body,
+        }));
+        let resp = try!(self.api.send(Method::Post, &path, Some(&body)));
+        if !resp.status.is_success() {
+            return Err(RequestError::HttpStatus(resp.status));
+        }
+        Ok(())
+    }
+
+    fn post_status(
+        &self,
+        repo: &Repo,
+        commit: &Commit,
+        status_body: &PostStatusDesc,
+    ) -> Result<(), RequestError> {
+        let path = format!(
+            "/projects/{}/statuses/{}",
+            repo.api_id(),
+            commit,
+        );
+        let body = try!(serde_json::to_string(status_body));
+        let resp = try!(self.api.send(Method::Post, &path, Some(&body)));
+        if !resp.status.is_success() {
+            return Err(RequestError::HttpStatus(resp.status));
+        }
+        Ok(())
+    }
+}
+
+fn merge_message(mr: &MergeRequestDesc, user: &str) -> String {
+    format!(
+        "{}\n\nMerge !{} r=@{}\n{}\n\n{}",
+        mr.title,
+        mr.iid,
+        user,
+        iter::repeat('_').take(72).collect::<String>(),
+        mr.description.as_ref().map(|x| &x[..]).unwrap_or(""),
+    )
+}
+
+/// What GitLab calls each commit status state.
+fn state_name(state: StatusState) -> &'static str {
+    match state {
+        StatusState::Pending => "pending",
+        StatusState::Running => "running",
+        StatusState::Success => "success",
+        StatusState::Failure | StatusState::Error => "failed",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::from_str as json_from_str;
+    use super::{MergeRequestHookDesc, NoteHookDesc, state_name};
+    use ui::{Status, render_comment, render_status};
+    use vcs::Commit;
+
+    #[test]
+    fn test_parse_mr_hook() {
+        let desc: MergeRequestHookDesc = json_from_str(r#"{
+            "object_kind": "merge_request",
+            "user": {"id": 1, "name": "Administrator", "username": "root"},
+            "project": {"id": 1, "path_with_namespace": "group/project"},
+            "object_attributes": {
+                "iid": 4,
+                "title": "Fix it",
+                "description": null,
+                "state": "opened",
+                "target_branch": "master",
+                "url": "https://gitlab.example.com/g/p/merge_requests/4",
+                "last_commit": {"id": "da1560886d4f094c3e6c9ef40349f7d3"},
+                "action": "update",
+                "work_in_progress": true
+            },
+            "changes": {
+                "target_branch": {"previous": "release", "current": "master"}
+            }
+        }"#).unwrap();
+        let mr = &desc.object_attributes;
+        assert_eq!(desc.project.path_with_namespace, "group/project");
+        assert_eq!(mr.iid, 4);
+        assert_eq!(mr.action.as_ref().unwrap(), "update");
+        assert_eq!(mr.draft.or(mr.work_in_progress), Some(true));
+        let retarget = desc.changes.unwrap().target_branch.unwrap();
+        assert_eq!(retarget.previous, "release");
+    }
+
+    #[test]
+    fn test_parse_note_hook() {
+        let desc: NoteHookDesc = json_from_str(r#"{
+            "object_kind": "note",
+            "user": {"id": 2, "name": "Reviewer", "username": "reviewer"},
+            "project": {"id": 1, "path_with_namespace": "group/project"},
+            "object_attributes": {
+                "note": "@aelita r+",
+                "noteable_type": "MergeRequest"
+            },
+            "merge_request": {
+                "iid": 4,
+                "title": "Fix it",
+                "description": "It was broken",
+                "state": "opened",
+                "target_branch": "master",
+                "url": "https://gitlab.example.com/g/p/merge_requests/4"
+            }
+        }"#).unwrap();
+        assert_eq!(desc.user.id, 2);
+        assert_eq!(desc.object_attributes.noteable_type, "MergeRequest");
+        assert!(desc.merge_request.unwrap().last_commit.is_none());
+    }
+
+    #[test]
+    fn test_state_name() {
+        let pull = Commit::from(
+            "da1560886d4f094c3e6c9ef40349f7d38b5d27d7".to_owned()
+        );
+        let merge = Commit::from(
+            "3e6c9ef40349f7d38b5d27d7da1560886d4f094c".to_owned()
+        );
+        let status = Status::Testing(pull.clone(), merge.clone(), None);
+        let state = render_status(&status).unwrap().state;
+        assert_eq!(state_name(state), "running");
+        assert!(render_comment(&status, "MR", "Target").is_none());
+        let status = Status::Unmoveable(pull.clone(), merge);
+        assert_eq!(state_name(render_status(&status).unwrap().state), "failed");
+        let status = Status::Unmergeable(pull);
+        assert_eq!(render_comment(&status, "MR", "Target").unwrap(),
+            ":x: Merge conflict!");
+    }
+}
@@ -5,27 +5,50 @@
     control.
  */
 
+pub mod gitea;
 pub mod github;
+pub mod gitlab;
 mod comments;
+mod forge;
 
+use ci::FailureSummary;
 use config::PipelinesConfig;
+use db::CiStateEntry;
 use hyper::Url;
 use pipeline::{GetPipelineId, PipelineId};
 use std::fmt::{self, Display};
+use std::sync::mpsc::Sender;
 use vcs::{Commit, Remote};
+use vcs::git::ToShortString;
 
 #[derive(Clone, Debug)]
 pub enum Message {
-    SendResult(PipelineId, Pr, Status)
+    SendResult(PipelineId, Pr, Status),
+    SendCiResult(PipelineId, Pr, CiResult),
+    /// The PR's place in the queue, counting from 1
+    SendQueuePosition(PipelineId, Pr, usize),
 }
 
 #[derive(Clone, Debug)]
 pub enum Event {
     Approved(PipelineId, Pr, Option<Commit>, String),
     Canceled(PipelineId, Pr),
-    Opened(PipelineId, Pr, Commit, String, Url),
-    Changed(PipelineId, Pr, Commit, String, Url),
+    /// The last field says whether the PR is a draft, and so cannot be
+    /// approved in this pipeline
+    Opened(PipelineId, Pr, Commit, String, Url, bool),
+    Changed(PipelineId, Pr, Commit, String, Url, bool),
     Closed(PipelineId, Pr),
+    /// The PR's base branch was changed, so it no longer belongs in this
+    /// pipeline
+    Retargeted(PipelineId, Pr),
+    /// Move a queued PR ahead of (or behind) the others;
+    /// higher priorities go first
+    Prioritized(PipelineId, Pr, i32),
+    /// Stop (true) or resume (false) starting builds from the queue
+    Paused(PipelineId, bool),
+    /// Something was pushed to the branch PRs go into, by the bot or
+    /// anyone else, so the settings the repo keeps there may have changed
+    Pushed(PipelineId),
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -33,16 +56,28 @@ pub enum Status {
     Approved(Commit),
     Invalidated,
     NoCommit,
+    /// Refused approval, or pulled from the queue, because it is a draft
+    Draft,
+    /// Pulled from the queue because the PR's base branch was changed
+    Retargeted,
     Unmergeable(Commit),
     StartingBuild(Commit, Commit),
     Testing(Commit, Commit, Option<Url>),
     Success(Commit, Commit, Option<Url>),
-    Failure(Commit, Commit, Option<Url>),
+    Failure(Commit, Commit, Option<Url>, FailureSummary),
     Unmoveable(Commit, Commit),
     Completed(Commit, Commit),
 }
 
+/// The state of one of the CI jobs that gate a build
 #[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CiResult {
+    pub name: String,
+    pub pull_commit: Commit,
+    pub state: CiStateEntry,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct Pr(String);
 
 impl Display for Pr {
@@ -73,14 +108,534 @@ impl Pr {
     }
 }
 
+/// The pipelines for pull requests into one of a repo's target branches.
+#[derive(Clone, Debug)]
+pub struct RepoPipelines {
+    /// The branch PRs have to target, or `None` to take PRs into any branch
+    pub branch: Option<String>,
+    pub pipeline_id: PipelineId,
+    pub try_pipeline_id: Option<PipelineId>,
+    /// The branches merges are built on. PRs from them are ignored.
+    pub staging_branches: Vec<String>,
+}
+
+/// Which of a target branch's pipelines a pipeline is.
+#[derive(Clone, Copy, Debug)]
+pub enum PipelineType {
+    Stage,
+    Try,
+}
+
+/// Front-ends that keep more settings per target branch than
+/// `RepoPipelines` does implement this to share the code that uses them.
+pub trait TargetPipelines {
+    fn branch(&self) -> Option<&str>;
+    fn pipeline_id(&self) -> PipelineId;
+    fn try_pipeline_id(&self) -> Option<PipelineId>;
+}
+
+impl TargetPipelines for RepoPipelines {
+    fn branch(&self) -> Option<&str> {
+        self.branch.as_ref().map(|b| &b[..])
+    }
+    fn pipeline_id(&self) -> PipelineId {
+        self.pipeline_id
+    }
+    fn try_pipeline_id(&self) -> Option<PipelineId> {
+        self.try_pipeline_id
+    }
+}
+
+/// The pipelines for PRs into `branch`: the ones for that branch, or else
+/// the ones that take PRs into any branch, wherever they are in the list.
+pub fn pipelines_for_branch<P: TargetPipelines>(
+    all_pipelines: Vec<P>,
+    branch: &str,
+) -> Option<P> {
+    match position_for_branch(&all_pipelines, branch) {
+        Some(i) => all_pipelines.into_iter().nth(i),
+        None => None,
+    }
+}
+
+fn position_for_branch<P: TargetPipelines>(
+    all_pipelines: &[P],
+    branch: &str,
+) -> Option<usize> {
+    all_pipelines.iter().position(|p| p.branch() == Some(branch))
+        .or_else(|| all_pipelines.iter().position(|p| p.branch().is_none()))
+}
+
+/// Is `branch` one that merges are built on? PRs from it are our own
+/// doing, or would be undone by the next build.
+pub fn is_staging_branch(all_pipelines: &[RepoPipelines], branch: &str)
+        -> bool {
+    all_pipelines.iter()
+        .any(|p| p.staging_branches.iter().any(|b| b == branch))
+}
+
+/// Pull a PR out of the pipelines for its old base branch, if it
+/// doesn't belong in them anymore. It shows up in the new ones as
+/// an ordinary edit, and has to be approved again there.
+pub fn handle_pr_retarget<P: TargetPipelines>(
+    send_event: &Sender<Event>,
+    all_pipelines: &[P],
+    pr: &Pr,
+    old_base: &str,
+    new_base: &str,
+) {
+    let old = position_for_branch(all_pipelines, old_base)
+        .map(|i| &all_pipelines[i]);
+    let new = position_for_branch(all_pipelines, new_base)
+        .map(|i| &all_pipelines[i]);
+    let old = match (old, new) {
+        (Some(old), Some(new)) if old.pipeline_id() == new.pipeline_id() =>
+            return,
+        (Some(old), _) => old,
+        (None, _) => return,
+    };
+    info!("PR {} moved from {} to {}", pr, old_base, new_base);
+    let pipeline_ids = Some(old.pipeline_id()).into_iter()
+        .chain(old.try_pipeline_id());
+    for pipeline_id in pipeline_ids {
+        send_event.send(Event::Retargeted(pipeline_id, pr.clone()))
+            .expect("Pipeline to be there");
+    }
+}
+
+/// Tell the pipelines for a branch that it was pushed to. `git_ref` is
+/// the full name of the ref; tags are ignored.
+pub fn handle_push<P: TargetPipelines>(
+    send_event: &Sender<Event>,
+    all_pipelines: Vec<P>,
+    git_ref: &str,
+) {
+    let prefix = "refs/heads/";
+    if !git_ref.starts_with(prefix) {
+        return;
+    }
+    let branch = &git_ref[prefix.len()..];
+    if let Some(pipelines) = pipelines_for_branch(all_pipelines, branch) {
+        info!("Got push to {}", branch);
+        send_event.send(Event::Pushed(pipelines.pipeline_id()))
+            .expect("Pipeline to be there");
+    }
+}
+
+/// The note to post for a command on a PR into a branch no pipeline
+/// takes, listing the branches that do.
+pub fn render_unmanaged_branch<P: TargetPipelines>(
+    all_pipelines: &[P],
+    pr: &str,
+    base: &str,
+) -> String {
+    let branches = all_pipelines.iter()
+        .filter_map(|p| p.branch())
+        .map(|branch| format!("`{}`", branch))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        ":information_source: This {} targets `{}`, \
+         which I don't merge into. I can merge into: {}",
+        pr,
+        base,
+        branches,
+    )
+}
+
+/// The comment to post about a status, if it's worth one.
+/// `pr` is what the front-end calls pull requests, like "MR",
+/// and `base` what it calls the branch they go into, like "Target".
+pub fn render_comment(status: &Status, pr: &str, base: &str)
+        -> Option<String> {
+    render_comment_line(status, pr, base).map(|mut body| {
+        if let Status::Failure(_, _, _, ref summary) = *status {
+            body.push_str(&render_failure_summary(summary));
+        }
+        body
+    })
+}
+
+/// The first line of the comment about a status, without the failure
+/// summary, for front-ends that keep a history of them.
+pub fn render_comment_line(status: &Status, pr: &str, base: &str)
+        -> Option<String> {
+    Some(match *status {
+        Status::Approved(_) |
+        Status::StartingBuild(_, _) |
+        Status::Testing(_, _, _) |
+        Status::Completed(_, _) => return None,
+        Status::Success(_, _, Some(ref url)) =>
+            format!(":+1: [Build succeeded]({})", url),
+        Status::Success(_, _, None) => ":+1: Build succeeded".to_owned(),
+        Status::Failure(_, _, Some(ref url), _) =>
+            format!(":-1: [Build failed]({})", url),
+        Status::Failure(_, _, None, _) => ":-1: Build failed".to_owned(),
+        Status::Unmergeable(_) => ":x: Merge conflict!".to_owned(),
+        Status::Unmoveable(_, _) =>
+            ":scream: Internal error while fast-forward master".to_owned(),
+        Status::Invalidated => ":no_good: New commits added".to_owned(),
+        Status::NoCommit =>
+            format!(":scream: Internal error: no commit found for {}", pr),
+        Status::Draft => format!(
+            ":pencil2: This {} is a draft; mark it ready for review first",
+            pr,
+        ),
+        Status::Retargeted => format!(
+            ":twisted_rightwards_arrows: {} branch changed; \
+             it needs to be approved again",
+            base,
+        ),
+    })
+}
+
+/// The most failed tests to list in a comment.
+const MAX_FAILED_TESTS: usize = 20;
+
+/// The most log to quote in a comment, in bytes.
+const MAX_LOG_LEN: usize = 4000;
+
+/// Render the failure summary as collapsed sections to go after the
+/// "Build failed" line of a comment, or nothing if there is no summary.
+pub fn render_failure_summary(summary: &FailureSummary) -> String {
+    let mut result = String::new();
+    if !summary.tests.is_empty() {
+        result.push_str(&format!(
+            "\n\n<details><summary>Failed tests ({})</summary>\n\n",
+            summary.tests.len(),
+        ));
+        for test in summary.tests.iter().take(MAX_FAILED_TESTS) {
+            result.push_str(&format!("* `{}`\n", test.replace('`', "'")));
+        }
+        if summary.tests.len() > MAX_FAILED_TESTS {
+            result.push_str(&format!(
+                "* ...and {} more\n",
+                summary.tests.len() - MAX_FAILED_TESTS,
+            ));
+        }
+        result.push_str("\n</details>");
+    }
+    if let Some(ref log) = summary.log {
+        // Keep the end of the log, where the error usually is.
+        let mut start = log.len().saturating_sub(MAX_LOG_LEN);
+        while !log.is_char_boundary(start) {
+            start += 1;
+        }
+        let log = log[start..].replace("```", "'''");
+        result.push_str("\n\n<details><summary>Build log</summary>\n\n");
+        if start != 0 {
+            result.push_str("(truncated)\n\n");
+        }
+        result.push_str("```\n");
+        result.push_str(&log);
+        result.push_str("\n```\n\n</details>");
+    }
+    result
+}
+
+/// The state of a commit status. Each front-end has its own names
+/// for them, and some don't tell all of them apart.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StatusState {
+    Pending,
+    Running,
+    Success,
+    Failure,
+    Error,
+}
+
+impl StatusState {
+    /// What GitHub calls the state; Gitea uses the same names.
+    /// Neither has a running state.
+    pub fn github_name(self) -> &'static str {
+        match self {
+            StatusState::Pending | StatusState::Running => "pending",
+            StatusState::Success => "success",
+            StatusState::Failure => "failure",
+            StatusState::Error => "error",
+        }
+    }
+}
+
+/// The commit status to set for a status, with the commits to set it on:
+/// the PR's own commit, and the merge commit being tested, if any.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CommitStatus<'a> {
+    pub pull_commit: &'a Commit,
+    pub merge_commit: Option<&'a Commit>,
+    pub state: StatusState,
+    pub url: Option<&'a Url>,
+    pub description: String,
+}
+
+pub fn render_status(status: &Status) -> Option<CommitStatus> {
+    let (pull_commit, merge_commit, state, url, description) = match *status {
+        Status::Approved(ref pull_commit) => (
+            pull_commit,
+            None,
+            StatusState::Pending,
+            None,
+            format!("Approved {}", pull_commit),
+        ),
+        Status::StartingBuild(ref pull_commit, ref merge_commit) => (
+            pull_commit,
+            Some(merge_commit),
+            StatusState::Running,
+            None,
+            testing_description(pull_commit, merge_commit),
+        ),
+        Status::Testing(ref pull_commit, ref merge_commit, ref url) => (
+            pull_commit,
+            Some(merge_commit),
+            StatusState::Running,
+            url.as_ref(),
+            testing_description(pull_commit, merge_commit),
+        ),
+        Status::Success(ref pull_commit, ref merge_commit, ref url) => (
+            pull_commit,
+            Some(merge_commit),
+            StatusState::Success,
+            url.as_ref(),
+            "Tests passed".to_owned(),
+        ),
+        Status::Failure(ref pull_commit, ref merge_commit, ref url, _) => (
+            pull_commit,
+            Some(merge_commit),
+            StatusState::Failure,
+            url.as_ref(),
+            "Tests failed".to_owned(),
+        ),
+        Status::Unmergeable(ref pull_commit) => (
+            pull_commit,
+            None,
+            StatusState::Failure,
+            None,
+            "Merge failed".to_owned(),
+        ),
+        Status::Unmoveable(ref pull_commit, ref merge_commit) => (
+            pull_commit,
+            Some(merge_commit),
+            StatusState::Error,
+            None,
+            "Merge failed".to_owned(),
+        ),
+        Status::Invalidated |
+        Status::NoCommit |
+        Status::Draft |
+        Status::Retargeted |
+        Status::Completed(_, _) => return None,
+    };
+    Some(CommitStatus {
+        pull_commit: pull_commit,
+        merge_commit: merge_commit,
+        state: state,
+        url: url,
+        description: description,
+    })
+}
+
+fn testing_description(pull_commit: &Commit, merge_commit: &Commit) -> String {
+    format!(
+        "Testing {} with merge commit {}",
+        &pull_commit.to_short_string()[..],
+        &merge_commit.to_short_string()[..],
+    )
+}
+
 impl GetPipelineId for Event {
-    fn pipeline_id<C: PipelinesConfig + ?Sized>(&self, _: &C) -> PipelineId {
-        match *self {
+    fn pipeline_id<C: PipelinesConfig + ?Sized>(&self, _: &C)
+            -> Option<PipelineId> {
+        Some(match *self {
             Event::Approved(i, _, _, _) => i,
             Event::Canceled(i, _) => i,
-            Event::Opened(i, _, _, _, _) => i,
-            Event::Changed(i, _, _, _, _) => i,
+            Event::Opened(i, _, _, _, _, _) => i,
+            Event::Changed(i, _, _, _, _, _) => i,
             Event::Closed(i, _) => i,
+            Event::Retargeted(i, _) => i,
+            Event::Prioritized(i, _, _) => i,
+            Event::Paused(i, _) => i,
+            Event::Pushed(i) => i,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ci::FailureSummary;
+    use pipeline::PipelineId;
+    use std::iter;
+    use std::sync::mpsc::channel;
+    use super::{Event, Pr, RepoPipelines, Status, StatusState};
+    use super::{MAX_FAILED_TESTS, handle_pr_retarget, handle_push};
+    use super::{pipelines_for_branch, render_comment};
+    use super::{render_failure_summary, render_status};
+    use vcs::Commit;
+
+    #[test]
+    fn test_pipelines_for_branch() {
+        let all = vec![
+            RepoPipelines{
+                branch: Some("master".to_owned()),
+                pipeline_id: PipelineId(0),
+                try_pipeline_id: None,
+                staging_branches: vec!["staging".to_owned()],
+            },
+            RepoPipelines{
+                branch: Some("release".to_owned()),
+                pipeline_id: PipelineId(1),
+                try_pipeline_id: None,
+                staging_branches: vec!["staging-release".to_owned()],
+            },
+        ];
+        let found = pipelines_for_branch(all.clone(), "release");
+        assert_eq!(found.map(|p| p.pipeline_id), Some(PipelineId(1)));
+        assert!(pipelines_for_branch(all, "gh-pages").is_none());
+    }
+
+    #[test]
+    fn any_branch_pipelines_come_last() {
+        // An upgraded deployment lists its old any-branch pipeline first.
+        let all = vec![
+            RepoPipelines{
+                branch: None,
+                pipeline_id: PipelineId(0),
+                try_pipeline_id: None,
+                staging_branches: vec!["staging".to_owned()],
+            },
+            RepoPipelines{
+                branch: Some("release".to_owned()),
+                pipeline_id: PipelineId(1),
+                try_pipeline_id: Some(PipelineId(2)),
+                staging_branches: vec!["staging-release".to_owned()],
+            },
+        ];
+        let found = pipelines_for_branch(all.clone(), "release");
+        assert_eq!(found.map(|p| p.pipeline_id), Some(PipelineId(1)));
+        let found = pipelines_for_branch(all.clone(), "gh-pages");
+        assert_eq!(found.map(|p| p.pipeline_id), Some(PipelineId(0)));
+        let (send_event, recv_event) = channel();
+        let pr = Pr::from("1".to_owned());
+        handle_pr_retarget(&send_event, &all, &pr, "master", "gh-pages");
+        handle_pr_retarget(&send_event, &all, &pr, "release", "master");
+        drop(send_event);
+        let retargeted: Vec<PipelineId> = recv_event.iter().map(|e| match e {
+            Event::Retargeted(pipeline_id, _) => pipeline_id,
+            e => panic!("unexpected event {:?}", e),
+        }).collect();
+        assert_eq!(retargeted, vec![PipelineId(1), PipelineId(2)]);
+    }
+
+    #[test]
+    fn test_handle_push() {
+        let all = vec![
+            RepoPipelines{
+                branch: Some("master".to_owned()),
+                pipeline_id: PipelineId(0),
+                try_pipeline_id: Some(PipelineId(1)),
+                staging_branches: vec!["staging".to_owned()],
+            },
+        ];
+        let (send_event, recv_event) = channel();
+        handle_push(&send_event, all.clone(), "refs/tags/master");
+        handle_push(&send_event, all.clone(), "refs/heads/staging");
+        handle_push(&send_event, all, "refs/heads/master");
+        drop(send_event);
+        let events: Vec<Event> = recv_event.iter().collect();
+        assert_eq!(events.len(), 1);
+        match events[0] {
+            Event::Pushed(PipelineId(0)) => {}
+            ref e => panic!("unexpected event {:?}", e),
         }
     }
+
+    #[test]
+    fn test_render_comment() {
+        let status = Status::Approved(Commit::from("a".to_owned()));
+        assert!(render_comment(&status, "MR", "Target").is_none());
+        let status = Status::Unmergeable(Commit::from("a".to_owned()));
+        assert_eq!(render_comment(&status, "MR", "Target").unwrap(),
+            ":x: Merge conflict!");
+        assert_eq!(render_comment(&Status::Retargeted, "MR", "Target")
+            .unwrap(),
+            ":twisted_rightwards_arrows: Target branch changed; \
+             it needs to be approved again");
+    }
+    #[test]
+    fn test_render_comment_failure() {
+        let commit = Commit::from("a".to_owned());
+        let status = Status::Failure(
+            commit.clone(),
+            commit,
+            None,
+            FailureSummary{
+                tests: vec!["foo".to_owned()],
+                log: None,
+            },
+        );
+        let body = render_comment(&status, "PR", "Base").unwrap();
+        assert!(body.starts_with(":-1: Build failed\n\n<details>"));
+        assert!(body.contains("* `foo`"));
+        assert_eq!(render_comment(&Status::Draft, "PR", "Base").unwrap(),
+            ":pencil2: This PR is a draft; mark it ready for review first");
+    }
+
+    #[test]
+    fn test_render_failure_summary_empty() {
+        assert_eq!(render_failure_summary(&FailureSummary::default()), "");
+    }
+
+    #[test]
+    fn test_render_failure_summary_tests() {
+        let summary = FailureSummary{
+            tests: (0..MAX_FAILED_TESTS + 2)
+                .map(|i| format!("test_{}", i))
+                .collect(),
+            log: None,
+        };
+        let rendered = render_failure_summary(&summary);
+        assert!(rendered.contains("Failed tests (22)"));
+        assert!(rendered.contains("* `test_19`\n"));
+        assert!(!rendered.contains("test_20"));
+        assert!(rendered.contains("...and 2 more"));
+        assert!(rendered.ends_with("</details>"));
+    }
+
+    #[test]
+    fn test_render_failure_summary_log() {
+        let summary = FailureSummary{
+            tests: vec![],
+            log: Some(format!(
+                "{}\nerror: ```boom```",
+                iter::repeat('x').take(5000).collect::<String>(),
+            )),
+        };
+        let rendered = render_failure_summary(&summary);
+        assert!(rendered.contains("(truncated)"));
+        assert!(rendered.contains("error: '''boom'''\n```"));
+        assert!(rendered.len() < 4200);
+    }
+
+    #[test]
+    fn test_render_status() {
+        let pull = Commit::from(
+            "da1560886d4f094c3e6c9ef40349f7d38b5d27d7".to_owned()
+        );
+        let merge = Commit::from(
+            "3e6c9ef40349f7d38b5d27d7da1560886d4f094c".to_owned()
+        );
+        let status = Status::Testing(pull.clone(), merge.clone(), None);
+        let rendered = render_status(&status).unwrap();
+        assert_eq!(rendered.pull_commit, &pull);
+        assert_eq!(rendered.merge_commit, Some(&merge));
+        assert_eq!(rendered.state, StatusState::Running);
+        // GitHub and Gitea have no running state.
+        assert_eq!(rendered.state.github_name(), "pending");
+        assert_eq!(
+            rendered.description,
+            "Testing da156 with merge commit 3e6c9",
+        );
+        let status = Status::Unmoveable(pull, merge);
+        assert_eq!(render_status(&status).unwrap().state, StatusState::Error);
+        assert!(render_status(&Status::Invalidated).is_none());
+    }
 }
@@ -1,10 +1,12 @@
 // This file is released under the same terms as Rust itself.
 
-use openssl::crypto::hash::Type;
+use openssl::crypto::hash::{hash, Type};
 use openssl::crypto::hmac::hmac;
 use openssl::crypto::memcmp::eq as secure_eq;
+use openssl::crypto::pkey::PKey;
 
 pub const SHA1_LEN: usize = 40;
+pub const SHA256_LEN: usize = 64;
 
 pub fn generate_sha1_hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
 	hmac(Type::SHA1, key, data)
@@ -13,4 +15,108 @@ pub fn generate_sha1_hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
 pub fn verify_sha1_hmac(key: &[u8], data: &[u8], signature: &[u8]) -> bool {
 	let expected_signature = hmac(Type::SHA1, key, data);
 	secure_eq(&expected_signature, signature)
-}
\ No newline at end of file
+}
+
+pub fn verify_sha256_hmac(key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+	let expected_signature = hmac(Type::SHA256, key, data);
+	secure_eq(&expected_signature, signature)
+}
+
+/// Compare a shared secret in constant time, as when a service sends its
+/// webhook secret as-is rather than signing with it.
+pub fn verify_token(expected: &[u8], token: &[u8]) -> bool {
+	// The comparison itself needs the lengths to match.
+	expected.len() == token.len() && secure_eq(expected, token)
+}
+
+/// Sign data with an RSA private key and SHA-256,
+/// as JSON Web Tokens call "RS256".
+pub fn sign_rs256(key: &PKey, data: &[u8]) -> Vec<u8> {
+	// This signs a digest, not the data itself.
+	key.sign_with_hash(&hash(Type::SHA256, data), Type::SHA256)
+}
+
+/// The URL-safe base64 encoding, without padding, used by JSON Web Tokens.
+pub fn base64url(data: &[u8]) -> String {
+	const ALPHABET: &'static [u8] =
+		b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+	let mut result = String::with_capacity((data.len() + 2) / 3 * 4);
+	for chunk in data.chunks(3) {
+		let b = [
+			chunk[0],
+			*chunk.get(1).unwrap_or(&0),
+			*chunk.get(2).unwrap_or(&0),
+		];
+		let n = ((b[0] as usize) << 16) | ((b[1] as usize) << 8) | b[2] as usize;
+		let chars = [
+			ALPHABET[(n >> 18) & 63],
+			ALPHABET[(n >> 12) & 63],
+			ALPHABET[(n >> 6) & 63],
+			ALPHABET[n & 63],
+		];
+		for &c in &chars[..chunk.len() + 1] {
+			result.push(c as char);
+		}
+	}
+	result
+}
+
+/// Decode standard base64, as GitHub sends file contents.
+/// Line breaks and padding are skipped.
+pub fn base64_decode(text: &str) -> Option<Vec<u8>> {
+	let mut result = Vec::with_capacity(text.len() / 4 * 3);
+	let mut n = 0u32;
+	let mut bits = 0;
+	for c in text.bytes() {
+		let value = match c {
+			b'A'...b'Z' => c - b'A',
+			b'a'...b'z' => c - b'a' + 26,
+			b'0'...b'9' => c - b'0' + 52,
+			b'+' => 62,
+			b'/' => 63,
+			b'=' | b'\n' | b'\r' => continue,
+			_ => return None,
+		};
+		n = (n << 6) | value as u32;
+		bits += 6;
+		if bits >= 8 {
+			bits -= 8;
+			result.push((n >> bits) as u8);
+			n &= (1 << bits) - 1;
+		}
+	}
+	Some(result)
+}
+
+#[cfg(test)]
+mod test {
+	use super::{base64_decode, base64url, verify_token};
+
+	#[test]
+	fn test_base64url() {
+		assert_eq!(base64url(b""), "");
+		assert_eq!(base64url(b"f"), "Zg");
+		assert_eq!(base64url(b"fo"), "Zm8");
+		assert_eq!(base64url(b"foo"), "Zm9v");
+		assert_eq!(base64url(b"foob"), "Zm9vYg");
+		assert_eq!(base64url(&[0xfb, 0xff]), "-_8");
+	}
+
+	#[test]
+	fn test_base64_decode() {
+		assert_eq!(base64_decode(""), Some(Vec::new()));
+		assert_eq!(base64_decode("Zg=="), Some(b"f".to_vec()));
+		assert_eq!(base64_decode("Zm8="), Some(b"fo".to_vec()));
+		assert_eq!(base64_decode("Zm9v\nYmFy\n"), Some(b"foobar".to_vec()));
+		assert_eq!(base64_decode("+/8="), Some(vec![0xfb, 0xff]));
+		assert_eq!(base64_decode("Zm9v!"), None);
+	}
+
+	#[test]
+	fn test_verify_token() {
+		assert!(verify_token(b"secret", b"secret"));
+		assert!(!verify_token(b"secret", b"secreT"));
+		assert!(!verify_token(b"secret", b"secre"));
+		assert!(!verify_token(b"secret", b""));
+	}
+}
@@ -0,0 +1,69 @@
+// This file is released under the same terms as Rust itself.
+
+//! Talking to the Gitea API, which the pull request front-end and the
+//! repository backend share. Forgejo speaks the same API.
+
+use hyper;
+use hyper::Client;
+use hyper::client::Response;
+use hyper::header::{Authorization, ContentType, UserAgent};
+use hyper::method::Method;
+use util::USER_AGENT;
+
+pub struct Api {
+    /// Where Gitea is, like `https://gitea.example.com`
+    host: String,
+    token: String,
+    client: Client,
+}
+
+impl Api {
+    pub fn new(host: String, token: String) -> Api {
+        Api {
+            host: host,
+            token: token,
+            client: Client::new(),
+        }
+    }
+
+    /// Make a request, with a JSON body if given.
+    /// The path is relative to the API root, like `/repos/owner/repo`.
+    pub fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&str>,
+    ) -> Result<Response, hyper::Error> {
+        let url = format!(
+            "{}/api/v1{}",
+            self.host.trim_right_matches('/'),
+            path,
+        );
+        let mut req = self.client.request(method, &url[..])
+            .header(UserAgent(USER_AGENT.to_owned()))
+            .header(Authorization(format!("token {}", self.token)));
+        if let Some(body) = body {
+            req = req.header(ContentType::json()).body(body);
+        }
+        req.send()
+    }
+
+    /// The address to fetch and push a repository over HTTPS,
+    /// signed in with the token.
+    pub fn git_url(&self, owner: &str, repo: &str) -> String {
+        let host = self.host.trim_right_matches('/');
+        let (scheme, host) = match host.find("://") {
+            Some(i) => (&host[..i + 3], &host[i + 3..]),
+            None => ("https://", host),
+        };
+        // Gitea takes a token as the user name, with this as the password.
+        format!(
+            "{}{}:x-oauth-basic@{}/{}/{}.git",
+            scheme,
+            self.token,
+            host,
+            owner,
+            repo,
+        )
+    }
+}
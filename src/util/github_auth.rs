@@ -0,0 +1,379 @@
+// This file is released under the same terms as Rust itself.
+
+//! Authenticating to the GitHub API.
+//!
+//! Either use a personal access token as-is,
+//! or act as a GitHub App: sign a JWT with the app's private key,
+//! and trade it for a token for the installation that covers a repo.
+
+use hyper;
+use hyper::header::{qitem, Accept, Authorization, Headers, UserAgent};
+use hyper::method::Method;
+use hyper::mime::Mime;
+use hyper::status::StatusCode;
+use openssl::crypto::pkey::PKey;
+use openssl::ssl::error::SslError;
+use rest::{self, authorization};
+use serde::Deserialize;
+use serde_json::{self, from_reader as json_from_reader};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::str;
+use std::sync::Mutex;
+use util::{self, crypto, REDACTED, USER_AGENT};
+use util::github_rate_limit::RateLimit;
+
+/// Installation tokens expire after an hour; replace them before then.
+const TOKEN_LIFETIME_SEC: i64 = 50 * 60;
+
+/// How long to believe the app isn't installed on a repo
+/// before looking through the installations again.
+const NOT_INSTALLED_TTL_SEC: i64 = 5 * 60;
+
+pub type Client = rest::Client<rest::Authorization<authorization::Token>>;
+
+#[derive(Clone)]
+pub enum Credentials {
+    /// A personal access token.
+    Token(String),
+    /// A GitHub App's ID and its PEM-encoded private key.
+    App(u64, Vec<u8>),
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Credentials::Token(_) =>
+                f.debug_tuple("Token").field(&REDACTED).finish(),
+            Credentials::App(app_id, _) =>
+                f.debug_tuple("App").field(&app_id).field(&REDACTED).finish(),
+        }
+    }
+}
+
+pub struct Auth {
+    host: String,
+    credentials: Credentials,
+    http: hyper::Client,
+    installations: Mutex<Installations>,
+    rate_limit: RateLimit,
+}
+
+#[derive(Default)]
+struct Installations {
+    /// Which installation covers each repo, by owner and name.
+    by_repo: HashMap<(String, String), u64>,
+    /// Each installation's token, and when we got it.
+    tokens: HashMap<u64, (String, i64)>,
+    /// Repos that no installation covered, and when we last looked.
+    not_installed: HashMap<(String, String), i64>,
+}
+
+impl Auth {
+    /// The name identifies this client's rate limit budget in the metrics.
+    pub fn new(
+        name: &'static str,
+        host: String,
+        credentials: Credentials,
+    ) -> Auth {
+        Auth {
+            host: host,
+            credentials: credentials,
+            http: hyper::Client::new(),
+            installations: Mutex::new(Installations::default()),
+            rate_limit: RateLimit::new(name),
+        }
+    }
+
+    /// The rate limit budget that requests made with these credentials
+    /// should go through.
+    pub fn rate_limit(&self) -> &RateLimit {
+        &self.rate_limit
+    }
+
+    /// Get an API client that can act on the given repo.
+    pub fn client(&self, owner: &str, repo: &str) -> Result<Client, Error> {
+        let token = try!(self.token(owner, repo));
+        Ok(rest::Client::new(USER_AGENT.to_owned())
+            .base(&self.host)
+            .authorization(rest::Authorization(authorization::Token{
+                token: token,
+            })))
+    }
+
+    /// Get a token that can act on the given repo.
+    pub fn token(&self, owner: &str, repo: &str) -> Result<String, Error> {
+        let (app_id, key) = match self.credentials {
+            Credentials::Token(ref token) => return Ok(token.clone()),
+            Credentials::App(app_id, ref key) => (app_id, key),
+        };
+        let repo_key = (owner.to_owned(), repo.to_owned());
+        let now = util::now_secs();
+        // Don't hold the lock while talking to GitHub;
+        // every other repo's requests would wait on it.
+        let known = {
+            let installations = self.installations.lock().unwrap();
+            match installations.by_repo.get(&repo_key) {
+                Some(&id) => match installations.tokens.get(&id) {
+                    Some(&(ref token, issued))
+                            if now - issued < TOKEN_LIFETIME_SEC =>
+                        return Ok(token.clone()),
+                    _ => Some(id),
+                },
+                None => match installations.not_installed.get(&repo_key) {
+                    Some(&checked) if now - checked < NOT_INSTALLED_TTL_SEC =>
+                        return Err(Error::NotInstalled(repo_key.0, repo_key.1)),
+                    _ => None,
+                },
+            }
+        };
+        let id = match known {
+            Some(id) => id,
+            None => {
+                // The app may have been installed somewhere new
+                // since we looked.
+                let discovered = try!(self.discover(app_id, key));
+                let mut guard = self.installations.lock().unwrap();
+                let installations = &mut *guard;
+                installations.by_repo = discovered.by_repo;
+                installations.tokens.extend(discovered.tokens);
+                let token = installations.by_repo.get(&repo_key)
+                    .and_then(|id| installations.tokens.get(id))
+                    .map(|&(ref token, _)| token.clone());
+                if token.is_none() {
+                    installations.not_installed.insert(repo_key.clone(), now);
+                }
+                return token.ok_or(
+                    Error::NotInstalled(repo_key.0, repo_key.1)
+                );
+            }
+        };
+        let jwt = try!(jwt(app_id, key));
+        let token = try!(self.installation_token(&jwt, id));
+        self.installations.lock().unwrap()
+            .tokens.insert(id, (token.clone(), now));
+        Ok(token)
+    }
+
+    /// Find out which repos each of the app's installations covers,
+    /// and get a token for each installation along the way.
+    fn discover(
+        &self,
+        app_id: u64,
+        key: &[u8],
+    ) -> Result<Installations, Error> {
+        #[derive(Deserialize)]
+        struct AccountDesc {
+            login: String,
+        }
+        #[derive(Deserialize)]
+        struct InstallationDesc {
+            id: u64,
+            account: AccountDesc,
+        }
+        #[derive(Deserialize)]
+        struct RepoDesc {
+            name: String,
+            owner: AccountDesc,
+        }
+        #[derive(Deserialize)]
+        struct ReposDesc {
+            repositories: Vec<RepoDesc>,
+        }
+        let jwt = try!(jwt(app_id, key));
+        let mut list: Vec<InstallationDesc> = Vec::new();
+        let mut next = Some(self.url("/app/installations?per_page=100"));
+        while let Some(url) = next {
+            let (page, next_url): (Vec<InstallationDesc>, _) = try!(
+                self.request_url(Method::Get, &url, format!("Bearer {}", jwt))
+            );
+            list.extend(page);
+            next = next_url;
+        }
+        let mut installations = Installations::default();
+        for installation in list {
+            let token = try!(self.installation_token(&jwt, installation.id));
+            installations.tokens.insert(
+                installation.id,
+                (token.clone(), util::now_secs()),
+            );
+            let mut count = 0;
+            let mut next =
+                Some(self.url("/installation/repositories?per_page=100"));
+            while let Some(url) = next {
+                let (repos, next_url): (ReposDesc, _) = try!(self.request_url(
+                    Method::Get,
+                    &url,
+                    format!("token {}", token),
+                ));
+                count += repos.repositories.len();
+                for repo in repos.repositories {
+                    installations.by_repo.insert(
+                        (repo.owner.login, repo.name),
+                        installation.id,
+                    );
+                }
+                next = next_url;
+            }
+            info!(
+                "App installation {} for {} covers {} repos",
+                installation.id,
+                installation.account.login,
+                count
+            );
+        }
+        Ok(installations)
+    }
+
+    fn installation_token(&self, jwt: &str, id: u64) -> Result<String, Error> {
+        #[derive(Deserialize)]
+        struct TokenDesc {
+            token: String,
+        }
+        let desc: TokenDesc = try!(self.request(
+            Method::Post,
+            &format!("/app/installations/{}/access_tokens", id),
+            format!("Bearer {}", jwt),
+        ));
+        Ok(desc.token)
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.host.trim_right_matches('/'), path)
+    }
+
+    fn request<T: Deserialize>(
+        &self,
+        method: Method,
+        path: &str,
+        authorization: String,
+    ) -> Result<T, Error> {
+        let url = self.url(path);
+        let (result, _) = try!(self.request_url(method, &url, authorization));
+        Ok(result)
+    }
+
+    /// Make a request to a full URL, such as the next page of a list.
+    /// Returns the URL of the page after it, too, if there is one.
+    fn request_url<T: Deserialize>(
+        &self,
+        method: Method,
+        url: &str,
+        authorization: String,
+    ) -> Result<(T, Option<String>), Error> {
+        let mime: Mime = "application/vnd.github.machine-man-preview+json"
+            .parse().expect("hard-coded mimes to be valid");
+        let idempotent = method == Method::Get;
+        let resp = try!(self.rate_limit.send(idempotent, || {
+            self.http.request(method.clone(), url)
+                .header(UserAgent(USER_AGENT.to_owned()))
+                .header(Authorization(authorization.clone()))
+                .header(Accept(vec![qitem(mime.clone())]))
+                .send()
+        }, |resp| resp));
+        if !resp.status.is_success() {
+            return Err(Error::HttpStatus(resp.status));
+        }
+        let next = next_page(&resp.headers);
+        Ok((try!(json_from_reader(resp)), next))
+    }
+}
+
+/// Find the `rel="next"` URL in GitHub's `Link` header.
+fn next_page(headers: &Headers) -> Option<String> {
+    let values = match headers.get_raw("Link") {
+        Some(values) => values,
+        None => return None,
+    };
+    for value in values {
+        let value = match str::from_utf8(value) {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        for link in value.split(',') {
+            let mut parts = link.split(';');
+            let url = parts.next().unwrap_or("").trim();
+            let is_next = parts.any(|param| param.trim() == r#"rel="next""#);
+            if is_next && url.starts_with('<') && url.ends_with('>') {
+                return Some(url[1..url.len() - 1].to_owned());
+            }
+        }
+    }
+    None
+}
+
+/// Make a JSON Web Token identifying the app, good for ten minutes.
+fn jwt(app_id: u64, key: &[u8]) -> Result<String, Error> {
+    let now = util::now_secs();
+    let header = r#"{"alg":"RS256","typ":"JWT"}"#;
+    // Backdate it a bit, in case our clock is ahead of GitHub's.
+    let claims = format!(
+        r#"{{"iat":{},"exp":{},"iss":{}}}"#,
+        now - 60,
+        now + 9 * 60,
+        app_id
+    );
+    let input = format!(
+        "{}.{}",
+        crypto::base64url(header.as_bytes()),
+        crypto::base64url(claims.as_bytes())
+    );
+    let key = try!(PKey::private_key_from_pem(&mut &key[..]));
+    let signature = crypto::sign_rs256(&key, input.as_bytes());
+    Ok(format!("{}.{}", input, crypto::base64url(&signature)))
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        /// HTTP-level error
+        HttpStatus(status: StatusCode) {}
+        /// HTTP-level error
+        Http(err: hyper::error::Error) {
+            cause(err)
+            from()
+        }
+        /// JSON error
+        Json(err: serde_json::error::Error) {
+            cause(err)
+            from()
+        }
+        /// Private key could not be loaded
+        Key(err: SslError) {
+            cause(err)
+            from()
+        }
+        /// I/O error
+        Io(err: io::Error) {
+            cause(err)
+            from()
+        }
+        /// The app is not installed on the repo
+        NotInstalled(owner: String, repo: String) {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::header::Headers;
+    use super::next_page;
+
+    fn link(value: &str) -> Headers {
+        let mut headers = Headers::new();
+        headers.set_raw("Link", vec![value.as_bytes().to_vec()]);
+        headers
+    }
+
+    #[test]
+    fn test_next_page() {
+        assert_eq!(next_page(&Headers::new()), None);
+        assert_eq!(
+            next_page(&link(
+                r#"<https://x/r?page=3>; rel="next", <https://x/r?page=5>; rel="last""#
+            )),
+            Some("https://x/r?page=3".to_owned())
+        );
+        assert_eq!(next_page(&link(r#"<https://x/r?page=1>; rel="prev""#)), None);
+    }
+}
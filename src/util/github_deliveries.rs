@@ -0,0 +1,202 @@
+// This file is released under the same terms as Rust itself.
+
+//! Remember which webhook deliveries have been handled.
+//!
+//! GitHub gives every delivery an id in `X-GitHub-Delivery`, and keeps it
+//! when the delivery is redelivered. Ids are forgotten after a while,
+//! so the table does not grow without bound.
+
+use db::Builder;
+use postgres::{Connection as PgConnection, TlsMode};
+use postgres::params::{ConnectParams, IntoConnectParams};
+use rusqlite::{self, Connection};
+use std::convert::AsRef;
+use std::error::Error;
+use std::path::Path;
+use util;
+
+/// How long to remember a delivery, in seconds.
+pub const WINDOW_SEC: i64 = 7 * 24 * 60 * 60;
+
+pub fn from_builder(builder: &Builder, listener: &str)
+        -> Result<Deliveries, Box<Error + Send + Sync>> {
+    Ok(match *builder {
+        Builder::Sqlite(ref c) =>
+            Deliveries::Sqlite(try!(Sqlite::open(c, listener))),
+        Builder::Postgres(ref c) =>
+            Deliveries::Postgres(try!(Postgres::open(c.clone(), listener))),
+    })
+}
+
+pub enum Deliveries {
+    Sqlite(Sqlite),
+    Postgres(Postgres),
+}
+
+impl Deliveries {
+    /// Whether a delivery has been recorded, and not forgotten yet.
+    pub fn seen(&mut self, delivery: &str) -> bool {
+        let now = util::now_secs();
+        match *self {
+            Deliveries::Sqlite(ref mut c) => c.seen(delivery, now),
+            Deliveries::Postgres(ref mut c) => c.seen(delivery, now),
+        }
+    }
+    /// Record a delivery once it has been handled,
+    /// returning false if it was already recorded.
+    pub fn record(&mut self, delivery: &str) -> bool {
+        let now = util::now_secs();
+        match *self {
+            Deliveries::Sqlite(ref mut c) => c.record(delivery, now),
+            Deliveries::Postgres(ref mut c) => c.record(delivery, now),
+        }
+    }
+}
+
+pub struct Sqlite {
+    conn: Connection,
+    listener: String,
+}
+
+impl Sqlite {
+    fn open<Q: AsRef<Path>>(path: Q, listener: &str)
+            -> rusqlite::Result<Self> {
+        let conn = try!(Connection::open(path));
+        try!(conn.execute_batch(r###"
+            CREATE TABLE IF NOT EXISTS github_deliveries (
+                listener TEXT,
+                delivery TEXT,
+                received_at INTEGER,
+                UNIQUE (listener, delivery)
+            )
+        "###));
+        Ok(Sqlite{
+            conn: conn,
+            listener: listener.to_owned(),
+        })
+    }
+    fn seen(&mut self, delivery: &str, now: i64) -> bool {
+        let mut stmt = self.conn.prepare(r###"
+            SELECT COUNT(*)
+            FROM github_deliveries
+            WHERE listener = ? AND delivery = ? AND received_at >= ?
+        "###).expect("Prepare seen delivery query");
+        let since = now - WINDOW_SEC;
+        let mut rows = stmt
+            .query_map(&[&self.listener, &delivery, &since], |row| {
+                row.get::<_, i64>(0)
+            })
+            .expect("Get seen delivery");
+        rows.next().map(|row| row.expect("SQLite to work")).unwrap_or(0) != 0
+    }
+    fn record(&mut self, delivery: &str, now: i64) -> bool {
+        let trans = self.conn
+            .transaction()
+            .expect("Start record delivery transaction");
+        trans.execute(r###"
+            DELETE FROM github_deliveries
+            WHERE received_at < ?
+        "###, &[&(now - WINDOW_SEC)]).expect("to forget old deliveries");
+        let inserted = trans.execute(r###"
+            INSERT OR IGNORE INTO github_deliveries
+                (listener, delivery, received_at)
+            VALUES (?, ?, ?)
+        "###, &[&self.listener, &delivery, &now])
+            .expect("to record delivery");
+        trans.commit().expect("Commit record delivery transaction");
+        inserted != 0
+    }
+}
+
+pub struct Postgres {
+    params: ConnectParams,
+    listener: String,
+}
+
+impl Postgres {
+    fn open<Q: IntoConnectParams>(
+        params: Q,
+        listener: &str,
+    ) -> Result<Self, Box<Error + Send + Sync>> {
+        let result = Postgres{
+            params: try!(params.into_connect_params()),
+            listener: listener.to_owned(),
+        };
+        try!(try!(result.conn()).batch_execute(r###"
+            CREATE TABLE IF NOT EXISTS github_deliveries (
+                listener TEXT,
+                delivery TEXT,
+                received_at BIGINT,
+                UNIQUE (listener, delivery)
+            )
+        "###));
+        Ok(result)
+    }
+    fn conn(&self) -> Result<PgConnection, Box<Error + Send + Sync>> {
+        Ok(try!(PgConnection::connect(self.params.clone(), TlsMode::None)))
+    }
+    fn seen(&mut self, delivery: &str, now: i64) -> bool {
+        retry!{{
+            let conn = retry_unwrap!(self.conn());
+            let stmt = retry_unwrap!(conn.prepare(r###"
+                SELECT COUNT(*)
+                FROM github_deliveries
+                WHERE listener = $1 AND delivery = $2 AND received_at >= $3
+            "###));
+            let rows = retry_unwrap!(stmt.query(
+                &[&self.listener, &delivery, &(now - WINDOW_SEC)]
+            ));
+            let count = rows.iter().next()
+                .map(|row| row.get::<_, i64>(0))
+                .unwrap_or(0);
+            count != 0
+        }}
+    }
+    fn record(&mut self, delivery: &str, now: i64) -> bool {
+        retry!{{
+            let conn = retry_unwrap!(self.conn());
+            let trans = retry_unwrap!(conn.transaction());
+            retry_unwrap!(trans.execute(r###"
+                DELETE FROM github_deliveries
+                WHERE received_at < $1
+            "###, &[&(now - WINDOW_SEC)]));
+            let inserted = retry_unwrap!(trans.execute(r###"
+                INSERT INTO github_deliveries
+                    (listener, delivery, received_at)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (listener, delivery) DO NOTHING
+            "###, &[&self.listener, &delivery, &now]));
+            retry_unwrap!(trans.commit());
+            inserted != 0
+        }}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Deliveries, Sqlite, WINDOW_SEC};
+
+    #[test]
+    fn test_record_once() {
+        let mut deliveries = Deliveries::Sqlite(
+            Sqlite::open(":memory:", "ui").unwrap()
+        );
+        // Seeing it is not enough; it has to be handled.
+        assert!(!deliveries.seen("abc"));
+        assert!(!deliveries.seen("abc"));
+        assert!(deliveries.record("abc"));
+        assert!(deliveries.seen("abc"));
+        assert!(!deliveries.record("abc"));
+        assert!(deliveries.record("def"));
+    }
+
+    #[test]
+    fn test_forget_old() {
+        let mut sqlite = Sqlite::open(":memory:", "ui").unwrap();
+        assert!(sqlite.record("abc", 100));
+        assert!(sqlite.seen("abc", 100 + WINDOW_SEC));
+        assert!(!sqlite.seen("abc", 101 + WINDOW_SEC));
+        assert!(!sqlite.record("abc", 100 + WINDOW_SEC));
+        assert!(sqlite.record("abc", 101 + WINDOW_SEC));
+    }
+}
@@ -4,31 +4,88 @@ use hex::FromHex;
 use hyper::header::Headers;
 use hyper::server::Request;
 use std::io::Read;
-use util::crypto::{SHA1_LEN, verify_sha1_hmac};
+use util::crypto::{SHA1_LEN, SHA256_LEN};
+use util::crypto::{verify_sha1_hmac, verify_sha256_hmac};
 
-pub fn parse(req: &mut Request, secret: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
-    let x_github_event = {
-        if let Some(xges) = req.headers.get_raw("X-Github-Event") {
-            if let Some(xge) = xges.get(0) {
-                xge.clone()
-            } else {
-                vec![]
+/// A webhook delivery whose signature checked out.
+pub struct Webhook {
+    /// The `X-Github-Event` header
+    pub event: Vec<u8>,
+    /// The `X-Github-Delivery` header, which stays the same on redelivery
+    pub delivery: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// Read a Github webhook and check its signature.
+///
+/// `X-Hub-Signature-256` is checked if it is present. Otherwise, unless
+/// `require_sha256` is set, the older SHA-1 `X-Hub-Signature` is.
+pub fn parse(
+    req: &mut Request,
+    secret: &[u8],
+    require_sha256: bool,
+) -> Option<Webhook> {
+    let x_github_event = get_header(&req.headers, "X-Github-Event");
+    let x_github_delivery = get_header(&req.headers, "X-Github-Delivery");
+    let sha256 = req.headers.get_raw("X-Hub-Signature-256").is_some();
+    let body = if sha256 {
+        read_body(req).and_then(|body| {
+            let signature = parse_signature(
+                &req.headers,
+                "X-Hub-Signature-256",
+                "sha256=",
+                SHA256_LEN,
+            );
+            match signature {
+                Some(ref s) if verify_sha256_hmac(secret, &body, s) =>
+                    Some(body),
+                Some(_) => {
+                    warn!("Got incorrect signature");
+                    None
+                }
+                None => None,
             }
-        } else {
-            vec![]
-        }
+        })
+    } else if require_sha256 {
+        warn!("Got webhook without X-Hub-Signature-256");
+        None
+    } else {
+        parse_body(req, "X-Hub-Signature", secret)
     };
-    let body = {
-        let mut body = Vec::new();
-        let result = req.read_to_end(&mut body);
-        if let Err(e) = result {
-            warn!("Failed to read body: {:?}", e);
-            return None;
-        } else {
-            body
+    let body = match body {
+        Some(body) => body,
+        None => return None,
+    };
+    let delivery = match String::from_utf8(x_github_delivery) {
+        Ok(ref delivery) if delivery.is_empty() => None,
+        Ok(delivery) => Some(delivery),
+        Err(_) => {
+            warn!("Got invalid X-Github-Delivery");
+            None
         }
     };
-    let signature = parse_signature(&req.headers);
+    Some(Webhook{
+        event: x_github_event,
+        delivery: delivery,
+        body: body,
+    })
+}
+
+/// Read a request body and check it against a `sha1=<hex>` HMAC
+/// signature in the given header.
+///
+/// This is the same signing scheme Github uses,
+/// but other services copy it under their own header name.
+pub fn parse_body(
+    req: &mut Request,
+    header: &str,
+    secret: &[u8],
+) -> Option<Vec<u8>> {
+    let body = match read_body(req) {
+        Some(body) => body,
+        None => return None,
+    };
+    let signature = parse_signature(&req.headers, header, "sha1=", SHA1_LEN);
     let signature = if let Some(signature) = signature {
         signature
     } else {
@@ -38,31 +95,76 @@ pub fn parse(req: &mut Request, secret: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
         warn!("Got incorrect signature");
         return None;
     }
-    Some((x_github_event, body))
+    Some(body)
 }
 
-fn parse_signature(headers: &Headers) -> Option<Vec<u8>> {
-    let x_hub_signature = {
-        if let Some(xges) = headers.get_raw("X-Hub-Signature") {
-            if let Some(xge) = xges.get(0) {
-                xge.clone()
-            } else {
-                vec![]
-            }
+/// Read a request body and check it against a bare hex HMAC-SHA256
+/// signature in the given header, as Gitea sends in `X-Gitea-Signature`.
+pub fn parse_sha256_body(
+    req: &mut Request,
+    header: &str,
+    secret: &[u8],
+) -> Option<Vec<u8>> {
+    let body = match read_body(req) {
+        Some(body) => body,
+        None => return None,
+    };
+    let signature = parse_signature(&req.headers, header, "", SHA256_LEN);
+    let signature = if let Some(signature) = signature {
+        signature
+    } else {
+        return None;
+    };
+    if !verify_sha256_hmac(secret, &body, &signature) {
+        warn!("Got incorrect signature");
+        return None;
+    }
+    Some(body)
+}
+
+fn read_body(req: &mut Request) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    let result = req.read_to_end(&mut body);
+    if let Err(e) = result {
+        warn!("Failed to read body: {:?}", e);
+        None
+    } else {
+        Some(body)
+    }
+}
+
+/// The first value of a header, or nothing if it is missing.
+pub fn get_header(headers: &Headers, header: &str) -> Vec<u8> {
+    if let Some(values) = headers.get_raw(header) {
+        if let Some(value) = values.get(0) {
+            value.clone()
         } else {
             vec![]
         }
-    };
-    if x_hub_signature.len() != SHA1_LEN+5 {
-        warn!("Got wrong length X-Hub-Signature");
+    } else {
+        vec![]
+    }
+}
+
+/// Parse a `<prefix><hex>` signature, where the hex is `len` digits long.
+fn parse_signature(
+    headers: &Headers,
+    header: &str,
+    prefix: &str,
+    len: usize,
+) -> Option<Vec<u8>> {
+    let x_hub_signature = get_header(headers, header);
+    if x_hub_signature.len() != len + prefix.len() ||
+            !x_hub_signature.starts_with(prefix.as_bytes()) {
+        warn!("Got malformed {}", header);
         return None;
     }
-    let signature = &x_hub_signature[5..]; // sha1=
+    let signature = &x_hub_signature[prefix.len()..];
     let signature = Vec::from_hex(&signature);
     let signature = if let Ok(signature) = signature {
         signature
     } else {
-        warn!("Got invalid hex in X-Hub-Signature");
+        warn!("Got invalid hex in {}", header);
         return None;
     };
     Some(signature)
@@ -72,13 +174,54 @@ fn parse_signature(headers: &Headers) -> Option<Vec<u8>> {
 mod test {
     use hyper::header::Headers;
     use super::parse_signature;
+    use util::crypto::{SHA1_LEN, SHA256_LEN};
     #[test]
     fn test_empty_signature() {
         let mut headers = Headers::new();
         headers.set_raw("X-Hub-Signature", vec![ vec![ ] ]);
-        assert!(parse_signature(&headers).is_none());
+        assert!(parse_signature(&headers, "X-Hub-Signature", "sha1=", SHA1_LEN)
+            .is_none());
         let mut headers = Headers::new();
         headers.set_raw("X-Hub-Signature", vec![ vec![ b' ' ] ]);
-        assert!(parse_signature(&headers).is_none());
+        assert!(parse_signature(&headers, "X-Hub-Signature", "sha1=", SHA1_LEN)
+            .is_none());
+    }
+    #[test]
+    fn test_sha256_signature() {
+        let hex = "757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+        let mut headers = Headers::new();
+        headers.set_raw(
+            "X-Hub-Signature-256",
+            vec![ format!("sha256={}", hex).into_bytes() ],
+        );
+        let signature = parse_signature(
+            &headers,
+            "X-Hub-Signature-256",
+            "sha256=",
+            SHA256_LEN,
+        );
+        assert_eq!(signature.map(|s| s.len()), Some(32));
+        // A SHA-1 signature is not accepted in its place.
+        let mut headers = Headers::new();
+        headers.set_raw(
+            "X-Hub-Signature-256",
+            vec![ format!("sha1={}", &hex[..SHA1_LEN]).into_bytes() ],
+        );
+        let signature = parse_signature(
+            &headers,
+            "X-Hub-Signature-256",
+            "sha256=",
+            SHA256_LEN,
+        );
+        assert!(signature.is_none());
+    }
+    #[test]
+    fn test_bare_sha256_signature() {
+        let hex = "757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+        let mut headers = Headers::new();
+        headers.set_raw("X-Gitea-Signature", vec![ hex.as_bytes().to_vec() ]);
+        let signature =
+            parse_signature(&headers, "X-Gitea-Signature", "", SHA256_LEN);
+        assert_eq!(signature.map(|s| s.len()), Some(32));
     }
 }
\ No newline at end of file
@@ -0,0 +1,289 @@
+// This file is released under the same terms as Rust itself.
+
+//! Staying within GitHub's API rate limits.
+//!
+//! Every response says how many requests are left, and when that budget
+//! resets. Once it runs out, or GitHub asks us to back off with a
+//! `Retry-After` header (its "secondary" limits), requests wait their turn
+//! instead of failing. Rate-limited requests were never carried out, so
+//! they are always sent again; other failures only are when the request
+//! is safe to repeat.
+
+use hyper::client::Response;
+use hyper::header::Headers;
+use hyper::status::StatusCode;
+use std::cmp;
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use util;
+
+/// How many times to send a request before giving up on it.
+const MAX_ATTEMPTS: u32 = 5;
+/// The longest a request will wait for the budget to reset, in seconds.
+/// After that it's sent anyway, and if it's still limited, it counts as
+/// a failed attempt.
+const MAX_WAIT_SEC: i64 = 5 * 60;
+/// GitHub asks for at least a minute between retries when a secondary
+/// limit is hit without saying how long to wait.
+const SECONDARY_WAIT_SEC: i64 = 60;
+
+lazy_static!{
+    /// Every budget, by client name, for reporting.
+    static ref BUDGETS: Mutex<Vec<(&'static str, Arc<Mutex<Budget>>)>> =
+        Mutex::new(Vec::new());
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+struct Budget {
+    limit: Option<i64>,
+    remaining: Option<i64>,
+    /// When the budget is refilled, in seconds since the Unix epoch
+    reset: i64,
+    /// When GitHub said we may try again, after a `Retry-After`
+    retry_after: i64,
+    /// How many responses were rejected by a rate limit
+    limited: u64,
+    /// How many requests were sent again
+    retried: u64,
+}
+
+impl Budget {
+    /// Update from a response's headers, and say whether it was rejected
+    /// by a rate limit.
+    fn observe(&mut self, status: StatusCode, headers: &Headers, now: i64)
+            -> bool {
+        if let Some(limit) = header_num(headers, "X-RateLimit-Limit") {
+            self.limit = Some(limit);
+        }
+        if let Some(remaining) = header_num(headers, "X-RateLimit-Remaining") {
+            self.remaining = Some(remaining);
+        }
+        if let Some(reset) = header_num(headers, "X-RateLimit-Reset") {
+            self.reset = reset;
+        }
+        let retry_after = header_num(headers, "Retry-After");
+        let limited = match status {
+            StatusCode::TooManyRequests => true,
+            StatusCode::Forbidden =>
+                retry_after.is_some() || self.remaining == Some(0),
+            _ => false,
+        };
+        if limited {
+            self.limited += 1;
+            if let Some(retry_after) = retry_after {
+                self.retry_after = now + retry_after;
+            } else if self.remaining != Some(0) {
+                self.retry_after = now + SECONDARY_WAIT_SEC;
+            }
+        }
+        limited
+    }
+    /// When the next request may be sent.
+    fn ready_at(&self, now: i64) -> i64 {
+        let reset = if self.remaining == Some(0) && self.reset > now {
+            self.reset
+        } else {
+            now
+        };
+        cmp::max(reset, self.retry_after)
+    }
+}
+
+pub struct RateLimit {
+    name: &'static str,
+    budget: Arc<Mutex<Budget>>,
+}
+
+impl RateLimit {
+    /// Track a budget. The name identifies it in the metrics.
+    pub fn new(name: &'static str) -> RateLimit {
+        let budget = Arc::new(Mutex::new(Budget::default()));
+        BUDGETS.lock().unwrap().push((name, budget.clone()));
+        RateLimit {
+            name: name,
+            budget: budget,
+        }
+    }
+
+    /// Send a request, waiting for the budget first, and sending it again
+    /// if it gets rate-limited.
+    ///
+    /// `send` is called once per attempt. `http` picks out the HTTP
+    /// response, for its status and headers. Requests that are
+    /// `idempotent` are also sent again after a server or network error.
+    pub fn send<R, E, F, H>(
+        &self,
+        idempotent: bool,
+        mut send: F,
+        http: H,
+    ) -> Result<R, E>
+        where F: FnMut() -> Result<R, E>, H: Fn(&R) -> &Response
+    {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            self.wait();
+            let result = send();
+            let retry = match result {
+                Ok(ref resp) => {
+                    let resp = http(resp);
+                    let limited = self.budget.lock().unwrap().observe(
+                        resp.status,
+                        &resp.headers,
+                        util::now_secs(),
+                    );
+                    if limited {
+                        warn!("{}: rate limited by GitHub", self.name);
+                    }
+                    limited || (idempotent && resp.status.is_server_error())
+                }
+                Err(_) => idempotent,
+            };
+            if !retry || attempt >= MAX_ATTEMPTS {
+                return result;
+            }
+            self.budget.lock().unwrap().retried += 1;
+            // Back off exponentially, on top of whatever the budget says.
+            thread::sleep(Duration::from_secs(1 << attempt));
+        }
+    }
+
+    /// Nothing is locked while waiting, so requests on other threads
+    /// are not held up behind this one.
+    fn wait(&self) {
+        let now = util::now_secs();
+        let ready_at = self.budget.lock().unwrap().ready_at(now);
+        if ready_at > now {
+            let wait = cmp::min(ready_at - now, MAX_WAIT_SEC);
+            info!("{}: waiting {}s for the GitHub rate limit", self.name, wait);
+            thread::sleep(Duration::from_secs(wait as u64));
+        }
+    }
+}
+
+fn header_num(headers: &Headers, header: &str) -> Option<i64> {
+    headers.get_raw(header)
+        .and_then(|values| values.get(0))
+        .and_then(|value| str::from_utf8(value).ok())
+        .and_then(|value| value.trim().parse().ok())
+}
+
+/// Render every budget in the Prometheus text format.
+pub fn metrics() -> String {
+    let budgets = BUDGETS.lock().unwrap();
+    let mut result = String::new();
+    result.push_str(
+        "# HELP aelita_github_rate_limit_remaining \
+         Requests left before the GitHub rate limit resets.\n\
+         # TYPE aelita_github_rate_limit_remaining gauge\n"
+    );
+    for &(name, ref budget) in budgets.iter() {
+        if let Some(remaining) = budget.lock().unwrap().remaining {
+            result.push_str(&format!(
+                "aelita_github_rate_limit_remaining{{client=\"{}\"}} {}\n",
+                name,
+                remaining,
+            ));
+        }
+    }
+    result.push_str(
+        "# HELP aelita_github_rate_limit_limit \
+         Requests allowed per GitHub rate limit window.\n\
+         # TYPE aelita_github_rate_limit_limit gauge\n"
+    );
+    for &(name, ref budget) in budgets.iter() {
+        if let Some(limit) = budget.lock().unwrap().limit {
+            result.push_str(&format!(
+                "aelita_github_rate_limit_limit{{client=\"{}\"}} {}\n",
+                name,
+                limit,
+            ));
+        }
+    }
+    result.push_str(
+        "# HELP aelita_github_rate_limited_total \
+         Responses rejected by a GitHub rate limit.\n\
+         # TYPE aelita_github_rate_limited_total counter\n"
+    );
+    for &(name, ref budget) in budgets.iter() {
+        result.push_str(&format!(
+            "aelita_github_rate_limited_total{{client=\"{}\"}} {}\n",
+            name,
+            budget.lock().unwrap().limited,
+        ));
+    }
+    result.push_str(
+        "# HELP aelita_github_retried_total \
+         GitHub requests that were sent again.\n\
+         # TYPE aelita_github_retried_total counter\n"
+    );
+    for &(name, ref budget) in budgets.iter() {
+        result.push_str(&format!(
+            "aelita_github_retried_total{{client=\"{}\"}} {}\n",
+            name,
+            budget.lock().unwrap().retried,
+        ));
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use hyper::header::Headers;
+    use hyper::status::StatusCode;
+    use super::{Budget, SECONDARY_WAIT_SEC};
+
+    fn headers(pairs: &[(&'static str, &str)]) -> Headers {
+        let mut headers = Headers::new();
+        for &(name, value) in pairs {
+            headers.set_raw(name, vec![value.as_bytes().to_vec()]);
+        }
+        headers
+    }
+
+    #[test]
+    fn test_primary_limit() {
+        let mut budget = Budget::default();
+        let limited = budget.observe(StatusCode::Ok, &headers(&[
+            ("X-RateLimit-Limit", "5000"),
+            ("X-RateLimit-Remaining", "0"),
+            ("X-RateLimit-Reset", "1100"),
+        ]), 1000);
+        assert!(!limited);
+        assert_eq!(budget.remaining, Some(0));
+        assert_eq!(budget.ready_at(1000), 1100);
+        let limited = budget.observe(StatusCode::Forbidden, &headers(&[
+            ("X-RateLimit-Remaining", "0"),
+        ]), 1001);
+        assert!(limited);
+        assert_eq!(budget.limited, 1);
+        // Once the reset time has passed, there is nothing to wait for.
+        assert_eq!(budget.ready_at(1200), 1200);
+    }
+
+    #[test]
+    fn test_secondary_limit() {
+        let mut budget = Budget::default();
+        let limited = budget.observe(StatusCode::Forbidden, &headers(&[
+            ("X-RateLimit-Remaining", "4000"),
+            ("Retry-After", "30"),
+        ]), 1000);
+        assert!(limited);
+        assert_eq!(budget.ready_at(1000), 1030);
+        let limited = budget.observe(
+            StatusCode::TooManyRequests,
+            &Headers::new(),
+            2000,
+        );
+        assert!(limited);
+        assert_eq!(budget.ready_at(2000), 2000 + SECONDARY_WAIT_SEC);
+        // A plain permission error is not a rate limit.
+        let limited = budget.observe(StatusCode::Forbidden, &headers(&[
+            ("X-RateLimit-Remaining", "3999"),
+        ]), 3000);
+        assert!(!limited);
+        assert_eq!(budget.ready_at(3000), 3000);
+    }
+}
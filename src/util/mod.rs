@@ -1,11 +1,28 @@
 // This file is released under the same terms as Rust itself.
 
 pub mod crypto;
+pub mod gitea_api;
+pub mod github_auth;
 pub mod github_headers;
+pub mod github_rate_limit;
+pub mod gitlab_api;
 
 pub const USER_AGENT: &'static str =
     "aelita/0.1 (https://github.com/AelitaBot/aelita)";
 
+/// Shown in place of tokens and keys in `Debug` output, so they stay out of
+/// the logs.
+pub const REDACTED: &'static str = "<redacted>";
+
+/// The current time, in seconds since the Unix epoch.
+pub fn now_secs() -> i64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
 pub const MIN_DELAY_SEC: u64 = 1;
 pub const MAX_DELAY_SEC: u64 = 60*2;
 
@@ -35,4 +52,7 @@ macro_rules! retry_unwrap {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+// This uses the retry macros, so it has to come after them.
+pub mod github_deliveries;
@@ -5,7 +5,7 @@ use std;
 use std::convert::From;
 use std::fs::File;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync::mpsc::{Sender, Receiver};
 use vcs::{self, Commit};
@@ -149,6 +149,26 @@ impl Worker {
                     }
                 }
             }
+            vcs::Message::FetchRepoConfig(pipeline_id) => {
+                let repo = match self.pipelines.repo_by_pipeline(pipeline_id) {
+                    Some(repo) => repo,
+                    None => {
+                        warn!("Got wrong pipeline ID {:?}", pipeline_id);
+                        return;
+                    }
+                };
+                match self.fetch_repo_config(&repo) {
+                    Err(e) => {
+                        warn!("Failed to read {}: {:?}", vcs::REPO_CONFIG, e);
+                    }
+                    Ok(contents) => {
+                        send_event.send(vcs::Event::FetchedRepoConfig(
+                            pipeline_id,
+                            contents,
+                        )).expect("Pipeline gone fetch repo config");
+                    }
+                }
+            }
         }
     }
     fn merge_to_staging(
@@ -222,6 +242,32 @@ impl Worker {
             .arg(format!("{}:{}", merge_commit, &repo.master_branch)));
         Ok(())
     }
+    fn fetch_repo_config(
+        &self,
+        repo: &Repo,
+    ) -> Result<Option<String>, GitError> {
+        try!(self.setup_dir(repo));
+        try_cmd!(Command::new(&self.executable), cmd,
+        cmd.current_dir(&repo.path)
+            .arg("fetch")
+            .arg("origin")
+            .arg(&repo.master_branch));
+        let master = format!("origin/{}", repo.master_branch);
+        let listed = try_cmd!(Command::new(&self.executable), cmd,
+        cmd.current_dir(&repo.path)
+            .arg("ls-tree")
+            .arg("--name-only")
+            .arg(&master)
+            .arg(vcs::REPO_CONFIG));
+        if listed.stdout.is_empty() {
+            return Ok(None);
+        }
+        let contents = try_cmd!(Command::new(&self.executable), cmd,
+        cmd.current_dir(&repo.path)
+            .arg("show")
+            .arg(format!("{}:{}", master, vcs::REPO_CONFIG)));
+        Ok(Some(String::from_utf8_lossy(&contents.stdout).into_owned()))
+    }
     fn setup_dir(&self, repo: &Repo) -> Result<(), GitError> {
         if !Path::new(&repo.path).exists() {
             try_cmd!(Command::new(&self.executable), cmd,
@@ -257,6 +303,67 @@ impl Worker {
     }
 }
 
+/// git, run in bare repositories for the backends that merge without a
+/// working copy, and push to the front-end's own git server.
+///
+/// The remote's address is passed in the environment on every call,
+/// so a token in it stays out of the logs and off the disk.
+pub struct Bare {
+    executable: String,
+    name: String,
+    email: String,
+    /// Where to keep the repositories
+    path: String,
+}
+
+impl Bare {
+    pub fn new(
+        executable: String,
+        name: String,
+        email: String,
+        path: String,
+    ) -> Bare {
+        Bare{
+            executable: executable,
+            name: name,
+            email: email,
+            path: path,
+        }
+    }
+    /// Create the repository for a project, if it isn't there yet.
+    pub fn setup_dir(&self, project: &str) -> Result<PathBuf, GitError> {
+        let dir = Path::new(&self.path).join(project);
+        if !dir.exists() {
+            try_cmd!(Command::new(&self.executable), cmd,
+            cmd.arg("init")
+                .arg("--bare")
+                .arg(&dir));
+        }
+        Ok(dir)
+    }
+    /// Run git with `origin` pointing at the given address,
+    /// and return what it printed.
+    pub fn run(
+        &self,
+        dir: &Path,
+        origin: &str,
+        args: &[&str],
+    ) -> Result<String, GitError> {
+        let out = try_cmd!(Command::new(&self.executable), cmd,
+        cmd.current_dir(dir)
+            .args(args)
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .env("GIT_CONFIG_COUNT", "1")
+            .env("GIT_CONFIG_KEY_0", "remote.origin.url")
+            .env("GIT_CONFIG_VALUE_0", origin)
+            .env("GIT_AUTHOR_NAME", &self.name)
+            .env("GIT_AUTHOR_EMAIL", &self.email)
+            .env("GIT_COMMITTER_NAME", &self.name)
+            .env("GIT_COMMITTER_EMAIL", &self.email));
+        Ok(String::from_utf8_lossy(&out.stdout).trim().to_owned())
+    }
+}
+
 quick_error! {
     #[derive(Debug)]
     pub enum GitError {
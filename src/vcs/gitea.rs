@@ -0,0 +1,284 @@
+// This file is released under the same terms as Rust itself.
+
+//! Merging into a Gitea repository, without a working copy.
+//!
+//! Gitea's API cannot merge arbitrary commits or move branches, so the
+//! merge is done in a bare repository, with `git merge-tree`, and pushed
+//! back over HTTPS, signed in with the API token. Staging is force-pushed,
+//! and master is only ever fast-forwarded to the exact commit that was
+//! tested; if it has moved, the push is rejected. This needs git 2.38 or
+//! newer.
+//!
+//! Forgejo speaks the same API.
+
+use hyper;
+use hyper::method::Method;
+use hyper::status::StatusCode;
+use pipeline::{self, PipelineId};
+use std::convert::From;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::sync::mpsc::{Sender, Receiver};
+use url::form_urlencoded::byte_serialize;
+use util::gitea_api::Api;
+use vcs::{self, Commit, Remote};
+use vcs::git::{Bare, GitError};
+
+pub trait PipelinesConfig: Send + Sync + 'static {
+    fn repo_by_pipeline(&self, PipelineId) -> Option<Repo>;
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Repo {
+    pub owner: String,
+    pub repo: String,
+    pub master_branch: String,
+    pub staging_branch: String,
+    pub push_to_master: bool,
+}
+
+pub struct Worker {
+    pipelines: Box<PipelinesConfig>,
+    api: Api,
+    git: Bare,
+}
+
+impl Worker {
+    pub fn new(
+        api: Api,
+        git: Bare,
+        pipelines: Box<PipelinesConfig>
+    ) -> Worker {
+        Worker{
+            pipelines: pipelines,
+            api: api,
+            git: git,
+        }
+    }
+}
+
+impl pipeline::Worker<vcs::Event, vcs::Message> for Worker {
+    fn run(
+        &self,
+        recv_msg: Receiver<vcs::Message>,
+        mut send_event: Sender<vcs::Event>
+    ) {
+        loop {
+            self.handle_message(
+                recv_msg.recv().expect("Pipeline went away"),
+                &mut send_event,
+            );
+        }
+    }
+}
+
+impl Worker {
+    fn handle_message(
+        &self,
+        msg: vcs::Message,
+        send_event: &mut Sender<vcs::Event>,
+    ) {
+        match msg {
+            vcs::Message::MergeToStaging(
+                pipeline_id, pull_commit, message, remote
+            ) => {
+                match self.merge_to_staging(
+                    pipeline_id, &pull_commit, &message, &remote
+                ) {
+                    Ok(merge_commit) => {
+                        send_event.send(vcs::Event::MergedToStaging(
+                            pipeline_id,
+                            pull_commit,
+                            merge_commit,
+                        )).expect("Pipeline gone merge to staging");
+                    },
+                    Err(e) => {
+                        warn!("Failed to merge to staging: {:?}", e);
+                        send_event.send(vcs::Event::FailedMergeToStaging(
+                            pipeline_id,
+                            pull_commit,
+                        )).expect("Pipeline gone merge to staging error");
+                    }
+                }
+            }
+            vcs::Message::MoveStagingToMaster(pipeline_id, merge_commit) => {
+                match self.move_to_master(pipeline_id, &merge_commit) {
+                    Ok(()) => {
+                        send_event.send(vcs::Event::MovedToMaster(
+                            pipeline_id,
+                            merge_commit,
+                        )).expect("Pipeline gone move to master");
+                    },
+                    Err(e) => {
+                        warn!("Failed to move to master: {:?}", e);
+                        send_event.send(vcs::Event::FailedMoveToMaster(
+                            pipeline_id,
+                            merge_commit,
+                        )).expect("Pipeline gone move to master error");
+                    }
+                }
+            }
+            vcs::Message::FetchRepoConfig(pipeline_id) => {
+                match self.fetch_repo_config(pipeline_id) {
+                    Ok(contents) => {
+                        send_event.send(vcs::Event::FetchedRepoConfig(
+                            pipeline_id,
+                            contents,
+                        )).expect("Pipeline gone fetch repo config");
+                    },
+                    Err(e) => {
+                        warn!("Failed to read {}: {:?}", vcs::REPO_CONFIG, e);
+                    }
+                }
+            }
+        }
+    }
+    fn fetch_repo_config(
+        &self,
+        pipeline_id: PipelineId,
+    ) -> Result<Option<String>, GiteaRequestError> {
+        let repo = match self.pipelines.repo_by_pipeline(pipeline_id) {
+            Some(repo) => repo,
+            None => return Err(GiteaRequestError::Pipeline(pipeline_id)),
+        };
+        let path = format!(
+            "/repos/{}/{}/raw/{}?ref={}",
+            repo.owner,
+            repo.repo,
+            vcs::REPO_CONFIG,
+            byte_serialize(repo.master_branch.as_bytes()).collect::<String>(),
+        );
+        debug!("Get repo config: {}", path);
+        let mut resp = try!(self.api.send(Method::Get, &path, None));
+        if resp.status == StatusCode::NotFound {
+            return Ok(None);
+        }
+        if !resp.status.is_success() {
+            return Err(GiteaRequestError::HttpStatus(resp.status));
+        }
+        let mut contents = String::new();
+        try!(resp.read_to_string(&mut contents));
+        Ok(Some(contents))
+    }
+    fn merge_to_staging(
+        &self,
+        pipeline_id: PipelineId,
+        pull_commit: &Commit,
+        message: &str,
+        remote: &Remote,
+    ) -> Result<Commit, GiteaRequestError> {
+        let repo = match self.pipelines.repo_by_pipeline(pipeline_id) {
+            Some(repo) => repo,
+            None => return Err(GiteaRequestError::Pipeline(pipeline_id)),
+        };
+        // Step 1: fetch master, and the pull request.
+        let (dir, origin) = try!(self.setup_dir(&repo));
+        let master_refspec =
+            format!("+refs/heads/{}:refs/aelita/master", repo.master_branch);
+        let pull_refspec = format!("+refs/{}:refs/aelita/pull", remote);
+        try!(self.git.run(&dir, &origin, &[
+            "fetch",
+            "origin",
+            &master_refspec[..],
+            &pull_refspec[..],
+        ]));
+        let master_sha = try!(self.git.run(&dir, &origin, &[
+            "rev-parse",
+            "refs/aelita/master",
+        ]));
+        // Step 2: merge them. This fails if there are conflicts.
+        let merged = try!(self.git.run(&dir, &origin, &[
+            "merge-tree",
+            "--write-tree",
+            &master_sha[..],
+            pull_commit.as_str(),
+        ]));
+        let tree = merged.lines().next().unwrap_or("").to_owned();
+        let merge_commit = try!(self.git.run(&dir, &origin, &[
+            "commit-tree",
+            &tree[..],
+            "-p",
+            &master_sha[..],
+            "-p",
+            pull_commit.as_str(),
+            "-m",
+            message,
+        ]));
+        // Step 3: point staging at it, where it was.
+        let refspec =
+            format!("{}:refs/heads/{}", merge_commit, repo.staging_branch);
+        try!(self.git.run(&dir, &origin, &[
+            "push",
+            "--force",
+            "origin",
+            &refspec[..],
+        ]));
+        Ok(Commit::from(merge_commit))
+    }
+    fn move_to_master(
+        &self,
+        pipeline_id: PipelineId,
+        merge_commit: &Commit,
+    ) -> Result<(), GiteaRequestError> {
+        let repo = match self.pipelines.repo_by_pipeline(pipeline_id) {
+            Some(repo) => repo,
+            None => return Err(GiteaRequestError::Pipeline(pipeline_id)),
+        };
+        if !repo.push_to_master {
+            return Ok(());
+        }
+        // The commit may have been made before a restart.
+        let (dir, origin) = try!(self.setup_dir(&repo));
+        let refspec =
+            format!("+refs/heads/{}:refs/aelita/staging", repo.staging_branch);
+        try!(self.git.run(&dir, &origin, &[
+            "fetch",
+            "origin",
+            &refspec[..],
+        ]));
+        // Without --force, this is rejected unless it is a fast-forward.
+        let refspec =
+            format!("{}:refs/heads/{}", merge_commit, repo.master_branch);
+        try!(self.git.run(&dir, &origin, &[
+            "push",
+            "origin",
+            &refspec[..],
+        ]));
+        Ok(())
+    }
+    /// The repository's bare copy, and the address to fetch and push it.
+    fn setup_dir(
+        &self,
+        repo: &Repo,
+    ) -> Result<(PathBuf, String), GiteaRequestError> {
+        let dir = try!(self.git.setup_dir(
+            &format!("gitea/{}/{}", repo.owner, repo.repo)
+        ));
+        Ok((dir, self.api.git_url(&repo.owner, &repo.repo)))
+    }
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum GiteaRequestError {
+        /// HTTP-level error
+        HttpStatus(status: StatusCode) {}
+        /// HTTP-level error
+        Http(err: hyper::error::Error) {
+            cause(err)
+            from()
+        }
+        /// I/O error
+        Io(err: io::Error) {
+            cause(err)
+            from()
+        }
+        /// Repo not found for pipeline
+        Pipeline(pipeline_id: PipelineId) {}
+        /// git failed, or refused to push
+        Git(err: GitError) {
+            cause(err)
+            from()
+        }
+    }
+}
@@ -4,12 +4,14 @@ use hyper;
 use hyper::header::{self, qitem, Accept};
 use hyper::status::StatusCode;
 use pipeline::{self, PipelineId};
-use rest::{authorization, Authorization, Client, Mime};
+use rest::Mime;
 use serde_json;
 use std;
 use std::convert::From;
 use std::sync::mpsc::{Sender, Receiver};
-use util::USER_AGENT;
+use url::form_urlencoded::byte_serialize;
+use util::crypto::base64_decode;
+use util::github_auth::{self, Auth};
 use vcs::{self, Commit};
 
 pub trait PipelinesConfig: Send + Sync + 'static {
@@ -27,22 +29,17 @@ pub struct Repo {
 
 pub struct Worker {
     pipelines: Box<PipelinesConfig>,
-    client: Client<Authorization<authorization::Token>>,
+    auth: Auth,
 }
 
 impl Worker {
     pub fn new(
-        host: String,
-        token: String,
+        auth: Auth,
         pipelines: Box<PipelinesConfig>
     ) -> Worker {
         Worker{
             pipelines: pipelines,
-            client: Client::new(USER_AGENT.to_owned())
-                .base(&host)
-                .authorization(Authorization(authorization::Token{
-                    token: token,
-                })),
+            auth: auth,
         }
     }
 }
@@ -108,6 +105,64 @@ impl Worker {
                     }
                 }
             }
+            vcs::Message::FetchRepoConfig(pipeline_id) => {
+                match self.fetch_repo_config(pipeline_id) {
+                    Ok(contents) => {
+                        send_event.send(vcs::Event::FetchedRepoConfig(
+                            pipeline_id,
+                            contents,
+                        )).expect("Pipeline gone fetch repo config");
+                    },
+                    Err(e) => {
+                        warn!("Failed to read {}: {:?}", vcs::REPO_CONFIG, e);
+                    }
+                }
+            }
+        }
+    }
+    fn fetch_repo_config(
+        &self,
+        pipeline_id: PipelineId,
+    ) -> Result<Option<String>, GithubRequestError> {
+        let repo = match self.pipelines.repo_by_pipeline(pipeline_id) {
+            Some(repo) => repo,
+            None => return Err(GithubRequestError::Pipeline(pipeline_id)),
+        };
+        let client = try!(self.auth.client(&repo.owner, &repo.repo));
+        let url = format!(
+            "/repos/{}/{}/contents/{}?ref={}",
+            repo.owner,
+            repo.repo,
+            vcs::REPO_CONFIG,
+            byte_serialize(repo.master_branch.as_bytes()).collect::<String>(),
+        );
+        debug!("Get repo config: {}", url);
+        #[derive(Deserialize, Serialize)]
+        struct ContentDesc {
+            content: String,
+            encoding: String,
+        }
+        let resp = try!(self.auth.rate_limit().send(true, || {
+            client.get(&url).expect("valid url")
+                .header(Self::accept())
+                .send()
+        }, |resp| &resp.http));
+        if resp.http.status == StatusCode::NotFound {
+            return Ok(None);
+        }
+        if !resp.is_success() {
+            return Err(GithubRequestError::HttpStatus(resp.http.status));
+        }
+        let content_desc: ContentDesc = try!(resp.json());
+        let contents = if content_desc.encoding == "base64" {
+            base64_decode(&content_desc.content)
+        } else {
+            None
+        };
+        match contents {
+            Some(contents) =>
+                Ok(Some(String::from_utf8_lossy(&contents).into_owned())),
+            None => Err(GithubRequestError::Encoding(content_desc.encoding)),
         }
     }
     fn move_to_master(
@@ -119,6 +174,7 @@ impl Worker {
             Some(repo) => repo,
             None => return Err(GithubRequestError::Pipeline(pipeline_id)),
         };
+        let client = try!(self.auth.client(&repo.owner, &repo.repo));
 
         // If GitHub's UI is also being used, it will also do this. But
         // protected branches rely on a strict happens-before relationship.
@@ -142,11 +198,11 @@ impl Worker {
             description: "Tests passed".to_owned(),
             context: "continuous-integration/aelita".to_owned(),
         };
-        let resp = try!(
-            try!(self.client.post(&url).expect("url").json(&status_body))
-                .header(Self::accept())
-                .send()
-        );
+        let resp = try!(self.auth.rate_limit().send(false, || {
+            let req = try!(client.post(&url).expect("url").json(&status_body));
+            let req = req.header(Self::accept());
+            Ok::<_, GithubRequestError>(try!(req.send()))
+        }, |resp| &resp.http));
         if !resp.is_success() {
             return Err(GithubRequestError::HttpStatus(resp.http.status))
         }
@@ -167,14 +223,13 @@ impl Worker {
             force: false,
             sha: merge_commit.to_string(),
         };
-        let resp = try!(
-            try!(
-                self.client.patch(&url).expect("valid url")
-                    .json(&update_desc)
-            )
-                .header(Self::accept())
-                .send()
-        );
+        let resp = try!(self.auth.rate_limit().send(true, || {
+            let req = try!(
+                client.patch(&url).expect("valid url").json(&update_desc)
+            );
+            let req = req.header(Self::accept());
+            Ok::<_, GithubRequestError>(try!(req.send()))
+        }, |resp| &resp.http));
         if !resp.is_success() {
             return Err(GithubRequestError::HttpStatus(resp.http.status));
         }
@@ -190,6 +245,7 @@ impl Worker {
             Some(repo) => repo,
             None => return Err(GithubRequestError::Pipeline(pipeline_id)),
         };
+        let client = try!(self.auth.client(&repo.owner, &repo.repo));
         // Step 1: get the contents of master.
         let url = format!(
             "/repos/{}/{}/git/refs/heads/{}",
@@ -206,11 +262,11 @@ impl Worker {
         struct RefDesc {
             object: ObjectDesc,
         }
-        let resp = try!(
-            self.client.get(&url).expect("valid url")
+        let resp = try!(self.auth.rate_limit().send(true, || {
+            client.get(&url).expect("valid url")
                 .header(Self::accept())
                 .send()
-        );
+        }, |resp| &resp.http));
         if !resp.is_success() {
             return Err(GithubRequestError::HttpStatus(resp.http.status));
         }
@@ -225,11 +281,11 @@ impl Worker {
             repo.repo,
             repo.staging_branch
         );
-        let resp = try!(
-            self.client.get(&url).expect("valid url")
+        let resp = try!(self.auth.rate_limit().send(true, || {
+            client.get(&url).expect("valid url")
                 .header(Self::accept())
                 .send()
-        );
+        }, |resp| &resp.http));
         let staging_up_to_date = if resp.is_success() {
             let resp_desc: RefDesc = try!(resp.json());
             let init_staging_sha = resp_desc.object.sha;
@@ -250,14 +306,13 @@ impl Worker {
                     force: true,
                     sha: master_sha,
                 };
-                let resp = try!(
-                    try!(
-                        self.client.patch(&url).expect("valid url")
-                            .json(&update_desc)
-                    )
-                        .header(Self::accept())
-                        .send()
-                );
+                let resp = try!(self.auth.rate_limit().send(true, || {
+                    let req = try!(
+                        client.patch(&url).expect("valid url").json(&update_desc)
+                    );
+                    let req = req.header(Self::accept());
+                    Ok::<_, GithubRequestError>(try!(req.send()))
+                }, |resp| &resp.http));
                 if !resp.is_success() {
                     return Err(GithubRequestError::HttpStatus(
                         resp.http.status
@@ -281,14 +336,13 @@ impl Worker {
                     git_ref: format!("refs/heads/{}", repo.staging_branch),
                     sha: master_sha,
                 };
-                let resp = try!(
-                    try!(
-                        self.client.post(&url).expect("valid url")
-                            .json(&create_desc)
-                    )
-                        .header(Self::accept())
-                        .send()
-                );
+                let resp = try!(self.auth.rate_limit().send(false, || {
+                    let req = try!(
+                        client.post(&url).expect("valid url").json(&create_desc)
+                    );
+                    let req = req.header(Self::accept());
+                    Ok::<_, GithubRequestError>(try!(req.send()))
+                }, |resp| &resp.http));
                 if !resp.is_success() {
                     return Err(GithubRequestError::HttpStatus(
                         resp.http.status
@@ -318,11 +372,13 @@ impl Worker {
             head: pull_commit.to_string(),
             commit_message: message,
         };
-        let resp = try!(
-            try!(self.client.post(&url).expect("valid url").json(&merge_desc))
-                .header(Self::accept())
-                .send()
-        );
+        let resp = try!(self.auth.rate_limit().send(false, || {
+            let req = try!(
+                client.post(&url).expect("valid url").json(&merge_desc)
+            );
+            let req = req.header(Self::accept());
+            Ok::<_, GithubRequestError>(try!(req.send()))
+        }, |resp| &resp.http));
         if !resp.is_success() {
             return Err(GithubRequestError::HttpStatus(resp.http.status));
         }
@@ -356,7 +412,14 @@ quick_error! {
             cause(err)
             from()
         }
+        /// Authentication error
+        Auth(err: github_auth::Error) {
+            cause(err)
+            from()
+        }
         /// Repo not found for pipeline
         Pipeline(pipeline_id: PipelineId) {}
+        /// File contents that could not be decoded
+        Encoding(encoding: String) {}
     }
 }
\ No newline at end of file
@@ -0,0 +1,468 @@
+// This file is released under the same terms as Rust itself.
+
+//! Merging through the GitLab API, without a working copy.
+//!
+//! GitLab does the merge itself, into `refs/merge-requests/<iid>/merge`.
+//! That commit carries GitLab's own merge message, so its tree is fetched
+//! into a bare repository and committed again with ours.
+//!
+//! The API cannot move a branch, so branches are pushed to over HTTPS,
+//! signed in with the API token. Master is only ever fast-forwarded to the
+//! exact commit that was tested; if it has moved, the push is rejected.
+
+use hyper;
+use hyper::method::Method;
+use hyper::status::StatusCode;
+use pipeline::{self, PipelineId};
+use serde_json::{self, from_reader as json_from_reader};
+use std::convert::From;
+use std::io::{self, Read};
+use std::sync::mpsc::{Sender, Receiver};
+use url::form_urlencoded::byte_serialize;
+use util::gitlab_api::{self, Api};
+use vcs::{self, Commit};
+use vcs::git::{Bare, GitError};
+
+pub trait PipelinesConfig: Send + Sync + 'static {
+    fn repo_by_pipeline(&self, PipelineId) -> Option<Repo>;
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Repo {
+    /// The project's full path, like `group/subgroup/project`
+    pub path: String,
+    pub master_branch: String,
+    pub staging_branch: String,
+    pub push_to_master: bool,
+}
+
+impl Repo {
+    fn api_id(&self) -> String {
+        gitlab_api::encode(&self.path)
+    }
+}
+
+pub struct Worker {
+    pipelines: Box<PipelinesConfig>,
+    api: Api,
+    git: Bare,
+}
+
+impl Worker {
+    pub fn new(
+        api: Api,
+        git: Bare,
+        pipelines: Box<PipelinesConfig>
+    ) -> Worker {
+        Worker{
+            pipelines: pipelines,
+            api: api,
+            git: git,
+        }
+    }
+}
+
+// JSON API structs
+#[derive(Deserialize, Serialize)]
+struct BranchCommitDesc {
+    id: String,
+}
+#[derive(Deserialize, Serialize)]
+struct BranchDesc {
+    commit: BranchCommitDesc,
+}
+#[derive(Deserialize, Serialize)]
+struct CommitDesc {
+    id: String,
+    parent_ids: Vec<String>,
+}
+#[derive(Deserialize, Serialize)]
+struct MergeRequestDesc {
+    iid: u32,
+    state: String,
+    target_branch: String,
+    sha: Option<String>,
+}
+#[derive(Deserialize, Serialize)]
+struct MergeRefDesc {
+    commit_id: String,
+}
+
+impl pipeline::Worker<vcs::Event, vcs::Message> for Worker {
+    fn run(
+        &self,
+        recv_msg: Receiver<vcs::Message>,
+        mut send_event: Sender<vcs::Event>
+    ) {
+        loop {
+            self.handle_message(
+                recv_msg.recv().expect("Pipeline went away"),
+                &mut send_event,
+            );
+        }
+    }
+}
+
+impl Worker {
+    fn handle_message(
+        &self,
+        msg: vcs::Message,
+        send_event: &mut Sender<vcs::Event>,
+    ) {
+        match msg {
+            vcs::Message::MergeToStaging(
+                pipeline_id, pull_commit, message, _
+            ) => {
+                match self.merge_to_staging(
+                    pipeline_id, &pull_commit, message
+                ) {
+                    Ok(merge_commit) => {
+                        send_event.send(vcs::Event::MergedToStaging(
+                            pipeline_id,
+                            pull_commit,
+                            merge_commit,
+                        )).expect("Pipeline gone merge to staging");
+                    },
+                    Err(e) => {
+                        warn!("Failed to merge to staging: {:?}", e);
+                        send_event.send(vcs::Event::FailedMergeToStaging(
+                            pipeline_id,
+                            pull_commit,
+                        )).expect("Pipeline gone merge to staging error");
+                    }
+                }
+            }
+            vcs::Message::MoveStagingToMaster(pipeline_id, merge_commit) => {
+                match self.move_to_master(pipeline_id, &merge_commit) {
+                    Ok(()) => {
+                        send_event.send(vcs::Event::MovedToMaster(
+                            pipeline_id,
+                            merge_commit,
+                        )).expect("Pipeline gone move to master");
+                    },
+                    Err(e) => {
+                        warn!("Failed to move to master: {:?}", e);
+                        send_event.send(vcs::Event::FailedMoveToMaster(
+                            pipeline_id,
+                            merge_commit,
+                        )).expect("Pipeline gone move to master error");
+                    }
+                }
+            }
+            vcs::Message::FetchRepoConfig(pipeline_id) => {
+                match self.fetch_repo_config(pipeline_id) {
+                    Ok(contents) => {
+                        send_event.send(vcs::Event::FetchedRepoConfig(
+                            pipeline_id,
+                            contents,
+                        )).expect("Pipeline gone fetch repo config");
+                    },
+                    Err(e) => {
+                        warn!("Failed to read {}: {:?}", vcs::REPO_CONFIG, e);
+                    }
+                }
+            }
+        }
+    }
+    fn fetch_repo_config(
+        &self,
+        pipeline_id: PipelineId,
+    ) -> Result<Option<String>, GitlabRequestError> {
+        let repo = match self.pipelines.repo_by_pipeline(pipeline_id) {
+            Some(repo) => repo,
+            None => return Err(GitlabRequestError::Pipeline(pipeline_id)),
+        };
+        let path = format!(
+            "/projects/{}/repository/files/{}/raw?ref={}",
+            repo.api_id(),
+            gitlab_api::encode(vcs::REPO_CONFIG),
+            byte_serialize(repo.master_branch.as_bytes()).collect::<String>(),
+        );
+        debug!("Get repo config: {}", path);
+        let mut resp = try!(self.api.send(Method::Get, &path, None));
+        if resp.status == StatusCode::NotFound {
+            return Ok(None);
+        }
+        if !resp.status.is_success() {
+            return Err(GitlabRequestError::HttpStatus(resp.status));
+        }
+        let mut contents = String::new();
+        try!(resp.read_to_string(&mut contents));
+        Ok(Some(contents))
+    }
+    fn merge_to_staging(
+        &self,
+        pipeline_id: PipelineId,
+        pull_commit: &Commit,
+        message: String,
+    ) -> Result<Commit, GitlabRequestError> {
+        let repo = match self.pipelines.repo_by_pipeline(pipeline_id) {
+            Some(repo) => repo,
+            None => return Err(GitlabRequestError::Pipeline(pipeline_id)),
+        };
+        // Step 1: find the merge request, and the contents of master.
+        let iid = try!(self.find_mr(&repo, pull_commit));
+        let master_sha = try!(self.branch_head(&repo, &repo.master_branch));
+        // Step 2: have GitLab merge the MR into its merge ref.
+        // This fails if the MR does not merge cleanly.
+        let path = format!(
+            "/projects/{}/merge_requests/{}/merge_ref",
+            repo.api_id(),
+            iid,
+        );
+        debug!("Refresh merge ref: {}", path);
+        let resp = try!(self.api.send(Method::Get, &path, None));
+        if !resp.status.is_success() {
+            return Err(GitlabRequestError::HttpStatus(resp.status));
+        }
+        let merge_ref: MergeRefDesc = try!(json_from_reader(resp));
+        let merge_commit = try!(self.get_commit(&repo, &merge_ref.commit_id));
+        if !is_merge_of(&merge_commit, &master_sha, pull_commit) {
+            return Err(GitlabRequestError::StaleMergeRef(
+                Commit::from(merge_commit.id)
+            ));
+        }
+        // Step 3: commit GitLab's tree again, with our own message.
+        let dir = try!(self.git.setup_dir(&format!("gitlab/{}", repo.path)));
+        let origin = self.api.git_url(&repo.path);
+        let refspec =
+            format!("+refs/merge-requests/{}/merge:refs/aelita/merge", iid);
+        try!(self.git.run(&dir, &origin, &[
+            "fetch",
+            "origin",
+            &refspec[..],
+        ]));
+        let fetched = try!(self.git.run(&dir, &origin, &[
+            "rev-parse",
+            "refs/aelita/merge",
+        ]));
+        if fetched != merge_commit.id {
+            return Err(GitlabRequestError::StaleMergeRef(
+                Commit::from(fetched)
+            ));
+        }
+        let tree = format!("{}^{{tree}}", merge_commit.id);
+        let staging_commit = try!(self.git.run(&dir, &origin, &[
+            "commit-tree",
+            &tree[..],
+            "-p",
+            master_sha.as_str(),
+            "-p",
+            pull_commit.as_str(),
+            "-m",
+            &message[..],
+        ]));
+        // Step 4: point staging at it.
+        let refspec =
+            format!("{}:refs/heads/{}", staging_commit, repo.staging_branch);
+        try!(self.git.run(&dir, &origin, &[
+            "push",
+            "--force",
+            "origin",
+            &refspec[..],
+        ]));
+        Ok(Commit::from(staging_commit))
+    }
+    fn move_to_master(
+        &self,
+        pipeline_id: PipelineId,
+        merge_commit: &Commit,
+    ) -> Result<(), GitlabRequestError> {
+        let repo = match self.pipelines.repo_by_pipeline(pipeline_id) {
+            Some(repo) => repo,
+            None => return Err(GitlabRequestError::Pipeline(pipeline_id)),
+        };
+        if !repo.push_to_master {
+            return Ok(());
+        }
+        // The commit may have been made before a restart, or elsewhere.
+        let dir = try!(self.git.setup_dir(&format!("gitlab/{}", repo.path)));
+        let origin = self.api.git_url(&repo.path);
+        let refspec =
+            format!("+refs/heads/{}:refs/aelita/staging", repo.staging_branch);
+        try!(self.git.run(&dir, &origin, &[
+            "fetch",
+            "origin",
+            &refspec[..],
+        ]));
+        // Without --force, this is rejected unless it is a fast-forward.
+        let refspec =
+            format!("{}:refs/heads/{}", merge_commit, repo.master_branch);
+        try!(self.git.run(&dir, &origin, &[
+            "push",
+            "origin",
+            &refspec[..],
+        ]));
+        Ok(())
+    }
+    /// The open MR into master whose head is `pull_commit`.
+    fn find_mr(
+        &self,
+        repo: &Repo,
+        pull_commit: &Commit,
+    ) -> Result<u32, GitlabRequestError> {
+        let path = format!(
+            "/projects/{}/repository/commits/{}/merge_requests",
+            repo.api_id(),
+            pull_commit,
+        );
+        debug!("Find merge request: {}", path);
+        let resp = try!(self.api.send(Method::Get, &path, None));
+        if !resp.status.is_success() {
+            return Err(GitlabRequestError::HttpStatus(resp.status));
+        }
+        let mrs: Vec<MergeRequestDesc> = try!(json_from_reader(resp));
+        match find_mr(&mrs, repo, pull_commit) {
+            Some(iid) => Ok(iid),
+            None => Err(GitlabRequestError::NoMergeRequest(
+                pull_commit.clone()
+            )),
+        }
+    }
+    fn branch_head(
+        &self,
+        repo: &Repo,
+        branch: &str,
+    ) -> Result<Commit, GitlabRequestError> {
+        let path = format!(
+            "/projects/{}/repository/branches/{}",
+            repo.api_id(),
+            gitlab_api::encode(branch),
+        );
+        debug!("Get branch SHA: {}", path);
+        let resp = try!(self.api.send(Method::Get, &path, None));
+        if !resp.status.is_success() {
+            return Err(GitlabRequestError::HttpStatus(resp.status));
+        }
+        let branch: BranchDesc = try!(json_from_reader(resp));
+        Ok(Commit::from(branch.commit.id))
+    }
+    fn get_commit(
+        &self,
+        repo: &Repo,
+        sha: &str,
+    ) -> Result<CommitDesc, GitlabRequestError> {
+        let path = format!(
+            "/projects/{}/repository/commits/{}",
+            repo.api_id(),
+            sha,
+        );
+        let resp = try!(self.api.send(Method::Get, &path, None));
+        if !resp.status.is_success() {
+            return Err(GitlabRequestError::HttpStatus(resp.status));
+        }
+        Ok(try!(json_from_reader(resp)))
+    }
+}
+
+fn find_mr(
+    mrs: &[MergeRequestDesc],
+    repo: &Repo,
+    pull_commit: &Commit,
+) -> Option<u32> {
+    mrs.iter().find(|mr| {
+        mr.state == "opened" &&
+            mr.target_branch == repo.master_branch &&
+            mr.sha.as_ref().map(|s| &s[..]) == Some(pull_commit.as_str())
+    }).map(|mr| mr.iid)
+}
+
+/// Is `commit` exactly the merge of `pull_commit` into `master_sha`?
+fn is_merge_of(
+    commit: &CommitDesc,
+    master_sha: &Commit,
+    pull_commit: &Commit,
+) -> bool {
+    commit.parent_ids.len() == 2 &&
+        commit.parent_ids[0] == master_sha.as_str() &&
+        commit.parent_ids[1] == pull_commit.as_str()
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum GitlabRequestError {
+        /// HTTP-level error
+        HttpStatus(status: StatusCode) {}
+        /// HTTP-level error
+        Http(err: hyper::error::Error) {
+            cause(err)
+            from()
+        }
+        /// JSON error
+        Json(err: serde_json::error::Error) {
+            cause(err)
+            from()
+        }
+        /// I/O error
+        Io(err: io::Error) {
+            cause(err)
+            from()
+        }
+        /// Repo not found for pipeline
+        Pipeline(pipeline_id: PipelineId) {}
+        /// No open merge request into master has this head
+        NoMergeRequest(pull_commit: Commit) {}
+        /// The merge ref is not a merge of the MR into the current master
+        StaleMergeRef(merge_commit: Commit) {}
+        /// git failed, or refused to push
+        Git(err: GitError) {
+            cause(err)
+            from()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::from_slice as json_from_slice;
+    use super::{CommitDesc, MergeRequestDesc, Repo, find_mr, is_merge_of};
+    use vcs::Commit;
+
+    const MASTER: &'static str = "1111111111111111111111111111111111111111";
+    const PULL: &'static str = "2222222222222222222222222222222222222222";
+
+    fn repo() -> Repo {
+        Repo{
+            path: "group/project".to_owned(),
+            master_branch: "master".to_owned(),
+            staging_branch: "staging".to_owned(),
+            push_to_master: true,
+        }
+    }
+
+    #[test]
+    fn test_find_mr() {
+        let mrs: Vec<MergeRequestDesc> = json_from_slice(format!(r#"[
+            {{"iid": 1, "state": "merged", "target_branch": "master",
+              "sha": "{0}"}},
+            {{"iid": 2, "state": "opened", "target_branch": "release",
+              "sha": "{0}"}},
+            {{"iid": 3, "state": "opened", "target_branch": "master",
+              "sha": "{0}"}}
+        ]"#, PULL).as_bytes()).unwrap();
+        assert_eq!(find_mr(&mrs, &repo(), &Commit::from(PULL.to_owned())),
+            Some(3));
+        assert_eq!(find_mr(&mrs, &repo(), &Commit::from(MASTER.to_owned())),
+            None);
+    }
+
+    #[test]
+    fn test_is_merge_of() {
+        let master = Commit::from(MASTER.to_owned());
+        let pull = Commit::from(PULL.to_owned());
+        let merge = CommitDesc{
+            id: "3333333333333333333333333333333333333333".to_owned(),
+            parent_ids: vec![MASTER.to_owned(), PULL.to_owned()],
+        };
+        assert!(is_merge_of(&merge, &master, &pull));
+        assert!(!is_merge_of(&merge, &pull, &master));
+        let stale = CommitDesc{
+            id: "4444444444444444444444444444444444444444".to_owned(),
+            parent_ids: vec![
+                "5555555555555555555555555555555555555555".to_owned(),
+                PULL.to_owned(),
+            ],
+        };
+        assert!(!is_merge_of(&stale, &master, &pull));
+    }
+}
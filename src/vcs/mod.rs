@@ -1,16 +1,23 @@
 // This file is released under the same terms as Rust itself.
 
 pub mod git;
+pub mod gitea;
 pub mod github;
+pub mod gitlab;
 
 use config::PipelinesConfig;
 use pipeline::{GetPipelineId, PipelineId};
 use std::fmt::{self, Display};
 
+/// Where a repository keeps its own settings, on each branch PRs go into.
+pub const REPO_CONFIG: &'static str = ".aelita.toml";
+
 #[derive(Clone, Debug)]
 pub enum Message {
     MergeToStaging(PipelineId, Commit, String, Remote),
     MoveStagingToMaster(PipelineId, Commit),
+    /// Read `REPO_CONFIG` from the branch the pipeline merges into.
+    FetchRepoConfig(PipelineId),
 }
 
 #[derive(Clone, Debug)]
@@ -19,6 +26,9 @@ pub enum Event {
     FailedMergeToStaging(PipelineId, Commit),
     MovedToMaster(PipelineId, Commit),
     FailedMoveToMaster(PipelineId, Commit),
+    /// The contents of `REPO_CONFIG`, or `None` if there isn't one.
+    /// Nothing is sent if it couldn't be read.
+    FetchedRepoConfig(PipelineId, Option<String>),
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -70,12 +80,14 @@ impl Display for Remote {
 }
 
 impl GetPipelineId for Event {
-    fn pipeline_id<C: PipelinesConfig + ?Sized>(&self, _: &C) -> PipelineId {
-        match *self {
+    fn pipeline_id<C: PipelinesConfig + ?Sized>(&self, _: &C)
+            -> Option<PipelineId> {
+        Some(match *self {
         	Event::MergedToStaging(i, _, _) => i,
     		Event::FailedMergeToStaging(i, _) => i,
     		Event::MovedToMaster(i, _) => i,
     		Event::FailedMoveToMaster(i, _) => i,
-        }
+    		Event::FetchedRepoConfig(i, _) => i,
+        })
     }
 }
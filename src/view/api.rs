@@ -0,0 +1,328 @@
+// This file is released under the same terms as Rust itself.
+
+//! JSON endpoints, for driving the queue from scripts.
+//!
+//! Every request needs an `Authorization: Bearer` header with the view's
+//! API token. Without a token in the config, the API is turned off.
+//!
+//! * `GET /api/pipelines` lists the pipelines, and whether they are paused.
+//! * `GET /api/entries?pipeline=NAME` lists what is running, what is queued,
+//!   and every opened pull request (including the queued and running ones).
+//! * `POST /api/approve` with `{"pipeline", "pr", "commit"}`, and optionally
+//!   `"reviewer"`, adds a pull request to the queue.
+//! * `POST /api/cancel` with `{"pipeline", "pr"}` takes it back out.
+//! * `POST /api/prioritize` with `{"pipeline", "pr", "priority"}` moves a
+//!   queued pull request. Higher priorities are built first.
+//! * `POST /api/pause` and `POST /api/resume` with `{"pipeline"}` stop and
+//!   restart taking pull requests off of the queue. A running build is left
+//!   to finish.
+//!
+//! Actions are handed to the pipeline the same way commands from a pull
+//! request's comments are, so they are answered with `202 Accepted` before
+//! they take effect.
+
+use db::{Db, InfoTransaction};
+use hyper::header::{Authorization, Bearer, ContentType};
+use hyper::method::Method;
+use hyper::server::{Request, Response};
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
+use quickersort::sort_by;
+use serde::Serialize;
+use serde_json::{self, from_reader as json_from_reader};
+use std::error::Error;
+use ui::{self, Pr};
+use url::form_urlencoded;
+use util::crypto::verify_token;
+use vcs::Commit;
+use view::{Event, Thread, WrapConvertable};
+
+impl<'a> Thread<'a> {
+    /// Whether the request carries the API token.
+    /// Always false if there is no token in the config.
+    pub fn has_api_token(&self, req: &Request) -> bool {
+        let token = match self.api_token {
+            Some(token) => token,
+            None => return false,
+        };
+        req.headers.get::<Authorization<Bearer>>()
+            .map(|auth| {
+                verify_token(token.as_bytes(), auth.0.token.as_bytes())
+            })
+            .unwrap_or(false)
+    }
+    pub fn handle_api_req(
+        &mut self,
+        mut req: Request,
+        mut res: Response,
+    ) -> Result<(), Box<Error>> {
+        if self.api_token.is_none() {
+            *res.status_mut() = StatusCode::NotFound;
+            try!(res.send(&[]));
+            return Ok(());
+        }
+        if !self.has_api_token(&req) {
+            return send_error(res, StatusCode::Unauthorized, "Bad token");
+        }
+        let (path, query) = if let RequestUri::AbsolutePath(ref path) =
+                req.uri {
+            let mut parts = path.splitn(2, '?');
+            (
+                parts.next().unwrap_or("").to_owned(),
+                parts.next().unwrap_or("").to_owned(),
+            )
+        } else {
+            return send_error(res, StatusCode::BadRequest, "Bad URI");
+        };
+        let method = req.method.clone();
+        macro_rules! try_json {
+            ($req: ident, $res: ident) => (
+                match json_from_reader(&mut $req) {
+                    Ok(desc) => desc,
+                    Err(e) => {
+                        warn!("Bad API request body: {:?}", e);
+                        return send_error(
+                            $res,
+                            StatusCode::BadRequest,
+                            "Bad request body",
+                        );
+                    }
+                }
+            )
+        }
+        macro_rules! try_pipeline {
+            ($this: ident, $name: expr, $res: ident) => (
+                match $this.pipelines.pipeline_by_name(&$name) {
+                    Some(pipeline_id) => pipeline_id,
+                    None => return send_error(
+                        $res,
+                        StatusCode::NotFound,
+                        "No such pipeline",
+                    ),
+                }
+            )
+        }
+        let event = match (method, &path[..]) {
+            (Method::Get, "/api/pipelines") => {
+                let mut pipelines = self.pipelines.all();
+                sort_by(&mut pipelines, &|a, b| a.0.cmp(&b.0));
+                let mut descs = Vec::with_capacity(pipelines.len());
+                for (name, pipeline_id) in pipelines {
+                    descs.push(PipelineDesc{
+                        name: name.into_owned(),
+                        paused: try!(self.db.is_paused(pipeline_id).wc()),
+                    });
+                }
+                return send_json(res, StatusCode::Ok, &descs);
+            }
+            (Method::Get, "/api/entries") => {
+                let mut name = None;
+                for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                    if key == "pipeline" {
+                        name = Some(value.into_owned());
+                    }
+                }
+                let name = match name {
+                    Some(name) => name,
+                    None => return send_error(
+                        res,
+                        StatusCode::BadRequest,
+                        "Missing pipeline parameter",
+                    ),
+                };
+                let pipeline_id = try_pipeline!(self, name, res);
+                let info = try!(self.db.transaction(InfoTransaction{
+                    pipeline_id: pipeline_id,
+                }).wc());
+                let desc = EntriesDesc{
+                    paused: info.paused,
+                    running: info.running.map(|entry| RunningDesc{
+                        pr: entry.pr.into(),
+                        commit: entry.pull_commit.into(),
+                        merge_commit: entry.merge_commit.map(Into::into),
+                        canceled: entry.canceled,
+                        built: entry.built,
+                    }),
+                    queued: info.queue.into_iter().map(|entry| QueuedDesc{
+                        pr: entry.pr.into(),
+                        commit: entry.commit.into(),
+                        priority: entry.priority,
+                    }).collect(),
+                    pending: info.pending.into_iter().map(|entry| PendingDesc{
+                        pr: entry.pr.into(),
+                        commit: entry.commit.into(),
+                        title: entry.title,
+                        url: entry.url.to_string(),
+                        draft: entry.draft,
+                    }).collect(),
+                };
+                return send_json(res, StatusCode::Ok, &desc);
+            }
+            (Method::Post, "/api/approve") => {
+                let desc: ApproveDesc = try_json!(req, res);
+                let pipeline_id = try_pipeline!(self, desc.pipeline, res);
+                let pr = Pr::from(desc.pr);
+                // The merge commit message is made from the title.
+                let pending = try!(
+                    self.db.peek_pending_by_pr(pipeline_id, &pr).wc()
+                );
+                let pending = match pending {
+                    Some(pending) => pending,
+                    None => return send_error(
+                        res,
+                        StatusCode::NotFound,
+                        "No such pull request",
+                    ),
+                };
+                let message = match desc.reviewer {
+                    Some(reviewer) => format!(
+                        "{}\n\nMerge #{} r=@{}",
+                        pending.title,
+                        pr,
+                        reviewer,
+                    ),
+                    None => format!("{}\n\nMerge #{}", pending.title, pr),
+                };
+                ui::Event::Approved(
+                    pipeline_id,
+                    pr,
+                    Some(Commit::from(desc.commit)),
+                    message,
+                )
+            }
+            (Method::Post, "/api/cancel") => {
+                let desc: PrDesc = try_json!(req, res);
+                let pipeline_id = try_pipeline!(self, desc.pipeline, res);
+                ui::Event::Canceled(pipeline_id, Pr::from(desc.pr))
+            }
+            (Method::Post, "/api/prioritize") => {
+                let desc: PrioritizeDesc = try_json!(req, res);
+                let pipeline_id = try_pipeline!(self, desc.pipeline, res);
+                ui::Event::Prioritized(
+                    pipeline_id,
+                    Pr::from(desc.pr),
+                    desc.priority,
+                )
+            }
+            (Method::Post, "/api/pause") => {
+                let desc: PipelineRefDesc = try_json!(req, res);
+                let pipeline_id = try_pipeline!(self, desc.pipeline, res);
+                ui::Event::Paused(pipeline_id, true)
+            }
+            (Method::Post, "/api/resume") => {
+                let desc: PipelineRefDesc = try_json!(req, res);
+                let pipeline_id = try_pipeline!(self, desc.pipeline, res);
+                ui::Event::Paused(pipeline_id, false)
+            }
+            (_, "/api/pipelines") | (_, "/api/entries") |
+            (_, "/api/approve") | (_, "/api/cancel") |
+            (_, "/api/prioritize") | (_, "/api/pause") |
+            (_, "/api/resume") => {
+                return send_error(
+                    res,
+                    StatusCode::MethodNotAllowed,
+                    "Method not allowed",
+                );
+            }
+            _ => {
+                return send_error(res, StatusCode::NotFound, "Not found");
+            }
+        };
+        self.send_event.send(Event::UiEvent(event)).unwrap();
+        *res.status_mut() = StatusCode::Accepted;
+        try!(res.send(&[]));
+        Ok(())
+    }
+}
+
+fn send_json<T: Serialize>(
+    mut res: Response,
+    status: StatusCode,
+    value: &T,
+) -> Result<(), Box<Error>> {
+    let body = try!(serde_json::to_string(value));
+    *res.status_mut() = status;
+    res.headers_mut().set(ContentType::json());
+    try!(res.send(body.as_bytes()));
+    Ok(())
+}
+
+fn send_error(
+    res: Response,
+    status: StatusCode,
+    error: &str,
+) -> Result<(), Box<Error>> {
+    send_json(res, status, &ErrorDesc{
+        error: error.to_owned(),
+    })
+}
+
+#[derive(Deserialize, Serialize)]
+struct ApproveDesc {
+    pipeline: String,
+    pr: String,
+    commit: String,
+    reviewer: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct PrDesc {
+    pipeline: String,
+    pr: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct PrioritizeDesc {
+    pipeline: String,
+    pr: String,
+    priority: i32,
+}
+
+#[derive(Deserialize, Serialize)]
+struct PipelineRefDesc {
+    pipeline: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct PipelineDesc {
+    name: String,
+    paused: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+struct EntriesDesc {
+    paused: bool,
+    running: Option<RunningDesc>,
+    queued: Vec<QueuedDesc>,
+    pending: Vec<PendingDesc>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct RunningDesc {
+    pr: String,
+    commit: String,
+    merge_commit: Option<String>,
+    canceled: bool,
+    built: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+struct QueuedDesc {
+    pr: String,
+    commit: String,
+    priority: i32,
+}
+
+#[derive(Deserialize, Serialize)]
+struct PendingDesc {
+    pr: String,
+    commit: String,
+    title: String,
+    url: String,
+    draft: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ErrorDesc {
+    error: String,
+}
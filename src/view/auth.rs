@@ -14,6 +14,7 @@ use std::convert::AsRef;
 use url::form_urlencoded;
 use util::USER_AGENT;
 use util::crypto::{generate_sha1_hmac, verify_sha1_hmac};
+use util::github_rate_limit::RateLimit;
 
 const GH_TOKEN_URI: &'static str =
     "https://github.com/login/oauth/access_token";
@@ -51,6 +52,7 @@ impl<'a> From<&'a Auth> for AuthRef<'a> {
 pub struct AuthManager<'a> {
     pub auth: AuthRef<'a>,
     pub secret: &'a str,
+    pub rate_limit: &'a RateLimit,
 }
 
 pub enum CheckResult<'a, 'b: 'a, 'c> {
@@ -208,11 +210,13 @@ impl<'a> AuthManager<'a> {
                                 .append_pair("state", &state)
                                 .finish();
                         let gh_res = try_map!(
-                            client
-                                .post(GH_TOKEN_URI)
-                                .body(gh_req.as_bytes())
-                                .headers(headers)
-                                .send(),
+                            self.rate_limit.send(false, || {
+                                client
+                                    .post(GH_TOKEN_URI)
+                                    .body(gh_req.as_bytes())
+                                    .headers(headers.clone())
+                                    .send()
+                            }, |gh_res| gh_res),
                             ErrGithubToken, res
                         );
                         #[derive(Deserialize, Serialize)]
@@ -232,15 +236,18 @@ impl<'a> AuthManager<'a> {
                             qitem(Mime(TopLevel::Application, SubLevel::Json,
                                        vec![(Attr::Charset, Value::Utf8)])),
                         ]));
+                        let url = format!(
+                            "{}/user?access_token={}",
+                            GH_API_URI,
+                            gh_token
+                        );
                         let gh_res = try_map!(
-                            client
-                                .get(&format!(
-                                    "{}/user?access_token={}",
-                                    GH_API_URI,
-                                    gh_token
-                                ))
-                                .headers(headers)
-                                .send(),
+                            self.rate_limit.send(true, || {
+                                client
+                                    .get(&url)
+                                    .headers(headers.clone())
+                                    .send()
+                            }, |gh_res| gh_res),
                             ErrGithubUsername, res
                         );
                         #[derive(Deserialize, Serialize)]
@@ -260,17 +267,20 @@ impl<'a> AuthManager<'a> {
                             qitem(Mime(TopLevel::Application, SubLevel::Json,
                                        vec![(Attr::Charset, Value::Utf8)])),
                         ]));
+                        let url = format!(
+                            "{}/orgs/{}/members/{}?access_token={}",
+                            GH_API_URI,
+                            org,
+                            gh_user,
+                            gh_token
+                        );
                         let gh_res = try_map!(
-                            client
-                                .get(&format!(
-                                    "{}/orgs/{}/members/{}?access_token={}",
-                                    GH_API_URI,
-                                    org,
-                                    gh_user,
-                                    gh_token
-                                ))
-                                .headers(headers)
-                                .send(),
+                            self.rate_limit.send(true, || {
+                                client
+                                    .get(&url)
+                                    .headers(headers.clone())
+                                    .send()
+                            }, |gh_res| gh_res),
                             ErrGithubOrgCheck, res
                         );
                         if gh_res.status.is_success() {
@@ -1,9 +1,12 @@
 // This file is released under the same terms as Rust itself.
 
+mod api;
 mod auth;
 
+use ci::CiId;
 use crossbeam;
-use db::{self, Db, DbBox, PendingEntry, QueueEntry, RunningEntry, Transaction};
+use db::{self, CiState, CiStateEntry, Db, DbBox, FlakyEntry};
+use db::{InfoTransaction, PendingEntry, Transaction};
 use horrorshow::prelude::*;
 use hyper::buffer::BufReader;
 use hyper::header::{ContentType, Headers};
@@ -20,11 +23,18 @@ use std::error::Error;
 use std::fmt::{self, Formatter};
 use std::io::{BufWriter, Write};
 use std::sync::mpsc::{Receiver, Sender};
+use ui;
+use util;
+use util::github_rate_limit::{self, RateLimit};
+use vcs;
 use view::auth::AuthManager;
 
 pub trait PipelinesConfig: Send + Sync + 'static {
     fn pipeline_by_name(&self, &str) -> Option<PipelineId>;
     fn all(&self) -> Vec<(Cow<str>, PipelineId)>;
+    fn cis_by_pipeline(&self, PipelineId) -> Vec<(CiId, Cow<str>)>;
+    /// What is wrong with the settings the repo keeps for itself.
+    fn problems(&self, PipelineId) -> Vec<Cow<str>>;
 }
 
 pub use view::auth::{Auth, AuthRef};
@@ -37,6 +47,10 @@ pub struct Worker {
     pipelines: Box<PipelinesConfig>,
     secret: String,
     auth: Auth,
+    /// Needed to use the JSON API; without it, the API is turned off
+    api_token: Option<String>,
+    /// For the GitHub API calls made while logging users in
+    rate_limit: RateLimit,
 }
 
 impl Worker {
@@ -46,6 +60,7 @@ impl Worker {
         pipelines: Box<PipelinesConfig>,
         secret: String,
         auth: Auth,
+        api_token: Option<String>,
     ) -> Self {
         Worker {
             listen: listen,
@@ -53,40 +68,53 @@ impl Worker {
             pipelines: pipelines,
             secret: secret,
             auth: auth.into(),
+            api_token: api_token,
+            rate_limit: RateLimit::new("view_auth"),
         }
     }
 }
 
 #[derive(Clone)]
-pub enum Event {}
+pub enum Event {
+    /// Commands given through the JSON API, for the pipeline to handle
+    /// as if they came from the pipeline's front-end
+    UiEvent(ui::Event),
+}
 
 #[derive(Clone)]
 pub enum Message {}
 
 impl pipeline::Worker<Event, Message> for Worker {
-    fn run(&self, _recv: Receiver<Message>, _send: Sender<Event>) {
+    fn run(&self, _recv: Receiver<Message>, send: Sender<Event>) {
         let listen: &str = self.listen.as_ref();
         let secret: &str = self.secret.as_ref();
+        let api_token: Option<&str> =
+            self.api_token.as_ref().map(AsRef::as_ref);
         let auth: AuthRef = (&self.auth).into();
         let pipelines = &*self.pipelines;
         let db_build = &self.db_build;
+        let rate_limit = &self.rate_limit;
         crossbeam::scope(|scope| {
             let mut threads = Vec::with_capacity(THREAD_COUNT);
             for _ in 0..THREAD_COUNT {
-                let (send, recv) = spmc::channel();
+                let (send_stream, recv) = spmc::channel();
+                let send_event = send.clone();
                 scope.spawn(move || {
                     let mut thread = Thread {
                         db: db_build.open()
                             .expect("opening DB to succeed"),
                         pipelines: pipelines,
+                        send_event: send_event,
+                        api_token: api_token,
                         auth_manager: AuthManager{
                             auth: auth,
                             secret: secret,
+                            rate_limit: rate_limit,
                         },
                     };
                     thread.run(recv)
                 });
-                threads.push(send);
+                threads.push(send_stream);
             }
             let mut listener = HttpListener::new(listen).expect("TCP socket");
             let mut i = 0;
@@ -102,6 +130,8 @@ impl pipeline::Worker<Event, Message> for Worker {
 struct Thread<'a> {
     db: DbBox,
     pipelines: &'a PipelinesConfig,
+    send_event: Sender<Event>,
+    api_token: Option<&'a str>,
     auth_manager: AuthManager<'a>,
 }
 
@@ -135,8 +165,36 @@ impl<'a> Thread<'a> {
     fn handle_req(
         &mut self,
         req: Request,
-        res: Response,
+        mut res: Response,
     ) -> Result<(), Box<Error>> {
+        // Scrapers can't log in, so metrics take the API token instead.
+        if let RequestUri::AbsolutePath(ref path) = req.uri {
+            if path == "/metrics" {
+                if !self.has_api_token(&req) {
+                    *res.status_mut() = if self.api_token.is_some() {
+                        StatusCode::Unauthorized
+                    } else {
+                        StatusCode::NotFound
+                    };
+                    try!(res.send(&[]));
+                    return Ok(());
+                }
+                res.headers_mut().set(ContentType(mime!(Text/Plain)));
+                let mut res = try!(res.start());
+                let metrics = github_rate_limit::metrics();
+                try!(res.write_all(metrics.as_bytes()));
+                return Ok(());
+            }
+        }
+        // The API has its own authentication, since scripts cannot log in.
+        let is_api = if let RequestUri::AbsolutePath(ref path) = req.uri {
+            path.starts_with("/api/")
+        } else {
+            false
+        };
+        if is_api {
+            return self.handle_api_req(req, res);
+        }
         let (req, mut res) = match self.auth_manager.check(req, res) {
             auth::CheckResult::Authenticated(req, res) => (req, res),
             auth::CheckResult::Err(e) => return Err(Box::new(e)),
@@ -187,10 +245,28 @@ impl<'a> Thread<'a> {
         _req: Request,
         mut res: Response<::hyper::net::Streaming>,
     ) -> Result<(), Box<Error>> {
-        let (pending_entries, queued_entries, running_entry) =
-            try!(self.db.transaction(InfoTransaction{
-                pipeline_id: pipeline_id
-            }).wc());
+        let info = try!(self.db.transaction(InfoTransaction{
+            pipeline_id: pipeline_id
+        }).wc());
+        let pending_entries = info.pending;
+        let queued_entries = info.queue;
+        let running_entry = info.running;
+        let mut cis = self.pipelines.cis_by_pipeline(pipeline_id);
+        sort_by(&mut cis, &|a, b| a.1.cmp(&b.1));
+        let ci_states = try!(self.db.transaction(JobsTransaction{
+            ci_ids: cis.iter().map(|x| x.0).collect(),
+        }).wc());
+        let mut jobs = Vec::new();
+        let mut flaky = Vec::new();
+        for ((_, name), (state, flaky_entries)) in
+                cis.into_iter().zip(ci_states.into_iter()) {
+            for entry in flaky_entries {
+                flaky.push((name.clone(), entry));
+            }
+            jobs.push((name, state));
+        }
+        let problems = self.pipelines.problems(pipeline_id);
+        let problems_title = format!("Problems in {}", vcs::REPO_CONFIG);
         let is_empty = pending_entries.is_empty();
         let mut running = None;
         let mut queued = Vec::new();
@@ -216,6 +292,18 @@ impl<'a> Thread<'a> {
                 }
                 body {
                     h1 { : name }
+                    @ if !problems.is_empty() {
+                        h2 { : &problems_title }
+                        p {
+                            : "The repo's own settings are left out until \
+                               these are fixed."
+                        }
+                        ul {
+                            @ for problem in &problems {
+                                li { code { : &**problem } }
+                            }
+                        }
+                    }
                     table {
                         thead {
                             th { : "Status" }
@@ -243,6 +331,45 @@ impl<'a> Thread<'a> {
                             }
                         }
                     }
+                    @ if !jobs.is_empty() {
+                        h2 { : "Jobs" }
+                        table {
+                            thead {
+                                th { : "Status" }
+                                th { : "Job" }
+                                th { : "Duration" }
+                            }
+                            tbody {
+                                |t| {
+                                    for &(ref name, ref state) in &jobs {
+                                        render_job(name, state.as_ref(), t);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    @ if !flaky.is_empty() {
+                        h2 { : "Flaky failures" }
+                        p {
+                            : "Failures that went away when the job was retried."
+                        }
+                        table {
+                            thead {
+                                th { : "Job" }
+                                th { : "Failure" }
+                                th { : "Times" }
+                            }
+                            tbody {
+                                @ for &(ref name, ref entry) in &flaky {
+                                    tr {
+                                        td { : &**name }
+                                        td { code { : &entry.signature } }
+                                        td { : entry.count }
+                                    }
+                                }
+                            }
+                        }
+                    }
                 }
             }
         };
@@ -278,13 +405,16 @@ impl<'a> Thread<'a> {
                         tbody {
                             @ for &(ref n, pid) in &pipelines { |t| {
                                 let n = &**n;
-                                let (opened, queue, running) = 
+                                let info =
                                     self.db.transaction(InfoTransaction{
                                         pipeline_id: pid
-                                    }).unwrap_or((vec![], vec![], None));
-                                let opened = opened.len();
-                                let queue = queue.len();
-                                let running = running.is_some();
+                                    }).ok();
+                                let opened = info.as_ref()
+                                    .map_or(0, |info| info.pending.len());
+                                let queue = info.as_ref()
+                                    .map_or(0, |info| info.queue.len());
+                                let running = info.as_ref()
+                                    .map_or(false, |i| i.running.is_some());
                                 let running = if running { 1 } else { 0 };
                                 let review = opened - queue - running;
                                 t << html!{
@@ -325,31 +455,25 @@ impl<'a> Thread<'a> {
     }
 }
 
-struct InfoTransaction {
-    pipeline_id: PipelineId,
+struct JobsTransaction {
+    ci_ids: Vec<CiId>,
 }
 
-impl Transaction for InfoTransaction {
-    type Return = (
-        Vec<PendingEntry>,
-        Vec<QueueEntry>,
-        Option<RunningEntry>,
-    );
+impl Transaction for JobsTransaction {
+    type Return = Vec<(Option<CiStateEntry>, Vec<FlakyEntry>)>;
     fn run<D: Db>(
         self,
         db: &mut D
     ) -> Result<Self::Return, Box<Error + Send + Sync>> {
         retry!{{
-            let pending_entries = retry_unwrap!(
-                db.list_pending(self.pipeline_id)
-            );
-            let queued_entries = retry_unwrap!(
-                db.list_queue(self.pipeline_id)
-            );
-            let running_entry = retry_unwrap!(
-                db.peek_running(self.pipeline_id)
-            );
-            Ok((pending_entries, queued_entries, running_entry))
+            let mut jobs = Vec::new();
+            for &ci_id in &self.ci_ids {
+                jobs.push((
+                    retry_unwrap!(db.get_ci_state(ci_id)),
+                    retry_unwrap!(db.list_flaky(ci_id)),
+                ));
+            }
+            Ok(jobs)
         }}
     }
 }
@@ -413,3 +537,43 @@ fn render_entry(
         }
     };
 }
+
+fn render_job(
+    name: &str,
+    state: Option<&CiStateEntry>,
+    t: &mut TemplateBuffer,
+) {
+    let status = match state.map(|state| state.state) {
+        None => "Idle",
+        Some(CiState::Queued) => "Queued",
+        Some(CiState::Running) => "Running",
+        Some(CiState::Succeeded) => "Passed",
+        Some(CiState::Failed) => "Failed",
+        Some(CiState::Canceled) => "Canceled",
+    };
+    let url = state.and_then(|state| state.url.as_ref());
+    let duration = match state.and_then(|state| state.started) {
+        Some(started) => {
+            let finished = state.and_then(|state| state.finished)
+                .unwrap_or_else(util::now_secs);
+            let secs = if finished > started { finished - started } else { 0 };
+            format!("{}m {}s", secs / 60, secs % 60)
+        }
+        None => String::new(),
+    };
+    t << html!{
+        tr {
+            td { : status }
+            td(class="fill-link") {
+                |t| {
+                    if let Some(url) = url {
+                        t << html!{ a(href=url.to_string()) { : name } };
+                    } else {
+                        t << html!{ : name };
+                    }
+                }
+            }
+            td { : duration }
+        }
+    };
+}
@@ -0,0 +1,455 @@
+// This file is released under the same terms as Rust itself.
+
+//! Test the entire system front-to-back, with Gitea in front.
+//! Like the GitHub round trip, this is only a test for the happy path.
+
+#![feature(proc_macro)]
+
+extern crate env_logger;
+extern crate hex;
+extern crate hyper;
+#[macro_use] extern crate lazy_static;
+#[macro_use] extern crate log;
+extern crate openssl;
+extern crate serde;
+extern crate serde_json;
+#[macro_use] extern crate serde_derive;
+
+use hex::ToHex;
+use hyper::buffer::BufReader;
+use hyper::client::Client;
+use hyper::header::{Authorization, Basic, Headers};
+use hyper::method::Method;
+use hyper::net::{HttpListener, NetworkListener, NetworkStream};
+use hyper::server::{Request, Response};
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Mutex, Once, ONCE_INIT};
+use std::thread;
+use std::time;
+
+const EXECUTABLE: &'static str = "target/debug/aelita";
+
+const PULL_SHA: &'static str = "55016813274e906e4cbfed97be83e19e6cd93d91";
+const MASTER_SHA: &'static str = "aa218f56b14c9653891f9e74264a383fa43fefbd";
+const MERGE_SHA: &'static str = "ba218f56b14c9653891f9e74264a383fa43fefbd";
+
+lazy_static!{
+    static ref ONE_AT_A_TIME: Mutex<()> = Mutex::new(());
+}
+
+static START: Once = ONCE_INIT;
+
+fn single_request<T, H>(listener: &mut HttpListener, h: H) -> T
+    where H: FnOnce(Request, Response) -> T
+{
+    let mut stream = listener.accept().unwrap();
+    let addr = stream.peer_addr()
+        .expect("webhook client address");
+    let mut stream_clone = stream.clone();
+    let mut buf_read = BufReader::new(
+        &mut stream_clone as &mut NetworkStream
+    );
+    let mut buf_write = BufWriter::new(&mut stream);
+    let req = Request::new(&mut buf_read, addr)
+        .expect("webhook Request");
+    let mut head = Headers::new();
+    let res = Response::new(&mut buf_write, &mut head);
+    h(req, res)
+}
+
+/// Check that a request to the fake Gitea is the one expected.
+fn assert_api_request(req: &Request, method: Method, path: &str) {
+    assert_eq!(req.method, method);
+    assert_eq!(
+        req.uri,
+        RequestUri::AbsolutePath(format!("/api/v1{}", path))
+    );
+    assert_eq!(
+        &req.headers.get_raw("Authorization").unwrap()[0][..],
+        b"token MY_ACCESS_TOKEN"
+    );
+}
+
+/// Send a webhook to Aelita, signed the way Gitea does it.
+fn send_webhook(event: &str, body: &str) {
+    let http_client = Client::new();
+    let mut http_headers = Headers::new();
+    http_headers.set_raw("X-Gitea-Event", vec![event.as_bytes().to_vec()]);
+    http_headers.set_raw("X-Gitea-Signature", vec![
+        openssl::crypto::hmac::hmac(
+            openssl::crypto::hash::Type::SHA256,
+            "ME_SECRET_LOL".as_bytes(),
+            body.as_bytes(),
+        ).to_hex().into_bytes()
+    ]);
+    http_client.post("http://localhost:9021")
+        .body(body.as_bytes())
+        .headers(http_headers)
+        .send()
+        .unwrap();
+}
+
+/// Open a pull request, and have a user with write access approve it.
+/// Returns once Aelita has asked where the pull request goes.
+fn open_and_approve(gitea_server: &mut HttpListener) {
+    info!("Pull request comes into existance.");
+    send_webhook("pull_request", concat!(r#" { "#,
+        r#" "action":"opened", "#,
+        r#" "number":1, "#,
+        r#" "repository":{ "#,
+            r#" "name":"testp", "#,
+            r#" "owner":{"login":"AelitaBot"} "#,
+        r#" }, "#,
+        r#" "pull_request":{ "#,
+            r#" "title":"HA!", "#,
+            r#" "html_url":"http://gitea.com/AelitaBot/testp/pulls/1", "#,
+            r#" "state":"open", "#,
+            r#" "number":1, "#,
+            r#" "base":{ "#,
+                r#" "ref":"master", "#,
+                r#" "sha":"aa218f56b14c9653891f9e74264a383fa43fefbd" "#,
+            r#" }, "#,
+            r#" "head":{ "#,
+                r#" "ref":"testu-patch-1", "#,
+                r#" "sha":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
+            r#" } "#,
+        r#" } "#,
+    r#" } "#));
+
+    info!("User posts comment to mark pull request reviewed.");
+    send_webhook("issue_comment", concat!(r#" { "#,
+        r#" "action":"created", "#,
+        r#" "issue":{ "#,
+            r#" "number":1, "#,
+            r#" "title":"My PR!", "#,
+            r#" "body":"Test", "#,
+            r#" "pull_request":{"merged":false,"merged_at":null}, "#,
+            r#" "state":"open", "#,
+            r#" "user":{"login":"testu"} "#,
+        r#" }, "#,
+        r#" "comment":{ "#,
+            r#" "user":{"login":"testu"}, "#,
+            r#" "body":"@AelitaBot r+" "#,
+        r#" }, "#,
+        r#" "repository":{ "#,
+            r#" "name":"testp", "#,
+            r#" "owner":{"login":"AelitaBot"} "#,
+        r#" }, "#,
+        r#" "is_pull":true "#,
+    r#" } "#));
+
+    info!("Aelita checks if user has permission to do that.");
+    single_request(gitea_server, |req, mut res| {
+        assert_api_request(
+            &req,
+            Method::Get,
+            "/repos/AelitaBot/testp/collaborators/testu/permission",
+        );
+        *res.status_mut() = StatusCode::Ok;
+        res.send(br#"{"permission":"write"}"#).unwrap();
+    });
+
+    info!("Aelita asks where the pull request goes.");
+    single_request(gitea_server, |req, mut res| {
+        assert_api_request(&req, Method::Get, "/repos/AelitaBot/testp/pulls/1");
+        *res.status_mut() = StatusCode::Ok;
+        res.send(concat!(r#" { "#,
+            r#" "number":1, "#,
+            r#" "base":{ "#,
+                r#" "ref":"master", "#,
+                r#" "sha":"aa218f56b14c9653891f9e74264a383fa43fefbd" "#,
+            r#" } "#,
+        r#" } "#).as_bytes()).unwrap();
+    });
+}
+
+fn expect_build_trigger(jenkins_server: &mut HttpListener) {
+    info!("Aelita sends build trigger.");
+    single_request(jenkins_server, |req, mut res| {
+        let path = "/job/testp/build?token=MY_BUILD_TOKEN".to_owned();
+        assert_eq!(
+            req.uri,
+            RequestUri::AbsolutePath(path)
+        );
+        assert_eq!(
+            req.headers.get::<Authorization<Basic>>().unwrap().0,
+            Basic{
+                username: "AelitaBot".to_owned(),
+                password: Some("MY_JENKINS_API_TOKEN".to_owned()),
+            }
+        );
+        *res.status_mut() = StatusCode::NoContent;
+        res.send(&[]).unwrap();
+    });
+}
+
+fn send_build_success(commit: &str) {
+    info!("Jenkins sends start notification to Aelita.");
+    let mut tcp_client = TcpStream::connect("localhost:9022").unwrap();
+    tcp_client.write(
+        r#"{"name":"testp","build":{"phase":"STARTED","full_url":"http://jenkins.com/job/1/","scm":{"commit":"CMMT"}}}"#
+            .replace("CMMT", commit)
+            .as_bytes()
+    ).unwrap();
+    drop(tcp_client);
+
+    info!("Jenkins sends finished notification to Aelita.");
+    let mut tcp_client = TcpStream::connect("localhost:9022").unwrap();
+    tcp_client.write(
+        r#"{"name":"testp","build":{"phase":"COMPLETED","status":"SUCCESS","full_url":"http://jenkins.com/job/1/","scm":{"commit":"CMMT"}}}"#
+            .replace("CMMT", commit)
+            .as_bytes()
+    ).unwrap();
+    drop(tcp_client);
+}
+
+#[test]
+fn one_item_gitea_round_trip() {
+    let _lock = ONE_AT_A_TIME.lock();
+    START.call_once(|| env_logger::init().unwrap());
+
+    if !Path::new(EXECUTABLE).exists() {
+        panic!("Integration tests require the executable to be built.");
+    }
+
+    let mut gitea_server = HttpListener::new(&"localhost:9031").unwrap();
+    let mut jenkins_server = HttpListener::new(&"localhost:9032").unwrap();
+
+    Command::new("/bin/tar")
+        .current_dir("./tests/")
+        .arg("-xvf")
+        .arg("cache.tar.gz")
+        .output()
+        .unwrap();
+
+    Command::new("/bin/rm")
+        .current_dir("./tests/")
+        .arg("db.sqlite")
+        .output()
+        .unwrap();
+
+    let executable = Path::new(EXECUTABLE).canonicalize().unwrap();
+    let mut aelita = Command::new(executable)
+        .current_dir("./tests/")
+        .arg("test-gitea-round-trip.toml")
+        .spawn()
+        .unwrap();
+
+    info!("Wait a sec for it to finish starting.");
+    thread::sleep(time::Duration::new(2, 0));
+
+    open_and_approve(&mut gitea_server);
+
+    expect_build_trigger(&mut jenkins_server);
+
+    info!("Aelita does the merge.");
+    let mut commit_string = String::new();
+    File::open(Path::new("tests/cache/origin/.git/refs/heads/staging"))
+        .unwrap()
+        .read_to_string(&mut commit_string)
+        .unwrap();
+    commit_string = commit_string.replace("\n", "").replace("\r", "");
+
+    send_build_success(&commit_string);
+
+    info!("Wait a sec for it to finish pushing.");
+    thread::sleep(time::Duration::new(2, 0));
+
+    info!("Aelita fast-forwards master.");
+    let mut master_string = String::new();
+    File::open(Path::new("tests/cache/origin/.git/refs/heads/master"))
+        .unwrap()
+        .read_to_string(&mut master_string)
+        .unwrap();
+    master_string = master_string.replace("\n", "").replace("\r", "");
+    assert_eq!(commit_string, master_string);
+
+    aelita.kill().unwrap();
+}
+
+#[test]
+fn one_item_gitea_round_trip_cloud() {
+    let _lock = ONE_AT_A_TIME.lock();
+    START.call_once(|| env_logger::init().unwrap());
+
+    if !Path::new(EXECUTABLE).exists() {
+        panic!("Integration tests require the executable to be built.");
+    }
+
+    Command::new("/bin/rm")
+        .current_dir("./tests/")
+        .arg("db.sqlite")
+        .output()
+        .unwrap();
+
+    let mut gitea_server = HttpListener::new(&"localhost:9031").unwrap();
+    let mut jenkins_server = HttpListener::new(&"localhost:9032").unwrap();
+    let mut gitea_git_server = HttpListener::new(&"localhost:9033").unwrap();
+
+    let executable = Path::new(EXECUTABLE).canonicalize().unwrap();
+    let mut aelita = Command::new(executable)
+        .current_dir("./tests/")
+        .arg("test-gitea-round-trip-cloud.toml")
+        .spawn()
+        .unwrap();
+
+    info!("Wait a sec for it to finish starting.");
+    thread::sleep(time::Duration::new(2, 0));
+
+    open_and_approve(&mut gitea_server);
+
+    #[derive(Deserialize)]
+    struct CreateBranchDesc {
+        new_branch_name: String,
+        old_ref_name: String,
+    }
+    #[derive(Deserialize)]
+    struct CreatePullDesc {
+        base: String,
+        head: String,
+    }
+    #[derive(Deserialize)]
+    struct MergePullDesc {
+        #[serde(rename="Do")]
+        merge_style: String,
+        #[serde(rename="MergeMessageField")]
+        message: Option<String>,
+        head_commit_id: String,
+    }
+
+    info!("Aelita checks for the current contents of master.");
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(
+            &req,
+            Method::Get,
+            "/repos/AelitaBot/testp/branches/master",
+        );
+        *res.status_mut() = StatusCode::Ok;
+        res.send(format!(r#"{{"commit":{{"id":"{}"}}}}"#, MASTER_SHA)
+            .as_bytes()).unwrap();
+    });
+
+    info!("Aelita resets staging to master.");
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(
+            &req,
+            Method::Delete,
+            "/repos/AelitaBot/testp/branches/staging",
+        );
+        *res.status_mut() = StatusCode::NoContent;
+        res.send(&[]).unwrap();
+    });
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(&req, Method::Post, "/repos/AelitaBot/testp/branches");
+        let desc: CreateBranchDesc =
+            serde_json::from_reader(req).expect("valid JSON");
+        *res.status_mut() = StatusCode::Created;
+        res.send(&[]).unwrap();
+        assert_eq!(desc.new_branch_name, "staging");
+        assert_eq!(desc.old_ref_name, MASTER_SHA);
+    });
+
+    info!("Aelita puts the pull request's commit on a branch.");
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(
+            &req,
+            Method::Delete,
+            "/repos/AelitaBot/testp/branches/staging.head",
+        );
+        *res.status_mut() = StatusCode::NotFound;
+        res.send(&[]).unwrap();
+    });
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(&req, Method::Post, "/repos/AelitaBot/testp/branches");
+        let desc: CreateBranchDesc =
+            serde_json::from_reader(req).expect("valid JSON");
+        *res.status_mut() = StatusCode::Created;
+        res.send(&[]).unwrap();
+        assert_eq!(desc.new_branch_name, "staging.head");
+        assert_eq!(desc.old_ref_name, PULL_SHA);
+    });
+
+    info!("Aelita merges it into staging.");
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(&req, Method::Post, "/repos/AelitaBot/testp/pulls");
+        let desc: CreatePullDesc =
+            serde_json::from_reader(req).expect("valid JSON");
+        *res.status_mut() = StatusCode::Created;
+        res.send(br#"{"number":2}"#).unwrap();
+        assert_eq!(desc.base, "staging");
+        assert_eq!(desc.head, "staging.head");
+    });
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(
+            &req,
+            Method::Post,
+            "/repos/AelitaBot/testp/pulls/2/merge",
+        );
+        let desc: MergePullDesc =
+            serde_json::from_reader(req).expect("valid JSON");
+        *res.status_mut() = StatusCode::Ok;
+        res.send(&[]).unwrap();
+        assert_eq!(desc.merge_style, "merge");
+        assert_eq!(desc.head_commit_id, PULL_SHA);
+        assert!(desc.message.unwrap().starts_with("Merge #1 a=@testu r=@testu"));
+    });
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(
+            &req,
+            Method::Get,
+            "/repos/AelitaBot/testp/branches/staging",
+        );
+        *res.status_mut() = StatusCode::Ok;
+        res.send(format!(r#"{{"commit":{{"id":"{}"}}}}"#, MERGE_SHA)
+            .as_bytes()).unwrap();
+    });
+
+    expect_build_trigger(&mut jenkins_server);
+
+    send_build_success(MERGE_SHA);
+
+    info!("Aelita checks staging is still what was tested.");
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(
+            &req,
+            Method::Get,
+            "/repos/AelitaBot/testp/branches/staging",
+        );
+        *res.status_mut() = StatusCode::Ok;
+        res.send(format!(r#"{{"commit":{{"id":"{}"}}}}"#, MERGE_SHA)
+            .as_bytes()).unwrap();
+    });
+
+    info!("Aelita fast-forwards master to staging.");
+    single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(&req, Method::Post, "/repos/AelitaBot/testp/pulls");
+        let desc: CreatePullDesc =
+            serde_json::from_reader(req).expect("valid JSON");
+        *res.status_mut() = StatusCode::Created;
+        res.send(br#"{"number":3}"#).unwrap();
+        assert_eq!(desc.base, "master");
+        assert_eq!(desc.head, "staging");
+    });
+    let master_string = single_request(&mut gitea_git_server, |req, mut res| {
+        assert_api_request(
+            &req,
+            Method::Post,
+            "/repos/AelitaBot/testp/pulls/3/merge",
+        );
+        let desc: MergePullDesc =
+            serde_json::from_reader(req).expect("valid JSON");
+        *res.status_mut() = StatusCode::Ok;
+        res.send(&[]).unwrap();
+        assert_eq!(desc.merge_style, "fast-forward-only");
+        desc.head_commit_id
+    });
+    assert_eq!(MERGE_SHA, master_string);
+
+    aelita.kill().unwrap();
+}
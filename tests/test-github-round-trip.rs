@@ -118,6 +118,7 @@ fn one_item_github_round_trip() {
             r#" "html_url":"http://github.com/testu/testp/pull_request/1", "#,
             r#" "state":"opened", "#,
             r#" "number":1, "#,
+            r#" "base":{"ref":"master"}, "#,
             r#" "head":{ "#,
                 r#" "sha":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
             r#" } "#,
@@ -335,6 +336,7 @@ fn one_item_team_github_round_trip() {
             r#" "html_url":"http://github.com/testu/testp/pull_request/1", "#,
             r#" "state":"opened", "#,
             r#" "number":1, "#,
+            r#" "base":{"ref":"master"}, "#,
             r#" "head":{ "#,
                 r#" "sha":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
             r#" } "#,
@@ -573,6 +575,7 @@ fn one_item_github_round_trip_status() {
             r#" "html_url":"http://github.com/testu/testp/pull_request/1", "#,
             r#" "state":"opened", "#,
             r#" "number":1, "#,
+            r#" "base":{"ref":"master"}, "#,
             r#" "head":{ "#,
                 r#" "sha":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
             r#" } "#,
@@ -795,6 +798,7 @@ fn one_item_github_round_trip_cloud() {
             r#" "html_url":"http://github.com/testu/testp/pull_request/1", "#,
             r#" "state":"opened", "#,
             r#" "number":1, "#,
+            r#" "base":{"ref":"master"}, "#,
             r#" "head":{ "#,
                 r#" "sha":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
             r#" } "#,
@@ -1078,6 +1082,7 @@ fn one_item_github_round_trip_cloud_no_staging() {
             r#" "html_url":"http://github.com/testu/testp/pull_request/1", "#,
             r#" "state":"opened", "#,
             r#" "number":1, "#,
+            r#" "base":{"ref":"master"}, "#,
             r#" "head":{ "#,
                 r#" "sha":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
             r#" } "#,
@@ -1428,6 +1433,7 @@ fn one_item_team_github_round_trip_with_postgres() {
             r#" "html_url":"http://github.com/testu/testp/pull_request/1", "#,
             r#" "state":"opened", "#,
             r#" "number":1, "#,
+            r#" "base":{"ref":"master"}, "#,
             r#" "head":{ "#,
                 r#" "sha":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
             r#" } "#,
@@ -1775,6 +1781,7 @@ fn one_item_github_round_trip_cloud_with_postgres_12f() {
             r#" "html_url":"http://github.com/testu/testp/pull_request/1", "#,
             r#" "state":"opened", "#,
             r#" "number":1, "#,
+            r#" "base":{"ref":"master"}, "#,
             r#" "head":{ "#,
                 r#" "sha":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
             r#" } "#,
@@ -2174,6 +2181,7 @@ fn one_item_github_round_trip_cloud_with_sqlite_12f() {
             r#" "html_url":"http://github.com/testu/testp/pull_request/1", "#,
             r#" "state":"opened", "#,
             r#" "number":1, "#,
+            r#" "base":{"ref":"master"}, "#,
             r#" "head":{ "#,
                 r#" "sha":"55016813274e906e4cbfed97be83e19e6cd93d91" "#,
             r#" } "#,